@@ -0,0 +1,131 @@
+//! Compares running programs by walking the tree
+//! with compiling and running them on the virtual machine.
+
+#![feature(test)]
+
+extern crate libsmac;
+extern crate test;
+
+use libsmac::codegen::compile;
+use libsmac::document::Document;
+use libsmac::interpreter::Interpreter;
+use libsmac::parser::node::Node;
+use libsmac::vm::Vm;
+
+use test::Bencher;
+
+/// Nested blocks of literals, `depth` levels deep.
+fn source(depth: usize) -> String {
+    let mut source = String::new();
+    for line in 0..200 {
+        for level in 0..depth {
+            source.push_str(&"  ".repeat(level));
+            match (line + level) % 4 {
+                0 => source.push_str(&format!("{}\n", line)),
+                1 => source.push_str(&format!("{}.5\n", line)),
+                2 => source.push_str("\"text\"\n"),
+                _ => source.push_str("true\n"),
+            }
+        }
+    }
+    source
+}
+
+/// Recursive calls.
+const FIB: &'static str = "\
+let fib n =
+  if n < 2
+    n
+  else
+    fib (n - 1) + fib (n - 2)
+
+fib 18
+";
+
+/// A `for` loop nested in a `while` loop.
+const LOOPS: &'static str = "\
+let mut total = 0
+let mut i = 0
+while i < 2000
+  for j in 0..10
+    total += i * j
+  i += 1
+total
+";
+
+/// Applying closures built by other closures.
+const CLOSURES: &'static str = "\
+let adder n = x -> x + n
+let compose f g = x -> f (g x)
+let step = compose (adder 1) (adder 2)
+
+let mut total = 0
+for i in 0..2000
+  total += step i
+total
+";
+
+fn parse(source: &str) -> Vec<Node> {
+    let document = Document::new(source);
+    assert!(document.diagnostics().is_empty());
+    document.ast().into_iter().cloned().collect()
+}
+
+#[bench]
+fn interpreter(b: &mut Bencher) {
+    let ast = parse(&source(8));
+    b.iter(|| Interpreter::new().run(&ast).unwrap())
+}
+
+#[bench]
+fn vm(b: &mut Bencher) {
+    let ast = parse(&source(8));
+    let program = compile(&ast).unwrap();
+    b.iter(|| Vm::new(&program).run().unwrap())
+}
+
+#[bench]
+fn vm_with_compilation(b: &mut Bencher) {
+    let ast = parse(&source(8));
+    b.iter(|| Vm::new(&compile(&ast).unwrap()).run().unwrap())
+}
+
+fn interpret(b: &mut Bencher, source: &str) {
+    let ast = parse(source);
+    b.iter(|| Interpreter::new().run(&ast).unwrap())
+}
+
+fn run(b: &mut Bencher, source: &str) {
+    let program = compile(&parse(source)).unwrap();
+    b.iter(|| Vm::new(&program).run().unwrap())
+}
+
+#[bench]
+fn interpreter_fib(b: &mut Bencher) {
+    interpret(b, FIB)
+}
+
+#[bench]
+fn vm_fib(b: &mut Bencher) {
+    run(b, FIB)
+}
+
+#[bench]
+fn interpreter_loops(b: &mut Bencher) {
+    interpret(b, LOOPS)
+}
+
+#[bench]
+fn vm_loops(b: &mut Bencher) {
+    run(b, LOOPS)
+}
+
+#[bench]
+fn interpreter_closures(b: &mut Bencher) {
+    interpret(b, CLOSURES)
+}
+
+#[bench]
+fn vm_closures(b: &mut Bencher) {
+    run(b, CLOSURES)
+}
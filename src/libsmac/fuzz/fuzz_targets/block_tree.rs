@@ -0,0 +1,22 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate libsmac;
+
+use libsmac::lexer::block_tree::BlockTree;
+
+use std::str;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = str::from_utf8(data) {
+        let mut block_tree = BlockTree::new(source, 0);
+        let indents = block_tree.collect_indents();
+        let root = block_tree.tree(&indents);
+
+        // Chunks are never invented, and come in source order
+        if let Some((first, last)) = root.lines() {
+            assert!(first <= last);
+            assert!(last < source.lines().count());
+        }
+    }
+});
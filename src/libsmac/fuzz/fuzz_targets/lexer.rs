@@ -0,0 +1,27 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate libsmac;
+
+use libsmac::lexer::{grab_smaragdine_lexer, grab_smaragdine_reader_lexer};
+
+use std::str;
+
+fuzz_target!(|data: &[u8]| {
+    // Readers take any bytes, replacing invalid UTF-8
+    let count = grab_smaragdine_reader_lexer(data).count();
+    assert!(count <= data.len());
+
+    if let Ok(source) = str::from_utf8(data) {
+        let mut previous = None;
+        for token in grab_smaragdine_lexer(source) {
+            // Every token moves the lexer forward
+            let span = *token.span();
+            if let Some(end) = previous {
+                assert!((span.start.line, span.start.col) >= end);
+            }
+            assert!((span.end.line, span.end.col) > (span.start.line, span.start.col));
+            previous = Some((span.end.line, span.end.col));
+        }
+    }
+});
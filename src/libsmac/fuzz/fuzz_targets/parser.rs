@@ -0,0 +1,24 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate libsmac;
+
+use libsmac::lexer::block_tree::BlockTree;
+use libsmac::lexer::{lex_branch, flatten_branch};
+use libsmac::parser::grab_smaragdine_parser;
+
+use std::str;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = str::from_utf8(data) {
+        let mut block_tree = BlockTree::new(source, 0);
+        let indents = block_tree.collect_indents();
+        let tokens = flatten_branch(&lex_branch(&block_tree.tree(&indents)));
+        let count = tokens.len();
+
+        // Every node consumes at least one token
+        let mut parser = grab_smaragdine_parser(tokens);
+        let nodes = parser.by_ref().take(count + 1).count();
+        assert!(nodes <= count);
+    }
+});
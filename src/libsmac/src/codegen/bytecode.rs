@@ -0,0 +1,419 @@
+use lexer::token::{Op, TokenPosition};
+use value::Value;
+
+/// The instructions of the virtual machine.
+///
+/// Each opcode is one byte, followed by its operands.
+/// Every operand is a little-endian `u16`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Opcode {
+    /// `Const index`: pushes a constant from the pool.
+    Const,
+    /// Pushes the unit value.
+    Unit,
+    /// Drops the top of the stack.
+    Pop,
+    /// `Load slot`: pushes a local.
+    Load,
+    /// `Store slot`: pops the top of the stack into a local.
+    Store,
+    /// `Jump offset`: continues at an offset in the function.
+    Jump,
+    /// `JumpIfFalse offset`: pops a boolean, jumping if it is false.
+    JumpIfFalse,
+    /// `Call function`: calls a function with its
+    /// arguments on top of the stack, first pushed first.
+    Call,
+    /// Pops the result and returns it to the caller.
+    Return,
+    /// Pops an operand and pushes the result of an operator.
+    Neg,
+    Not,
+    BitNot,
+    /// Pops two operands, the right one first,
+    /// and pushes the result of an operator.
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `List count`: pops that many values, the last
+    /// pushed last, and pushes a list of them.
+    List,
+    /// Pops an index and a list, and pushes the element.
+    Index,
+    /// Pops a value, an index and a list, and pushes
+    /// the list with the element replaced.
+    SetIndex,
+    /// `Record template`: pops a value for each field of the
+    /// record constant `template`, first pushed first, and
+    /// pushes a record of them.
+    Record,
+    /// `Field name`: pops a record and pushes the field
+    /// named by a string constant.
+    Field,
+    /// `SetField name`: pops a value and a record, and pushes
+    /// the record with the named field replaced.
+    SetField,
+    /// `Variant template`: pops a value for each element of the
+    /// payload of the variant constant `template`, first pushed
+    /// first, and pushes the variant with them as its payload.
+    Variant,
+    /// `Tuple count`: pops that many values, the last
+    /// pushed last, and pushes a tuple of them.
+    Tuple,
+    /// `Element index`: pops a tuple or variant, and pushes
+    /// that element of it or of its payload.
+    Element,
+    /// `IsVariant name`: pops a variant, and pushes whether
+    /// it is the one named by a string constant.
+    IsVariant,
+    /// Pops the value a `match` had no arm for, and fails.
+    Unmatched,
+    /// `Mark slot`: stores the height of the stack in a local.
+    Mark,
+    /// `Unwind slot`: pops a value, drops what was pushed since
+    /// the height marked in a local, and pushes the value back.
+    Unwind,
+    /// Pops a list and pushes its length.
+    Length,
+    /// Pops the end and the start of a range, and
+    /// pushes a list of the ints in it.
+    Range,
+    RangeInclusive,
+    /// Pops a value and pushes a new cell holding it.
+    Cell,
+    /// `LoadCell slot`: pushes the value in the cell in a local.
+    LoadCell,
+    /// `StoreCell slot`: pops the top of the stack into the cell in a local.
+    StoreCell,
+    /// `Closure function`: pops a cell for each variable the function
+    /// captures, first pushed first, and pushes a closure of them.
+    Closure,
+    /// `Apply count`: pops that many arguments, first pushed first,
+    /// and the closure pushed before them, and calls it.
+    Apply,
+    /// `Method function`: pops a value and pushes its field named
+    /// after the function, or without one, calls the function on it.
+    Method,
+}
+
+const OPCODES: [Opcode; 50] = [
+    Opcode::Const,
+    Opcode::Unit,
+    Opcode::Pop,
+    Opcode::Load,
+    Opcode::Store,
+    Opcode::Jump,
+    Opcode::JumpIfFalse,
+    Opcode::Call,
+    Opcode::Return,
+    Opcode::Neg,
+    Opcode::Not,
+    Opcode::BitNot,
+    Opcode::Add,
+    Opcode::Sub,
+    Opcode::Mul,
+    Opcode::Div,
+    Opcode::Mod,
+    Opcode::BitAnd,
+    Opcode::BitOr,
+    Opcode::BitXor,
+    Opcode::Shl,
+    Opcode::Shr,
+    Opcode::Eq,
+    Opcode::Ne,
+    Opcode::Lt,
+    Opcode::Le,
+    Opcode::Gt,
+    Opcode::Ge,
+    Opcode::List,
+    Opcode::Index,
+    Opcode::SetIndex,
+    Opcode::Record,
+    Opcode::Field,
+    Opcode::SetField,
+    Opcode::Variant,
+    Opcode::Tuple,
+    Opcode::Element,
+    Opcode::IsVariant,
+    Opcode::Unmatched,
+    Opcode::Mark,
+    Opcode::Unwind,
+    Opcode::Length,
+    Opcode::Range,
+    Opcode::RangeInclusive,
+    Opcode::Cell,
+    Opcode::LoadCell,
+    Opcode::StoreCell,
+    Opcode::Closure,
+    Opcode::Apply,
+    Opcode::Method,
+];
+
+impl Opcode {
+    pub fn from_byte(byte: u8) -> Option<Opcode> {
+        OPCODES.get(byte as usize).cloned()
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        *self as u8
+    }
+
+    /// The number of `u16` operands following the opcode.
+    pub fn operands(&self) -> usize {
+        match *self {
+            Opcode::Const | Opcode::Load | Opcode::Store
+                | Opcode::Jump | Opcode::JumpIfFalse | Opcode::Call
+                | Opcode::List | Opcode::Record | Opcode::Field | Opcode::SetField
+                | Opcode::Variant | Opcode::Tuple | Opcode::Element | Opcode::IsVariant
+                | Opcode::Mark | Opcode::Unwind | Opcode::LoadCell | Opcode::StoreCell
+                | Opcode::Closure | Opcode::Apply | Opcode::Method => 1,
+            _ => 0,
+        }
+    }
+
+    /// The opcode applying a unary operator.
+    pub fn unary(op: Op) -> Option<Opcode> {
+        match op {
+            Op::Sub    => Some(Opcode::Neg),
+            Op::Not    => Some(Opcode::Not),
+            Op::BitNot => Some(Opcode::BitNot),
+            _          => None,
+        }
+    }
+
+    /// The opcode applying a binary operator, other than
+    /// `&&` and `||` which are compiled to jumps.
+    pub fn binary(op: Op) -> Option<Opcode> {
+        match op {
+            Op::Add    => Some(Opcode::Add),
+            Op::Sub    => Some(Opcode::Sub),
+            Op::Mul    => Some(Opcode::Mul),
+            Op::Div    => Some(Opcode::Div),
+            Op::Mod    => Some(Opcode::Mod),
+            Op::BitAnd => Some(Opcode::BitAnd),
+            Op::BitOr  => Some(Opcode::BitOr),
+            Op::BitXor => Some(Opcode::BitXor),
+            Op::Shl    => Some(Opcode::Shl),
+            Op::Shr    => Some(Opcode::Shr),
+            Op::Eq     => Some(Opcode::Eq),
+            Op::Ne     => Some(Opcode::Ne),
+            Op::Lt     => Some(Opcode::Lt),
+            Op::Le     => Some(Opcode::Le),
+            Op::Gt     => Some(Opcode::Gt),
+            Op::Ge     => Some(Opcode::Ge),
+            Op::Range  => Some(Opcode::Range),
+            Op::RangeInclusive => Some(Opcode::RangeInclusive),
+            _          => None,
+        }
+    }
+
+    /// The operator an arithmetic or comparison opcode applies.
+    pub fn operator(&self) -> Option<Op> {
+        match *self {
+            Opcode::Neg    => Some(Op::Sub),
+            Opcode::Not    => Some(Op::Not),
+            Opcode::BitNot => Some(Op::BitNot),
+            Opcode::Add    => Some(Op::Add),
+            Opcode::Sub    => Some(Op::Sub),
+            Opcode::Mul    => Some(Op::Mul),
+            Opcode::Div    => Some(Op::Div),
+            Opcode::Mod    => Some(Op::Mod),
+            Opcode::BitAnd => Some(Op::BitAnd),
+            Opcode::BitOr  => Some(Op::BitOr),
+            Opcode::BitXor => Some(Op::BitXor),
+            Opcode::Shl    => Some(Op::Shl),
+            Opcode::Shr    => Some(Op::Shr),
+            Opcode::Eq     => Some(Op::Eq),
+            Opcode::Ne     => Some(Op::Ne),
+            Opcode::Lt     => Some(Op::Lt),
+            Opcode::Le     => Some(Op::Le),
+            Opcode::Gt     => Some(Op::Gt),
+            Opcode::Ge     => Some(Op::Ge),
+            Opcode::Range  => Some(Op::Range),
+            Opcode::RangeInclusive => Some(Op::RangeInclusive),
+            _              => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Opcode::Const       => "const",
+            Opcode::Unit        => "unit",
+            Opcode::Pop         => "pop",
+            Opcode::Load        => "load",
+            Opcode::Store       => "store",
+            Opcode::Jump        => "jump",
+            Opcode::JumpIfFalse => "jump_if_false",
+            Opcode::Call        => "call",
+            Opcode::Return      => "return",
+            Opcode::Neg         => "neg",
+            Opcode::Not         => "not",
+            Opcode::BitNot      => "bit_not",
+            Opcode::Add         => "add",
+            Opcode::Sub         => "sub",
+            Opcode::Mul         => "mul",
+            Opcode::Div         => "div",
+            Opcode::Mod         => "mod",
+            Opcode::BitAnd      => "bit_and",
+            Opcode::BitOr       => "bit_or",
+            Opcode::BitXor      => "bit_xor",
+            Opcode::Shl         => "shl",
+            Opcode::Shr         => "shr",
+            Opcode::Eq          => "eq",
+            Opcode::Ne          => "ne",
+            Opcode::Lt          => "lt",
+            Opcode::Le          => "le",
+            Opcode::Gt          => "gt",
+            Opcode::Ge          => "ge",
+            Opcode::List        => "list",
+            Opcode::Index       => "index",
+            Opcode::SetIndex    => "set_index",
+            Opcode::Record      => "record",
+            Opcode::Field       => "field",
+            Opcode::SetField    => "set_field",
+            Opcode::Variant     => "variant",
+            Opcode::Tuple       => "tuple",
+            Opcode::Element     => "element",
+            Opcode::IsVariant   => "is_variant",
+            Opcode::Unmatched   => "unmatched",
+            Opcode::Mark        => "mark",
+            Opcode::Unwind      => "unwind",
+            Opcode::Length      => "length",
+            Opcode::Range       => "range",
+            Opcode::RangeInclusive => "range_inclusive",
+            Opcode::Cell        => "cell",
+            Opcode::LoadCell    => "load_cell",
+            Opcode::StoreCell   => "store_cell",
+            Opcode::Closure     => "closure",
+            Opcode::Apply       => "apply",
+            Opcode::Method      => "method",
+        }
+    }
+}
+
+/// A function's code, along with what
+/// the machine needs to set up its frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name:     String,
+    /// The number of arguments, which become its first locals.
+    pub arity:    u16,
+    /// The number of variables it captures, which come
+    /// before its arguments in its first locals.
+    pub captures: u16,
+    /// The number of locals, arguments and captures included.
+    pub locals:   u16,
+    pub code:     Vec<u8>,
+    /// The source position of the code from each offset on,
+    /// sorted by offset.
+    pub lines:    Vec<(usize, TokenPosition)>,
+}
+
+impl Function {
+    pub fn new(name: &str, arity: u16) -> Function {
+        Function {
+            name:     name.to_owned(),
+            arity:    arity,
+            captures: 0,
+            locals:   arity,
+            code:     Vec::new(),
+            lines:    Vec::new(),
+        }
+    }
+
+    /// Appends an instruction, returning its offset.
+    pub fn emit(&mut self, opcode: Opcode, operands: &[u16], position: TokenPosition) -> usize {
+        debug_assert_eq!(opcode.operands(), operands.len());
+
+        let offset = self.code.len();
+        if self.lines.last().map_or(true, |&(_, p)| p != position) {
+            self.lines.push((offset, position))
+        }
+
+        self.code.push(opcode.to_byte());
+        for operand in operands.iter() {
+            self.code.extend_from_slice(&operand.to_le_bytes())
+        }
+        offset
+    }
+
+    /// Replaces the first operand of the instruction at `offset`,
+    /// used to point jumps at code emitted after them.
+    pub fn patch(&mut self, offset: usize, operand: u16) {
+        let bytes = operand.to_le_bytes();
+        self.code[offset + 1] = bytes[0];
+        self.code[offset + 2] = bytes[1];
+    }
+
+    /// Reads the operand at `offset`.
+    pub fn operand(&self, offset: usize) -> Option<u16> {
+        match (self.code.get(offset), self.code.get(offset + 1)) {
+            (Some(&low), Some(&high)) => Some(u16::from_le_bytes([low, high])),
+            _                         => None,
+        }
+    }
+
+    /// The source position of the instruction at `offset`.
+    pub fn position(&self, offset: usize) -> Option<TokenPosition> {
+        match self.lines.binary_search_by_key(&offset, |&(o, _)| o) {
+            Ok(i)  => Some(self.lines[i].1),
+            Err(0) => None,
+            Err(i) => Some(self.lines[i - 1].1),
+        }
+    }
+}
+
+/// A compiled program: the constant pool shared by
+/// its functions, and the functions themselves.
+/// The first function is the entry point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub constants: Vec<Value>,
+    pub functions: Vec<Function>,
+}
+
+impl Program {
+    pub fn new() -> Program {
+        Program {
+            constants: Vec::new(),
+            functions: Vec::new(),
+        }
+    }
+
+    /// Adds a constant to the pool, reusing an equal one,
+    /// or returns `None` once the pool is full.
+    pub fn add_constant(&mut self, value: Value) -> Option<u16> {
+        let index = match self.constants.iter().position(|c| *c == value) {
+            Some(index) => index,
+            None        => {
+                self.constants.push(value);
+                self.constants.len() - 1
+            },
+        };
+        if index <= u16::max_value() as usize {
+            Some(index as u16)
+        } else {
+            self.constants.pop();
+            None
+        }
+    }
+
+    /// Adds a function, returning its index.
+    pub fn add_function(&mut self, function: Function) -> u16 {
+        self.functions.push(function);
+        (self.functions.len() - 1) as u16
+    }
+}
@@ -0,0 +1,466 @@
+use diagnostic::Diagnostic;
+use lexer::token::Op;
+use parser::node::{Node, NodeType};
+use typeck::{Type, Types};
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// The runtime the emitted C includes as `smac.h`.
+pub const HEADER: &'static str = include_str!("smac.h");
+
+/// Translates type-checked nodes to a C file.
+///
+/// Each top-level function `f` becomes a C function
+/// `smaragdine_f`, with `?` and `!` in its name written
+/// as `_p` and `_b`. Unless `SMAC_NO_MAIN` is defined,
+/// `main` evaluates the other top-level nodes and prints
+/// the last value the way `smac run` does.
+pub fn emit_c(nodes: &[Node], types: &Types) -> Result<String, Diagnostic> {
+    let mut names: HashMap<String, String> = HashMap::new();
+    for node in nodes.iter() {
+        if let NodeType::Function(ref name, _, _) = *node.node_type() {
+            let mangled = format!("smaragdine_{}", mangle(name));
+            if let Some(other) = names.iter().find(|&(_, m)| *m == mangled).map(|(n, _)| n.clone()) {
+                return Err(error(node, format!("`{}` and `{}` have the same name in C", other, name)))
+            }
+            names.insert(name.clone(), mangled);
+        }
+    }
+
+    let mut output = String::from("/* Generated by smac. */\n\n#include \"smac.h\"\n\n");
+
+    let mut definitions = String::new();
+    for node in nodes.iter() {
+        if let NodeType::Function(ref name, ref parameters, ref body) = *node.node_type() {
+            let (parameter_types, result) = match types.function(name) {
+                Some(&Type::Function(ref parameters, ref result)) => (parameters, &**result),
+                _ => return Err(error(node, format!("Unknown name `{}`", name))),
+            };
+
+            let mut emitter = Emitter::new(types, &names);
+            let mut declaration = format!("{} {}(", c_type(node, result)?, names[name]);
+            let mut scope = Vec::new();
+            for (i, (parameter, t)) in parameters.iter().zip(parameter_types.iter()).enumerate() {
+                let variable = emitter.variable(parameter);
+                if i > 0 {
+                    declaration.push_str(", ");
+                }
+                write!(declaration, "{} {}", c_type(node, t)?, variable).unwrap();
+                scope.push((parameter.clone(), variable));
+            }
+            declaration.push(')');
+            emitter.scopes.push(scope);
+
+            let value = emitter.node(body)?;
+            emitter.line(&format!("return {};", value));
+
+            writeln!(output, "{};", declaration).unwrap();
+            write!(definitions, "\n{} {{\n{}}}\n", declaration, emitter.code).unwrap();
+        }
+    }
+    output.push_str(&definitions);
+
+    // The program's value, as `Interpreter::run` finds it
+    let mut emitter = Emitter::new(types, &names);
+    emitter.scopes.push(Vec::new());
+    let mut value = (String::from("0"), Type::Unit);
+    for node in nodes.iter() {
+        value = match *node.node_type() {
+            NodeType::Function(..) => (String::from("0"), Type::Unit),
+            _                      => (emitter.node(node)?, emitter.type_of(node)),
+        }
+    }
+    let print = match value.1 {
+        Type::Unit   => None,
+        Type::Int    => Some("smac_print_int"),
+        Type::Float  => Some("smac_print_float"),
+        Type::Bool   => Some("smac_print_bool"),
+        Type::Char   => Some("smac_print_char"),
+        Type::String => Some("smac_print_string"),
+        ref t        => return Err(error(nodes.last().unwrap(), format!("Cannot print `{}`", t))),
+    };
+    match print {
+        Some(print) => emitter.line(&format!("{}({});", print, value.0)),
+        None        => emitter.line(&format!("(void){};", value.0)),
+    }
+
+    write!(output, "\n#ifndef SMAC_NO_MAIN\nint main(void) {{\n{}    return 0;\n}}\n#endif\n", emitter.code).unwrap();
+    Ok(output)
+}
+
+fn error(node: &Node, message: String) -> Diagnostic {
+    Diagnostic::new(node.span().start, message)
+}
+
+/// Writes a name with only the characters C allows.
+fn mangle(name: &str) -> String {
+    name.replace('?', "_p").replace('!', "_b")
+}
+
+fn c_type(node: &Node, t: &Type) -> Result<&'static str, Diagnostic> {
+    match *t {
+        Type::Unit   => Ok("smac_unit"),
+        Type::Int    => Ok("int64_t"),
+        Type::Float  => Ok("double"),
+        Type::Bool   => Ok("bool"),
+        Type::Char   => Ok("smac_char"),
+        Type::String => Ok("smac_string"),
+        ref t        => Err(error(node, format!("Cannot translate `{}` to C", t))),
+    }
+}
+
+/// Writes C which computes an integer exactly, since the
+/// most negative one has no literal.
+fn int_literal(value: i64) -> String {
+    if value == i64::min_value() {
+        String::from("INT64_MIN")
+    } else if value < 0 {
+        format!("(-INT64_C({}))", -value)
+    } else {
+        format!("INT64_C({})", value)
+    }
+}
+
+fn float_literal(value: f64) -> String {
+    if value.is_infinite() {
+        String::from("HUGE_VAL")
+    } else {
+        // Debug output reads back as the same value
+        format!("{:?}", value)
+    }
+}
+
+/// Writes a string with every byte C could misread escaped.
+fn string_literal(value: &str) -> String {
+    let mut literal = String::from("smac_str(\"");
+    for &byte in value.as_bytes() {
+        match byte {
+            b'"' | b'\\' | b'?' => write!(literal, "\\{:03o}", byte).unwrap(),
+            b' '..=b'~'         => literal.push(byte as char),
+            _                   => write!(literal, "\\{:03o}", byte).unwrap(),
+        }
+    }
+    write!(literal, "\", {})", value.len()).unwrap();
+    literal
+}
+
+/// Writes the statements of one C function.
+///
+/// Each node becomes statements storing its value in a fresh
+/// temporary, so C evaluates everything in the order the
+/// interpreter does.
+struct Emitter<'a> {
+    types:     &'a Types,
+    /// The C name of each top-level function.
+    functions: &'a HashMap<String, String>,
+    code:      String,
+    indent:    usize,
+    /// The C names of the locals in scope, innermost last.
+    scopes:    Vec<Vec<(String, String)>>,
+    names:     usize,
+}
+
+impl<'a> Emitter<'a> {
+    fn new(types: &'a Types, functions: &'a HashMap<String, String>) -> Emitter<'a> {
+        Emitter {
+            types:     types,
+            functions: functions,
+            code:      String::new(),
+            indent:    1,
+            scopes:    Vec::new(),
+            names:     0,
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.indent {
+            self.code.push_str("    ");
+        }
+        self.code.push_str(line);
+        self.code.push('\n');
+    }
+
+    /// A C name for a new local.
+    fn variable(&mut self, name: &str) -> String {
+        self.names += 1;
+        format!("v_{}_{}", mangle(name), self.names)
+    }
+
+    /// Declares a temporary, and assigns it `value` if any.
+    fn temporary(&mut self, node: &Node, t: &Type, value: Option<String>) -> Result<String, Diagnostic> {
+        self.names += 1;
+        let name = format!("t{}", self.names);
+        let declaration = match value {
+            Some(value) => format!("{} {} = {};", c_type(node, t)?, name, value),
+            None        => format!("{} {};", c_type(node, t)?, name),
+        };
+        self.line(&declaration);
+        Ok(name)
+    }
+
+    fn type_of(&self, node: &Node) -> Type {
+        self.types.of(node).cloned().unwrap_or(Type::Unit)
+    }
+
+    fn lookup(&self, node: &Node, name: &str) -> Result<String, Diagnostic> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(&(_, ref variable)) = scope.iter().rev().find(|&&(ref n, _)| n == name) {
+                return Ok(variable.clone())
+            }
+        }
+        Err(error(node, format!("Unknown name `{}`", name)))
+    }
+
+    /// Emits the statements computing a node, returning
+    /// C naming its value which has no side effects.
+    fn node(&mut self, node: &Node) -> Result<String, Diagnostic> {
+        let t = self.type_of(node);
+        let value = match *node.node_type() {
+            NodeType::StringLiteral(ref value)  => return Ok(string_literal(value)),
+            NodeType::CharLiteral(value)        => return Ok(format!("UINT32_C({})", value as u32)),
+            NodeType::IntLiteral(value, _)      => return Ok(int_literal(value as i64)),
+            NodeType::FloatLiteral(value)       => return Ok(float_literal(value)),
+            NodeType::BooleanLiteral(value)     => return Ok(value.to_string()),
+            NodeType::Identifier(ref name) => {
+                // Copied, since later code may assign to it
+                self.lookup(node, name)?
+            },
+            NodeType::Unary(op, ref operand) => {
+                let operand = self.node(operand)?;
+                match (op, &t) {
+                    (Op::Sub, &Type::Int) => format!("smac_neg({})", operand),
+                    (Op::Sub, _)          => format!("-{}", operand),
+                    (Op::Not, _)          => format!("!{}", operand),
+                    (Op::BitNot, _)       => format!("~{}", operand),
+                    _                     => operand,
+                }
+            },
+            NodeType::Binary(Op::And, ref left, ref right) => return self.short_circuit(node, left, right, true),
+            NodeType::Binary(Op::Or, ref left, ref right)  => return self.short_circuit(node, left, right, false),
+            NodeType::Binary(Op::Range, ..) | NodeType::Binary(Op::RangeInclusive, ..) => {
+                return Err(error(node, "Cannot compile ranges".to_owned()))
+            },
+            NodeType::Binary(op, ref left, ref right) => {
+                let operands = self.type_of(left);
+                let l = self.node(left)?;
+                let r = self.node(right)?;
+                self.binary(node, op, &operands, l, r)?
+            },
+            NodeType::Call(ref function, ref arguments) => {
+                let name = match *function.node_type() {
+                    NodeType::Identifier(ref name) if self.lookup(function, name).is_err() => name,
+                    _ => return Err(error(function, "Only named functions can be called".to_owned())),
+                };
+                let function = match self.functions.get(name) {
+                    Some(function) => function.clone(),
+                    None           => return Err(error(node, format!("Unknown name `{}`", name))),
+                };
+                // Partial application makes a closure
+                match self.types.function(name) {
+                    Some(&Type::Function(ref parameters, _)) if parameters.len() == arguments.len() => (),
+                    _ => return Err(error(node, "Cannot compile closures".to_owned())),
+                }
+                let mut values = Vec::new();
+                for argument in arguments.iter() {
+                    values.push(self.node(argument)?)
+                }
+                let position = node.span().start;
+                self.line(&format!("smac_enter({}, {});", position.line, position.col));
+                let result = self.temporary(node, &t, Some(format!("{}({})", function, values.join(", "))))?;
+                self.line("smac_leave();");
+                return Ok(result)
+            },
+            NodeType::If(ref condition, ref then, ref otherwise) => {
+                let condition = self.node(condition)?;
+                let result    = self.temporary(node, &t, None)?;
+                self.line(&format!("if ({}) {{", condition));
+                self.indent += 1;
+                let value = self.node(then)?;
+                match *otherwise {
+                    Some(_) => self.line(&format!("{} = {};", result, value)),
+                    None    => self.line(&format!("(void){};", value)),
+                }
+                self.indent -= 1;
+                self.line("} else {");
+                self.indent += 1;
+                let value = match *otherwise {
+                    Some(ref otherwise) => self.node(otherwise)?,
+                    None                => String::from("0"),
+                };
+                self.line(&format!("{} = {};", result, value));
+                self.indent -= 1;
+                self.line("}");
+                return Ok(result)
+            },
+            NodeType::Let(ref name, _, ref value) => {
+                let value = match *value {
+                    Some(ref value) => value,
+                    None            => return Err(error(node, "Cannot compile bindings without a value".to_owned())),
+                };
+                let t        = self.type_of(value);
+                let value    = self.node(value)?;
+                let variable = self.variable(name);
+                self.line(&format!("{} {} = {};", c_type(node, &t)?, variable, value));
+                self.scopes.last_mut().unwrap().push((name.clone(), variable));
+                return Ok(String::from("0"))
+            },
+            NodeType::Function(..) => {
+                return Err(error(node, "Functions can only be defined at the top level".to_owned()))
+            },
+            NodeType::Assign(ref target, ref value) => {
+                let value    = self.node(value)?;
+                let variable = match *target.node_type() {
+                    NodeType::Identifier(ref name) => self.lookup(target, name)?,
+                    _ => return Err(error(target, "Invalid assignment target".to_owned())),
+                };
+                self.line(&format!("{} = {};", variable, value));
+                return Ok(String::from("0"))
+            },
+            NodeType::CompoundAssign(op, ref target, ref value) => {
+                let operands = self.type_of(value);
+                let value    = self.node(value)?;
+                let variable = match *target.node_type() {
+                    NodeType::Identifier(ref name) => self.lookup(target, name)?,
+                    _ => return Err(error(target, "Invalid assignment target".to_owned())),
+                };
+                let result = self.binary(node, op, &operands, variable.clone(), value)?;
+                self.line(&format!("{} = {};", variable, result));
+                return Ok(String::from("0"))
+            },
+            NodeType::Block(ref nodes) => {
+                let result = self.temporary(node, &t, None)?;
+                self.line("{");
+                self.indent += 1;
+                self.scopes.push(Vec::new());
+                let mut value = String::from("0");
+                for node in nodes.iter() {
+                    value = self.node(node)?
+                }
+                self.line(&format!("{} = {};", result, value));
+                self.scopes.pop();
+                self.indent -= 1;
+                self.line("}");
+                return Ok(result)
+            },
+            NodeType::Field(..) => {
+                return Err(error(node, "Cannot compile field access".to_owned()))
+            },
+            NodeType::List(..) | NodeType::Index(..) => {
+                return Err(error(node, "Cannot compile lists".to_owned()))
+            },
+            NodeType::Record(..) | NodeType::With(..) => {
+                return Err(error(node, "Cannot compile records".to_owned()))
+            },
+            NodeType::Enum(..) | NodeType::Match(..) => {
+                return Err(error(node, "Cannot compile enums".to_owned()))
+            },
+            NodeType::Tuple(..) => {
+                return Err(error(node, "Cannot compile tuples".to_owned()))
+            },
+            NodeType::While(..) | NodeType::For(..) | NodeType::Break(_) | NodeType::Continue => {
+                return Err(error(node, "Cannot compile loops".to_owned()))
+            },
+            NodeType::Lambda(..) => {
+                return Err(error(node, "Cannot compile closures".to_owned()))
+            },
+            NodeType::Error => {
+                return Err(error(node, "Cannot compile code with syntax errors".to_owned()))
+            },
+        };
+        self.temporary(node, &t, Some(value))
+    }
+
+    /// `&&` when `and` is true, otherwise `||`.
+    fn short_circuit(&mut self, node: &Node, left: &Node, right: &Node, and: bool) -> Result<String, Diagnostic> {
+        let left   = self.node(left)?;
+        let result = self.temporary(node, &Type::Bool, Some(left))?;
+        self.line(&format!("if ({}{}) {{", if and { "" } else { "!" }, result));
+        self.indent += 1;
+        let right = self.node(right)?;
+        self.line(&format!("{} = {};", result, right));
+        self.indent -= 1;
+        self.line("}");
+        Ok(result)
+    }
+
+    /// C for a binary operator on operands of type `t`.
+    fn binary(&self, node: &Node, op: Op, t: &Type, l: String, r: String) -> Result<String, Diagnostic> {
+        let position = node.span().start;
+        let helper = match (op, t) {
+            (Op::Add, &Type::Int)    => "smac_add",
+            (Op::Sub, &Type::Int)    => "smac_sub",
+            (Op::Mul, &Type::Int)    => "smac_mul",
+            (Op::Div, &Type::Int) | (Op::Mod, &Type::Int) => {
+                let helper = if op == Op::Div { "smac_div" } else { "smac_rem" };
+                return Ok(format!("{}({}, {}, {}, {})", helper, l, r, position.line, position.col))
+            },
+            (Op::Shl, _)             => "smac_shl",
+            (Op::Shr, _)             => "smac_shr",
+            (Op::Mod, &Type::Float)  => "fmod",
+            (Op::Add, &Type::String) => "smac_concat",
+            (Op::Eq, &Type::String)  => "smac_equal",
+            (Op::Ne, &Type::String)  => return Ok(format!("!smac_equal({}, {})", l, r)),
+            (_, &Type::String)       => {
+                return Ok(format!("smac_compare({}, {}) {} 0", l, r, c_operator(node, op)?))
+            },
+            _ => return Ok(format!("{} {} {}", l, c_operator(node, op)?, r)),
+        };
+        Ok(format!("{}({}, {})", helper, l, r))
+    }
+}
+
+/// The C operator matching `op` wherever C defines it.
+fn c_operator(node: &Node, op: Op) -> Result<&'static str, Diagnostic> {
+    match op {
+        Op::Add    => Ok("+"),
+        Op::Sub    => Ok("-"),
+        Op::Mul    => Ok("*"),
+        Op::Div    => Ok("/"),
+        Op::BitAnd => Ok("&"),
+        Op::BitOr  => Ok("|"),
+        Op::BitXor => Ok("^"),
+        Op::Eq     => Ok("=="),
+        Op::Ne     => Ok("!="),
+        Op::Lt     => Ok("<"),
+        Op::Le     => Ok("<="),
+        Op::Gt     => Ok(">"),
+        Op::Ge     => Ok(">="),
+        _          => Err(error(node, format!("Invalid operator `{}`", op.as_str()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{emit_c, mangle, string_literal};
+    use document::Document;
+    use typeck::check;
+
+    fn emit(source: &str) -> Result<String, String> {
+        let document = Document::new(source);
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let types = check(&ast).unwrap();
+        emit_c(&ast, &types).map_err(|d| format!("{} {}", d.position(), d.message()))
+    }
+
+    #[test]
+    fn emit_functions_and_main() {
+        let c = emit("let even? n = n % 2 == 0\nlet x = 1 << 3\neven? x").unwrap();
+
+        assert!(c.contains("#include \"smac.h\"\n"));
+        assert!(c.contains("\nbool smaragdine_even_p(int64_t v_n_1);\n"));
+        assert!(c.contains("smac_rem(t2, INT64_C(2), 1, 14)"));
+        assert!(c.contains("smac_shl(INT64_C(1), INT64_C(3))"));
+        assert!(c.contains("    smac_enter(3, 0);\n"));
+        assert!(c.contains("smac_print_bool("));
+        assert!(c.contains("#ifndef SMAC_NO_MAIN\nint main(void) {\n"));
+    }
+
+    #[test]
+    fn escape_names_and_strings() {
+        assert_eq!(mangle("swap!"), "swap_b");
+        assert_eq!(string_literal("a\"?\\\n\u{e9}"), "smac_str(\"a\\042\\077\\134\\012\\303\\251\", 7)");
+        assert_eq!(emit("let a? x = x\nlet a_p x = x"),
+                   Err("[ln 2, col 0] `a?` and `a_p` have the same name in C".to_owned()));
+    }
+}
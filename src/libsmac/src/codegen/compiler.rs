@@ -0,0 +1,981 @@
+use codegen::bytecode::{Opcode, Function, Program};
+use diagnostic::Diagnostic;
+use lexer::token::{Op, TokenPosition};
+use parser::node::{self, Node, NodeType, Pattern, Visitor};
+use value::Value;
+
+use std::collections::HashMap;
+
+/// Lowers nodes to a program whose entry point
+/// evaluates them in order, returning the last value.
+///
+/// Top-level functions follow the entry point,
+/// in the order they are defined. Records are built
+/// from a template constant with each field unit, and
+/// variants likewise from one with each element of their
+/// payload unit. A `match` tests its arms in order.
+///
+/// Lambdas follow the top-level functions, taking the cells
+/// of the variables they capture before their arguments.
+/// Any variable a lambda captures lives in a cell, so that
+/// assignments to it are seen through every closure.
+/// A function given all its arguments is called directly,
+/// and one given fewer, or used as a value, is a closure.
+pub fn compile(nodes: &[Node]) -> Result<Program, Diagnostic> {
+    let mut program    = Program::new();
+    let mut signatures = HashMap::new();
+    let mut records    = HashMap::new();
+    let mut variants   = HashMap::new();
+    for node in nodes.iter() {
+        match *node.node_type() {
+            NodeType::Function(ref name, ref parameters, _) => {
+                if signatures.contains_key(name) || records.contains_key(name) || variants.contains_key(name) {
+                    return Err(error(node, format!("`{}` is already defined", name)))
+                }
+                let index = signatures.len() + 1;
+                if index > u16::max_value() as usize || parameters.len() > u16::max_value() as usize {
+                    return Err(error(node, "Too many functions".to_owned()))
+                }
+                signatures.insert(name.clone(), (index as u16, parameters.len()));
+            },
+            NodeType::Record(ref name, ref fields) => {
+                if signatures.contains_key(name) || records.contains_key(name) || variants.contains_key(name) {
+                    return Err(error(node, format!("`{}` is already defined", name)))
+                }
+                let fields = fields.iter().map(|&(ref f, _)| (f.clone(), Value::Unit)).collect::<Vec<_>>();
+                let arity  = fields.len();
+                match program.add_constant(Value::Record(name.clone(), fields)) {
+                    Some(index) => records.insert(name.clone(), (index, arity)),
+                    None        => return Err(error(node, "Too many constants".to_owned())),
+                };
+            },
+            NodeType::Enum(ref name, ref declared) => {
+                for &(ref variant, ref payload) in declared.iter() {
+                    if signatures.contains_key(variant) || records.contains_key(variant) || variants.contains_key(variant) {
+                        return Err(error(node, format!("`{}` is already defined", variant)))
+                    }
+                    let template = Value::Variant(name.clone(), variant.clone(), vec![Value::Unit; payload.len()]);
+                    match program.add_constant(template) {
+                        Some(index) => variants.insert(variant.clone(), (index, payload.len())),
+                        None        => return Err(error(node, "Too many constants".to_owned())),
+                    };
+                }
+            },
+            _ => (),
+        }
+    }
+
+    let mut functions = Vec::new();
+    let mut lambdas   = Vec::new();
+
+    let main = {
+        let mut compiler = Compiler::new(&mut program, &signatures, &records, &variants, &mut lambdas,
+                                         Function::new("main", 0), Vec::new());
+        compiler.captured = captured(nodes);
+        for (i, node) in nodes.iter().enumerate() {
+            if i > 0 {
+                compiler.function.emit(Opcode::Pop, &[], node.span().start);
+            }
+            match *node.node_type() {
+                NodeType::Function(..) | NodeType::Record(..) | NodeType::Enum(..) => {
+                    compiler.function.emit(Opcode::Unit, &[], node.span().start);
+                },
+                _ => compiler.compile_node(node)?,
+            }
+        }
+        if nodes.is_empty() {
+            compiler.function.emit(Opcode::Unit, &[], TokenPosition::default());
+        }
+        let end = nodes.last().map_or(TokenPosition::default(), |n| n.span().end);
+        compiler.function.emit(Opcode::Return, &[], end);
+        compiler.function
+    };
+    functions.push(main);
+
+    for node in nodes.iter() {
+        if let NodeType::Function(ref name, ref parameters, ref body) = *node.node_type() {
+            let function = Function::new(name, parameters.len() as u16);
+            let mut compiler = Compiler::new(&mut program, &signatures, &records, &variants, &mut lambdas,
+                                             function, parameters.clone());
+            compiler.compile_body(body)?;
+            functions.push(compiler.function)
+        }
+    }
+
+    functions.extend(lambdas);
+    program.functions = functions;
+    Ok(program)
+}
+
+fn error(node: &Node, message: String) -> Diagnostic {
+    Diagnostic::new(node.span().start, message)
+}
+
+/// The names the lambdas in some nodes capture.
+fn captured(nodes: &[Node]) -> Vec<String> {
+    let mut lambdas = Lambdas { names: Vec::new() };
+    for node in nodes.iter() {
+        lambdas.visit_node(node)
+    }
+    lambdas.names
+}
+
+struct Lambdas {
+    names: Vec<String>,
+}
+
+impl Visitor for Lambdas {
+    fn visit_lambda(&mut self, parameters: &Vec<String>, body: &Node) {
+        for name in node::captures(parameters, body).into_iter() {
+            if !self.names.contains(&name) {
+                self.names.push(name)
+            }
+        }
+        self.visit_node(body)
+    }
+}
+
+struct Compiler<'a> {
+    program:    &'a mut Program,
+    /// The index and arity of each top-level function.
+    signatures: &'a HashMap<String, (u16, usize)>,
+    /// The template constant and field count of each record.
+    records:    &'a HashMap<String, (u16, usize)>,
+    /// The template constant and payload length of each variant.
+    variants:   &'a HashMap<String, (u16, usize)>,
+    /// The lambdas compiled so far, which follow the
+    /// top-level functions.
+    lambdas:    &'a mut Vec<Function>,
+    function:   Function,
+    /// The slots of the locals in scope, innermost last.
+    scopes:     Vec<Vec<(String, u16)>>,
+    /// The loops around the node being compiled, innermost last.
+    loops:      Vec<Loop>,
+    /// The names lambdas in the function capture, whose
+    /// variables are put in cells.
+    captured:   Vec<String>,
+    /// The slots holding cells.
+    cells:      Vec<u16>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(program: &'a mut Program, signatures: &'a HashMap<String, (u16, usize)>,
+           records: &'a HashMap<String, (u16, usize)>, variants: &'a HashMap<String, (u16, usize)>,
+           lambdas: &'a mut Vec<Function>, function: Function, parameters: Vec<String>) -> Compiler<'a> {
+        let parameters = parameters.into_iter().enumerate().map(|(i, p)| (p, i as u16)).collect();
+        Compiler {
+            program:    program,
+            signatures: signatures,
+            records:    records,
+            variants:   variants,
+            lambdas:    lambdas,
+            function:   function,
+            scopes:     vec![parameters],
+            loops:      Vec::new(),
+            captured:   Vec::new(),
+            cells:      Vec::new(),
+        }
+    }
+
+    /// Compiles a function's body and its return, first
+    /// putting the parameters lambdas capture in cells.
+    fn compile_body(&mut self, body: &Node) -> Result<(), Diagnostic> {
+        self.captured = captured(::std::slice::from_ref(body));
+        let position   = body.span().start;
+        let parameters = self.scopes[0].clone();
+        for (name, slot) in parameters.into_iter() {
+            if self.captured.contains(&name) && !self.cells.contains(&slot) {
+                self.function.emit(Opcode::Load, &[slot], position);
+                self.bind(&name, slot, position);
+            }
+        }
+        self.compile_node(body)?;
+        self.function.emit(Opcode::Return, &[], body.span().end);
+        Ok(())
+    }
+
+    /// Leaves the value of the last node on the stack.
+    fn compile_sequence(&mut self, nodes: &[Node], position: TokenPosition) -> Result<(), Diagnostic> {
+        if nodes.is_empty() {
+            self.function.emit(Opcode::Unit, &[], position);
+            return Ok(())
+        }
+
+        for (i, node) in nodes.iter().enumerate() {
+            if i > 0 {
+                self.function.emit(Opcode::Pop, &[], node.span().start);
+            }
+            self.compile_node(node)?
+        }
+        Ok(())
+    }
+
+    /// Leaves the value of a node on the stack.
+    fn compile_node(&mut self, node: &Node) -> Result<(), Diagnostic> {
+        let position = node.span().start;
+        let value = match *node.node_type() {
+            NodeType::StringLiteral(ref value)  => Value::String(value.clone()),
+            NodeType::CharLiteral(value)        => Value::Char(value),
+            NodeType::IntLiteral(value, _)      => Value::Int(value as i64),
+            NodeType::FloatLiteral(value)       => Value::Float(value),
+            NodeType::BooleanLiteral(value)     => Value::Boolean(value),
+            NodeType::Identifier(ref name) => {
+                let slot = match (self.local(node, name), self.variants.get(name), self.signatures.get(name)) {
+                    // A variant without a payload is its own template
+                    (Err(_), Some(&(template, 0)), _) => {
+                        self.function.emit(Opcode::Const, &[template], position);
+                        return Ok(())
+                    },
+                    // A function as a value is a closure capturing nothing
+                    (Err(_), _, Some(&(index, _))) => {
+                        self.function.emit(Opcode::Closure, &[index], position);
+                        return Ok(())
+                    },
+                    (slot, _, _) => slot?,
+                };
+                self.load(slot, position);
+                return Ok(())
+            },
+            NodeType::Unary(op, ref operand) => {
+                self.compile_node(operand)?;
+                if let Some(opcode) = Opcode::unary(op) {
+                    self.function.emit(opcode, &[], position);
+                }
+                return Ok(())
+            },
+            NodeType::Binary(Op::And, ref left, ref right) => {
+                self.compile_node(left)?;
+                let otherwise = self.function.emit(Opcode::JumpIfFalse, &[0], position);
+                self.compile_node(right)?;
+                let end = self.function.emit(Opcode::Jump, &[0], position);
+                self.patch(otherwise, node)?;
+                self.constant(Value::Boolean(false), position)?;
+                return self.patch(end, node)
+            },
+            NodeType::Binary(Op::Or, ref left, ref right) => {
+                self.compile_node(left)?;
+                let otherwise = self.function.emit(Opcode::JumpIfFalse, &[0], position);
+                self.constant(Value::Boolean(true), position)?;
+                let end = self.function.emit(Opcode::Jump, &[0], position);
+                self.patch(otherwise, node)?;
+                self.compile_node(right)?;
+                return self.patch(end, node)
+            },
+            NodeType::Binary(op, ref left, ref right) => {
+                self.compile_node(left)?;
+                self.compile_node(right)?;
+                match Opcode::binary(op) {
+                    Some(opcode) => self.function.emit(opcode, &[], position),
+                    None => return Err(error(node, format!("Invalid operator `{}`", op.as_str()))),
+                };
+                return Ok(())
+            },
+            NodeType::Call(ref function, ref arguments) => {
+                let global = match *function.node_type() {
+                    NodeType::Identifier(ref name) if self.local(function, name).is_err() => Some(name),
+                    _ => None,
+                };
+                let applied = match (global, global.and_then(|name| self.signatures.get(name).cloned())) {
+                    // A function given exactly all it takes is called without a
+                    // closure. Given more, every argument is evaluated before
+                    // the call, so it is applied as a closure instead.
+                    (_, Some((index, arity))) if arguments.len() == arity => {
+                        for argument in arguments.iter() {
+                            self.compile_node(argument)?
+                        }
+                        self.function.emit(Opcode::Call, &[index], position);
+                        arity
+                    },
+                    (Some(name), None) => {
+                        let (opcode, index) = self.constructor(function, name, arguments.len())?;
+                        for argument in arguments.iter() {
+                            self.compile_node(argument)?
+                        }
+                        self.function.emit(opcode, &[index], position);
+                        return Ok(())
+                    },
+                    _ => {
+                        self.compile_node(function)?;
+                        0
+                    },
+                };
+
+                // Any arguments left are applied to what the call returned
+                let rest = &arguments[applied..];
+                if rest.is_empty() {
+                    return Ok(())
+                }
+                if rest.len() > u16::max_value() as usize {
+                    return Err(error(node, "Too many arguments".to_owned()))
+                }
+                for argument in rest.iter() {
+                    self.compile_node(argument)?
+                }
+                self.function.emit(Opcode::Apply, &[rest.len() as u16], position);
+                return Ok(())
+            },
+            NodeType::If(ref condition, ref then, ref otherwise) => {
+                self.compile_node(condition)?;
+                let jump = self.function.emit(Opcode::JumpIfFalse, &[0], position);
+                self.compile_node(then)?;
+                if otherwise.is_none() {
+                    self.function.emit(Opcode::Pop, &[], position);
+                    self.function.emit(Opcode::Unit, &[], position);
+                }
+                let end = self.function.emit(Opcode::Jump, &[0], position);
+                self.patch(jump, node)?;
+                match *otherwise {
+                    Some(ref otherwise) => self.compile_node(otherwise)?,
+                    None                => {
+                        self.function.emit(Opcode::Unit, &[], position);
+                    },
+                }
+                return self.patch(end, node)
+            },
+            NodeType::Let(ref name, _, ref value) => {
+                // A binding without a value is assigned before it is read
+                match *value {
+                    Some(ref value) => self.compile_node(value)?,
+                    None            => {
+                        self.function.emit(Opcode::Unit, &[], position);
+                    },
+                }
+                let slot = self.allocate(node)?;
+                self.scopes.last_mut().unwrap().push((name.clone(), slot));
+                self.bind(name, slot, position);
+                self.function.emit(Opcode::Unit, &[], position);
+                return Ok(())
+            },
+            NodeType::Function(..) => {
+                return Err(error(node, "Functions can only be defined at the top level".to_owned()))
+            },
+            NodeType::Lambda(ref parameters, ref body) => {
+                return self.compile_lambda(node, parameters, body)
+            },
+            NodeType::Record(..) | NodeType::Enum(..) => {
+                return Err(error(node, "Types can only be declared at the top level".to_owned()))
+            },
+            NodeType::Match(ref value, ref arms) => {
+                self.compile_node(value)?;
+                let subject = self.allocate(node)?;
+                self.function.emit(Opcode::Store, &[subject], position);
+
+                let mut ends = Vec::new();
+                for arm in arms.iter() {
+                    let position  = arm.span.start;
+                    let mut fails = Vec::new();
+                    self.compile_test(&arm.pattern, subject, &mut Vec::new(), &mut fails, position)?;
+                    self.scopes.push(Vec::new());
+                    self.compile_bindings(node, &arm.pattern, subject, &mut Vec::new(), position)?;
+                    self.compile_node(&arm.body)?;
+                    self.scopes.pop();
+                    ends.push(self.function.emit(Opcode::Jump, &[0], position));
+                    for fail in fails.into_iter() {
+                        self.patch(fail, node)?
+                    }
+                }
+                self.function.emit(Opcode::Load, &[subject], position);
+                self.function.emit(Opcode::Unmatched, &[], position);
+                for end in ends.into_iter() {
+                    self.patch(end, node)?
+                }
+                return Ok(())
+            },
+            NodeType::While(ref condition, ref body) => {
+                let mark = self.allocate(node)?;
+                self.function.emit(Opcode::Mark, &[mark], position);
+                let start = self.label(node)?;
+                self.compile_node(condition)?;
+                let done = self.function.emit(Opcode::JumpIfFalse, &[0], position);
+                self.compile_loop(node, mark, body, &[])?;
+                self.function.emit(Opcode::Jump, &[start], position);
+                return self.finish_loop(node, &[done])
+            },
+            NodeType::For(ref name, ref values, ref body) => {
+                return self.compile_for(node, name, values, body)
+            },
+            NodeType::Break(ref value) => {
+                match *value {
+                    Some(ref value) => self.compile_node(value)?,
+                    None            => {
+                        self.function.emit(Opcode::Unit, &[], position);
+                    },
+                }
+                let mark = match self.loops.last() {
+                    Some(innermost) => innermost.mark,
+                    None            => return Err(error(node, "`break` outside of a loop".to_owned())),
+                };
+                self.function.emit(Opcode::Unwind, &[mark], position);
+                let jump = self.function.emit(Opcode::Jump, &[0], position);
+                self.loops.last_mut().unwrap().breaks.push(jump);
+                return Ok(())
+            },
+            NodeType::Continue => {
+                let mark = match self.loops.last() {
+                    Some(innermost) => innermost.mark,
+                    None            => return Err(error(node, "`continue` outside of a loop".to_owned())),
+                };
+                // Leaves a value for the jump to skip over like any node
+                self.function.emit(Opcode::Unit, &[], position);
+                self.function.emit(Opcode::Unwind, &[mark], position);
+                self.function.emit(Opcode::Pop, &[], position);
+                let jump = self.function.emit(Opcode::Jump, &[0], position);
+                self.loops.last_mut().unwrap().continues.push(jump);
+                return Ok(())
+            },
+            NodeType::Assign(ref target, ref value) => {
+                self.compile_node(value)?;
+                let slot = match *target.node_type() {
+                    NodeType::Identifier(ref name) => self.local(target, name)?,
+                    NodeType::Index(..) | NodeType::Field(..) => self.compile_set_place(target, None)?,
+                    _ => return Err(error(target, "Invalid assignment target".to_owned())),
+                };
+                self.store(slot, position);
+                self.function.emit(Opcode::Unit, &[], position);
+                return Ok(())
+            },
+            NodeType::CompoundAssign(op, ref target, ref value) => {
+                self.compile_node(value)?;
+                let slot = self.compile_set_place(target, Some((op, node)))?;
+                self.store(slot, position);
+                self.function.emit(Opcode::Unit, &[], position);
+                return Ok(())
+            },
+            NodeType::Block(ref nodes) => {
+                self.scopes.push(Vec::new());
+                self.compile_sequence(nodes, position)?;
+                self.scopes.pop();
+                return Ok(())
+            },
+            NodeType::Field(ref record, ref name) => {
+                self.compile_node(record)?;
+                // Without such a field, `x.f` is `f x`, which is only
+                // known once the record is
+                if let Some(&(index, _)) = self.signatures.get(name) {
+                    self.function.emit(Opcode::Method, &[index], position);
+                    return Ok(())
+                }
+                let name = self.name(name, position)?;
+                self.function.emit(Opcode::Field, &[name], position);
+                return Ok(())
+            },
+            NodeType::With(ref record, ref fields) => {
+                self.compile_node(record)?;
+                for &(ref name, ref value) in fields.iter() {
+                    self.compile_node(value)?;
+                    let name = self.name(name, value.span().start)?;
+                    self.function.emit(Opcode::SetField, &[name], value.span().start);
+                }
+                return Ok(())
+            },
+            NodeType::List(ref elements) => {
+                if elements.len() > u16::max_value() as usize {
+                    return Err(error(node, "Too many elements".to_owned()))
+                }
+                for element in elements.iter() {
+                    self.compile_node(element)?
+                }
+                self.function.emit(Opcode::List, &[elements.len() as u16], position);
+                return Ok(())
+            },
+            NodeType::Index(ref list, ref index) => {
+                self.compile_node(list)?;
+                self.compile_node(index)?;
+                self.function.emit(Opcode::Index, &[], position);
+                return Ok(())
+            },
+            NodeType::Tuple(ref elements) => {
+                if elements.len() > u16::max_value() as usize {
+                    return Err(error(node, "Too many elements".to_owned()))
+                }
+                for element in elements.iter() {
+                    self.compile_node(element)?
+                }
+                self.function.emit(Opcode::Tuple, &[elements.len() as u16], position);
+                return Ok(())
+            },
+            NodeType::Error => {
+                return Err(error(node, "Cannot compile code with syntax errors".to_owned()))
+            },
+        };
+        self.constant(value, position)
+    }
+
+    /// Compiles a `for` loop, counting through a range
+    /// without building it.
+    fn compile_for(&mut self, node: &Node, name: &str, values: &Node, body: &Node) -> Result<(), Diagnostic> {
+        let position = node.span().start;
+        let index    = self.allocate(node)?;
+        let end      = self.allocate(node)?;
+        let range    = match *values.node_type() {
+            NodeType::Binary(op, ref start, ref last) if op == Op::Range || op == Op::RangeInclusive => {
+                self.compile_node(start)?;
+                self.function.emit(Opcode::Store, &[index], position);
+                self.compile_node(last)?;
+                self.function.emit(Opcode::Store, &[end], position);
+                Some(op)
+            },
+            _ => {
+                self.compile_node(values)?;
+                self.function.emit(Opcode::Store, &[end], position);
+                self.constant(Value::Int(0), position)?;
+                self.function.emit(Opcode::Store, &[index], position);
+                None
+            },
+        };
+
+        let mark = self.allocate(node)?;
+        self.function.emit(Opcode::Mark, &[mark], position);
+        let start = self.label(node)?;
+        self.function.emit(Opcode::Load, &[index], position);
+        self.function.emit(Opcode::Load, &[end], position);
+        match range {
+            Some(Op::RangeInclusive) => {
+                self.function.emit(Opcode::Le, &[], position);
+            },
+            Some(_) => {
+                self.function.emit(Opcode::Lt, &[], position);
+            },
+            None => {
+                self.function.emit(Opcode::Length, &[], position);
+                self.function.emit(Opcode::Lt, &[], position);
+            },
+        }
+        let mut done = vec![self.function.emit(Opcode::JumpIfFalse, &[0], position)];
+
+        let element = self.allocate(node)?;
+        self.function.emit(Opcode::Load, &[if range.is_some() { index } else { end }], position);
+        if range.is_none() {
+            self.function.emit(Opcode::Load, &[index], position);
+            self.function.emit(Opcode::Index, &[], position);
+        }
+        self.bind(name, element, position);
+        self.compile_loop(node, mark, body, &[(name.to_owned(), element)])?;
+
+        // The last value of an inclusive range may have no successor
+        if range == Some(Op::RangeInclusive) {
+            self.function.emit(Opcode::Load, &[index], position);
+            self.function.emit(Opcode::Load, &[end], position);
+            self.function.emit(Opcode::Ne, &[], position);
+            done.push(self.function.emit(Opcode::JumpIfFalse, &[0], position));
+        }
+        self.function.emit(Opcode::Load, &[index], position);
+        self.constant(Value::Int(1), position)?;
+        self.function.emit(Opcode::Add, &[], position);
+        self.function.emit(Opcode::Store, &[index], position);
+        self.function.emit(Opcode::Jump, &[start], position);
+        self.finish_loop(node, &done)
+    }
+
+    /// Compiles the body of a loop with `locals` in scope,
+    /// dropping its value, and points its `continue` jumps
+    /// past it.
+    fn compile_loop(&mut self, node: &Node, mark: u16, body: &Node, locals: &[(String, u16)]) -> Result<(), Diagnostic> {
+        self.loops.push(Loop {
+            mark:      mark,
+            breaks:    Vec::new(),
+            continues: Vec::new(),
+        });
+        self.scopes.push(locals.to_vec());
+        let result = self.compile_node(body);
+        self.scopes.pop();
+        result?;
+        self.function.emit(Opcode::Pop, &[], body.span().start);
+        let continues = ::std::mem::replace(&mut self.loops.last_mut().unwrap().continues, Vec::new());
+        for jump in continues.into_iter() {
+            self.patch(jump, node)?
+        }
+        Ok(())
+    }
+
+    /// Ends the innermost loop, which the jumps in `done` leave
+    /// with unit and its `break` jumps with their value.
+    fn finish_loop(&mut self, node: &Node, done: &[usize]) -> Result<(), Diagnostic> {
+        let position = node.span().start;
+        for &jump in done.iter() {
+            self.patch(jump, node)?
+        }
+        self.function.emit(Opcode::Unit, &[], position);
+        let innermost = self.loops.pop().unwrap();
+        for jump in innermost.breaks.into_iter() {
+            self.patch(jump, node)?
+        }
+        Ok(())
+    }
+
+    /// Compiles the checks that the part of the value in `subject`
+    /// at `path` matches a pattern, adding the offset of the jump
+    /// taken when one fails to `fails`.
+    fn compile_test(&mut self, pattern: &Pattern, subject: u16, path: &mut Vec<u16>,
+                    fails: &mut Vec<usize>, position: TokenPosition) -> Result<(), Diagnostic> {
+        let parts = match *pattern {
+            Pattern::Wildcard | Pattern::Binding(_) => return Ok(()),
+            Pattern::Literal(ref literal) => {
+                self.load_part(subject, path, position);
+                self.compile_node(literal)?;
+                self.function.emit(Opcode::Eq, &[], position);
+                fails.push(self.function.emit(Opcode::JumpIfFalse, &[0], position));
+                return Ok(())
+            },
+            Pattern::Tuple(ref elements) => elements,
+            Pattern::Variant(ref name, ref payload) => {
+                self.load_part(subject, path, position);
+                let name = self.name(name, position)?;
+                self.function.emit(Opcode::IsVariant, &[name], position);
+                fails.push(self.function.emit(Opcode::JumpIfFalse, &[0], position));
+                payload
+            },
+        };
+        for (i, part) in parts.iter().enumerate() {
+            path.push(i as u16);
+            self.compile_test(part, subject, path, fails, position)?;
+            path.pop();
+        }
+        Ok(())
+    }
+
+    /// Stores the parts of the value in `subject` at `path`
+    /// that a pattern names in new locals in the innermost scope.
+    fn compile_bindings(&mut self, node: &Node, pattern: &Pattern, subject: u16, path: &mut Vec<u16>,
+                        position: TokenPosition) -> Result<(), Diagnostic> {
+        let parts = match *pattern {
+            Pattern::Wildcard | Pattern::Literal(_) => return Ok(()),
+            Pattern::Binding(ref name) => {
+                self.load_part(subject, path, position);
+                let slot = self.allocate(node)?;
+                self.scopes.last_mut().unwrap().push((name.clone(), slot));
+                self.bind(name, slot, position);
+                return Ok(())
+            },
+            Pattern::Tuple(ref parts) | Pattern::Variant(_, ref parts) => parts,
+        };
+        for (i, part) in parts.iter().enumerate() {
+            path.push(i as u16);
+            self.compile_bindings(node, part, subject, path, position)?;
+            path.pop();
+        }
+        Ok(())
+    }
+
+    /// Pushes the part of the value in `subject` at `path`.
+    fn load_part(&mut self, subject: u16, path: &[u16], position: TokenPosition) {
+        self.function.emit(Opcode::Load, &[subject], position);
+        for &index in path.iter() {
+            self.function.emit(Opcode::Element, &[index], position);
+        }
+    }
+
+    /// Compiles an assignment to an element or field, with the
+    /// value on the stack, leaving the updated value to store in
+    /// the slot returned. Lists and records are values, so every
+    /// one on the way from the variable to the place is rebuilt.
+    /// Given the operator of a compound assignment, the place
+    /// is set to it applied to the place and the value, with
+    /// the indices on the way evaluated once.
+    fn compile_set_place(&mut self, target: &Node, op: Option<(Op, &Node)>) -> Result<u16, Diagnostic> {
+        let position = target.span().start;
+        let value = self.allocate(target)?;
+        self.function.emit(Opcode::Store, &[value], position);
+
+        let mut path = Vec::new();
+        let mut root = target;
+        loop {
+            match *root.node_type() {
+                NodeType::Index(ref list, ref index) => {
+                    path.push(Ok(&**index));
+                    root = list
+                },
+                NodeType::Field(ref record, ref name) => {
+                    path.push(Err(name));
+                    root = record
+                },
+                _ => break,
+            }
+        }
+        path.reverse();
+
+        let slot = match *root.node_type() {
+            NodeType::Identifier(ref name) => self.local(root, name)?,
+            _ => return Err(error(root, "Invalid assignment target".to_owned())),
+        };
+
+        let mut steps = Vec::with_capacity(path.len());
+        for step in path.into_iter() {
+            match step {
+                Ok(index) => {
+                    self.compile_node(index)?;
+                    let temporary = self.allocate(index)?;
+                    self.function.emit(Opcode::Store, &[temporary], position);
+                    steps.push(Step::Index(temporary))
+                },
+                Err(name) => steps.push(Step::Field(self.name(name, position)?)),
+            }
+        }
+
+        if let Some((op, node)) = op {
+            self.follow(slot, &steps, position);
+            self.function.emit(Opcode::Load, &[value], position);
+            match Opcode::binary(op) {
+                Some(opcode) => self.function.emit(opcode, &[], node.span().start),
+                None => return Err(error(node, format!("Invalid operator `{}`", op.as_str()))),
+            };
+            self.function.emit(Opcode::Store, &[value], position);
+        }
+
+        self.rebuild(slot, &steps, 0, value, position);
+        Ok(slot)
+    }
+
+    /// Pushes the value reached by following `steps` from `slot`.
+    fn follow(&mut self, slot: u16, steps: &[Step], position: TokenPosition) {
+        self.load(slot, position);
+        for step in steps.iter() {
+            match *step {
+                Step::Index(index) => {
+                    self.function.emit(Opcode::Load, &[index], position);
+                    self.function.emit(Opcode::Index, &[], position)
+                },
+                Step::Field(name) => self.function.emit(Opcode::Field, &[name], position),
+            };
+        }
+    }
+
+    /// Pushes the value reached by following the first `depth`
+    /// steps from `slot`, with the place the rest lead to
+    /// replaced by `value`.
+    fn rebuild(&mut self, slot: u16, steps: &[Step], depth: usize, value: u16, position: TokenPosition) {
+        if depth == steps.len() {
+            self.function.emit(Opcode::Load, &[value], position);
+            return
+        }
+        self.follow(slot, &steps[..depth], position);
+        match steps[depth] {
+            Step::Index(index) => {
+                self.function.emit(Opcode::Load, &[index], position);
+                self.rebuild(slot, steps, depth + 1, value, position);
+                self.function.emit(Opcode::SetIndex, &[], position)
+            },
+            Step::Field(name) => {
+                self.rebuild(slot, steps, depth + 1, value, position);
+                self.function.emit(Opcode::SetField, &[name], position)
+            },
+        };
+    }
+
+    /// Compiles a lambda to a function taking the cells of the
+    /// variables it captures, and pushes a closure of them.
+    fn compile_lambda(&mut self, node: &Node, parameters: &[String], body: &Node) -> Result<(), Diagnostic> {
+        let position = node.span().start;
+        let captures = node::captures(parameters, body).into_iter()
+            .filter_map(|name| self.local(node, &name).ok().map(|slot| (name, slot)))
+            .collect::<Vec<_>>();
+        let index = 1 + self.signatures.len() + self.lambdas.len();
+        if index > u16::max_value() as usize || captures.len() + parameters.len() > u16::max_value() as usize {
+            return Err(error(node, "Too many functions".to_owned()))
+        }
+
+        // Reserves the index before any lambdas in the body take one
+        let name = format!("<lambda {}:{}>", position.line, position.col);
+        self.lambdas.push(Function::new(&name, parameters.len() as u16));
+        let mut function = Function::new(&name, parameters.len() as u16);
+        function.captures = captures.len() as u16;
+        function.locals   = (captures.len() + parameters.len()) as u16;
+        let locals = captures.iter().map(|&(ref name, _)| name.clone()).chain(parameters.iter().cloned()).collect();
+        let function = {
+            let mut compiler = Compiler::new(&mut *self.program, self.signatures, self.records, self.variants,
+                                             &mut *self.lambdas, function, locals);
+            compiler.cells = (0..captures.len() as u16).collect();
+            compiler.compile_body(body)?;
+            compiler.function
+        };
+        self.lambdas[index - 1 - self.signatures.len()] = function;
+
+        // Cells are passed as they are, not their values
+        for &(_, slot) in captures.iter() {
+            self.function.emit(Opcode::Load, &[slot], position);
+        }
+        self.function.emit(Opcode::Closure, &[index as u16], position);
+        Ok(())
+    }
+
+    /// Pops the value of a new variable into its slot,
+    /// in a cell if a lambda captures it.
+    fn bind(&mut self, name: &str, slot: u16, position: TokenPosition) {
+        if self.captured.iter().any(|c| c == name) {
+            self.function.emit(Opcode::Cell, &[], position);
+            self.cells.push(slot)
+        }
+        self.function.emit(Opcode::Store, &[slot], position);
+    }
+
+    /// Pushes the value of a variable.
+    fn load(&mut self, slot: u16, position: TokenPosition) {
+        let opcode = if self.cells.contains(&slot) { Opcode::LoadCell } else { Opcode::Load };
+        self.function.emit(opcode, &[slot], position);
+    }
+
+    /// Pops a new value for a variable.
+    fn store(&mut self, slot: u16, position: TokenPosition) {
+        let opcode = if self.cells.contains(&slot) { Opcode::StoreCell } else { Opcode::Store };
+        self.function.emit(opcode, &[slot], position);
+    }
+
+    /// The constant naming a field.
+    fn name(&mut self, name: &str, position: TokenPosition) -> Result<u16, Diagnostic> {
+        self.program.add_constant(Value::String(name.to_owned()))
+            .ok_or_else(|| Diagnostic::new(position, "Too many constants".to_owned()))
+    }
+
+    /// Reserves a new local slot.
+    fn allocate(&mut self, node: &Node) -> Result<u16, Diagnostic> {
+        if self.function.locals == u16::max_value() {
+            return Err(error(node, "Too many locals".to_owned()))
+        }
+        self.function.locals += 1;
+        Ok(self.function.locals - 1)
+    }
+
+    fn constant(&mut self, value: Value, position: TokenPosition) -> Result<(), Diagnostic> {
+        match self.program.add_constant(value) {
+            Some(index) => {
+                self.function.emit(Opcode::Const, &[index], position);
+                Ok(())
+            },
+            None => Err(Diagnostic::new(position, "Too many constants".to_owned())),
+        }
+    }
+
+    /// The offset of the end of the code, for jumps back to it.
+    fn label(&self, node: &Node) -> Result<u16, Diagnostic> {
+        let target = self.function.code.len();
+        if target > u16::max_value() as usize {
+            return Err(error(node, "Function too large".to_owned()))
+        }
+        Ok(target as u16)
+    }
+
+    /// Points the jump at `offset` to the end of the code.
+    fn patch(&mut self, offset: usize, node: &Node) -> Result<(), Diagnostic> {
+        let target = self.function.code.len();
+        if target > u16::max_value() as usize {
+            return Err(error(node, "Function too large".to_owned()))
+        }
+        self.function.patch(offset, target as u16);
+        Ok(())
+    }
+
+    fn local(&self, node: &Node, name: &str) -> Result<u16, Diagnostic> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(&(_, slot)) = scope.iter().rev().find(|&&(ref n, _)| n == name) {
+                return Ok(slot)
+            }
+        }
+        Err(error(node, format!("Unknown name `{}`", name)))
+    }
+
+    /// The instruction building the record or variant
+    /// `name`, with `arguments` arguments.
+    fn constructor(&self, function: &Node, name: &str, arguments: usize) -> Result<(Opcode, u16), Diagnostic> {
+        let (opcode, &(index, arity)) = match (self.records.get(name), self.variants.get(name)) {
+            (Some(record), _)     => (Opcode::Record, record),
+            (None, Some(variant)) => (Opcode::Variant, variant),
+            (None, None)          => return Err(error(function, format!("Unknown name `{}`", name))),
+        };
+        if arity != arguments {
+            return Err(error(function, format!("`{}` takes {} arguments, found {}", name, arity, arguments)))
+        }
+        Ok((opcode, index))
+    }
+}
+
+/// A loop being compiled: the local its height on the stack is
+/// marked in, and the jumps its `break`s and `continue`s make.
+struct Loop {
+    mark:      u16,
+    breaks:    Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// A step from a variable to the place an assignment replaces:
+/// an element at the index in a local, or a named field.
+enum Step {
+    Index(u16),
+    Field(u16),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compile;
+    use codegen::bytecode::Opcode;
+    use document::Document;
+    use lexer::token::Span;
+    use parser::node::{Node, NodeType};
+    use value::Value;
+    use vm::Vm;
+
+    fn node(node_type: NodeType) -> Node {
+        Node::new(node_type, Span::default())
+    }
+
+    #[test]
+    fn compile_blocks() {
+        let nodes = vec![
+            node(NodeType::IntLiteral(1, false)),
+            node(NodeType::Block(vec![
+                node(NodeType::BooleanLiteral(true)),
+                node(NodeType::IntLiteral(1, false)),
+            ])),
+        ];
+        let program = compile(&nodes).unwrap();
+
+        assert_eq!(program.constants, vec![Value::Int(1), Value::Boolean(true)]);
+        assert_eq!(program.functions.len(), 1);
+        assert_eq!(program.functions[0].code, vec![
+            Opcode::Const.to_byte(), 0, 0,
+            Opcode::Pop.to_byte(),
+            Opcode::Const.to_byte(), 1, 0,
+            Opcode::Pop.to_byte(),
+            Opcode::Const.to_byte(), 0, 0,
+            Opcode::Return.to_byte(),
+        ]);
+    }
+
+    #[test]
+    fn compile_functions_after_main() {
+        let document = Document::new("let double x = 2 * x\nlet y = double 4\nif y > 5\n  y\nelse\n  0");
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let program = compile(&ast).unwrap();
+
+        assert_eq!(program.functions.iter().map(|f| (&f.name[..], f.arity, f.locals)).collect::<Vec<_>>(),
+                   vec![("main", 0, 1), ("double", 1, 1)]);
+        assert_eq!(Vm::new(&program).run(), Ok(Value::Int(8)));
+    }
+
+    #[test]
+    fn compile_index_assignment() {
+        let document = Document::new("let xs = [[1, 2], [3]]\nlet i = 0\nxs[i + 1][i] = xs[0][1] + 10\nxs");
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let program = compile(&ast).unwrap();
+
+        // `xs` and `i`, then the value and two indices
+        assert_eq!(program.functions[0].locals, 5);
+        assert_eq!(Vm::new(&program).run().map(|v| v.to_string()), Ok("[[1, 2], [12]]".to_owned()));
+    }
+
+    #[test]
+    fn compile_lambdas_after_functions() {
+        let document = Document::new("let id x = x\nlet n = 1\nlet add = x -> y -> x + y + n\nn = 2\n(add 3) 4");
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let program = compile(&ast).unwrap();
+
+        assert_eq!(program.functions.iter().map(|f| (&f.name[..], f.arity, f.captures)).collect::<Vec<_>>(),
+                   vec![("main", 0, 0), ("id", 1, 0), ("<lambda 3:10>", 1, 1), ("<lambda 3:15>", 1, 2)]);
+        assert_eq!(Vm::new(&program).run(), Ok(Value::Int(9)));
+    }
+
+    #[test]
+    fn refuse_errors() {
+        let nodes = vec![node(NodeType::Block(vec![node(NodeType::Error)]))];
+        assert!(compile(&nodes).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+use codegen::bytecode::{Opcode, Program};
+use serialize::{quote, float};
+use value::Value;
+
+/// Prints a program's constants and instructions,
+/// marking where each source line's code starts.
+///
+/// ```text
+/// constants:
+///   0  1
+///
+/// function main (arity 0, locals 0):
+///   ; ln 1, col 0
+///   0000  const          0  ; 1
+///   0003  return
+/// ```
+pub fn disassemble(program: &Program) -> String {
+    let mut out = String::new();
+
+    out.push_str("constants:\n");
+    for (i, constant) in program.constants.iter().enumerate() {
+        out.push_str(&format!("  {:<2} {}\n", i, repr(constant)))
+    }
+
+    for function in program.functions.iter() {
+        if function.captures > 0 {
+            out.push_str(&format!("\nfunction {} (arity {}, captures {}, locals {}):\n",
+                                  function.name, function.arity, function.captures, function.locals));
+        } else {
+            out.push_str(&format!("\nfunction {} (arity {}, locals {}):\n",
+                                  function.name, function.arity, function.locals));
+        }
+
+        let mut lines  = function.lines.iter().peekable();
+        let mut offset = 0;
+        while offset < function.code.len() {
+            while lines.peek().map_or(false, |&&(o, _)| o <= offset) {
+                let &(_, position) = lines.next().unwrap();
+                out.push_str(&format!("  ; ln {}, col {}\n", position.line, position.col))
+            }
+
+            let opcode = match Opcode::from_byte(function.code[offset]) {
+                Some(opcode) => opcode,
+                None         => {
+                    out.push_str(&format!("  {:04}  <invalid {:#04x}>\n", offset, function.code[offset]));
+                    offset += 1;
+                    continue
+                },
+            };
+            let operand = function.operand(offset + 1);
+
+            let line = match (opcode, operand) {
+                _ if opcode.operands() == 0 => opcode.as_str().to_owned(),
+                (_, None)                   => opcode.as_str().to_owned(),
+                (Opcode::Const, Some(index)) | (Opcode::Record, Some(index))
+                    | (Opcode::Field, Some(index)) | (Opcode::SetField, Some(index))
+                    | (Opcode::Variant, Some(index)) | (Opcode::IsVariant, Some(index)) => {
+                    let value = program.constants.get(index as usize).map_or("?".to_owned(), repr);
+                    format!("{:<14} {}  ; {}", opcode.as_str(), index, value)
+                },
+                (Opcode::Call, Some(index)) | (Opcode::Closure, Some(index))
+                    | (Opcode::Method, Some(index)) => {
+                    let name = program.functions.get(index as usize).map_or("?", |f| &f.name);
+                    format!("{:<14} {}  ; {}", opcode.as_str(), index, name)
+                },
+                (Opcode::Jump, Some(target)) | (Opcode::JumpIfFalse, Some(target)) => {
+                    format!("{:<14} {:04}", opcode.as_str(), target)
+                },
+                (_, Some(operand)) => format!("{:<14} {}", opcode.as_str(), operand),
+            };
+            out.push_str(&format!("  {:04}  {}\n", offset, line.trim_end()));
+
+            offset += 1 + 2 * opcode.operands()
+        }
+    }
+
+    out
+}
+
+/// A constant as it would be written in source.
+fn repr(value: &Value) -> String {
+    match *value {
+        Value::String(ref value) => quote(value),
+        Value::Char(value)       => {
+            let quoted = quote(&value.to_string());
+            format!("'{}'", &quoted[1..quoted.len() - 1])
+        },
+        Value::Float(value)      => float(value),
+        ref value                => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassemble;
+    use codegen::compile;
+    use lexer::token::{Span, TokenPosition};
+    use parser::node::{Node, NodeType};
+
+    fn node(node_type: NodeType, line: usize) -> Node {
+        let position = TokenPosition { line: line, col: 0 };
+        Node::new(node_type, Span::new(position, position))
+    }
+
+    #[test]
+    fn disassemble_with_lines() {
+        let nodes = vec![
+            node(NodeType::StringLiteral("a".to_owned()), 1),
+            node(NodeType::Block(vec![
+                node(NodeType::CharLiteral('b'), 2),
+            ]), 2),
+        ];
+
+        assert_eq!(disassemble(&compile(&nodes).unwrap()), concat!(
+            "constants:\n",
+            "  0  \"a\"\n",
+            "  1  'b'\n",
+            "\n",
+            "function main (arity 0, locals 0):\n",
+            "  ; ln 1, col 0\n",
+            "  0000  const          0  ; \"a\"\n",
+            "  ; ln 2, col 0\n",
+            "  0003  pop\n",
+            "  0004  const          1  ; 'b'\n",
+            "  0007  return\n",
+        ));
+    }
+}
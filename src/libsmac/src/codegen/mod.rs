@@ -0,0 +1,13 @@
+pub mod bytecode;
+pub mod compiler;
+pub mod object;
+pub mod disasm;
+pub mod c;
+pub mod wasm;
+
+pub use self::bytecode::{Opcode, Function, Program};
+pub use self::compiler::compile;
+pub use self::object::{ObjectError, load, write};
+pub use self::disasm::disassemble;
+pub use self::c::emit_c;
+pub use self::wasm::emit_wasm;
@@ -0,0 +1,471 @@
+//! Compiled programs as `.smc` object files.
+//!
+//! All numbers are little-endian. A file is laid out as:
+//!
+//! ```text
+//! header     "SMC\0" magic, u16 version
+//! constants  u32 count, then for each a u8 tag and its payload
+//! functions  u32 count, then for each its name, u16 arity,
+//!            u16 captures, u16 locals, u32 code length and code,
+//!            and u32 line count with a u32 offset, line and
+//!            column per line
+//! ```
+//!
+//! Strings are a u32 byte length followed by UTF-8. Records,
+//! which are only constant as templates for building others,
+//! are their type name and a u32 field count, then the name
+//! and constant of each field. Variants, which are templates
+//! too unless they have no payload, are their enum type name,
+//! their own name and a u32 count, then the constant of each
+//! element of their payload.
+
+use codegen::bytecode::{Opcode, Function, Program};
+use lexer::token::TokenPosition;
+use value::Value;
+
+use std::fmt;
+use std::str;
+
+pub const MAGIC: &'static [u8; 4] = b"SMC\0";
+pub const VERSION: u16 = 8;
+
+const TAG_UNIT:    u8 = 0;
+const TAG_INT:     u8 = 1;
+const TAG_FLOAT:   u8 = 2;
+const TAG_BOOLEAN: u8 = 3;
+const TAG_CHAR:    u8 = 4;
+const TAG_STRING:  u8 = 5;
+const TAG_RECORD:  u8 = 6;
+const TAG_VARIANT: u8 = 7;
+
+/// Why an object file was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectError {
+    offset:  usize,
+    message: String,
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid object file at byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl ObjectError {
+    pub fn new(offset: usize, message: String) -> ObjectError {
+        ObjectError {
+            offset:  offset,
+            message: message,
+        }
+    }
+
+    /// The byte offset the problem was found at.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn message(&self) -> &String {
+        &self.message
+    }
+}
+
+/// Serializes a program.
+pub fn write(program: &Program) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+
+    write_u32(&mut out, program.constants.len());
+    for constant in program.constants.iter() {
+        write_constant(&mut out, constant)
+    }
+
+    write_u32(&mut out, program.functions.len());
+    for function in program.functions.iter() {
+        write_str(&mut out, &function.name);
+        out.extend_from_slice(&function.arity.to_le_bytes());
+        out.extend_from_slice(&function.captures.to_le_bytes());
+        out.extend_from_slice(&function.locals.to_le_bytes());
+        write_u32(&mut out, function.code.len());
+        out.extend_from_slice(&function.code);
+        write_u32(&mut out, function.lines.len());
+        for &(offset, position) in function.lines.iter() {
+            write_u32(&mut out, offset);
+            write_u32(&mut out, position.line);
+            write_u32(&mut out, position.col);
+        }
+    }
+
+    out
+}
+
+fn write_constant(out: &mut Vec<u8>, constant: &Value) {
+    match *constant {
+        Value::Unit => out.push(TAG_UNIT),
+        Value::Int(value) => {
+            out.push(TAG_INT);
+            out.extend_from_slice(&value.to_le_bytes())
+        },
+        Value::Float(value) => {
+            out.push(TAG_FLOAT);
+            out.extend_from_slice(&value.to_bits().to_le_bytes())
+        },
+        Value::Boolean(value) => {
+            out.push(TAG_BOOLEAN);
+            out.push(value as u8)
+        },
+        Value::Char(value) => {
+            out.push(TAG_CHAR);
+            out.extend_from_slice(&(value as u32).to_le_bytes())
+        },
+        Value::String(ref value) => {
+            out.push(TAG_STRING);
+            write_str(out, value)
+        },
+        Value::Record(ref name, ref fields) => {
+            out.push(TAG_RECORD);
+            write_str(out, name);
+            write_u32(out, fields.len());
+            for &(ref field, ref value) in fields.iter() {
+                write_str(out, field);
+                write_constant(out, value)
+            }
+        },
+        Value::Variant(ref e, ref name, ref payload) => {
+            out.push(TAG_VARIANT);
+            write_str(out, e);
+            write_str(out, name);
+            write_u32(out, payload.len());
+            for value in payload.iter() {
+                write_constant(out, value)
+            }
+        },
+        Value::List(_) | Value::Tuple(_) | Value::Closure(_) | Value::Cell(_) => {
+            unreachable!("lists, tuples and closures are never constant")
+        },
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u32).to_le_bytes())
+}
+
+fn write_str(out: &mut Vec<u8>, value: &str) {
+    write_u32(out, value.len());
+    out.extend_from_slice(value.as_bytes())
+}
+
+/// Deserializes a program, checking that it is well-formed
+/// so the virtual machine can run it: every instruction and
+/// operand must be valid, and every jump must land on one.
+pub fn load(data: &[u8]) -> Result<Program, ObjectError> {
+    let mut reader = Reader {
+        data:   data,
+        offset: 0,
+    };
+
+    if reader.bytes(MAGIC.len())? != MAGIC {
+        return Err(ObjectError::new(0, "Not a Smaragdine object file".to_owned()))
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(reader.error_at(MAGIC.len(), format!("Unsupported version {}", version)))
+    }
+
+    let mut program = Program::new();
+
+    let constants = reader.u32()?;
+    for _ in 0..constants {
+        let constant = reader.constant()?;
+        program.constants.push(constant)
+    }
+
+    let functions = reader.u32()?;
+    if functions == 0 {
+        return Err(reader.error("Program has no entry point".to_owned()))
+    }
+    let mut starts = Vec::new();
+    for _ in 0..functions {
+        let name = reader.string()?;
+        let mut function = Function::new(&name, reader.u16()?);
+        function.captures = reader.u16()?;
+        function.locals = reader.u16()?;
+        if (function.locals as usize) < function.arity as usize + function.captures as usize {
+            return Err(reader.error_at(reader.offset - 2, "Fewer locals than arguments and captures".to_owned()))
+        }
+
+        let length = reader.u32()?;
+        starts.push(reader.offset);
+        function.code = reader.bytes(length)?.to_vec();
+
+        let lines = reader.u32()?;
+        for _ in 0..lines {
+            let start  = reader.offset;
+            let offset = reader.u32()?;
+            let line   = reader.u32()?;
+            let col    = reader.u32()?;
+            let sorted = function.lines.last().map_or(true, |&(o, _)| o < offset);
+            if !sorted || offset >= function.code.len() {
+                return Err(reader.error_at(start, "Line table out of order".to_owned()))
+            }
+            function.lines.push((offset, TokenPosition { line: line, col: col }))
+        }
+
+        program.functions.push(function)
+    }
+
+    if reader.offset != data.len() {
+        return Err(reader.error("Trailing data".to_owned()))
+    }
+
+    for (function, &start) in program.functions.iter().zip(starts.iter()) {
+        verify(&program, function).map_err(|(offset, message)| {
+            ObjectError::new(start + offset, message.to_owned())
+        })?
+    }
+
+    Ok(program)
+}
+
+/// Checks every instruction of a function, returning
+/// the code offset and message of the first bad one.
+fn verify(program: &Program, function: &Function) -> Result<(), (usize, &'static str)> {
+    let mut starts = Vec::new();
+    let mut jumps  = Vec::new();
+    let mut offset = 0;
+
+    while offset < function.code.len() {
+        starts.push(offset);
+        let opcode = match Opcode::from_byte(function.code[offset]) {
+            Some(opcode) => opcode,
+            None         => return Err((offset, "Invalid instruction")),
+        };
+        let operand = if opcode.operands() > 0 {
+            match function.operand(offset + 1) {
+                Some(operand) => operand as usize,
+                None          => return Err((offset, "Truncated instruction")),
+            }
+        } else {
+            0
+        };
+
+        let valid = match opcode {
+            Opcode::Const                => operand < program.constants.len(),
+            Opcode::Record => match program.constants.get(operand) {
+                Some(&Value::Record(..)) => true,
+                _                        => false,
+            },
+            Opcode::Variant => match program.constants.get(operand) {
+                Some(&Value::Variant(..)) => true,
+                _                         => false,
+            },
+            Opcode::Field | Opcode::SetField | Opcode::IsVariant => match program.constants.get(operand) {
+                Some(&Value::String(_)) => true,
+                _                       => false,
+            },
+            Opcode::Load | Opcode::Store | Opcode::LoadCell | Opcode::StoreCell
+                | Opcode::Mark | Opcode::Unwind => operand < function.locals as usize,
+            Opcode::Call | Opcode::Closure | Opcode::Method => operand < program.functions.len(),
+            Opcode::Jump | Opcode::JumpIfFalse => {
+                jumps.push((offset, operand));
+                true
+            },
+            _                            => true,
+        };
+        if !valid {
+            return Err((offset, "Operand out of range"))
+        }
+
+        offset += 1 + 2 * opcode.operands()
+    }
+
+    for &(offset, target) in jumps.iter() {
+        if starts.binary_search(&target).is_err() {
+            return Err((offset, "Jump into the middle of an instruction"))
+        }
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    data:   &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: String) -> ObjectError {
+        ObjectError::new(self.offset, message)
+    }
+
+    fn error_at(&self, offset: usize, message: String) -> ObjectError {
+        ObjectError::new(offset, message)
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], ObjectError> {
+        if self.data.len() - self.offset < length {
+            return Err(self.error("Unexpected end of file".to_owned()))
+        }
+        let bytes = &self.data[self.offset..self.offset + length];
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ObjectError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn flag(&mut self) -> Result<bool, ObjectError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(self.error_at(self.offset - 1, "Invalid boolean".to_owned())),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, ObjectError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<usize, ObjectError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn u64(&mut self) -> Result<u64, ObjectError> {
+        let low  = self.u32()? as u64;
+        let high = self.u32()? as u64;
+        Ok(high << 32 | low)
+    }
+
+    fn constant(&mut self) -> Result<Value, ObjectError> {
+        let start = self.offset;
+        Ok(match self.u8()? {
+            TAG_UNIT    => Value::Unit,
+            TAG_INT     => Value::Int(self.u64()? as i64),
+            TAG_FLOAT   => Value::Float(f64::from_bits(self.u64()?)),
+            TAG_BOOLEAN => Value::Boolean(self.flag()?),
+            TAG_CHAR    => {
+                match ::std::char::from_u32(self.u32()? as u32) {
+                    Some(c) => Value::Char(c),
+                    None    => return Err(self.error_at(start + 1, "Invalid char".to_owned())),
+                }
+            },
+            TAG_STRING  => Value::String(self.string()?),
+            TAG_RECORD  => {
+                let name  = self.string()?;
+                let count = self.u32()?;
+                let mut fields = Vec::new();
+                for _ in 0..count {
+                    let field = self.string()?;
+                    fields.push((field, self.constant()?))
+                }
+                Value::Record(name, fields)
+            },
+            TAG_VARIANT => {
+                let e     = self.string()?;
+                let name  = self.string()?;
+                let count = self.u32()?;
+                let mut payload = Vec::new();
+                for _ in 0..count {
+                    payload.push(self.constant()?)
+                }
+                Value::Variant(e, name, payload)
+            },
+            tag         => return Err(self.error_at(start, format!("Unknown constant tag {}", tag))),
+        })
+    }
+
+    fn string(&mut self) -> Result<String, ObjectError> {
+        let length = self.u32()?;
+        let start  = self.offset;
+        match str::from_utf8(self.bytes(length)?) {
+            Ok(s)  => Ok(s.to_owned()),
+            Err(_) => Err(self.error_at(start, "Invalid UTF-8".to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write, load};
+    use codegen::bytecode::{Opcode, Function, Program};
+    use lexer::token::TokenPosition;
+    use value::Value;
+
+    fn program() -> Program {
+        let mut program = Program::new();
+        for constant in vec![
+            Value::Unit,
+            Value::Int(-5),
+            Value::Float(0.5),
+            Value::Boolean(true),
+            Value::Char('é'),
+            Value::String("text".to_owned()),
+            Value::Record("P".to_owned(), vec![("x".to_owned(), Value::Unit)]),
+            Value::Variant("Shape".to_owned(), "Rect".to_owned(), vec![Value::Unit, Value::Unit]),
+        ] {
+            program.add_constant(constant);
+        }
+
+        let mut main = Function::new("main", 0);
+        main.locals = 1;
+        main.emit(Opcode::Const, &[3], TokenPosition { line: 1, col: 0 });
+        main.emit(Opcode::JumpIfFalse, &[7], TokenPosition { line: 1, col: 0 });
+        main.emit(Opcode::Unit, &[], TokenPosition { line: 2, col: 2 });
+        main.emit(Opcode::Return, &[], TokenPosition { line: 2, col: 2 });
+        program.add_function(main);
+
+        let mut lambda = Function::new("<lambda 2:2>", 1);
+        lambda.captures = 1;
+        lambda.locals   = 2;
+        lambda.emit(Opcode::LoadCell, &[0], TokenPosition { line: 2, col: 2 });
+        lambda.emit(Opcode::Return, &[], TokenPosition { line: 2, col: 2 });
+        program.add_function(lambda);
+        program
+    }
+
+    #[test]
+    fn round_trip() {
+        let program = program();
+        assert_eq!(load(&write(&program)), Ok(program));
+    }
+
+    #[test]
+    fn reject_malformed_files() {
+        let data = write(&program());
+
+        let mut magic = data.clone();
+        magic[0] = b'X';
+        assert_eq!(load(&magic).unwrap_err().message(), "Not a Smaragdine object file");
+
+        let mut version = data.clone();
+        version[4] = 9;
+        assert_eq!(load(&version).unwrap_err().message(), "Unsupported version 9");
+
+        for length in 0..data.len() {
+            assert!(load(&data[..length]).is_err(), "accepted {} bytes", length);
+        }
+
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert_eq!(load(&trailing).unwrap_err().message(), "Trailing data");
+    }
+
+    #[test]
+    fn reject_bad_code() {
+        let mut program = program();
+        program.functions[0].patch(3, 8);
+        assert_eq!(load(&write(&program)).unwrap_err().message(),
+                   "Jump into the middle of an instruction");
+
+        let mut program = self::program();
+        let constants = program.constants.len() as u16;
+        program.functions[0].patch(0, constants);
+        assert_eq!(load(&write(&program)).unwrap_err().message(), "Operand out of range");
+
+        let mut program = self::program();
+        program.functions[0].code[6] = 0xFF;
+        assert_eq!(load(&write(&program)).unwrap_err().message(), "Invalid instruction");
+    }
+}
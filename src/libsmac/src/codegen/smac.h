@@ -0,0 +1,248 @@
+/*
+ * The Smaragdine runtime for C generated by `smac build --emit=c`.
+ *
+ * Integers wrap on overflow, shift amounts are taken modulo 64 and
+ * `>>` keeps the sign, all without relying on behaviour C leaves
+ * undefined or to the implementation. Strings are never freed.
+ *
+ * Link with the maths library (`-lm`) for float `%`.
+ */
+
+#ifndef SMAC_H
+#define SMAC_H
+
+#include <inttypes.h>
+#include <math.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef unsigned char smac_unit;
+/* A Unicode scalar value. */
+typedef uint32_t smac_char;
+/* UTF-8, not terminated. */
+typedef struct {
+    const char *data;
+    size_t length;
+} smac_string;
+
+/* How deep calls may nest, as in the interpreter. */
+#define SMAC_MAX_DEPTH 1000
+
+static unsigned smac_depth = 0;
+
+/* Reports a runtime error the way `smac run` does and exits. */
+static inline void smac_fail(unsigned line, unsigned col, const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "error [ln %u, col %u]: %s\n", line, col, message);
+    exit(1);
+}
+
+static inline void smac_enter(unsigned line, unsigned col) {
+    if (smac_depth == SMAC_MAX_DEPTH) {
+        smac_fail(line, col, "Stack overflow");
+    }
+    smac_depth++;
+}
+
+static inline void smac_leave(void) {
+    smac_depth--;
+}
+
+/* Converts modulo 2^64, which a cast only does where
+   the implementation chooses to. */
+static inline int64_t smac_wrap(uint64_t value) {
+    if (value <= INT64_MAX) {
+        return (int64_t)value;
+    }
+    return -(int64_t)(UINT64_MAX - value) - 1;
+}
+
+static inline int64_t smac_neg(int64_t value) {
+    return smac_wrap(0 - (uint64_t)value);
+}
+
+static inline int64_t smac_add(int64_t left, int64_t right) {
+    return smac_wrap((uint64_t)left + (uint64_t)right);
+}
+
+static inline int64_t smac_sub(int64_t left, int64_t right) {
+    return smac_wrap((uint64_t)left - (uint64_t)right);
+}
+
+static inline int64_t smac_mul(int64_t left, int64_t right) {
+    return smac_wrap((uint64_t)left * (uint64_t)right);
+}
+
+static inline int64_t smac_div(int64_t left, int64_t right, unsigned line, unsigned col) {
+    if (right == 0) {
+        smac_fail(line, col, "Division by zero");
+    }
+    /* The minimum divided by -1 overflows */
+    if (right == -1) {
+        return smac_neg(left);
+    }
+    return left / right;
+}
+
+static inline int64_t smac_rem(int64_t left, int64_t right, unsigned line, unsigned col) {
+    if (right == 0) {
+        smac_fail(line, col, "Division by zero");
+    }
+    if (right == -1) {
+        return 0;
+    }
+    return left % right;
+}
+
+static inline int64_t smac_shl(int64_t left, int64_t right) {
+    return smac_wrap((uint64_t)left << ((uint64_t)right & 63));
+}
+
+static inline int64_t smac_shr(int64_t left, int64_t right) {
+    unsigned shift = (unsigned)((uint64_t)right & 63);
+    /* Shifting a negative value right is up to the implementation */
+    return left < 0 ? ~(~left >> shift) : left >> shift;
+}
+
+static inline smac_string smac_str(const char *data, size_t length) {
+    smac_string string;
+    string.data = data;
+    string.length = length;
+    return string;
+}
+
+static inline smac_string smac_concat(smac_string left, smac_string right) {
+    char *data = malloc(left.length + right.length + 1);
+    if (data == NULL) {
+        fputs("error: out of memory\n", stderr);
+        exit(1);
+    }
+    memcpy(data, left.data, left.length);
+    memcpy(data + left.length, right.data, right.length);
+    return smac_str(data, left.length + right.length);
+}
+
+/* Orders strings by their bytes, which is also code point order. */
+static inline int smac_compare(smac_string left, smac_string right) {
+    size_t length = left.length < right.length ? left.length : right.length;
+    int order = length == 0 ? 0 : memcmp(left.data, right.data, length);
+    if (order != 0) {
+        return order;
+    }
+    return (left.length > right.length) - (left.length < right.length);
+}
+
+static inline bool smac_equal(smac_string left, smac_string right) {
+    return smac_compare(left, right) == 0;
+}
+
+static inline void smac_print_int(int64_t value) {
+    printf("%" PRId64 "\n", value);
+}
+
+/* Prints the shortest digits that read back as the
+   same value, laid out the way Rust debug prints them. */
+static inline void smac_print_float(double value) {
+    char buffer[32];
+    char digits[24];
+    size_t count = 0;
+    int precision, exponent, i;
+    const char *c;
+
+    if (value != value) {
+        puts("NaN");
+        return;
+    }
+    if (signbit(value)) {
+        putchar('-');
+        value = -value;
+    }
+    if (isinf(value)) {
+        puts("inf");
+        return;
+    }
+    if (value == 0) {
+        puts("0.0");
+        return;
+    }
+
+    for (precision = 0; precision < 17; precision++) {
+        snprintf(buffer, sizeof buffer, "%.*e", precision, value);
+        if (strtod(buffer, NULL) == value) {
+            break;
+        }
+    }
+    for (c = buffer; *c != 'e'; c++) {
+        if (*c != '.') {
+            digits[count++] = *c;
+        }
+    }
+    exponent = atoi(c + 1);
+    while (count > 1 && digits[count - 1] == '0') {
+        count--;
+    }
+
+    if (value < 1e-4 || value >= 1e16) {
+        putchar(digits[0]);
+        if (count > 1) {
+            printf(".%.*s", (int)count - 1, digits + 1);
+        }
+        printf("e%d\n", exponent);
+    } else if (exponent < 0) {
+        fputs("0.", stdout);
+        for (i = -1; i > exponent; i--) {
+            putchar('0');
+        }
+        printf("%.*s\n", (int)count, digits);
+    } else {
+        for (i = 0; i <= exponent; i++) {
+            putchar((size_t)i < count ? digits[i] : '0');
+        }
+        if ((size_t)exponent + 1 < count) {
+            printf(".%.*s\n", (int)(count - exponent - 1), digits + exponent + 1);
+        } else {
+            puts(".0");
+        }
+    }
+}
+
+static inline void smac_print_bool(bool value) {
+    puts(value ? "true" : "false");
+}
+
+static inline void smac_print_char(smac_char value) {
+    unsigned char buffer[4];
+    size_t length;
+    if (value < 0x80) {
+        buffer[0] = (unsigned char)value;
+        length = 1;
+    } else if (value < 0x800) {
+        buffer[0] = (unsigned char)(0xC0 | value >> 6);
+        buffer[1] = (unsigned char)(0x80 | (value & 0x3F));
+        length = 2;
+    } else if (value < 0x10000) {
+        buffer[0] = (unsigned char)(0xE0 | value >> 12);
+        buffer[1] = (unsigned char)(0x80 | (value >> 6 & 0x3F));
+        buffer[2] = (unsigned char)(0x80 | (value & 0x3F));
+        length = 3;
+    } else {
+        buffer[0] = (unsigned char)(0xF0 | value >> 18);
+        buffer[1] = (unsigned char)(0x80 | (value >> 12 & 0x3F));
+        buffer[2] = (unsigned char)(0x80 | (value >> 6 & 0x3F));
+        buffer[3] = (unsigned char)(0x80 | (value & 0x3F));
+        length = 4;
+    }
+    fwrite(buffer, 1, length, stdout);
+    putchar('\n');
+}
+
+static inline void smac_print_string(smac_string value) {
+    fwrite(value.data, 1, value.length, stdout);
+    putchar('\n');
+}
+
+#endif
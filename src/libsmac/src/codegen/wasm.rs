@@ -0,0 +1,1106 @@
+//! WebAssembly modules, as `.wasm` binaries and `.wat` text.
+//!
+//! Values are kept as `i64` for `int`, `f64` for `float` and
+//! `i32` for everything else: `bool` is 0 or 1, `char` its
+//! code point, unit 0, and a `string` points at its byte
+//! length, an `i32`, followed by its UTF-8 bytes.
+//!
+//! Modules import two functions from the host:
+//!
+//! ```text
+//! smac.fail (line: i32, col: i32, message: i32)   reports a
+//!           runtime error, after which the module traps
+//! smac.fmod (left: f64, right: f64) -> f64        float `%`,
+//!           which is JavaScript's `%`
+//! ```
+//!
+//! and export their memory as `smac.memory`, every top-level
+//! function by its name, and `smac.main`, which evaluates
+//! the other top-level nodes and returns the last value.
+
+use diagnostic::Diagnostic;
+use interpreter::MAX_DEPTH;
+use lexer::token::Op;
+use parser::node::{Node, NodeType};
+use typeck::{Type, Types};
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+pub const MAGIC: &'static [u8; 4] = b"\0asm";
+pub const VERSION: u32 = 1;
+
+const PAGE_SIZE: usize = 1 << 16;
+
+const FAIL:    u32 = 0;
+const FMOD:    u32 = 1;
+const CONCAT:  u32 = 2;
+const COMPARE: u32 = 3;
+/// The index of the first top-level function.
+const FIRST:   u32 = 4;
+
+const HEAP:  u32 = 0;
+const DEPTH: u32 = 1;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ValType {
+    I32,
+    I64,
+    F64,
+}
+
+impl ValType {
+    fn byte(&self) -> u8 {
+        match *self {
+            ValType::I32 => 0x7F,
+            ValType::I64 => 0x7E,
+            ValType::F64 => 0x7C,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match *self {
+            ValType::I32 => "i32",
+            ValType::I64 => "i64",
+            ValType::F64 => "f64",
+        }
+    }
+}
+
+/// The values a block leaves behind.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlockType {
+    Empty,
+    Value(ValType),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Instruction {
+    Unreachable,
+    Block(BlockType),
+    Loop(BlockType),
+    If(BlockType),
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Call(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    /// Loads an `i32` at an address plus an offset.
+    I32Load(u32),
+    /// Loads a byte at an address plus an offset.
+    I32Load8U(u32),
+    /// Stores an `i32` at an address plus an offset.
+    I32Store(u32),
+    MemorySize,
+    MemoryGrow,
+    MemoryCopy,
+    I32Const(i32),
+    I64Const(i64),
+    F64Const(f64),
+    /// An instruction without immediates: its opcode and name.
+    Plain(u8, &'static str),
+}
+
+/// The instructions without immediates used here.
+pub mod op {
+    use super::Instruction::{self, Plain};
+
+    pub const I32_EQZ:   Instruction = Plain(0x45, "i32.eqz");
+    pub const I32_EQ:    Instruction = Plain(0x46, "i32.eq");
+    pub const I32_NE:    Instruction = Plain(0x47, "i32.ne");
+    pub const I32_LT_S:  Instruction = Plain(0x48, "i32.lt_s");
+    pub const I32_LT_U:  Instruction = Plain(0x49, "i32.lt_u");
+    pub const I32_GT_S:  Instruction = Plain(0x4A, "i32.gt_s");
+    pub const I32_GT_U:  Instruction = Plain(0x4B, "i32.gt_u");
+    pub const I32_LE_S:  Instruction = Plain(0x4C, "i32.le_s");
+    pub const I32_LE_U:  Instruction = Plain(0x4D, "i32.le_u");
+    pub const I32_GE_S:  Instruction = Plain(0x4E, "i32.ge_s");
+    pub const I32_GE_U:  Instruction = Plain(0x4F, "i32.ge_u");
+    pub const I64_EQZ:   Instruction = Plain(0x50, "i64.eqz");
+    pub const I64_EQ:    Instruction = Plain(0x51, "i64.eq");
+    pub const I64_NE:    Instruction = Plain(0x52, "i64.ne");
+    pub const I64_LT_S:  Instruction = Plain(0x53, "i64.lt_s");
+    pub const I64_GT_S:  Instruction = Plain(0x55, "i64.gt_s");
+    pub const I64_LE_S:  Instruction = Plain(0x57, "i64.le_s");
+    pub const I64_GE_S:  Instruction = Plain(0x59, "i64.ge_s");
+    pub const F64_EQ:    Instruction = Plain(0x61, "f64.eq");
+    pub const F64_NE:    Instruction = Plain(0x62, "f64.ne");
+    pub const F64_LT:    Instruction = Plain(0x63, "f64.lt");
+    pub const F64_GT:    Instruction = Plain(0x64, "f64.gt");
+    pub const F64_LE:    Instruction = Plain(0x65, "f64.le");
+    pub const F64_GE:    Instruction = Plain(0x66, "f64.ge");
+    pub const I32_ADD:   Instruction = Plain(0x6A, "i32.add");
+    pub const I32_SUB:   Instruction = Plain(0x6B, "i32.sub");
+    pub const I32_AND:   Instruction = Plain(0x71, "i32.and");
+    pub const I32_SHL:   Instruction = Plain(0x74, "i32.shl");
+    pub const I32_SHR_U: Instruction = Plain(0x76, "i32.shr_u");
+    pub const I64_ADD:   Instruction = Plain(0x7C, "i64.add");
+    pub const I64_SUB:   Instruction = Plain(0x7D, "i64.sub");
+    pub const I64_MUL:   Instruction = Plain(0x7E, "i64.mul");
+    pub const I64_DIV_S: Instruction = Plain(0x7F, "i64.div_s");
+    pub const I64_REM_S: Instruction = Plain(0x81, "i64.rem_s");
+    pub const I64_AND:   Instruction = Plain(0x83, "i64.and");
+    pub const I64_OR:    Instruction = Plain(0x84, "i64.or");
+    pub const I64_XOR:   Instruction = Plain(0x85, "i64.xor");
+    pub const I64_SHL:   Instruction = Plain(0x86, "i64.shl");
+    pub const I64_SHR_S: Instruction = Plain(0x87, "i64.shr_s");
+    pub const F64_NEG:   Instruction = Plain(0x9A, "f64.neg");
+    pub const F64_ADD:   Instruction = Plain(0xA0, "f64.add");
+    pub const F64_SUB:   Instruction = Plain(0xA1, "f64.sub");
+    pub const F64_MUL:   Instruction = Plain(0xA2, "f64.mul");
+    pub const F64_DIV:   Instruction = Plain(0xA3, "f64.div");
+}
+
+use self::Instruction::*;
+
+/// A function defined in the module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub type_index: u32,
+    /// The locals after the parameters.
+    pub locals:     Vec<ValType>,
+    pub body:       Vec<Instruction>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    /// Parameter and result types.
+    pub types:     Vec<(Vec<ValType>, Vec<ValType>)>,
+    /// Imported functions: module, name and type.
+    pub imports:   Vec<(&'static str, &'static str, u32)>,
+    pub functions: Vec<Function>,
+    /// The initial size of memory, in pages.
+    pub pages:     u32,
+    /// Mutable `i32` globals and their initial values.
+    pub globals:   Vec<i32>,
+    /// Exported functions, by name.
+    pub exports:   Vec<(String, u32)>,
+    /// The bytes memory starts with.
+    pub data:      Vec<u8>,
+}
+
+/// Translates type-checked nodes to a module.
+pub fn emit_wasm(nodes: &[Node], types: &Types) -> Result<Module, Diagnostic> {
+    let mut module = Module {
+        types:     Vec::new(),
+        imports:   Vec::new(),
+        functions: Vec::new(),
+        pages:     0,
+        globals:   Vec::new(),
+        exports:   Vec::new(),
+        data:      Vec::new(),
+    };
+
+    let fail = module.type_index(vec![ValType::I32; 3], vec![]);
+    let fmod = module.type_index(vec![ValType::F64; 2], vec![ValType::F64]);
+    module.imports.push(("smac", "fail", fail));
+    module.imports.push(("smac", "fmod", fmod));
+
+    let mut strings = Strings::default();
+    let messages = Messages {
+        division: strings.add(&mut module.data, "Division by zero"),
+        overflow: strings.add(&mut module.data, "Stack overflow"),
+    };
+
+    let pair = module.type_index(vec![ValType::I32; 2], vec![ValType::I32]);
+    module.functions.push(concat(pair));
+    module.functions.push(compare(pair));
+
+    let mut indices = HashMap::new();
+    for node in nodes.iter() {
+        if let NodeType::Function(ref name, _, _) = *node.node_type() {
+            let index = FIRST + indices.len() as u32;
+            indices.insert(name.clone(), index);
+            module.exports.push((name.clone(), index));
+        }
+    }
+
+    for node in nodes.iter() {
+        if let NodeType::Function(ref name, ref parameters, ref body) = *node.node_type() {
+            let (parameter_types, result) = match types.function(name) {
+                Some(&Type::Function(ref parameters, ref result)) => (parameters, &**result),
+                _ => return Err(error(node, format!("Unknown name `{}`", name))),
+            };
+            let mut params = Vec::new();
+            for t in parameter_types.iter() {
+                params.push(value_type(node, t)?)
+            }
+            let result     = value_type(node, result)?;
+            let count      = params.len();
+            let type_index = module.type_index(params, vec![result]);
+
+            let mut lowering = Lowering::new(types, &indices, &mut strings, &mut module.data, messages, count);
+            lowering.scopes.push(parameters.iter().cloned().zip(0..).collect());
+            lowering.node(body)?;
+            lowering.body.push(End);
+
+            let function = Function {
+                type_index: type_index,
+                locals:     lowering.locals,
+                body:       lowering.body,
+            };
+            module.functions.push(function);
+        }
+    }
+
+    // The program's value, as `Interpreter::run` finds it
+    let main = {
+        let mut lowering = Lowering::new(types, &indices, &mut strings, &mut module.data, messages, 0);
+        lowering.scopes.push(Vec::new());
+        let mut result = ValType::I32;
+        for (i, node) in nodes.iter().enumerate() {
+            if i > 0 {
+                lowering.body.push(Drop);
+            }
+            result = match *node.node_type() {
+                NodeType::Function(..) => {
+                    lowering.body.push(I32Const(0));
+                    ValType::I32
+                },
+                _ => {
+                    lowering.node(node)?;
+                    value_type(node, &lowering.type_of(node))?
+                },
+            }
+        }
+        if nodes.is_empty() {
+            lowering.body.push(I32Const(0));
+        }
+        lowering.body.push(End);
+        (result, lowering.locals, lowering.body)
+    };
+    let index = FIRST + module.functions.len() as u32 - 2;
+    let type_index = module.type_index(vec![], vec![main.0]);
+    module.functions.push(Function {
+        type_index: type_index,
+        locals:     main.1,
+        body:       main.2,
+    });
+    module.exports.push(("smac.main".to_owned(), index));
+
+    // Strings made at runtime follow the literals
+    let heap = (module.data.len() + 3) & !3;
+    module.globals = vec![heap as i32, 0];
+    module.pages = ((heap + PAGE_SIZE - 1) / PAGE_SIZE).max(1) as u32;
+
+    Ok(module)
+}
+
+fn error(node: &Node, message: String) -> Diagnostic {
+    Diagnostic::new(node.span().start, message)
+}
+
+fn value_type(node: &Node, t: &Type) -> Result<ValType, Diagnostic> {
+    match *t {
+        Type::Int   => Ok(ValType::I64),
+        Type::Float => Ok(ValType::F64),
+        Type::Unit | Type::Bool | Type::Char | Type::String => Ok(ValType::I32),
+        ref t => Err(error(node, format!("Cannot translate `{}` to WebAssembly", t))),
+    }
+}
+
+/// String literals already laid out in memory, by value.
+#[derive(Default)]
+struct Strings {
+    addresses: HashMap<String, i32>,
+}
+
+impl Strings {
+    fn add(&mut self, data: &mut Vec<u8>, value: &str) -> i32 {
+        if let Some(&address) = self.addresses.get(value) {
+            return address
+        }
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        let address = data.len() as i32;
+        data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        data.extend_from_slice(value.as_bytes());
+        self.addresses.insert(value.to_owned(), address);
+        address
+    }
+}
+
+/// The addresses of the runtime error messages.
+#[derive(Copy, Clone)]
+struct Messages {
+    division: i32,
+    overflow: i32,
+}
+
+/// Joins two strings into a new one at the end of the heap,
+/// growing memory as needed.
+fn concat(type_index: u32) -> Function {
+    let (a, b, la, lb, p) = (0, 1, 2, 3, 4);
+    let body = vec![
+        LocalGet(a), I32Load(0), LocalSet(la),
+        LocalGet(b), I32Load(0), LocalSet(lb),
+        GlobalGet(HEAP), LocalSet(p),
+        // The heap stays aligned
+        LocalGet(p), LocalGet(la), op::I32_ADD, LocalGet(lb), op::I32_ADD,
+        I32Const(7), op::I32_ADD, I32Const(-4), op::I32_AND, GlobalSet(HEAP),
+        Block(BlockType::Empty),
+        GlobalGet(HEAP), MemorySize, I32Const(16), op::I32_SHL, op::I32_LE_U, BrIf(0),
+        GlobalGet(HEAP), MemorySize, I32Const(16), op::I32_SHL, op::I32_SUB,
+        I32Const(PAGE_SIZE as i32 - 1), op::I32_ADD, I32Const(16), op::I32_SHR_U,
+        MemoryGrow, I32Const(-1), op::I32_NE, BrIf(0),
+        Unreachable,
+        End,
+        LocalGet(p), LocalGet(la), LocalGet(lb), op::I32_ADD, I32Store(0),
+        LocalGet(p), I32Const(4), op::I32_ADD, LocalGet(a), I32Const(4), op::I32_ADD, LocalGet(la), MemoryCopy,
+        LocalGet(p), I32Const(4), op::I32_ADD, LocalGet(la), op::I32_ADD,
+        LocalGet(b), I32Const(4), op::I32_ADD, LocalGet(lb), MemoryCopy,
+        LocalGet(p),
+        End,
+    ];
+    Function {
+        type_index: type_index,
+        locals:     vec![ValType::I32; 3],
+        body:       body,
+    }
+}
+
+/// Orders two strings by their bytes, returning -1, 0 or 1.
+fn compare(type_index: u32) -> Function {
+    let (a, b, la, lb, n, i, x, y) = (0, 1, 2, 3, 4, 5, 6, 7);
+    let body = vec![
+        LocalGet(a), I32Load(0), LocalSet(la),
+        LocalGet(b), I32Load(0), LocalSet(lb),
+        LocalGet(la), LocalGet(lb), LocalGet(la), LocalGet(lb), op::I32_LT_U, Select, LocalSet(n),
+        Block(BlockType::Empty),
+        Loop(BlockType::Empty),
+        LocalGet(i), LocalGet(n), op::I32_GE_U, BrIf(1),
+        LocalGet(a), LocalGet(i), op::I32_ADD, I32Load8U(4), LocalSet(x),
+        LocalGet(b), LocalGet(i), op::I32_ADD, I32Load8U(4), LocalSet(y),
+        LocalGet(x), LocalGet(y), op::I32_NE,
+        If(BlockType::Empty),
+        LocalGet(x), LocalGet(y), op::I32_GT_U, LocalGet(x), LocalGet(y), op::I32_LT_U, op::I32_SUB, Return,
+        End,
+        LocalGet(i), I32Const(1), op::I32_ADD, LocalSet(i),
+        Br(0),
+        End,
+        End,
+        LocalGet(la), LocalGet(lb), op::I32_GT_U, LocalGet(la), LocalGet(lb), op::I32_LT_U, op::I32_SUB,
+        End,
+    ];
+    Function {
+        type_index: type_index,
+        locals:     vec![ValType::I32; 6],
+        body:       body,
+    }
+}
+
+/// Lowers the nodes of one function. Every node leaves
+/// exactly one value on the stack.
+struct Lowering<'a> {
+    types:      &'a Types,
+    /// The index of each top-level function.
+    functions:  &'a HashMap<String, u32>,
+    strings:    &'a mut Strings,
+    data:       &'a mut Vec<u8>,
+    messages:   Messages,
+    parameters: usize,
+    locals:     Vec<ValType>,
+    body:       Vec<Instruction>,
+    /// The indices of the locals in scope, innermost last.
+    scopes:     Vec<Vec<(String, u32)>>,
+}
+
+impl<'a> Lowering<'a> {
+    fn new(types: &'a Types, functions: &'a HashMap<String, u32>, strings: &'a mut Strings,
+           data: &'a mut Vec<u8>, messages: Messages, parameters: usize) -> Lowering<'a> {
+        Lowering {
+            types:      types,
+            functions:  functions,
+            strings:    strings,
+            data:       data,
+            messages:   messages,
+            parameters: parameters,
+            locals:     Vec::new(),
+            body:       Vec::new(),
+            scopes:     Vec::new(),
+        }
+    }
+
+    fn type_of(&self, node: &Node) -> Type {
+        self.types.of(node).cloned().unwrap_or(Type::Unit)
+    }
+
+    fn local(&mut self, t: ValType) -> u32 {
+        self.locals.push(t);
+        (self.parameters + self.locals.len() - 1) as u32
+    }
+
+    fn lookup(&self, node: &Node, name: &str) -> Result<u32, Diagnostic> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(&(_, index)) = scope.iter().rev().find(|&&(ref n, _)| n == name) {
+                return Ok(index)
+            }
+        }
+        Err(error(node, format!("Unknown name `{}`", name)))
+    }
+
+    /// Reports a runtime error at `node` and traps.
+    fn fail(&mut self, node: &Node, message: i32) {
+        let position = node.span().start;
+        self.body.extend_from_slice(&[
+            // The module can run again after the trap
+            I32Const(0), GlobalSet(DEPTH),
+            I32Const(position.line as i32), I32Const(position.col as i32), I32Const(message),
+            Call(FAIL),
+            Unreachable,
+        ]);
+    }
+
+    fn node(&mut self, node: &Node) -> Result<(), Diagnostic> {
+        match *node.node_type() {
+            NodeType::StringLiteral(ref value) => {
+                let address = self.strings.add(self.data, value);
+                self.body.push(I32Const(address))
+            },
+            NodeType::CharLiteral(value)    => self.body.push(I32Const(value as i32)),
+            NodeType::IntLiteral(value, _)  => self.body.push(I64Const(value as i64)),
+            NodeType::FloatLiteral(value)   => self.body.push(F64Const(value)),
+            NodeType::BooleanLiteral(value) => self.body.push(I32Const(value as i32)),
+            NodeType::Identifier(ref name) => {
+                let index = self.lookup(node, name)?;
+                self.body.push(LocalGet(index))
+            },
+            NodeType::Unary(op, ref operand) => {
+                match (op, self.type_of(operand)) {
+                    (Op::Sub, Type::Int) => {
+                        self.body.push(I64Const(0));
+                        self.node(operand)?;
+                        self.body.push(op::I64_SUB)
+                    },
+                    (Op::Sub, _) => {
+                        self.node(operand)?;
+                        self.body.push(op::F64_NEG)
+                    },
+                    (Op::Not, _) => {
+                        self.node(operand)?;
+                        self.body.push(op::I32_EQZ)
+                    },
+                    (Op::BitNot, _) => {
+                        self.node(operand)?;
+                        self.body.extend_from_slice(&[I64Const(-1), op::I64_XOR])
+                    },
+                    _ => self.node(operand)?,
+                }
+            },
+            NodeType::Binary(Op::And, ref left, ref right) => {
+                self.node(left)?;
+                self.body.push(If(BlockType::Value(ValType::I32)));
+                self.node(right)?;
+                self.body.extend_from_slice(&[Else, I32Const(0), End])
+            },
+            NodeType::Binary(Op::Or, ref left, ref right) => {
+                self.node(left)?;
+                self.body.extend_from_slice(&[If(BlockType::Value(ValType::I32)), I32Const(1), Else]);
+                self.node(right)?;
+                self.body.push(End)
+            },
+            NodeType::Binary(Op::Range, ..) | NodeType::Binary(Op::RangeInclusive, ..) => {
+                return Err(error(node, "Cannot compile ranges".to_owned()))
+            },
+            NodeType::Binary(op, ref left, ref right) => {
+                let t = self.type_of(left);
+                self.node(left)?;
+                self.node(right)?;
+                self.binary(node, op, &t)?
+            },
+            NodeType::Call(ref function, ref arguments) => {
+                let name = match *function.node_type() {
+                    NodeType::Identifier(ref name) if self.lookup(function, name).is_err() => name,
+                    _ => return Err(error(function, "Only named functions can be called".to_owned())),
+                };
+                let index = match self.functions.get(name) {
+                    Some(&index) => index,
+                    None         => return Err(error(node, format!("Unknown name `{}`", name))),
+                };
+                // Partial application makes a closure
+                match self.types.function(name) {
+                    Some(&Type::Function(ref parameters, _)) if parameters.len() == arguments.len() => (),
+                    _ => return Err(error(node, "Cannot compile closures".to_owned())),
+                }
+                for argument in arguments.iter() {
+                    self.node(argument)?
+                }
+                self.body.extend_from_slice(&[GlobalGet(DEPTH), I32Const(MAX_DEPTH as i32), op::I32_EQ,
+                                              If(BlockType::Empty)]);
+                let overflow = self.messages.overflow;
+                self.fail(node, overflow);
+                self.body.extend_from_slice(&[
+                    End,
+                    GlobalGet(DEPTH), I32Const(1), op::I32_ADD, GlobalSet(DEPTH),
+                    Call(index),
+                    GlobalGet(DEPTH), I32Const(1), op::I32_SUB, GlobalSet(DEPTH),
+                ])
+            },
+            NodeType::If(ref condition, ref then, ref otherwise) => {
+                let t = value_type(node, &self.type_of(node))?;
+                self.node(condition)?;
+                self.body.push(If(BlockType::Value(t)));
+                self.node(then)?;
+                if otherwise.is_none() {
+                    self.body.extend_from_slice(&[Drop, I32Const(0)])
+                }
+                self.body.push(Else);
+                match *otherwise {
+                    Some(ref otherwise) => self.node(otherwise)?,
+                    None                => self.body.push(I32Const(0)),
+                }
+                self.body.push(End)
+            },
+            NodeType::Let(ref name, _, ref value) => {
+                let value = match *value {
+                    Some(ref value) => value,
+                    None            => return Err(error(node, "Cannot compile bindings without a value".to_owned())),
+                };
+                let t = value_type(node, &self.type_of(value))?;
+                self.node(value)?;
+                let index = self.local(t);
+                self.body.extend_from_slice(&[LocalSet(index), I32Const(0)]);
+                self.scopes.last_mut().unwrap().push((name.clone(), index))
+            },
+            NodeType::Function(..) => {
+                return Err(error(node, "Functions can only be defined at the top level".to_owned()))
+            },
+            NodeType::Assign(ref target, ref value) => {
+                self.node(value)?;
+                let index = match *target.node_type() {
+                    NodeType::Identifier(ref name) => self.lookup(target, name)?,
+                    _ => return Err(error(target, "Invalid assignment target".to_owned())),
+                };
+                self.body.extend_from_slice(&[LocalSet(index), I32Const(0)])
+            },
+            NodeType::CompoundAssign(op, ref target, ref value) => {
+                let t = self.type_of(value);
+                // Evaluated before the target is read, as elsewhere
+                self.node(value)?;
+                let operand = self.local(value_type(node, &t)?);
+                self.body.push(LocalSet(operand));
+                let index = match *target.node_type() {
+                    NodeType::Identifier(ref name) => self.lookup(target, name)?,
+                    _ => return Err(error(target, "Invalid assignment target".to_owned())),
+                };
+                self.body.extend_from_slice(&[LocalGet(index), LocalGet(operand)]);
+                self.binary(node, op, &t)?;
+                self.body.extend_from_slice(&[LocalSet(index), I32Const(0)])
+            },
+            NodeType::Block(ref nodes) => {
+                self.scopes.push(Vec::new());
+                for (i, node) in nodes.iter().enumerate() {
+                    if i > 0 {
+                        self.body.push(Drop);
+                    }
+                    self.node(node)?
+                }
+                if nodes.is_empty() {
+                    self.body.push(I32Const(0));
+                }
+                self.scopes.pop();
+            },
+            NodeType::Field(..) => {
+                return Err(error(node, "Cannot compile field access".to_owned()))
+            },
+            NodeType::List(..) | NodeType::Index(..) => {
+                return Err(error(node, "Cannot compile lists".to_owned()))
+            },
+            NodeType::Record(..) | NodeType::With(..) => {
+                return Err(error(node, "Cannot compile records".to_owned()))
+            },
+            NodeType::Enum(..) | NodeType::Match(..) => {
+                return Err(error(node, "Cannot compile enums".to_owned()))
+            },
+            NodeType::Tuple(..) => {
+                return Err(error(node, "Cannot compile tuples".to_owned()))
+            },
+            NodeType::While(..) | NodeType::For(..) | NodeType::Break(_) | NodeType::Continue => {
+                return Err(error(node, "Cannot compile loops".to_owned()))
+            },
+            NodeType::Lambda(..) => {
+                return Err(error(node, "Cannot compile closures".to_owned()))
+            },
+            NodeType::Error => {
+                return Err(error(node, "Cannot compile code with syntax errors".to_owned()))
+            },
+        }
+        Ok(())
+    }
+
+    /// Applies `op` to two operands of type `t` on the stack.
+    fn binary(&mut self, node: &Node, op: Op, t: &Type) -> Result<(), Diagnostic> {
+        let instructions: &[Instruction] = match (op, t) {
+            (Op::Div, &Type::Int) | (Op::Mod, &Type::Int) => return Ok(self.divide(node, op)),
+
+            (Op::Add, &Type::Int)    => &[op::I64_ADD],
+            (Op::Sub, &Type::Int)    => &[op::I64_SUB],
+            (Op::Mul, &Type::Int)    => &[op::I64_MUL],
+            (Op::BitAnd, _)          => &[op::I64_AND],
+            (Op::BitOr, _)           => &[op::I64_OR],
+            (Op::BitXor, _)          => &[op::I64_XOR],
+            (Op::Shl, _)             => &[op::I64_SHL],
+            (Op::Shr, _)             => &[op::I64_SHR_S],
+            (Op::Eq, &Type::Int)     => &[op::I64_EQ],
+            (Op::Ne, &Type::Int)     => &[op::I64_NE],
+            (Op::Lt, &Type::Int)     => &[op::I64_LT_S],
+            (Op::Le, &Type::Int)     => &[op::I64_LE_S],
+            (Op::Gt, &Type::Int)     => &[op::I64_GT_S],
+            (Op::Ge, &Type::Int)     => &[op::I64_GE_S],
+
+            (Op::Add, &Type::Float)  => &[op::F64_ADD],
+            (Op::Sub, &Type::Float)  => &[op::F64_SUB],
+            (Op::Mul, &Type::Float)  => &[op::F64_MUL],
+            (Op::Div, &Type::Float)  => &[op::F64_DIV],
+            (Op::Mod, &Type::Float)  => &[Call(FMOD)],
+            (Op::Eq, &Type::Float)   => &[op::F64_EQ],
+            (Op::Ne, &Type::Float)   => &[op::F64_NE],
+            (Op::Lt, &Type::Float)   => &[op::F64_LT],
+            (Op::Le, &Type::Float)   => &[op::F64_LE],
+            (Op::Gt, &Type::Float)   => &[op::F64_GT],
+            (Op::Ge, &Type::Float)   => &[op::F64_GE],
+
+            (Op::Add, &Type::String) => &[Call(CONCAT)],
+            (Op::Eq, &Type::String)  => &[Call(COMPARE), op::I32_EQZ],
+            (Op::Ne, &Type::String)  => &[Call(COMPARE), I32Const(0), op::I32_NE],
+            (Op::Lt, &Type::String)  => &[Call(COMPARE), I32Const(0), op::I32_LT_S],
+            (Op::Le, &Type::String)  => &[Call(COMPARE), I32Const(0), op::I32_LE_S],
+            (Op::Gt, &Type::String)  => &[Call(COMPARE), I32Const(0), op::I32_GT_S],
+            (Op::Ge, &Type::String)  => &[Call(COMPARE), I32Const(0), op::I32_GE_S],
+
+            // Characters compare by code point, the rest only for equality
+            (Op::Eq, _)              => &[op::I32_EQ],
+            (Op::Ne, _)              => &[op::I32_NE],
+            (Op::Lt, _)              => &[op::I32_LT_U],
+            (Op::Le, _)              => &[op::I32_LE_U],
+            (Op::Gt, _)              => &[op::I32_GT_U],
+            (Op::Ge, _)              => &[op::I32_GE_U],
+
+            _ => return Err(error(node, format!("Invalid operator `{}`", op.as_str()))),
+        };
+        self.body.extend_from_slice(instructions);
+        Ok(())
+    }
+
+    /// Integer `/` or `%`, which trap where the
+    /// interpreter reports an error or wraps.
+    fn divide(&mut self, node: &Node, op: Op) {
+        let right = self.local(ValType::I64);
+        let left  = self.local(ValType::I64);
+        self.body.extend_from_slice(&[LocalSet(right), LocalSet(left),
+                                      LocalGet(right), op::I64_EQZ, If(BlockType::Empty)]);
+        let division = self.messages.division;
+        self.fail(node, division);
+        self.body.extend_from_slice(&[
+            End,
+            LocalGet(right), I64Const(-1), op::I64_EQ,
+            If(BlockType::Value(ValType::I64)),
+        ]);
+        if op == Op::Div {
+            self.body.extend_from_slice(&[I64Const(0), LocalGet(left), op::I64_SUB]);
+        } else {
+            self.body.push(I64Const(0));
+        }
+        self.body.extend_from_slice(&[
+            Else,
+            LocalGet(left), LocalGet(right), if op == Op::Div { op::I64_DIV_S } else { op::I64_REM_S },
+            End,
+        ]);
+    }
+}
+
+impl Module {
+    /// The index of a function type, added if new.
+    fn type_index(&mut self, params: Vec<ValType>, results: Vec<ValType>) -> u32 {
+        let signature = (params, results);
+        match self.types.iter().position(|t| *t == signature) {
+            Some(index) => index as u32,
+            None        => {
+                self.types.push(signature);
+                self.types.len() as u32 - 1
+            },
+        }
+    }
+
+    /// Encodes the module in the binary format.
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+
+        let mut section = Vec::new();
+        write_u32(&mut section, self.types.len() as u32);
+        for &(ref params, ref results) in self.types.iter() {
+            section.push(0x60);
+            write_types(&mut section, params);
+            write_types(&mut section, results);
+        }
+        write_section(&mut out, 1, &section);
+
+        section.clear();
+        write_u32(&mut section, self.imports.len() as u32);
+        for &(module, name, type_index) in self.imports.iter() {
+            write_name(&mut section, module);
+            write_name(&mut section, name);
+            section.push(0x00);
+            write_u32(&mut section, type_index);
+        }
+        write_section(&mut out, 2, &section);
+
+        section.clear();
+        write_u32(&mut section, self.functions.len() as u32);
+        for function in self.functions.iter() {
+            write_u32(&mut section, function.type_index);
+        }
+        write_section(&mut out, 3, &section);
+
+        section.clear();
+        write_u32(&mut section, 1);
+        section.push(0x00);
+        write_u32(&mut section, self.pages);
+        write_section(&mut out, 5, &section);
+
+        section.clear();
+        write_u32(&mut section, self.globals.len() as u32);
+        for &value in self.globals.iter() {
+            section.extend_from_slice(&[ValType::I32.byte(), 0x01]);
+            encode(&mut section, &I32Const(value));
+            encode(&mut section, &End);
+        }
+        write_section(&mut out, 6, &section);
+
+        section.clear();
+        write_u32(&mut section, self.exports.len() as u32 + 1);
+        write_name(&mut section, "smac.memory");
+        section.push(0x02);
+        write_u32(&mut section, 0);
+        for &(ref name, index) in self.exports.iter() {
+            write_name(&mut section, name);
+            section.push(0x00);
+            write_u32(&mut section, index);
+        }
+        write_section(&mut out, 7, &section);
+
+        section.clear();
+        write_u32(&mut section, self.functions.len() as u32);
+        for function in self.functions.iter() {
+            let mut code = Vec::new();
+            let runs = runs(&function.locals);
+            write_u32(&mut code, runs.len() as u32);
+            for (count, t) in runs {
+                write_u32(&mut code, count);
+                code.push(t.byte());
+            }
+            for instruction in function.body.iter() {
+                encode(&mut code, instruction);
+            }
+            write_u32(&mut section, code.len() as u32);
+            section.extend_from_slice(&code);
+        }
+        write_section(&mut out, 10, &section);
+
+        section.clear();
+        write_u32(&mut section, 1);
+        section.push(0x00);
+        encode(&mut section, &I32Const(0));
+        encode(&mut section, &End);
+        write_u32(&mut section, self.data.len() as u32);
+        section.extend_from_slice(&self.data);
+        write_section(&mut out, 11, &section);
+
+        out
+    }
+
+    /// Writes the module in the text format. Indices are written
+    /// as numbers, with the index each item defines in a comment.
+    pub fn to_text(&self) -> String {
+        let mut out = String::from("(module\n");
+
+        for (i, &(ref params, ref results)) in self.types.iter().enumerate() {
+            writeln!(out, "  (type (;{};) (func{}))", i, signature(params, results)).unwrap();
+        }
+        for (i, &(module, name, type_index)) in self.imports.iter().enumerate() {
+            writeln!(out, "  (import {:?} {:?} (func (;{};) (type {})))", module, name, i, type_index).unwrap();
+        }
+
+        for (i, function) in self.functions.iter().enumerate() {
+            let (ref params, ref results) = self.types[function.type_index as usize];
+            write!(out, "  (func (;{};) (type {}){}", self.imports.len() + i, function.type_index,
+                   signature(params, results)).unwrap();
+            if !function.locals.is_empty() {
+                out.push_str(" (local");
+                for t in function.locals.iter() {
+                    write!(out, " {}", t.as_str()).unwrap();
+                }
+                out.push(')');
+            }
+            out.push('\n');
+
+            let mut depth = 2;
+            let last = function.body.len() - 1;
+            for instruction in function.body[..last].iter() {
+                if let End = *instruction {
+                    depth -= 1;
+                }
+                let indent = if let Else = *instruction { depth - 1 } else { depth };
+                writeln!(out, "{:width$}{}", "", text(instruction), width = indent * 2).unwrap();
+                match *instruction {
+                    Block(_) | Loop(_) | If(_) => depth += 1,
+                    _                          => (),
+                }
+            }
+            out.truncate(out.len() - 1);
+            out.push_str(")\n");
+        }
+
+        writeln!(out, "  (memory (;0;) {})", self.pages).unwrap();
+        for (i, &value) in self.globals.iter().enumerate() {
+            writeln!(out, "  (global (;{};) (mut i32) (i32.const {}))", i, value).unwrap();
+        }
+        writeln!(out, "  (export \"smac.memory\" (memory 0))").unwrap();
+        for &(ref name, index) in self.exports.iter() {
+            writeln!(out, "  (export {} (func {}))", string(name.as_bytes()), index).unwrap();
+        }
+        writeln!(out, "  (data (;0;) (i32.const 0) {}))", string(&self.data)).unwrap();
+
+        out
+    }
+}
+
+/// Groups consecutive locals of the same type.
+fn runs(locals: &[ValType]) -> Vec<(u32, ValType)> {
+    let mut runs: Vec<(u32, ValType)> = Vec::new();
+    for &t in locals.iter() {
+        match runs.last_mut() {
+            Some(&mut (ref mut count, last)) if last == t => *count += 1,
+            _ => runs.push((1, t)),
+        }
+    }
+    runs
+}
+
+fn signature(params: &[ValType], results: &[ValType]) -> String {
+    let mut out = String::new();
+    for (keyword, types) in [("param", params), ("result", results)].iter() {
+        if !types.is_empty() {
+            write!(out, " ({}", keyword).unwrap();
+            for t in types.iter() {
+                write!(out, " {}", t.as_str()).unwrap();
+            }
+            out.push(')');
+        }
+    }
+    out
+}
+
+/// Writes bytes as a text format string.
+fn string(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &byte in bytes.iter() {
+        match byte {
+            b'"' | b'\\'  => write!(out, "\\{:02x}", byte).unwrap(),
+            b' '..=b'~'   => out.push(byte as char),
+            _             => write!(out, "\\{:02x}", byte).unwrap(),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn block_type(t: &BlockType) -> String {
+    match *t {
+        BlockType::Empty    => String::new(),
+        BlockType::Value(t) => format!(" (result {})", t.as_str()),
+    }
+}
+
+fn text(instruction: &Instruction) -> String {
+    match *instruction {
+        Unreachable         => "unreachable".to_owned(),
+        Block(ref t)        => format!("block{}", block_type(t)),
+        Loop(ref t)         => format!("loop{}", block_type(t)),
+        If(ref t)           => format!("if{}", block_type(t)),
+        Else                => "else".to_owned(),
+        End                 => "end".to_owned(),
+        Br(depth)           => format!("br {}", depth),
+        BrIf(depth)         => format!("br_if {}", depth),
+        Return              => "return".to_owned(),
+        Call(index)         => format!("call {}", index),
+        Drop                => "drop".to_owned(),
+        Select              => "select".to_owned(),
+        LocalGet(index)     => format!("local.get {}", index),
+        LocalSet(index)     => format!("local.set {}", index),
+        LocalTee(index)     => format!("local.tee {}", index),
+        GlobalGet(index)    => format!("global.get {}", index),
+        GlobalSet(index)    => format!("global.set {}", index),
+        I32Load(0)          => "i32.load".to_owned(),
+        I32Load(offset)     => format!("i32.load offset={}", offset),
+        I32Load8U(0)        => "i32.load8_u".to_owned(),
+        I32Load8U(offset)   => format!("i32.load8_u offset={}", offset),
+        I32Store(0)         => "i32.store".to_owned(),
+        I32Store(offset)    => format!("i32.store offset={}", offset),
+        MemorySize          => "memory.size".to_owned(),
+        MemoryGrow          => "memory.grow".to_owned(),
+        MemoryCopy          => "memory.copy".to_owned(),
+        I32Const(value)     => format!("i32.const {}", value),
+        I64Const(value)     => format!("i64.const {}", value),
+        F64Const(value) if value.is_infinite() => "f64.const inf".to_owned(),
+        // Debug output reads back as the same value
+        F64Const(value)     => format!("f64.const {:?}", value),
+        Plain(_, name)      => name.to_owned(),
+    }
+}
+
+fn encode(out: &mut Vec<u8>, instruction: &Instruction) {
+    match *instruction {
+        Unreachable       => out.push(0x00),
+        Block(ref t)      => write_block(out, 0x02, t),
+        Loop(ref t)       => write_block(out, 0x03, t),
+        If(ref t)         => write_block(out, 0x04, t),
+        Else              => out.push(0x05),
+        End               => out.push(0x0B),
+        Br(depth)         => write_immediate(out, 0x0C, depth),
+        BrIf(depth)       => write_immediate(out, 0x0D, depth),
+        Return            => out.push(0x0F),
+        Call(index)       => write_immediate(out, 0x10, index),
+        Drop              => out.push(0x1A),
+        Select            => out.push(0x1B),
+        LocalGet(index)   => write_immediate(out, 0x20, index),
+        LocalSet(index)   => write_immediate(out, 0x21, index),
+        LocalTee(index)   => write_immediate(out, 0x22, index),
+        GlobalGet(index)  => write_immediate(out, 0x23, index),
+        GlobalSet(index)  => write_immediate(out, 0x24, index),
+        // Alignments are the natural ones, as powers of two
+        I32Load(offset)   => write_memarg(out, 0x28, 2, offset),
+        I32Load8U(offset) => write_memarg(out, 0x2D, 0, offset),
+        I32Store(offset)  => write_memarg(out, 0x36, 2, offset),
+        MemorySize        => out.extend_from_slice(&[0x3F, 0x00]),
+        MemoryGrow        => out.extend_from_slice(&[0x40, 0x00]),
+        MemoryCopy        => out.extend_from_slice(&[0xFC, 0x0A, 0x00, 0x00]),
+        I32Const(value) => {
+            out.push(0x41);
+            write_i64(out, value as i64)
+        },
+        I64Const(value) => {
+            out.push(0x42);
+            write_i64(out, value)
+        },
+        F64Const(value) => {
+            out.push(0x44);
+            out.extend_from_slice(&value.to_bits().to_le_bytes())
+        },
+        Plain(opcode, _) => out.push(opcode),
+    }
+}
+
+fn write_block(out: &mut Vec<u8>, opcode: u8, t: &BlockType) {
+    out.push(opcode);
+    match *t {
+        BlockType::Empty    => out.push(0x40),
+        BlockType::Value(t) => out.push(t.byte()),
+    }
+}
+
+fn write_immediate(out: &mut Vec<u8>, opcode: u8, immediate: u32) {
+    out.push(opcode);
+    write_u32(out, immediate)
+}
+
+fn write_memarg(out: &mut Vec<u8>, opcode: u8, align: u32, offset: u32) {
+    out.push(opcode);
+    write_u32(out, align);
+    write_u32(out, offset)
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, section: &[u8]) {
+    out.push(id);
+    write_u32(out, section.len() as u32);
+    out.extend_from_slice(section)
+}
+
+fn write_types(out: &mut Vec<u8>, types: &[ValType]) {
+    write_u32(out, types.len() as u32);
+    for t in types.iter() {
+        out.push(t.byte())
+    }
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_u32(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes())
+}
+
+/// Unsigned LEB128.
+fn write_u32(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return
+        }
+        out.push(byte | 0x80)
+    }
+}
+
+/// Signed LEB128.
+fn write_i64(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return
+        }
+        out.push(byte | 0x80)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{emit_wasm, write_u32, write_i64};
+    use document::Document;
+    use typeck::check;
+
+    #[test]
+    fn encode_leb128() {
+        let mut out = Vec::new();
+        write_u32(&mut out, 624485);
+        write_i64(&mut out, -123456);
+        write_i64(&mut out, 63);
+        write_i64(&mut out, 64);
+        write_i64(&mut out, i64::min_value());
+        assert_eq!(out, vec![
+            0xE5, 0x8E, 0x26,
+            0xC0, 0xBB, 0x78,
+            0x3F,
+            0xC0, 0x00,
+            0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7F,
+        ]);
+    }
+
+    #[test]
+    fn export_functions() {
+        let document = Document::new("let double x = 2 * x\nlet s = \"a\" + \"b\"\ndouble 4");
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let module = emit_wasm(&ast, &check(&ast).unwrap()).unwrap();
+
+        assert_eq!(&module.to_binary()[..8], b"\0asm\x01\0\0\0");
+        assert_eq!(module.exports, vec![("double".to_owned(), 4), ("smac.main".to_owned(), 5)]);
+
+        let text = module.to_text();
+        assert!(text.contains("\n  (func (;4;) (type 3) (param i64) (result i64)\n    i64.const 2\n    local.get 0\n    i64.mul)\n"));
+        assert!(text.contains("\n  (export \"double\" (func 4))\n"));
+        assert!(text.ends_with("\\10\\00\\00\\00Division by zero\\0e\\00\\00\\00Stack overflow\\00\\00\\01\\00\\00\\00a\\00\\00\\00\\01\\00\\00\\00b\"))\n"));
+    }
+}
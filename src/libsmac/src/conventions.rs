@@ -0,0 +1,303 @@
+//! Naming conventions, which give the suffixes of names meaning.
+//!
+//! A function whose name ends in `?` is a predicate, and must
+//! return `bool`. One whose name ends in `!` has effects, and a
+//! function with effects must be named so. Arguments are passed
+//! by value, parameters cannot be assigned to and there is no
+//! I/O, so the only effect is assigning to a binding from outside
+//! the function. A function not named with `!` is pure, and calling
+//! a `!` function from one is reported too.
+//!
+//! Functions are those declared with `let f x =` and lambdas bound
+//! with `let`. Others belong to the function around them. Each rule
+//! is reported at the level `Conventions` gives it.
+
+use diagnostic::Diagnostic;
+use parser::node::{Node, NodeType, Visitor, walk_node};
+use typeck::{Type, Types};
+
+/// How a broken rule is reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// The level of each rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conventions {
+    /// Functions ending in `?` return `bool`.
+    pub predicates: Level,
+    /// Functions with effects end in `!`.
+    pub effects:    Level,
+    /// Functions not ending in `!` call none that do.
+    pub purity:     Level,
+}
+
+impl Default for Conventions {
+    fn default() -> Conventions {
+        Conventions {
+            predicates: Level::Deny,
+            effects:    Level::Deny,
+            purity:     Level::Warn,
+        }
+    }
+}
+
+impl Conventions {
+    /// The level of a rule, by name.
+    pub fn level_mut(&mut self, rule: &str) -> Option<&mut Level> {
+        match rule {
+            "predicates" => Some(&mut self.predicates),
+            "effects"    => Some(&mut self.effects),
+            "purity"     => Some(&mut self.purity),
+            _            => None,
+        }
+    }
+}
+
+/// Checks the names of the functions in a typed program,
+/// returning a diagnostic for each rule broken at a
+/// level other than `Allow`.
+pub fn check(nodes: &[Node], types: &Types, conventions: &Conventions) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        types:       types,
+        conventions: conventions,
+        scopes:      vec![Vec::new()],
+        functions:   Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    for node in nodes.iter() {
+        checker.visit_node(node)
+    }
+    checker.diagnostics
+}
+
+/// A named function being checked.
+struct Function {
+    name:  String,
+    /// The number of scopes around it.
+    outer: usize,
+}
+
+struct Checker<'a> {
+    types:       &'a Types,
+    conventions: &'a Conventions,
+    /// The names bound in each scope, innermost last.
+    scopes:      Vec<Vec<String>>,
+    /// The named functions around the node being checked.
+    functions:   Vec<Function>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn report(&mut self, level: Level, node: &Node, message: String) {
+        let position = node.span().start;
+        match level {
+            Level::Allow => (),
+            Level::Warn  => self.diagnostics.push(Diagnostic::warning(position, message)),
+            Level::Deny  => self.diagnostics.push(Diagnostic::new(position, message)),
+        }
+    }
+
+    fn scoped<F: FnOnce(&mut Self)>(&mut self, names: Vec<String>, f: F) {
+        self.scopes.push(names);
+        f(self);
+        self.scopes.pop();
+    }
+
+    /// Checks a named function of type `t`, which takes
+    /// `parameters` and sees the scopes around it.
+    fn function(&mut self, node: &Node, name: &str, t: Option<&Type>, parameters: &[String], body: &Node) {
+        if name.ends_with('?') {
+            match t {
+                Some(&Type::Function(_, ref result)) if **result != Type::Bool => {
+                    let message = format!("`{}` must return `bool`, since its name ends in `?`, but returns `{}`", name, result);
+                    let level = self.conventions.predicates;
+                    self.report(level, node, message)
+                },
+                _ => (),
+            }
+        }
+
+        self.functions.push(Function { name: name.to_owned(), outer: self.scopes.len() });
+        self.scoped(parameters.to_vec(), |checker| checker.visit_node(body));
+        self.functions.pop();
+    }
+
+    /// Checks a call of the function named `callee`.
+    fn call(&mut self, node: &Node, callee: &str) {
+        if !callee.ends_with('!') {
+            return
+        }
+        let message = match self.functions.last() {
+            Some(function) if !function.name.ends_with('!') => format!(
+                "`{}` calls `{}`, which has effects, but its name does not end in `!`",
+                function.name, callee,
+            ),
+            _ => return,
+        };
+        let level = self.conventions.purity;
+        self.report(level, node, message)
+    }
+
+    /// Checks an assignment to `target`, which is a
+    /// name, or a field or element of what one names.
+    fn assign(&mut self, target: &Node, node: &Node) {
+        let name = match *node.node_type() {
+            NodeType::Identifier(ref name) => name,
+            NodeType::Field(ref value, _) => return self.assign(target, value),
+            NodeType::Index(ref list, ref index) => {
+                self.visit_node(index);
+                return self.assign(target, list)
+            },
+            _ => return self.visit_node(node),
+        };
+
+        // Declared in a scope around the function
+        let scope = self.scopes.iter().rposition(|scope| scope.contains(name));
+        let message = match (self.functions.last(), scope) {
+            (Some(function), Some(scope)) if scope < function.outer && !function.name.ends_with('!') => format!(
+                "`{}` assigns to `{}` from outside it, so its name must end in `!`",
+                function.name, name,
+            ),
+            _ => return,
+        };
+        let level = self.conventions.effects;
+        self.report(level, target, message)
+    }
+}
+
+impl<'a> Visitor for Checker<'a> {
+    fn visit_node(&mut self, node: &Node) {
+        match *node.node_type() {
+            NodeType::Let(ref name, _, ref value) => {
+                match value.as_ref().map(|value| (value, value.node_type())) {
+                    Some((value, &NodeType::Lambda(ref parameters, ref body))) => {
+                        let t = self.types.of(value);
+                        self.function(node, name, t, parameters, body)
+                    },
+                    Some((value, _)) => self.visit_node(value),
+                    None             => (),
+                }
+                self.scopes.last_mut().unwrap().push(name.clone())
+            },
+            NodeType::Function(ref name, ref parameters, ref body) => {
+                // Functions see none of the names around them
+                let scopes = ::std::mem::replace(&mut self.scopes, Vec::new());
+                let t = self.types.function(name);
+                self.function(node, name, t, parameters, body);
+                self.scopes = scopes;
+            },
+            NodeType::Lambda(ref parameters, ref body) => {
+                self.scoped(parameters.clone(), |checker| checker.visit_node(body))
+            },
+            NodeType::Assign(ref target, ref value) | NodeType::CompoundAssign(_, ref target, ref value) => {
+                self.visit_node(value);
+                self.assign(target, target)
+            },
+            NodeType::Call(ref function, ref arguments) => {
+                if let NodeType::Identifier(ref name) = *function.node_type() {
+                    self.call(node, name)
+                }
+                self.visit_node(function);
+                for argument in arguments.iter() {
+                    self.visit_node(argument)
+                }
+            },
+            NodeType::Field(ref value, ref name) => {
+                // A method-style call
+                if self.types.function(name).is_some() {
+                    self.call(node, name)
+                }
+                self.visit_node(value)
+            },
+            NodeType::For(ref name, ref values, ref body) => {
+                self.visit_node(values);
+                self.scoped(vec![name.clone()], |checker| checker.visit_node(body))
+            },
+            NodeType::Match(ref value, ref arms) => {
+                self.visit_node(value);
+                for arm in arms.iter() {
+                    let mut names = Vec::new();
+                    arm.pattern.bindings(&mut names);
+                    self.scoped(names, |checker| checker.visit_node(&arm.body))
+                }
+            },
+            NodeType::Block(ref nodes) => {
+                self.scoped(Vec::new(), |checker| {
+                    for node in nodes.iter() {
+                        checker.visit_node(node)
+                    }
+                })
+            },
+            _ => walk_node(self, node),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, Conventions, Level};
+    use document::Document;
+    use typeck;
+
+    fn diagnostics(source: &str, conventions: &Conventions) -> Vec<String> {
+        let document = Document::new(source);
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let types = typeck::check(&ast).unwrap();
+        check(&ast, &types, conventions).iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn check_predicates() {
+        let source = "let even? n = n % 2 == 0\nlet half? n = n / 2\nlet small? = n -> n\nsmall? (half? 4)\neven? 2";
+        assert_eq!(diagnostics(source, &Conventions::default()), vec![
+            "error [ln 2, col 0]: `half?` must return `bool`, since its name ends in `?`, but returns `int`",
+            "error [ln 3, col 0]: `small?` must return `bool`, since its name ends in `?`, but returns `int`",
+        ]);
+    }
+
+    #[test]
+    fn check_effects() {
+        let source = concat!(
+            "let mut count = 0\n",
+            "let bump! = n -> count += n\n",
+            "let bump = n -> count += n\n",
+            "let local = n ->\n  let mut total = 0\n  for x in 0..n\n    total += x\n  total\n",
+        );
+        assert_eq!(diagnostics(source, &Conventions::default()), vec![
+            "error [ln 3, col 16]: `bump` assigns to `count` from outside it, so its name must end in `!`",
+        ]);
+    }
+
+    #[test]
+    fn check_purity() {
+        let source = concat!(
+            "let mut log = []\n",
+            "let record! = x -> log += [x]\n",
+            "let pure = x ->\n  record! x\n  x\n",
+            "let impure! = x -> record! x\n",
+            "let show! x = x\n",
+            "let shown x = x.show! + 1\n",
+            "record! 1\n",
+        );
+        assert_eq!(diagnostics(source, &Conventions::default()), vec![
+            "warning [ln 4, col 2]: `pure` calls `record!`, which has effects, but its name does not end in `!`",
+            "warning [ln 8, col 14]: `shown` calls `show!`, which has effects, but its name does not end in `!`",
+        ]);
+    }
+
+    #[test]
+    fn configure_levels() {
+        let source = "let half? n = n / 2\nlet mut n = 0\nlet set! = x -> n = x\nlet f = x -> set! x\nf 1";
+        let mut conventions = Conventions::default();
+        *conventions.level_mut("predicates").unwrap() = Level::Allow;
+        *conventions.level_mut("purity").unwrap() = Level::Deny;
+        assert!(conventions.level_mut("unknown").is_none());
+        assert_eq!(diagnostics(source, &conventions), vec![
+            "error [ln 4, col 13]: `f` calls `set!`, which has effects, but its name does not end in `!`",
+        ]);
+    }
+}
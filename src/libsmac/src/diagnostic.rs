@@ -0,0 +1,73 @@
+use lexer::token::TokenPosition;
+
+use std::fmt;
+
+/// How bad a problem is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// Stops compilation.
+    Error,
+    /// Reported, but compilation goes on.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Severity::Error   => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in the source,
+/// reported without stopping compilation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    position: TokenPosition,
+    message: String,
+    severity: Severity,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}: {}", self.severity, self.position, self.message)
+    }
+}
+
+impl Diagnostic {
+    pub fn new(position: TokenPosition, message: String) -> Diagnostic {
+        Diagnostic {
+            position: position,
+            message: message,
+            severity: Severity::Error,
+        }
+    }
+
+    pub fn warning(position: TokenPosition, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::new(position, message)
+        }
+    }
+
+    pub fn position(&self) -> &TokenPosition {
+        &self.position
+    }
+
+    pub fn position_mut(&mut self) -> &mut TokenPosition {
+        &mut self.position
+    }
+
+    pub fn message(&self) -> &String {
+        &self.message
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
@@ -0,0 +1,450 @@
+use diagnostic::Diagnostic;
+use lexer::lex_line;
+use lexer::flatten_chunks;
+use lexer::block_tree::{BlockTree, Branch, Chunk, ChunkValue};
+use lexer::token::{Token, TokenType, Kw, Span};
+use parser::grab_smaragdine_parser;
+use parser::node::{Node, Arm, VisitorMut, walk_node_mut, walk_pattern_mut};
+
+use std::mem;
+use std::ops::Range;
+
+/// A text edit, replacing a byte range of the source.
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub text:  String,
+}
+
+impl Edit {
+    pub fn new(range: Range<usize>, text: &str) -> Edit {
+        Edit {
+            range: range,
+            text:  text.to_owned(),
+        }
+    }
+}
+
+/// The lines touched by an edit, used to map
+/// untouched lines back to their old line numbers.
+struct LineEdit {
+    first:    usize,
+    old_last: usize,
+    new_last: usize,
+}
+
+impl LineEdit {
+    fn new(source: &str, edit: &Edit) -> LineEdit {
+        let first    = source[..edit.range.start].matches('\n').count();
+        let old_last = first + source[edit.range.start..edit.range.end].matches('\n').count();
+        let new_last = first + edit.text.matches('\n').count();
+
+        LineEdit {
+            first:    first,
+            old_last: old_last,
+            new_last: new_last,
+        }
+    }
+
+    /// Maps a range of new lines to the old lines
+    /// it came from, if no line in it was touched.
+    fn old_range(&self, start: usize, end: usize) -> Option<(usize, usize)> {
+        if end < self.first {
+            Some((start, end))
+        } else if start > self.new_last {
+            Some((start - self.new_last + self.old_last, end - self.new_last + self.old_last))
+        } else {
+            None
+        }
+    }
+}
+
+/// Top-level line together with its indented blocks,
+/// parsed as one unit.
+#[derive(Debug)]
+struct Group {
+    start:       usize,
+    end:         usize,
+    nodes:       Vec<Node>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Moves the spans of reused nodes to their new lines.
+struct Renumber {
+    from: usize,
+    to:   usize,
+}
+
+impl Renumber {
+    fn shift(&self, span: &mut Span) {
+        span.start.line = span.start.line - self.from + self.to;
+        span.end.line   = span.end.line - self.from + self.to;
+    }
+}
+
+impl VisitorMut for Renumber {
+    fn visit_node_mut(&mut self, node: &mut Node) {
+        self.shift(node.span_mut());
+        walk_node_mut(self, node)
+    }
+
+    fn visit_match_mut(&mut self, value: &mut Node, arms: &mut Vec<Arm>) {
+        self.visit_node_mut(value);
+        for arm in arms.iter_mut() {
+            self.shift(&mut arm.span);
+            walk_pattern_mut(self, &mut arm.pattern);
+            self.visit_node_mut(&mut arm.body)
+        }
+    }
+}
+
+/// A lexed and parsed source document which can be edited
+/// in place, only recomputing the affected lines and blocks.
+#[derive(Debug)]
+pub struct Document {
+    source: String,
+    root:   Branch<'static>,
+    groups: Vec<Group>,
+}
+
+impl Document {
+    pub fn new(source: &str) -> Document {
+        let mut document = Document {
+            source: source.to_owned(),
+            root:   Branch::new(Vec::new()),
+            groups: Vec::new(),
+        };
+        document.rebuild(None);
+        document
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The lexed block tree.
+    pub fn root(&self) -> &Branch<'static> {
+        &self.root
+    }
+
+    pub fn ast(&self) -> Vec<&Node> {
+        self.groups.iter().flat_map(|g| g.nodes.iter()).collect()
+    }
+
+    pub fn diagnostics(&self) -> Vec<&Diagnostic> {
+        self.groups.iter().flat_map(|g| g.diagnostics.iter()).collect()
+    }
+
+    /// Applies `edit`, reusing the tokens, blocks and nodes
+    /// of every line it does not touch.
+    ///
+    /// Panics if the edit range is out of bounds or
+    /// does not lie on char boundaries.
+    pub fn apply_edit(&mut self, edit: &Edit) {
+        let lines = LineEdit::new(&self.source, edit);
+
+        let mut source = String::with_capacity(self.source.len() + edit.text.len());
+        source.push_str(&self.source[..edit.range.start]);
+        source.push_str(&edit.text);
+        source.push_str(&self.source[edit.range.end..]);
+
+        self.source = source;
+        self.rebuild(Some(lines));
+    }
+
+    fn rebuild(&mut self, lines: Option<LineEdit>) {
+        let mut old_root   = mem::replace(&mut self.root, Branch::new(Vec::new()));
+        let mut old_groups = mem::replace(&mut self.groups, Vec::new());
+
+        let tree = {
+            let mut block_tree = BlockTree::new(&self.source, 0);
+            let indents = block_tree.collect_indents();
+            block_tree.tree(&indents)
+        };
+
+        self.root = relex_branch(&tree, &mut old_root, lines.as_ref());
+
+        let mut start = 0;
+        while start < self.root.value.len() {
+            let mut end = start + 1;
+            while end < self.root.value.len() {
+                match self.root.value[end].value() {
+                    &ChunkValue::Block(_)           => end += 1,
+                    // An `else` belongs to the `if` above it
+                    &ChunkValue::Tokens(ref tokens) if starts_with_else(tokens) => end += 1,
+                    _                               => break,
+                }
+            }
+
+            let chunks     = &self.root.value[start..end];
+            let first_line = chunks[0].line();
+            let last_line  = chunks[chunks.len() - 1].last_line();
+
+            let reused = lines.as_ref()
+                .and_then(|l| l.old_range(first_line, last_line))
+                .and_then(|(s, e)| old_groups.iter_mut().find(|g| g.start == s && g.end == e));
+
+            let group = match reused {
+                Some(old) => {
+                    let mut diagnostics = mem::replace(&mut old.diagnostics, Vec::new());
+                    for d in diagnostics.iter_mut() {
+                        let line = d.position().line;
+                        d.position_mut().line = line - old.start + first_line
+                    }
+
+                    let mut nodes    = mem::replace(&mut old.nodes, Vec::new());
+                    let mut renumber = Renumber { from: old.start, to: first_line };
+                    for n in nodes.iter_mut() {
+                        renumber.visit_node_mut(n)
+                    }
+
+                    Group {
+                        start:       first_line,
+                        end:         last_line,
+                        nodes:       nodes,
+                        diagnostics: diagnostics,
+                    }
+                },
+                None => {
+                    let mut parser = grab_smaragdine_parser(flatten_chunks(chunks));
+
+                    Group {
+                        start:       first_line,
+                        end:         last_line,
+                        nodes:       parser.by_ref().collect(),
+                        diagnostics: parser.take_diagnostics(),
+                    }
+                },
+            };
+
+            self.groups.push(group);
+
+            start = end
+        }
+    }
+}
+
+fn starts_with_else(tokens: &[Token]) -> bool {
+    match tokens.first() {
+        Some(token) => *token.token_type() == TokenType::Keyword(Kw::Else),
+        None        => false,
+    }
+}
+
+/// Lexes a source branch, taking tokens and blocks
+/// from `old` wherever `lines` leaves them untouched.
+fn relex_branch(branch: &Branch, old: &mut Branch<'static>, lines: Option<&LineEdit>) -> Branch<'static> {
+    let mut lexed_branch = Branch::new(Vec::new());
+
+    for c in branch.value.iter() {
+        let (first, last) = (c.line(), c.last_line());
+        let old_range     = lines.and_then(|l| l.old_range(first, last));
+
+        let reused = match (c.value(), old_range) {
+            (&ChunkValue::Source(_), Some((s, _))) => take_tokens(old, s).map(|t| Chunk::new(ChunkValue::Tokens(t), s)),
+            (&ChunkValue::Block(_), Some((s, e)))  => take_block(old, s, e).map(|b| Chunk::new(ChunkValue::Block(b), s)),
+            _                                      => None,
+        };
+
+        let chunk = match reused {
+            Some(mut chunk) => {
+                chunk.renumber(first);
+                chunk
+            },
+            None => match c.value() {
+                &ChunkValue::Source(s) => {
+                    let tokens = lex_line(s, first).into_iter().map(Token::into_owned).collect();
+                    Chunk::new(ChunkValue::Tokens(tokens), first)
+                },
+                &ChunkValue::Block(ref b) => Chunk::new(ChunkValue::Block(relex_branch(b, old, lines)), first),
+                _ => continue,
+            },
+        };
+
+        lexed_branch.value.push(chunk)
+    }
+
+    lexed_branch
+}
+
+/// Finds the chunk of `branch` which covers `line`,
+/// i.e. the last chunk starting on or before it.
+fn covering_chunk<'a, 'b>(branch: &'a mut Branch<'b>, line: usize) -> Option<&'a mut Chunk<'b>> {
+    branch.value.iter_mut().take_while(|c| c.line() <= line).last()
+}
+
+fn take_tokens<'a>(old: &mut Branch<'a>, line: usize) -> Option<Vec<Token<'a>>> {
+    match covering_chunk(old, line) {
+        Some(chunk) => {
+            let is_line = chunk.line() == line;
+            match chunk.value_mut() {
+                &mut ChunkValue::Tokens(ref mut t) if is_line => Some(mem::replace(t, Vec::new())),
+                &mut ChunkValue::Block(ref mut b)             => take_tokens(b, line),
+                _                                             => None,
+            }
+        },
+        None => None,
+    }
+}
+
+fn take_block<'a>(old: &mut Branch<'a>, start: usize, end: usize) -> Option<Branch<'a>> {
+    match covering_chunk(old, start) {
+        Some(chunk) => match chunk.value_mut() {
+            &mut ChunkValue::Block(ref mut b) => {
+                if b.lines() == Some((start, end)) {
+                    Some(mem::replace(b, Branch::new(Vec::new())))
+                } else {
+                    take_block(b, start, end)
+                }
+            },
+            _ => None,
+        },
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Document, Edit};
+    use lexer::{lex_branch, flatten_branch};
+    use lexer::block_tree::{BlockTree, Branch, ChunkValue};
+    use lexer::token::Token;
+    use parser::grab_smaragdine_parser;
+    use parser::node::{Node, NodeType, Arm, Visitor, walk_node, walk_block, walk_pattern};
+    use lexer::token::Span;
+
+    fn assert_from_scratch(document: &Document) {
+        let fresh = Document::new(document.source());
+        assert_eq!(document.root(), fresh.root());
+        assert_eq!(document.ast(), fresh.ast());
+        assert_eq!(document.diagnostics(), fresh.diagnostics());
+
+        let mut block_tree = BlockTree::new(document.source(), 0);
+        let indents        = block_tree.collect_indents();
+        let lexed_root     = lex_branch(&block_tree.tree(&indents));
+        let ast: Vec<Node> = grab_smaragdine_parser(flatten_branch(&lexed_root)).collect();
+        assert_eq!(document.root(), &lexed_root);
+        assert_eq!(document.ast(), ast.iter().collect::<Vec<_>>());
+
+        let spans = |b: &Branch| flatten_branch(b).iter().map(|t| *t.span()).collect::<Vec<_>>();
+        assert_eq!(spans(document.root()), spans(&lexed_root));
+
+        let mut node_spans = NodeSpans(Vec::new());
+        walk_block(&mut node_spans, &ast);
+        let mut document_spans = NodeSpans(Vec::new());
+        for n in document.ast() {
+            document_spans.visit_node(n)
+        }
+        assert_eq!(document_spans.0, node_spans.0);
+    }
+
+    struct NodeSpans(Vec<Span>);
+
+    impl Visitor for NodeSpans {
+        fn visit_node(&mut self, node: &Node) {
+            self.0.push(*node.span());
+            walk_node(self, node)
+        }
+
+        fn visit_match(&mut self, value: &Node, arms: &Vec<Arm>) {
+            self.visit_node(value);
+            for arm in arms.iter() {
+                self.0.push(arm.span);
+                walk_pattern(self, &arm.pattern);
+                self.visit_node(&arm.body)
+            }
+        }
+    }
+
+    fn edit_at(document: &mut Document, needle: &str, text: &str) {
+        let start = document.source().find(needle).unwrap();
+        document.apply_edit(&Edit::new(start..start + needle.len(), text));
+        assert_from_scratch(document);
+    }
+
+    const SOURCE: &'static str = "1\n  2\n  3\n    4\n5\n  6\n7\n";
+
+    #[test]
+    fn edit_within_line() {
+        let mut document = Document::new(SOURCE);
+        edit_at(&mut document, "3", "0x30 3.5");
+        edit_at(&mut document, "6", "'a' \"b\"");
+    }
+
+    #[test]
+    fn edit_inserting_lines() {
+        let mut document = Document::new(SOURCE);
+        edit_at(&mut document, "5", "5\n  true\n    false\n5");
+        edit_at(&mut document, "1\n", "\n\n1\n\n");
+    }
+
+    #[test]
+    fn edit_removing_lines() {
+        let mut document = Document::new(SOURCE);
+        edit_at(&mut document, "  3\n    4\n", "");
+        edit_at(&mut document, "5\n  6\n7", "8");
+    }
+
+    #[test]
+    fn edit_changing_indentation() {
+        let mut document = Document::new(SOURCE);
+        edit_at(&mut document, "  2", "2");
+        edit_at(&mut document, "7", "  7");
+        edit_at(&mut document, "    4", "4");
+    }
+
+    fn tokens_ptr(branch: &Branch<'static>, path: &[usize]) -> *const Token<'static> {
+        match branch.value[path[0]].value() {
+            &ChunkValue::Tokens(ref t) => t.as_ptr(),
+            &ChunkValue::Block(ref b)  => tokens_ptr(b, &path[1..]),
+            _                          => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn edit_with_syntax_errors() {
+        let mut document = Document::new(SOURCE);
+        edit_at(&mut document, "3", ") 3");
+        edit_at(&mut document, "6", "6 ]");
+        edit_at(&mut document, "1\n", ")\n1\n");
+        assert_eq!(document.diagnostics().len(), 3);
+        edit_at(&mut document, ") 3", "3");
+        assert_eq!(document.diagnostics().len(), 2);
+    }
+
+    #[test]
+    fn edit_above_match() {
+        let mut document = Document::new("1\nmatch x\n  0 -> 1\n  _ -> 2\n");
+        edit_at(&mut document, "1", "0\n1");
+
+        let lines = match *document.ast()[2].node_type() {
+            NodeType::Match(_, ref arms) => arms.iter().map(|arm| arm.span.start.line).collect::<Vec<_>>(),
+            _ => unreachable!(),
+        };
+        assert_eq!(lines, vec![4, 5]);
+    }
+
+    #[test]
+    fn edit_around_else() {
+        let mut document = Document::new("if true\n  1\nelse\n  2\n3");
+        assert!(document.diagnostics().is_empty());
+        assert_eq!(document.ast().len(), 2);
+
+        edit_at(&mut document, "2", "x");
+        edit_at(&mut document, "else", "else if false");
+        edit_at(&mut document, "3", "else\n  4");
+        assert_eq!(document.ast().len(), 1);
+    }
+
+    #[test]
+    fn edit_reuses_untouched_tokens() {
+        let mut document = Document::new(SOURCE);
+        let before       = tokens_ptr(document.root(), &[1, 2, 0]);
+        let after        = tokens_ptr(document.root(), &[4, 0]);
+
+        edit_at(&mut document, "5", "5\n5");
+
+        assert_eq!(tokens_ptr(document.root(), &[1, 2, 0]), before);
+        assert_eq!(tokens_ptr(document.root(), &[5, 0]), after);
+    }
+}
@@ -0,0 +1,785 @@
+use diagnostic::Diagnostic;
+use lexer::token::{Op, TokenPosition};
+use parser::node::{self, Node, NodeType, Pattern, Arm};
+use value::{self, Value, Closure};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// How deep calls may nest before the interpreter gives up.
+pub const MAX_DEPTH: usize = 1000;
+
+/// How much of the native stack calls may use before the
+/// interpreter gives up, leaving headroom on the 2 MiB
+/// stacks threads get by default. Unoptimized builds use
+/// far more of it per call.
+const STACK_BUDGET: usize = 1 << 20;
+
+/// Runs calls somewhere other than the tree walker,
+/// such as in native code.
+pub trait Dispatch {
+    /// Runs a call to a top-level function, or
+    /// returns `None` to leave it to the interpreter.
+    fn call(&mut self, name: &str, arguments: &[Value], position: TokenPosition)
+        -> Option<Result<Value, Diagnostic>>;
+}
+
+/// A top-level function, which has a name, or a lambda,
+/// which has the variables around it that it captures.
+struct Function {
+    name:       Option<String>,
+    parameters: Vec<String>,
+    captures:   Vec<String>,
+    body:       Node,
+}
+
+/// A jump out of the body of a loop, on its way to the
+/// loop while the nodes around it unwind.
+enum Flow {
+    Break(Value),
+    Continue,
+}
+
+/// Evaluates nodes directly, walking the tree.
+///
+/// A block evaluates to its last node, and a program to
+/// its last top-level node. Bindings, assignments and
+/// definitions evaluate to unit.
+pub struct Interpreter<'d> {
+    /// The top-level functions, then the lambdas evaluated
+    /// so far, which closures are numbered by.
+    functions: Vec<Rc<Function>>,
+    /// The number of each top-level function.
+    names:     HashMap<String, usize>,
+    /// The fields of each record type, in the order they are declared.
+    records:   HashMap<String, Vec<String>>,
+    /// The enum type of each variant and the length of its payload.
+    variants:  HashMap<String, (String, usize)>,
+    /// The number of each lambda, by the address of its node.
+    numbers:   HashMap<usize, usize>,
+    /// The scopes of the function being run, innermost last.
+    /// Variables captured by closures hold a `Value::Cell`.
+    scopes:    Vec<Vec<(String, Value)>>,
+    /// The `break` or `continue` being run, if any. It travels
+    /// up as an error that the nearest loop takes back.
+    flow:      Option<Flow>,
+    depth:     usize,
+    /// The native stack address of the outermost call.
+    stack:     usize,
+    dispatch:  Option<&'d mut Dispatch>,
+}
+
+impl<'d> Interpreter<'d> {
+    pub fn new() -> Interpreter<'d> {
+        Interpreter {
+            functions: Vec::new(),
+            names:     HashMap::new(),
+            records:   HashMap::new(),
+            variants:  HashMap::new(),
+            numbers:   HashMap::new(),
+            scopes:    vec![Vec::new()],
+            flow:      None,
+            depth:     0,
+            stack:     0,
+            dispatch:  None,
+        }
+    }
+
+    /// An interpreter offering every call to `dispatch` first.
+    pub fn with_dispatch(dispatch: &'d mut Dispatch) -> Interpreter<'d> {
+        let mut interpreter = Interpreter::new();
+        interpreter.dispatch = Some(dispatch);
+        interpreter
+    }
+
+    pub fn run(&mut self, nodes: &[Node]) -> Result<Value, Diagnostic> {
+        self.functions.clear();
+        self.names.clear();
+        self.records.clear();
+        self.variants.clear();
+        self.numbers.clear();
+        self.scopes = vec![Vec::new()];
+        self.flow   = None;
+        self.depth  = 0;
+
+        // Functions and types can be used before their definition
+        for node in nodes.iter() {
+            match *node.node_type() {
+                NodeType::Function(ref name, ref parameters, ref body) => {
+                    self.names.insert(name.clone(), self.functions.len());
+                    self.functions.push(Rc::new(Function {
+                        name:       Some(name.clone()),
+                        parameters: parameters.clone(),
+                        captures:   Vec::new(),
+                        body:       (**body).clone(),
+                    }));
+                },
+                NodeType::Record(ref name, ref fields) => {
+                    self.records.insert(name.clone(), fields.iter().map(|&(ref f, _)| f.clone()).collect());
+                },
+                NodeType::Enum(ref name, ref variants) => {
+                    for &(ref variant, ref payload) in variants.iter() {
+                        self.variants.insert(variant.clone(), (name.clone(), payload.len()));
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        let mut value = Value::Unit;
+        for node in nodes.iter() {
+            value = match *node.node_type() {
+                NodeType::Function(..) | NodeType::Record(..) | NodeType::Enum(..) => Value::Unit,
+                _ => self.eval(node)?,
+            }
+        }
+        Ok(value)
+    }
+
+    pub fn eval(&mut self, node: &Node) -> Result<Value, Diagnostic> {
+        // Each kind of node is evaluated in its own method, which
+        // keeps this frame small since calls recurse through it
+        match *node.node_type() {
+            NodeType::StringLiteral(ref value)  => Ok(Value::String(value.clone())),
+            NodeType::CharLiteral(value)        => Ok(Value::Char(value)),
+            NodeType::IntLiteral(value, _)      => Ok(Value::Int(value as i64)),
+            NodeType::FloatLiteral(value)       => Ok(Value::Float(value)),
+            NodeType::BooleanLiteral(value)     => Ok(Value::Boolean(value)),
+            NodeType::Identifier(ref name) => {
+                if let Some(slot) = self.lookup(name) {
+                    return Ok(read(slot))
+                }
+                match (self.variants.get(name), self.names.get(name)) {
+                    (Some(&(ref e, 0)), _) => Ok(Value::Variant(e.clone(), name.clone(), Vec::new())),
+                    (_, Some(&number))     => Ok(Value::Closure(Rc::new(self.closure(number)))),
+                    _                      => Err(error(node, format!("Unknown name `{}`", name))),
+                }
+            },
+            NodeType::Unary(op, ref operand) => {
+                let operand = self.eval(operand)?;
+                value::unary(op, operand).map_err(|message| error(node, message))
+            },
+            NodeType::Binary(op, ref left, ref right)          => self.eval_binary(node, op, left, right),
+            NodeType::Call(ref function, ref arguments)        => self.eval_call(node, function, arguments),
+            NodeType::Field(ref value, ref name) => {
+                let value  = self.eval(value)?;
+                let number = match self.names.get(name) {
+                    // Without such a field, `x.f` is `f x`
+                    Some(&number) if value::field(&value, name).is_err() => number,
+                    _ => return value::field(&value, name).map(Value::clone).map_err(|message| error(node, message)),
+                };
+                let closure = self.closure(number);
+                self.apply(node, &closure, vec![value])
+            },
+            NodeType::List(ref elements) => {
+                let mut values = Vec::with_capacity(elements.len());
+                for element in elements.iter() {
+                    values.push(self.eval(element)?)
+                }
+                Ok(Value::List(values))
+            },
+            NodeType::Index(ref list, ref index) => {
+                let list  = self.eval(list)?;
+                let index = self.eval(index)?;
+                value::index(&list, &index).map(Value::clone).map_err(|message| error(node, message))
+            },
+            NodeType::If(ref condition, ref then, ref otherwise) => self.eval_if(condition, then, otherwise),
+            NodeType::Let(ref name, _, ref value) => {
+                // A binding without a value is assigned before it is read
+                let value = match *value {
+                    Some(ref value) => self.eval(value)?,
+                    None            => Value::Unit,
+                };
+                self.scopes.last_mut().unwrap().push((name.clone(), value));
+                Ok(Value::Unit)
+            },
+            NodeType::Function(..) => {
+                Err(error(node, "Functions can only be defined at the top level".to_owned()))
+            },
+            NodeType::Lambda(ref parameters, ref body) => self.eval_lambda(node, parameters, body),
+            NodeType::Record(..) | NodeType::Enum(..) => {
+                Err(error(node, "Types can only be declared at the top level".to_owned()))
+            },
+            NodeType::Tuple(ref elements) => {
+                let mut values = Vec::with_capacity(elements.len());
+                for element in elements.iter() {
+                    values.push(self.eval(element)?)
+                }
+                Ok(Value::Tuple(values))
+            },
+            NodeType::Match(ref value, ref arms) => self.eval_match(node, value, arms),
+            NodeType::While(ref condition, ref body) => self.eval_while(condition, body),
+            NodeType::For(ref name, ref values, ref body) => self.eval_for(name, values, body),
+            NodeType::Break(ref value) => {
+                let value = match *value {
+                    Some(ref value) => self.eval(value)?,
+                    None            => Value::Unit,
+                };
+                self.flow = Some(Flow::Break(value));
+                Err(error(node, "`break` outside of a loop".to_owned()))
+            },
+            NodeType::Continue => {
+                self.flow = Some(Flow::Continue);
+                Err(error(node, "`continue` outside of a loop".to_owned()))
+            },
+            NodeType::With(ref value, ref fields) => self.eval_with(node, value, fields),
+            NodeType::Assign(ref target, ref value) => self.eval_assign(node, None, target, value),
+            NodeType::CompoundAssign(op, ref target, ref value) => self.eval_assign(node, Some(op), target, value),
+            NodeType::Block(ref nodes)              => self.eval_block(nodes),
+            NodeType::Error => {
+                Err(Diagnostic::new(node.span().start, "Cannot run code with syntax errors".to_owned()))
+            },
+        }
+    }
+
+    fn eval_binary(&mut self, node: &Node, op: Op, left: &Node, right: &Node) -> Result<Value, Diagnostic> {
+        let l = self.eval(left)?;
+        match (op, l) {
+            (Op::And, Value::Boolean(false)) => Ok(Value::Boolean(false)),
+            (Op::Or, Value::Boolean(true))   => Ok(Value::Boolean(true)),
+            (Op::And, Value::Boolean(_)) | (Op::Or, Value::Boolean(_)) => {
+                let r = self.eval(right)?;
+                self.expect_bool(right, r).map(Value::Boolean)
+            },
+            (Op::And, l) | (Op::Or, l) => self.expect_bool(left, l).map(Value::Boolean),
+            (op, l) => {
+                let r = self.eval(right)?;
+                value::binary(op, l, r).map_err(|message| error(node, message))
+            },
+        }
+    }
+
+    fn eval_call(&mut self, node: &Node, function: &Node, arguments: &[Node]) -> Result<Value, Diagnostic> {
+        // Anything but the name of a function, record or variant
+        // evaluates to the closure called, before its arguments
+        let closure = match *function.node_type() {
+            NodeType::Identifier(ref name) if self.lookup(name).is_none() => None,
+            _ => match self.eval(function)? {
+                Value::Closure(closure) => Some(closure),
+                value => return Err(error(function, format!("Cannot call `{}`", value.type_name()))),
+            },
+        };
+
+        let mut values = Vec::with_capacity(arguments.len());
+        for argument in arguments.iter() {
+            values.push(self.eval(argument)?)
+        }
+        match closure {
+            Some(closure) => self.apply(node, &closure, values),
+            None          => self.call(node, function, values),
+        }
+    }
+
+    /// Makes a closure of a lambda, sharing the variables
+    /// it captures with the scopes around it.
+    fn eval_lambda(&mut self, node: &Node, parameters: &[String], body: &Node) -> Result<Value, Diagnostic> {
+        let key = node as *const Node as usize;
+        let number = match self.numbers.get(&key) {
+            Some(&number) => number,
+            None => {
+                // The names that are variables here, rather than
+                // functions and variants, are the same every time
+                let captures = node::captures(parameters, body).into_iter()
+                    .filter(|name| self.lookup(name).is_some())
+                    .collect();
+                self.functions.push(Rc::new(Function {
+                    name:       None,
+                    parameters: parameters.to_vec(),
+                    captures:   captures,
+                    body:       body.clone(),
+                }));
+                self.numbers.insert(key, self.functions.len() - 1);
+                self.functions.len() - 1
+            },
+        };
+
+        let lambda   = self.functions[number].clone();
+        let captured = lambda.captures.iter().map(|name| self.capture(name)).collect();
+        Ok(Value::Closure(Rc::new(Closure {
+            function:  number,
+            arity:     parameters.len(),
+            captured:  captured,
+            arguments: Vec::new(),
+        })))
+    }
+
+    /// Shares a variable with a closure, moving it
+    /// into a cell the first time it is captured.
+    fn capture(&mut self, name: &str) -> Rc<RefCell<Value>> {
+        let slot = self.lookup(name).unwrap();
+        if let Value::Cell(ref cell) = *slot {
+            return cell.clone()
+        }
+        let cell = Rc::new(RefCell::new(::std::mem::replace(slot, Value::Unit)));
+        *slot = Value::Cell(cell.clone());
+        cell
+    }
+
+    fn eval_if(&mut self, condition: &Node, then: &Node, otherwise: &Option<Box<Node>>) -> Result<Value, Diagnostic> {
+        let c = self.eval(condition)?;
+        if self.expect_bool(condition, c)? {
+            let value = self.eval(then)?;
+            Ok(if otherwise.is_some() { value } else { Value::Unit })
+        } else {
+            match *otherwise {
+                Some(ref otherwise) => self.eval(otherwise),
+                None                => Ok(Value::Unit),
+            }
+        }
+    }
+
+    fn eval_while(&mut self, condition: &Node, body: &Node) -> Result<Value, Diagnostic> {
+        loop {
+            let c = self.eval(condition)?;
+            if !self.expect_bool(condition, c)? {
+                return Ok(Value::Unit)
+            }
+            if let Some(value) = self.eval_body(body)? {
+                return Ok(value)
+            }
+        }
+    }
+
+    fn eval_for(&mut self, name: &str, values: &Node, body: &Node) -> Result<Value, Diagnostic> {
+        // Ranges are counted through rather than built as lists
+        if let NodeType::Binary(op, ref start, ref end) = *values.node_type() {
+            if op == Op::Range || op == Op::RangeInclusive {
+                let (start, end) = match (self.eval(start)?, self.eval(end)?) {
+                    (Value::Int(start), Value::Int(end)) => (start, end),
+                    (start, end) => return value::binary(op, start, end).map_err(|message| error(values, message)),
+                };
+                let range: Box<Iterator<Item = i64>> = if op == Op::Range {
+                    Box::new(start..end)
+                } else {
+                    Box::new(start..=end)
+                };
+                for i in range {
+                    if self.eval_step(name, Value::Int(i), body)? {
+                        break
+                    }
+                }
+                return Ok(Value::Unit)
+            }
+        }
+
+        let elements = match self.eval(values)? {
+            Value::List(elements) => elements,
+            value => return Err(error(values, format!("Cannot loop over `{}`", value.type_name()))),
+        };
+        for element in elements.into_iter() {
+            if self.eval_step(name, element, body)? {
+                break
+            }
+        }
+        Ok(Value::Unit)
+    }
+
+    /// Runs the body of a `for` loop with `name` bound
+    /// to `value`, and tells whether it broke out.
+    fn eval_step(&mut self, name: &str, value: Value, body: &Node) -> Result<bool, Diagnostic> {
+        self.scopes.push(vec![(name.to_owned(), value)]);
+        let result = self.eval_body(body);
+        self.scopes.pop();
+        result.map(|value| value.is_some())
+    }
+
+    /// Runs the body of a loop once, giving the value it
+    /// broke out with, if it did.
+    fn eval_body(&mut self, body: &Node) -> Result<Option<Value>, Diagnostic> {
+        match self.eval(body) {
+            Ok(_) => Ok(None),
+            Err(diagnostic) => match self.flow.take() {
+                Some(Flow::Break(value)) => Ok(Some(value)),
+                Some(Flow::Continue)     => Ok(None),
+                None                     => Err(diagnostic),
+            },
+        }
+    }
+
+    fn eval_with(&mut self, node: &Node, value: &Node, fields: &[(String, Node)]) -> Result<Value, Diagnostic> {
+        let mut record = self.eval(value)?;
+        for &(ref name, ref new) in fields.iter() {
+            let new = self.eval(new)?;
+            *value::field_mut(&mut record, name).map_err(|message| error(node, message))? = new
+        }
+        Ok(record)
+    }
+
+    /// Assigns to `target`, or applies `op` to it and the value
+    /// if given, evaluating the target's indices once.
+    fn eval_assign(&mut self, node: &Node, op: Option<Op>, target: &Node, value: &Node) -> Result<Value, Diagnostic> {
+        let value = self.eval(value)?;
+        let mut indices = Vec::new();
+        self.eval_indices(target, &mut indices)?;
+
+        let variable = variable(target);
+        let slot = match *variable.node_type() {
+            NodeType::Identifier(ref name) => match self.lookup(name) {
+                Some(slot) => slot,
+                None       => return Err(error(variable, format!("Unknown name `{}`", name))),
+            },
+            _ => return Err(error(target, "Invalid assignment target".to_owned())),
+        };
+        let update = |place: &mut Value| -> Result<(), Diagnostic> {
+            *place = match op {
+                Some(op) => value::binary(op, place.clone(), value).map_err(|message| error(node, message))?,
+                None     => value,
+            };
+            Ok(())
+        };
+        match *slot {
+            Value::Cell(ref cell) => update(place(&mut cell.borrow_mut(), target, &mut indices.into_iter())?)?,
+            ref mut slot          => update(place(slot, target, &mut indices.into_iter())?)?,
+        }
+        Ok(Value::Unit)
+    }
+
+    /// Evaluates the indices in an assignment target,
+    /// outermost first.
+    fn eval_indices(&mut self, target: &Node, indices: &mut Vec<Value>) -> Result<(), Diagnostic> {
+        match *target.node_type() {
+            NodeType::Field(ref value, _) => self.eval_indices(value, indices),
+            NodeType::Index(ref list, ref index) => {
+                self.eval_indices(list, indices)?;
+                indices.push(self.eval(index)?);
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn eval_match(&mut self, node: &Node, value: &Node, arms: &[Arm]) -> Result<Value, Diagnostic> {
+        let value = self.eval(value)?;
+        for arm in arms.iter() {
+            let mut scope = Vec::new();
+            if self.matches(&arm.pattern, &value, &mut scope)? {
+                self.scopes.push(scope);
+                let result = self.eval(&arm.body);
+                self.scopes.pop();
+                return result
+            }
+        }
+        Err(error(node, format!("No arm matches `{}`", value)))
+    }
+
+    /// Whether a pattern matches a value, binding its names in `scope`.
+    fn matches(&mut self, pattern: &Pattern, value: &Value, scope: &mut Vec<(String, Value)>) -> Result<bool, Diagnostic> {
+        let parts = match (pattern, value) {
+            (&Pattern::Wildcard, _) => return Ok(true),
+            (&Pattern::Binding(ref name), _) => {
+                scope.push((name.clone(), value.clone()));
+                return Ok(true)
+            },
+            (&Pattern::Literal(ref literal), _) => {
+                let literal = self.eval(literal)?;
+                return Ok(literal == *value)
+            },
+            (&Pattern::Tuple(ref patterns), &Value::Tuple(ref values)) => (patterns, values),
+            (&Pattern::Variant(ref name, ref patterns), &Value::Variant(_, ref variant, ref values)) => {
+                if name != variant {
+                    return Ok(false)
+                }
+                (patterns, values)
+            },
+            _ => return Ok(false),
+        };
+        if parts.0.len() != parts.1.len() {
+            return Ok(false)
+        }
+        for (pattern, value) in parts.0.iter().zip(parts.1.iter()) {
+            if !self.matches(pattern, value, scope)? {
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+
+    fn eval_block(&mut self, nodes: &[Node]) -> Result<Value, Diagnostic> {
+        self.scopes.push(Vec::new());
+        let mut result = Ok(Value::Unit);
+        for node in nodes.iter() {
+            result = self.eval(node);
+            if result.is_err() {
+                break
+            }
+        }
+        self.scopes.pop();
+        result
+    }
+
+    fn call(&mut self, node: &Node, function: &Node, arguments: Vec<Value>) -> Result<Value, Diagnostic> {
+        let name = match *function.node_type() {
+            NodeType::Identifier(ref name) => name,
+            _                              => unreachable!(),
+        };
+        match self.names.get(name) {
+            Some(&number) => {
+                let closure = self.closure(number);
+                self.apply(node, &closure, arguments)
+            },
+            None => self.construct(function, name, arguments),
+        }
+    }
+
+    /// A top-level function as a value.
+    fn closure(&self, number: usize) -> Closure {
+        Closure {
+            function:  number,
+            arity:     self.functions[number].parameters.len(),
+            captured:  Vec::new(),
+            arguments: Vec::new(),
+        }
+    }
+
+    /// Applies a closure to arguments. Given too few, it makes a
+    /// closure of them, and given too many, it applies what the
+    /// function returns to the rest.
+    fn apply(&mut self, node: &Node, closure: &Closure, mut arguments: Vec<Value>) -> Result<Value, Diagnostic> {
+        if arguments.len() < closure.missing() {
+            return Ok(Value::Closure(Rc::new(closure.partial(arguments))))
+        }
+
+        let rest   = arguments.split_off(closure.missing());
+        let result = self.invoke(node, closure, arguments)?;
+        if rest.is_empty() {
+            return Ok(result)
+        }
+        match result {
+            Value::Closure(next) => self.apply(node, &next, rest),
+            value => Err(error(node, format!("Cannot call `{}`", value.type_name()))),
+        }
+    }
+
+    /// Calls the function of a closure with the last of its
+    /// arguments, with the variables it captured in a scope
+    /// around its parameters.
+    fn invoke(&mut self, node: &Node, closure: &Closure, last: Vec<Value>) -> Result<Value, Diagnostic> {
+        let function = self.functions[closure.function].clone();
+        let mut arguments = closure.arguments.clone();
+        arguments.extend(last);
+
+        if let Some(ref name) = function.name {
+            if let Some(ref mut dispatch) = self.dispatch {
+                if let Some(result) = dispatch.call(name, &arguments, node.span().start) {
+                    return result
+                }
+            }
+        }
+
+        let captured = function.captures.iter().cloned()
+            .zip(closure.captured.iter().map(|cell| Value::Cell(cell.clone())))
+            .collect();
+        let scope = function.parameters.iter().cloned().zip(arguments.into_iter()).collect();
+        self.enter(node, vec![captured, scope], &function.body)
+    }
+
+    /// Runs the body of a function in its own scopes.
+    fn enter(&mut self, node: &Node, scopes: Vec<Vec<(String, Value)>>, body: &Node) -> Result<Value, Diagnostic> {
+        let here = &scopes as *const _ as usize;
+        if self.depth == 0 {
+            self.stack = here
+        }
+        if self.depth == MAX_DEPTH || self.stack.saturating_sub(here) > STACK_BUDGET {
+            return Err(error(node, "Stack overflow".to_owned()))
+        }
+
+        let scopes = ::std::mem::replace(&mut self.scopes, scopes);
+        self.depth += 1;
+        let result = self.eval(body);
+        self.depth -= 1;
+        self.scopes = scopes;
+        // A loop around the call cannot be left from inside it
+        self.flow = None;
+        result
+    }
+
+    /// Builds a record from the values of its fields,
+    /// or a variant from its payload.
+    fn construct(&mut self, function: &Node, name: &str, arguments: Vec<Value>) -> Result<Value, Diagnostic> {
+        let fields = match self.records.get(name) {
+            Some(fields) => fields,
+            None => match self.variants.get(name) {
+                Some(&(ref e, length)) => {
+                    if length != arguments.len() {
+                        return Err(error(function, format!("`{}` takes {} arguments, found {}", name, length, arguments.len())))
+                    }
+                    return Ok(Value::Variant(e.clone(), name.to_owned(), arguments))
+                },
+                None => return Err(error(function, format!("Unknown name `{}`", name))),
+            },
+        };
+        if fields.len() != arguments.len() {
+            return Err(error(function, format!("`{}` takes {} arguments, found {}", name, fields.len(), arguments.len())))
+        }
+        Ok(Value::Record(name.to_owned(), fields.iter().cloned().zip(arguments.into_iter()).collect()))
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<&mut Value> {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(&mut (_, ref mut value)) = scope.iter_mut().rev().find(|&&mut (ref n, _)| n == name) {
+                return Some(value)
+            }
+        }
+        None
+    }
+
+    fn expect_bool(&self, node: &Node, value: Value) -> Result<bool, Diagnostic> {
+        match value {
+            Value::Boolean(value) => Ok(value),
+            value => Err(error(node, format!("Expected `bool`, found `{}`", value.type_name()))),
+        }
+    }
+}
+
+fn error(node: &Node, message: String) -> Diagnostic {
+    Diagnostic::new(node.span().start, message)
+}
+
+/// The value of a variable, through the cell
+/// holding it if a closure captured it.
+fn read(slot: &Value) -> Value {
+    match *slot {
+        Value::Cell(ref cell) => cell.borrow().clone(),
+        ref value             => value.clone(),
+    }
+}
+
+/// The variable an assignment target writes to part of.
+fn variable(target: &Node) -> &Node {
+    match *target.node_type() {
+        NodeType::Field(ref value, _) | NodeType::Index(ref value, _) => variable(value),
+        _ => target,
+    }
+}
+
+/// The field or element of `variable` an assignment
+/// writes to, or the variable itself, given the values
+/// of its indices.
+fn place<'v, I>(variable: &'v mut Value, target: &Node, indices: &mut I) -> Result<&'v mut Value, Diagnostic>
+    where I: Iterator<Item = Value>
+{
+    match *target.node_type() {
+        NodeType::Field(ref value, ref name) => {
+            let record = place(variable, value, indices)?;
+            value::field_mut(record, name).map_err(|message| error(target, message))
+        },
+        NodeType::Index(ref list, _) => {
+            let list  = place(variable, list, indices)?;
+            let index = indices.next().unwrap();
+            value::index_mut(list, &index).map_err(|message| error(target, message))
+        },
+        _ => Ok(variable),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Interpreter;
+    use document::Document;
+    use value::Value;
+
+    fn run(source: &str) -> Result<Value, String> {
+        let document = Document::new(source);
+        assert!(document.diagnostics().is_empty(), "{:?}", document.diagnostics());
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        Interpreter::new().run(&ast).map_err(|d| format!("{} {}", d.position(), d.message()))
+    }
+
+    #[test]
+    fn run_functions_and_scopes() {
+        assert_eq!(run("let r = fact 10\nlet fact n =\n  if n < 2\n    1\n  else\n    n * fact (n - 1)\nr"),
+                   Ok(Value::Int(3628800)));
+        assert_eq!(run("let x = 1\nif true\n  let x = 2\n  x = x + 1\nx"), Ok(Value::Int(1)));
+        assert_eq!(run("let x = 1\nif true\n  x = x + 1\nx"), Ok(Value::Int(2)));
+        assert_eq!(run("let s = \"a\" + \"b\"\ns == \"ab\" && 1 != 2 || 1 / 0 == 0"), Ok(Value::Boolean(true)));
+    }
+
+    #[test]
+    fn report_runtime_errors() {
+        assert_eq!(run("let f x = 10 / x\n1 + f 0"), Err("[ln 1, col 10] Division by zero".to_owned()));
+        assert_eq!(run("let f x = f x\nf 1"), Err("[ln 1, col 10] Stack overflow".to_owned()));
+        assert_eq!(run("let p = 1\np.x"), Err("[ln 2, col 0] `int` has no field `x`".to_owned()));
+        assert_eq!(run("let s = \"a\"\ns.len = 2"), Err("[ln 2, col 0] `string` has no field `len`".to_owned()));
+        assert_eq!(run("let xs = [1, 2]\n1 + xs[2]"), Err("[ln 2, col 4] Index 2 is out of bounds for a list of length 2".to_owned()));
+        assert_eq!(run("let xs = [[1]]\nxs[0][-1] = 2"), Err("[ln 2, col 0] Index -1 is out of bounds for a list of length 1".to_owned()));
+    }
+
+    #[test]
+    fn read_and_write_lists() {
+        assert_eq!(run("let xs = [[1, 2], [3]]\nlet ys = xs\nxs[1][0] = xs[0][1] * 10\nxs = xs + [[]]\n[xs, ys]").map(|v| v.to_string()),
+                   Ok("[[[1, 2], [20], []], [[1, 2], [3]]]".to_owned()));
+    }
+
+    #[test]
+    fn read_and_write_fields() {
+        let source = concat!(
+            "type Point\n  x: int\n  y: int\n",
+            "type Line\n  start: Point\n  width: int\n",
+            "let line = Line (Point 1 2) 3\nlet copy = line\n",
+            "line.start.x = line.width + 1\n(line.start).y = 0\n",
+            "let moved = copy with width = 5, start = (copy.start with y = 7)\n",
+            "[line, copy, moved]",
+        );
+        assert_eq!(run(source).map(|v| v.to_string()), Ok(concat!(
+            "[Line { start: Point { x: 4, y: 0 }, width: 3 }, Line { start: Point { x: 1, y: 2 }, width: 3 }, ",
+            "Line { start: Point { x: 1, y: 7 }, width: 5 }]",
+        ).to_owned()));
+        assert_eq!(run("type P\n  x: int\nP 1 == P 1 && P 1 != P 2"), Ok(Value::Boolean(true)));
+    }
+
+    #[test]
+    fn run_loops() {
+        let source = concat!(
+            "let total = 0\nfor i in 0..10\n  if i % 2 == 0\n    continue\n  if i > 7\n    break\n  total = total + i\n",
+            "let n = 0\nlet found =\n  while true\n    n = n + 1\n    if n * n > 50\n      break n\n",
+            "let xs = []\nfor x in [1, 2, 3]\n  for y in x..=3\n    xs = xs + [x * y]\n",
+            "while n > 0\n  n = n - 4\n",
+            "[total, found, n, xs[5]] + xs",
+        );
+        assert_eq!(run(source).map(|v| v.to_string()), Ok("[16, 8, 0, 9, 1, 2, 3, 4, 6, 9]".to_owned()));
+        assert_eq!(run("let f x =\n  break\nwhile true\n  f 1"), Err("[ln 2, col 2] `break` outside of a loop".to_owned()));
+        assert_eq!(run("for c in 'a'..2\n  c"), Err("[ln 1, col 9] Cannot apply `..` to `char` and `int`".to_owned()));
+    }
+
+    #[test]
+    fn match_variants_and_tuples() {
+        let source = concat!(
+            "type Shape\n  Circle int\n  Rect int int\n  Dot\n",
+            "let area s =\n  match s\n    Circle r -> 3 * r * r\n    Rect w h -> w * h\n    Dot -> 0\n",
+            "let pair = (area (Rect 2 3), Dot)\n",
+            "match pair\n  (6, Dot) -> [area (Circle 1), area Dot]\n  _ -> []",
+        );
+        assert_eq!(run(source).map(|v| v.to_string()), Ok("[3, 0]".to_owned()));
+        assert_eq!(run("type T\n  A int\n  B\nmatch A 2\n  A 1 -> 0\n  B -> 1"),
+                   Err("[ln 4, col 0] No arm matches `A(2)`".to_owned()));
+    }
+
+    #[test]
+    fn capture_variables_in_closures() {
+        let source = concat!(
+            "let counter start =\n  let count = start\n  step ->\n    count = count + step\n    count\n",
+            "let next = counter 10\nnext 1\nnext 2\n",
+            "let adder = x -> y -> x + y\nlet add2 = adder 2\n",
+            "let fs = []\nfor i in 0..3\n  fs = fs + [x -> x * 10 + i]\n",
+            "let shared = 0\nlet bump = _ -> shared = shared + 1\nbump 0\nbump 0\n",
+            "[next 3, add2 5, (fs[0]) 1, (fs[2]) 1, shared]",
+        );
+        assert_eq!(run(source).map(|v| v.to_string()), Ok("[16, 7, 10, 12, 2]".to_owned()));
+        assert_eq!(run("let f = x -> x
+f 1 2"), Err("[ln 2, col 0] Cannot call `int`".to_owned()));
+        assert_eq!(run("let x = 1
+x 2"), Err("[ln 2, col 0] Cannot call `int`".to_owned()));
+    }
+    #[test]
+    fn apply_functions_partially() {
+        let source = concat!(
+            "let add x y z = x + y + z\nlet twice f x = f (f x)\nlet make x = y -> x * y\n",
+            "let add1 = add 1\nlet add3 = add1 2\n",
+            "let fs = [add 0 0, add1 1, twice (* 2), (10 -), (+ 1)]\n",
+            "[add3 4, (fs[0]) 1, (fs[1]) 1, (fs[2]) 3, (fs[3]) 4, (fs[4]) 5, make 6 7, twice (add 1 1) 0]",
+        );
+        assert_eq!(run(source).map(|v| v.to_string()), Ok("[7, 1, 3, 12, 6, 6, 42, 4]".to_owned()));
+        assert_eq!(run("let f x y = x\nf 1 2 3"), Err("[ln 2, col 0] Cannot call `int`".to_owned()));
+    }
+}
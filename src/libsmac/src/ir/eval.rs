@@ -0,0 +1,157 @@
+use diagnostic::Diagnostic;
+use interpreter::MAX_DEPTH;
+use ir::{self, Block, Function, InstructionKind, Module, Terminator};
+use value::{self, Value};
+
+/// Runs a module's top-level code, returning its value.
+///
+/// Runtime errors are reported where `Interpreter::run`
+/// reports them, which the IR keeps the positions for.
+pub fn run(module: &Module) -> Result<Value, Diagnostic> {
+    Runner {
+        module: module,
+        frames: vec![Frame::new(&module.main, Vec::new())],
+    }.run()
+}
+
+/// A call being run.
+struct Frame<'m> {
+    function: &'m Function,
+    values:   Vec<Value>,
+    block:    Block,
+    /// The next instruction of the block.
+    next:     usize,
+}
+
+impl<'m> Frame<'m> {
+    fn new(function: &'m Function, arguments: Vec<Value>) -> Frame<'m> {
+        let mut values = vec![Value::Unit; function.values.len()];
+        for (&parameter, argument) in function.parameters().iter().zip(arguments.into_iter()) {
+            values[parameter.0 as usize] = argument
+        }
+        Frame {
+            function: function,
+            values:   values,
+            block:    Block(0),
+            next:     0,
+        }
+    }
+
+    fn get(&self, value: ir::Value) -> Value {
+        self.values[value.0 as usize].clone()
+    }
+}
+
+/// Runs calls on a stack of its own rather than
+/// recursing, which deep calls would overflow.
+struct Runner<'m> {
+    module: &'m Module,
+    frames: Vec<Frame<'m>>,
+}
+
+impl<'m> Runner<'m> {
+    fn run(&mut self) -> Result<Value, Diagnostic> {
+        loop {
+            let module = self.module;
+            let depth  = self.frames.len() - 1;
+            let frame  = self.frames.last_mut().unwrap();
+            let block  = &frame.function.blocks[frame.block.0 as usize];
+
+            if let Some(instruction) = block.instructions.get(frame.next) {
+                frame.next += 1;
+                let position = instruction.position;
+                let value = match instruction.kind {
+                    InstructionKind::Const(ref c) => c.to_value(),
+                    InstructionKind::Unary(op, operand) => {
+                        value::unary(op, frame.get(operand)).map_err(|message| Diagnostic::new(position, message))?
+                    },
+                    InstructionKind::Binary(op, left, right) => {
+                        value::binary(op, frame.get(left), frame.get(right))
+                            .map_err(|message| Diagnostic::new(position, message))?
+                    },
+                    InstructionKind::Call(ref name, ref operands) => {
+                        let callee = match module.function(name) {
+                            Some(callee) => callee,
+                            None => return Err(Diagnostic::new(position, format!("Unknown name `{}`", name))),
+                        };
+                        if depth == MAX_DEPTH {
+                            return Err(Diagnostic::new(position, "Stack overflow".to_owned()))
+                        }
+                        let operands = operands.iter().map(|&v| frame.get(v)).collect();
+                        self.frames.push(Frame::new(callee, operands));
+                        continue
+                    },
+                };
+                frame.values[instruction.result.0 as usize] = value;
+                continue
+            }
+
+            let (target, passed) = match block.terminator {
+                Some(Terminator::Jump(target, ref passed)) => (target, passed),
+                Some(Terminator::Branch(condition, then, ref then_arguments, otherwise, ref otherwise_arguments)) => {
+                    match frame.get(condition) {
+                        Value::Boolean(true) => (then, then_arguments),
+                        _                    => (otherwise, otherwise_arguments),
+                    }
+                },
+                Some(Terminator::Return(value)) => {
+                    let value = frame.get(value);
+                    self.frames.pop();
+                    match self.frames.last_mut() {
+                        Some(caller) => {
+                            // The call is the instruction before the next
+                            let block = &caller.function.blocks[caller.block.0 as usize];
+                            let result = block.instructions[caller.next - 1].result;
+                            caller.values[result.0 as usize] = value;
+                            continue
+                        },
+                        None => return Ok(value),
+                    }
+                },
+                None => panic!("{} of {} has no terminator", frame.block, frame.function.display_name()),
+            };
+            let arguments: Vec<_> = passed.iter().map(|&v| frame.get(v)).collect();
+            let parameters = &frame.function.blocks[target.0 as usize].parameters;
+            for (&parameter, argument) in parameters.iter().zip(arguments.into_iter()) {
+                frame.values[parameter.0 as usize] = argument
+            }
+            frame.block = target;
+            frame.next  = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use document::Document;
+    use interpreter::Interpreter;
+    use ir::lower;
+    use typeck::check;
+    use value::Value;
+
+    fn both(source: &str) -> (Result<Value, String>, Result<Value, String>) {
+        let document = Document::new(source);
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let types = check(&ast).unwrap();
+        let module = lower(&ast, &types).unwrap();
+        let describe = |d: ::diagnostic::Diagnostic| format!("{} {}", d.position(), d.message());
+        (run(&module).map_err(&describe), Interpreter::new().run(&ast).map_err(&describe))
+    }
+
+    #[test]
+    fn agree_with_the_interpreter() {
+        let sources = [
+            "let r = fact 10\nlet fact n =\n  if n < 2\n    1\n  else\n    n * fact (n - 1)\nr",
+            "let x = 1\nif true\n  let mut x = 2\n  x = x + 1\nx",
+            "let mut x = 1\nlet mut y = 2\nif x < y\n  x = x + 1\nelse\n  y = 0\nx * 10 + y",
+            "let s = \"a\" + \"b\"\ns == \"ab\" && 1 != 2 || 1 / 0 == 0",
+            "let f x = 10 / x\n1 + f 0",
+            "let f x = f x\nf 1",
+        ];
+        for source in sources.iter() {
+            let (ir, interpreted) = both(source);
+            assert_eq!(ir, interpreted, "{}", source);
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use diagnostic::Diagnostic;
+use ir::{Block, Constant, Function, Instruction, InstructionKind, Module, Terminator, Value};
+use lexer::token::{Op, TokenPosition};
+use parser::node::{Node, NodeType};
+use typeck::{Type, Types};
+
+/// Stands for the block a branch joins, until it exists.
+const PENDING: Block = Block(::std::u32::MAX);
+
+/// Translates type-checked nodes to IR.
+///
+/// Locals only exist while lowering: a use of one becomes
+/// the value it was last given. Where branches join, the
+/// locals they left different are passed to the next block,
+/// followed by the value of the branching expression.
+pub fn lower(nodes: &[Node], types: &Types) -> Result<Module, Diagnostic> {
+    let mut functions = Vec::new();
+    for node in nodes.iter() {
+        if let NodeType::Function(ref name, ref parameters, ref body) = *node.node_type() {
+            let (parameter_types, result) = match types.function(name) {
+                Some(&Type::Function(ref parameters, ref result)) => (parameters.clone(), (**result).clone()),
+                _ => return Err(error(node, format!("Unknown name `{}`", name))),
+            };
+
+            let mut lowering = Lowering::new(types, Function::new(Some(name.clone()), result));
+            let mut scope = Vec::new();
+            for (parameter, t) in parameters.iter().zip(parameter_types.into_iter()) {
+                let value = lowering.function.value(t);
+                lowering.function.blocks[0].parameters.push(value);
+                scope.push((parameter.clone(), value));
+            }
+            lowering.scopes.push(scope);
+
+            let value = lowering.node(body)?;
+            lowering.terminate(Terminator::Return(value));
+            functions.push(lowering.function);
+        }
+    }
+
+    // The program's value, as `Interpreter::run` finds it
+    let mut lowering = Lowering::new(types, Function::new(None, Type::Unit));
+    lowering.scopes.push(Vec::new());
+    let mut value = None;
+    for node in nodes.iter() {
+        value = match *node.node_type() {
+            NodeType::Function(..) => None,
+            _                      => Some(lowering.node(node)?),
+        }
+    }
+    let value = match value {
+        Some(value) => value,
+        None        => lowering.unit(),
+    };
+    lowering.function.result = lowering.function.type_of(value).clone();
+    lowering.terminate(Terminator::Return(value));
+
+    Ok(Module {
+        main:      lowering.function,
+        functions: functions,
+    })
+}
+
+fn error(node: &Node, message: String) -> Diagnostic {
+    Diagnostic::new(node.span().start, message)
+}
+
+/// Control leaving a block for the one a branch joins:
+/// the block, the locals then and the branch's value.
+struct Edge {
+    from:   Block,
+    locals: Vec<Value>,
+    value:  Value,
+}
+
+/// Builds one function.
+struct Lowering<'a> {
+    types:    &'a Types,
+    function: Function,
+    /// The block instructions are added to.
+    current:  Block,
+    /// The value of each local in scope, innermost last.
+    scopes:   Vec<Vec<(String, Value)>>,
+    /// The function's unit value, once needed.
+    unit:     Option<Value>,
+}
+
+impl<'a> Lowering<'a> {
+    fn new(types: &'a Types, function: Function) -> Lowering<'a> {
+        Lowering {
+            types:    types,
+            function: function,
+            current:  Block(0),
+            scopes:   Vec::new(),
+            unit:     None,
+        }
+    }
+
+    fn type_of(&self, node: &Node) -> Type {
+        self.types.of(node).cloned().unwrap_or(Type::Unit)
+    }
+
+    fn emit(&mut self, kind: InstructionKind, t: Type, position: TokenPosition) -> Value {
+        let result = self.function.value(t);
+        self.function.blocks[self.current.0 as usize].instructions.push(Instruction {
+            result:   result,
+            kind:     kind,
+            position: position,
+        });
+        result
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        self.function.blocks[self.current.0 as usize].terminator = Some(terminator)
+    }
+
+    /// Unit, defined once at the start of the function
+    /// so that it is available everywhere.
+    fn unit(&mut self) -> Value {
+        if let Some(unit) = self.unit {
+            return unit
+        }
+        let unit = self.function.value(Type::Unit);
+        self.function.blocks[0].instructions.insert(0, Instruction {
+            result:   unit,
+            kind:     InstructionKind::Const(Constant::Unit),
+            position: TokenPosition::default(),
+        });
+        self.unit = Some(unit);
+        unit
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<&mut Value> {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(&mut (_, ref mut value)) = scope.iter_mut().rev().find(|&&mut (ref n, _)| n == name) {
+                return Some(value)
+            }
+        }
+        None
+    }
+
+    /// The values of all locals in scope.
+    fn locals(&self) -> Vec<Value> {
+        self.scopes.iter().flat_map(|scope| scope.iter().map(|&(_, value)| value)).collect()
+    }
+
+    fn set_locals(&mut self, values: &[Value]) {
+        let slots = self.scopes.iter_mut().flat_map(|scope| scope.iter_mut().map(|&mut (_, ref mut value)| value));
+        for (slot, &value) in slots.zip(values.iter()) {
+            *slot = value
+        }
+    }
+
+    /// Leaves the current block for the one the branch joins.
+    fn edge(&self, value: Value) -> Edge {
+        Edge {
+            from:   self.current,
+            locals: self.locals(),
+            value:  value,
+        }
+    }
+
+    /// Starts the block where `edges` join, returning the
+    /// value the branches left as a parameter of type `t`.
+    fn join(&mut self, edges: Vec<Edge>, t: Type) -> Value {
+        let block = self.function.block();
+
+        let mut locals  = edges[0].locals.clone();
+        let mut changed = Vec::new();
+        for i in 0..locals.len() {
+            if edges.iter().any(|edge| edge.locals[i] != locals[i]) {
+                let t = self.function.type_of(locals[i]).clone();
+                locals[i] = self.function.value(t);
+                self.function.blocks[block.0 as usize].parameters.push(locals[i]);
+                changed.push(i);
+            }
+        }
+        let value = self.function.value(t);
+        self.function.blocks[block.0 as usize].parameters.push(value);
+
+        for edge in edges.into_iter() {
+            let mut arguments: Vec<_> = changed.iter().map(|&i| edge.locals[i]).collect();
+            arguments.push(edge.value);
+
+            let from = &mut self.function.blocks[edge.from.0 as usize];
+            from.terminator = Some(match from.terminator.take() {
+                None => Terminator::Jump(block, arguments),
+                Some(Terminator::Branch(condition, then, then_arguments, otherwise, otherwise_arguments)) => {
+                    if then == PENDING {
+                        Terminator::Branch(condition, block, arguments, otherwise, otherwise_arguments)
+                    } else {
+                        Terminator::Branch(condition, then, then_arguments, block, arguments)
+                    }
+                },
+                Some(_) => unreachable!("a block left for a join has no other successor"),
+            });
+        }
+
+        self.set_locals(&locals);
+        self.current = block;
+        value
+    }
+
+    /// Lowers one arm of a branch in a scope of its own.
+    fn arm(&mut self, node: &Node) -> Result<Value, Diagnostic> {
+        self.scopes.push(Vec::new());
+        let value = self.node(node);
+        self.scopes.pop();
+        value
+    }
+
+    fn node(&mut self, node: &Node) -> Result<Value, Diagnostic> {
+        let t        = self.type_of(node);
+        let position = node.span().start;
+        let kind = match *node.node_type() {
+            NodeType::StringLiteral(ref value) => InstructionKind::Const(Constant::String(value.clone())),
+            NodeType::CharLiteral(value)       => InstructionKind::Const(Constant::Char(value)),
+            NodeType::IntLiteral(value, _)     => InstructionKind::Const(Constant::Int(value as i64)),
+            NodeType::FloatLiteral(value)      => InstructionKind::Const(Constant::Float(value)),
+            NodeType::BooleanLiteral(value)    => InstructionKind::Const(Constant::Bool(value)),
+            NodeType::Identifier(ref name) => {
+                return match self.lookup(name) {
+                    Some(&mut value) => Ok(value),
+                    None             => Err(error(node, format!("Unknown name `{}`", name))),
+                }
+            },
+            NodeType::Unary(op, ref operand) => InstructionKind::Unary(op, self.node(operand)?),
+            NodeType::Binary(Op::And, ref left, ref right) => return self.short_circuit(left, right, true),
+            NodeType::Binary(Op::Or, ref left, ref right)  => return self.short_circuit(left, right, false),
+            NodeType::Binary(Op::Range, ..) | NodeType::Binary(Op::RangeInclusive, ..) => {
+                return Err(error(node, "Cannot compile ranges".to_owned()))
+            },
+            NodeType::Binary(op, ref left, ref right) => {
+                let l = self.node(left)?;
+                let r = self.node(right)?;
+                InstructionKind::Binary(op, l, r)
+            },
+            NodeType::Call(ref function, ref arguments) => {
+                let name = match *function.node_type() {
+                    NodeType::Identifier(ref name) if self.lookup(name).is_none() => name,
+                    _ => return Err(error(function, "Only named functions can be called".to_owned())),
+                };
+                match self.types.function(name) {
+                    // Partial application makes a closure
+                    Some(&Type::Function(ref parameters, _)) if parameters.len() == arguments.len() => (),
+                    Some(_) => return Err(error(node, "Cannot compile closures".to_owned())),
+                    None    => return Err(error(function, format!("Unknown name `{}`", name))),
+                }
+                let mut values = Vec::new();
+                for argument in arguments.iter() {
+                    values.push(self.node(argument)?)
+                }
+                InstructionKind::Call(name.clone(), values)
+            },
+            NodeType::If(ref condition, ref then, ref otherwise) => {
+                let condition  = self.node(condition)?;
+                let then_block = self.function.block();
+                let else_block = self.function.block();
+                self.terminate(Terminator::Branch(condition, then_block, vec![], else_block, vec![]));
+                let before = self.locals();
+
+                self.current = then_block;
+                let value = self.arm(then)?;
+                let value = if otherwise.is_some() { value } else { self.unit() };
+                let then_edge = self.edge(value);
+
+                self.set_locals(&before);
+                self.current = else_block;
+                let value = match *otherwise {
+                    Some(ref otherwise) => self.arm(otherwise)?,
+                    None                => self.unit(),
+                };
+                let else_edge = self.edge(value);
+
+                return Ok(self.join(vec![then_edge, else_edge], t))
+            },
+            NodeType::Let(ref name, _, ref value) => {
+                let value = match *value {
+                    Some(ref value) => self.node(value)?,
+                    None            => return Err(error(node, "Cannot compile bindings without a value".to_owned())),
+                };
+                self.scopes.last_mut().unwrap().push((name.clone(), value));
+                return Ok(self.unit())
+            },
+            NodeType::Function(..) => {
+                return Err(error(node, "Functions can only be defined at the top level".to_owned()))
+            },
+            NodeType::Assign(ref target, ref value) => {
+                let value = self.node(value)?;
+                match *target.node_type() {
+                    NodeType::Identifier(ref name) => match self.lookup(name) {
+                        Some(slot) => *slot = value,
+                        None       => return Err(error(target, format!("Unknown name `{}`", name))),
+                    },
+                    _ => return Err(error(target, "Invalid assignment target".to_owned())),
+                }
+                return Ok(self.unit())
+            },
+            NodeType::CompoundAssign(op, ref target, ref value) => {
+                let t = self.type_of(value);
+                let r = self.node(value)?;
+                let name = match *target.node_type() {
+                    NodeType::Identifier(ref name) => name,
+                    _ => return Err(error(target, "Invalid assignment target".to_owned())),
+                };
+                let l = match self.lookup(name) {
+                    Some(&mut l) => l,
+                    None         => return Err(error(target, format!("Unknown name `{}`", name))),
+                };
+                let result = self.emit(InstructionKind::Binary(op, l, r), t, position);
+                *self.lookup(name).unwrap() = result;
+                return Ok(self.unit())
+            },
+            NodeType::Block(ref nodes) => {
+                self.scopes.push(Vec::new());
+                let mut value = Ok(None);
+                for node in nodes.iter() {
+                    value = self.node(node).map(Some);
+                    if value.is_err() {
+                        break
+                    }
+                }
+                self.scopes.pop();
+                return match value? {
+                    Some(value) => Ok(value),
+                    None        => Ok(self.unit()),
+                }
+            },
+            NodeType::Field(..) => {
+                return Err(error(node, "Cannot compile field access".to_owned()))
+            },
+            NodeType::List(..) | NodeType::Index(..) => {
+                return Err(error(node, "Cannot compile lists".to_owned()))
+            },
+            NodeType::Record(..) | NodeType::With(..) => {
+                return Err(error(node, "Cannot compile records".to_owned()))
+            },
+            NodeType::Enum(..) | NodeType::Match(..) => {
+                return Err(error(node, "Cannot compile enums".to_owned()))
+            },
+            NodeType::Tuple(..) => {
+                return Err(error(node, "Cannot compile tuples".to_owned()))
+            },
+            NodeType::While(..) | NodeType::For(..) | NodeType::Break(_) | NodeType::Continue => {
+                return Err(error(node, "Cannot compile loops".to_owned()))
+            },
+            NodeType::Lambda(..) => {
+                return Err(error(node, "Cannot compile closures".to_owned()))
+            },
+            NodeType::Error => {
+                return Err(error(node, "Cannot compile code with syntax errors".to_owned()))
+            },
+        };
+        Ok(self.emit(kind, t, position))
+    }
+
+    /// `&&` when `and` is true, otherwise `||`. When the
+    /// left operand decides, it is the value passed on.
+    fn short_circuit(&mut self, left: &Node, right: &Node, and: bool) -> Result<Value, Diagnostic> {
+        let l     = self.node(left)?;
+        let right_block = self.function.block();
+        self.terminate(if and {
+            Terminator::Branch(l, right_block, vec![], PENDING, vec![])
+        } else {
+            Terminator::Branch(l, PENDING, vec![], right_block, vec![])
+        });
+        let decided = self.edge(l);
+
+        self.current = right_block;
+        let r = self.node(right)?;
+        let evaluated = self.edge(r);
+
+        Ok(self.join(vec![decided, evaluated], Type::Bool))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lower;
+    use document::Document;
+    use typeck::check;
+
+    /// The lines of the IR for `source`, without indentation.
+    fn dump(source: &str) -> Vec<String> {
+        let document = Document::new(source);
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let types = check(&ast).unwrap();
+        lines(&lower(&ast, &types).unwrap().to_string())
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|line| line.trim().to_owned()).filter(|line| !line.is_empty()).collect()
+    }
+
+    #[test]
+    fn lower_branches() {
+        assert_eq!(dump("let mut x = 1\nif x < 2\n  x = 5\nx"), lines(indoc!("
+            function toplevel() -> int {
+            b0:
+                v1: () = const ()
+                v0: int = const 1
+                v2: int = const 2
+                v3: bool = v0 < v2
+                branch v3, b1, b2
+            b1:
+                v4: int = const 5
+                jump b3(v4, v1)
+            b2:
+                jump b3(v0, v1)
+            b3(v5: int, v6: ()):
+                return v5
+            }
+        ")));
+    }
+
+    #[test]
+    fn lower_short_circuits() {
+        assert_eq!(dump("let f a b = a && b || false\nf true true"), lines(indoc!("
+            function toplevel() -> bool {
+            b0:
+                v0: bool = const true
+                v1: bool = const true
+                v2: bool = call @f(v0, v1)
+                return v2
+            }
+
+            function @f(bool, bool) -> bool {
+            b0(v0: bool, v1: bool):
+                branch v0, b1, b2(v0)
+            b1:
+                jump b2(v1)
+            b2(v2: bool):
+                branch v2, b4(v2), b3
+            b3:
+                v3: bool = const false
+                jump b4(v3)
+            b4(v4: bool):
+                return v4
+            }
+        ")));
+    }
+}
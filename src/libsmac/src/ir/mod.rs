@@ -0,0 +1,537 @@
+//! A mid-level intermediate representation.
+//!
+//! Functions are control-flow graphs of basic blocks. Every
+//! value is defined once, by an instruction or as a parameter
+//! of a block; where control flow joins, the values that
+//! differ between the incoming edges are passed to the block
+//! as arguments instead of through phi nodes.
+
+pub mod lower;
+pub mod verify;
+pub mod eval;
+pub mod opt;
+
+pub use self::lower::lower;
+pub use self::verify::{VerifyError, verify};
+pub use self::eval::run;
+pub use self::opt::{PassManager, optimize};
+
+use lexer::token::{Op, TokenPosition};
+use typeck::Type;
+use value;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// A value defined in a function.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Value(pub u32);
+
+/// A basic block of a function.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Block(pub u32);
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "b{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Unit,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    String(String),
+}
+
+impl Constant {
+    /// The value the constant stands for.
+    pub fn to_value(&self) -> value::Value {
+        match *self {
+            Constant::Unit              => value::Value::Unit,
+            Constant::Int(value)        => value::Value::Int(value),
+            Constant::Float(value)      => value::Value::Float(value),
+            Constant::Bool(value)       => value::Value::Boolean(value),
+            Constant::Char(value)       => value::Value::Char(value),
+            Constant::String(ref value) => value::Value::String(value.clone()),
+        }
+    }
+
+    pub fn from_value(value: value::Value) -> Constant {
+        match value {
+            value::Value::Unit          => Constant::Unit,
+            value::Value::Int(value)    => Constant::Int(value),
+            value::Value::Float(value)  => Constant::Float(value),
+            value::Value::Boolean(value) => Constant::Bool(value),
+            value::Value::Char(value)   => Constant::Char(value),
+            value::Value::String(value) => Constant::String(value),
+            // Operators on constants never make one
+            value::Value::Record(..) | value::Value::List(_)
+                | value::Value::Variant(..) | value::Value::Tuple(_)
+                | value::Value::Closure(_) | value::Value::Cell(_) => unreachable!("only scalars are constant"),
+        }
+    }
+
+    /// Whether two constants are the same value. Unlike `==`,
+    /// floats are compared by their bits, telling `0.0` from
+    /// `-0.0` and finding each NaN the same as itself.
+    pub fn same(&self, other: &Constant) -> bool {
+        match (self, other) {
+            (&Constant::Float(a), &Constant::Float(b)) => a.to_bits() == b.to_bits(),
+            (a, b)                                     => a == b,
+        }
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Constant::Unit              => write!(f, "()"),
+            Constant::Int(value)        => write!(f, "{}", value),
+            Constant::Float(value)      => write!(f, "{:?}", value),
+            Constant::Bool(value)       => write!(f, "{}", value),
+            Constant::Char(value)       => write!(f, "{:?}", value),
+            Constant::String(ref value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstructionKind {
+    Const(Constant),
+    Unary(Op, Value),
+    /// Any binary operator but `&&` and `||`, which
+    /// are lowered to branches.
+    Binary(Op, Value, Value),
+    /// A call to a top-level function.
+    Call(String, Vec<Value>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub result:   Value,
+    pub kind:     InstructionKind,
+    /// Where runtime errors are reported.
+    pub position: TokenPosition,
+}
+
+impl Instruction {
+    /// The values the instruction uses.
+    pub fn arguments(&self) -> Vec<Value> {
+        match self.kind {
+            InstructionKind::Const(_)                  => vec![],
+            InstructionKind::Unary(_, operand)         => vec![operand],
+            InstructionKind::Binary(_, left, right)    => vec![left, right],
+            InstructionKind::Call(_, ref arguments)    => arguments.clone(),
+        }
+    }
+
+    pub fn arguments_mut(&mut self) -> Vec<&mut Value> {
+        match self.kind {
+            InstructionKind::Const(_)                          => vec![],
+            InstructionKind::Unary(_, ref mut operand)         => vec![operand],
+            InstructionKind::Binary(_, ref mut left, ref mut right) => vec![left, right],
+            InstructionKind::Call(_, ref mut arguments)        => arguments.iter_mut().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(Block, Vec<Value>),
+    /// Goes to the first block if the condition is true,
+    /// otherwise to the second, passing their arguments.
+    Branch(Value, Block, Vec<Value>, Block, Vec<Value>),
+    Return(Value),
+}
+
+impl Terminator {
+    /// The blocks control can go to next, with their arguments.
+    pub fn successors(&self) -> Vec<(Block, &[Value])> {
+        match *self {
+            Terminator::Jump(block, ref arguments) => vec![(block, &arguments[..])],
+            Terminator::Branch(_, then, ref then_arguments, otherwise, ref otherwise_arguments) => {
+                vec![(then, &then_arguments[..]), (otherwise, &otherwise_arguments[..])]
+            },
+            Terminator::Return(_) => vec![],
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<(&mut Block, &mut Vec<Value>)> {
+        match *self {
+            Terminator::Jump(ref mut block, ref mut arguments) => vec![(block, arguments)],
+            Terminator::Branch(_, ref mut then, ref mut then_arguments, ref mut otherwise, ref mut otherwise_arguments) => {
+                vec![(then, then_arguments), (otherwise, otherwise_arguments)]
+            },
+            Terminator::Return(_) => vec![],
+        }
+    }
+
+    /// The values the terminator uses.
+    pub fn arguments(&self) -> Vec<Value> {
+        let mut values = match *self {
+            Terminator::Branch(condition, ..) => vec![condition],
+            Terminator::Return(value)         => vec![value],
+            Terminator::Jump(..)              => vec![],
+        };
+        for (_, arguments) in self.successors() {
+            values.extend_from_slice(arguments)
+        }
+        values
+    }
+
+    pub fn arguments_mut(&mut self) -> Vec<&mut Value> {
+        match *self {
+            Terminator::Jump(_, ref mut arguments) => arguments.iter_mut().collect(),
+            Terminator::Branch(ref mut condition, _, ref mut then_arguments, _, ref mut otherwise_arguments) => {
+                Some(condition).into_iter().chain(then_arguments.iter_mut()).chain(otherwise_arguments.iter_mut()).collect()
+            },
+            Terminator::Return(ref mut value) => vec![value],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub parameters:   Vec<Value>,
+    pub instructions: Vec<Instruction>,
+    /// Missing only while the block is being built.
+    pub terminator:   Option<Terminator>,
+}
+
+impl BasicBlock {
+    pub fn new() -> BasicBlock {
+        BasicBlock {
+            parameters:   Vec::new(),
+            instructions: Vec::new(),
+            terminator:   None,
+        }
+    }
+}
+
+/// A function. The parameters of its first block, the
+/// entry, are the function's parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    /// `None` for the top-level code.
+    pub name:   Option<String>,
+    pub result: Type,
+    pub blocks: Vec<BasicBlock>,
+    /// The type of each value.
+    pub values: Vec<Type>,
+}
+
+impl Function {
+    pub fn new(name: Option<String>, result: Type) -> Function {
+        Function {
+            name:   name,
+            result: result,
+            blocks: vec![BasicBlock::new()],
+            values: Vec::new(),
+        }
+    }
+
+    pub fn value(&mut self, t: Type) -> Value {
+        self.values.push(t);
+        Value(self.values.len() as u32 - 1)
+    }
+
+    pub fn block(&mut self) -> Block {
+        self.blocks.push(BasicBlock::new());
+        Block(self.blocks.len() as u32 - 1)
+    }
+
+    pub fn type_of(&self, value: Value) -> &Type {
+        &self.values[value.0 as usize]
+    }
+
+    pub fn parameters(&self) -> &[Value] {
+        &self.blocks[0].parameters
+    }
+
+    /// The blocks each block is branched to from,
+    /// once for each way it is.
+    pub fn predecessors(&self) -> Vec<Vec<usize>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for (b, block) in self.blocks.iter().enumerate() {
+            if let Some(ref terminator) = block.terminator {
+                for (target, _) in terminator.successors() {
+                    if let Some(list) = predecessors.get_mut(target.0 as usize) {
+                        list.push(b)
+                    }
+                }
+            }
+        }
+        predecessors
+    }
+
+    /// The blocks reachable from the entry, each after every
+    /// block dominating it: a reverse postorder.
+    pub fn reverse_postorder(&self) -> Vec<usize> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order   = Vec::new();
+        // Blocks with the index of the next successor to visit
+        let mut stack   = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((b, next)) = stack.pop() {
+            let successors = match self.blocks[b].terminator {
+                Some(ref terminator) => terminator.successors(),
+                None                 => vec![],
+            };
+            match successors.get(next) {
+                Some(&(target, _)) => {
+                    stack.push((b, next + 1));
+                    let t = target.0 as usize;
+                    if t < visited.len() && !visited[t] {
+                        visited[t] = true;
+                        stack.push((t, 0))
+                    }
+                },
+                None => order.push(b),
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// The blocks dominating each block reachable from the
+    /// entry, as a set indexed by block, or `None` for the
+    /// blocks which cannot be reached.
+    pub fn dominators(&self) -> Vec<Option<Vec<bool>>> {
+        let count        = self.blocks.len();
+        let predecessors = self.predecessors();
+        let order        = self.reverse_postorder();
+
+        let mut dominators: Vec<Option<Vec<bool>>> = vec![None; count];
+        for &b in order.iter() {
+            dominators[b] = Some(if b == 0 {
+                (0..count).map(|d| d == 0).collect()
+            } else {
+                vec![true; count]
+            })
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in order.iter().skip(1) {
+                let mut set = vec![true; count];
+                for &p in predecessors[b].iter() {
+                    if let Some(ref of_p) = dominators[p] {
+                        for d in 0..count {
+                            set[d] = set[d] && of_p[d]
+                        }
+                    }
+                }
+                set[b] = true;
+                if dominators[b].as_ref() != Some(&set) {
+                    dominators[b] = Some(set);
+                    changed = true
+                }
+            }
+        }
+        dominators
+    }
+
+    /// Makes every use of a value in `substitutions` a use
+    /// of the value it maps to, following chains of them.
+    pub fn substitute(&mut self, substitutions: &HashMap<Value, Value>) {
+        if substitutions.is_empty() {
+            return
+        }
+        let resolve = |value: &mut Value| {
+            while let Some(&next) = substitutions.get(value) {
+                *value = next
+            }
+        };
+        for block in self.blocks.iter_mut() {
+            for instruction in block.instructions.iter_mut() {
+                for value in instruction.arguments_mut() {
+                    resolve(value)
+                }
+            }
+            if let Some(ref mut terminator) = block.terminator {
+                for value in terminator.arguments_mut() {
+                    resolve(value)
+                }
+            }
+        }
+    }
+
+    /// Removes the blocks not to `keep`, which nothing
+    /// left may branch to. The entry must be kept.
+    pub fn retain_blocks(&mut self, keep: &[bool]) {
+        let mut numbers = Vec::with_capacity(keep.len());
+        let mut next = 0;
+        for &kept in keep.iter() {
+            numbers.push(Block(next));
+            if kept {
+                next += 1
+            }
+        }
+
+        let blocks = ::std::mem::replace(&mut self.blocks, Vec::new());
+        for (mut block, _) in blocks.into_iter().zip(keep.iter()).filter(|&(_, &kept)| kept) {
+            if let Some(ref mut terminator) = block.terminator {
+                for (target, _) in terminator.successors_mut() {
+                    *target = numbers[target.0 as usize]
+                }
+            }
+            self.blocks.push(block)
+        }
+    }
+
+    /// Numbers the values afresh in the order they are
+    /// defined, forgetting those no longer defined.
+    pub fn renumber(&mut self) {
+        let mut numbers = HashMap::new();
+        let mut values  = Vec::new();
+        for block in self.blocks.iter() {
+            let results = block.instructions.iter().map(|instruction| instruction.result);
+            for value in block.parameters.iter().cloned().chain(results) {
+                numbers.insert(value, Value(values.len() as u32));
+                values.push(self.values[value.0 as usize].clone());
+            }
+        }
+
+        for block in self.blocks.iter_mut() {
+            for parameter in block.parameters.iter_mut() {
+                *parameter = numbers[parameter]
+            }
+            for instruction in block.instructions.iter_mut() {
+                instruction.result = numbers[&instruction.result];
+                for value in instruction.arguments_mut() {
+                    *value = numbers[value]
+                }
+            }
+            if let Some(ref mut terminator) = block.terminator {
+                for value in terminator.arguments_mut() {
+                    *value = numbers[value]
+                }
+            }
+        }
+        self.values = values;
+    }
+
+    /// What the function is called in the text format.
+    pub fn display_name(&self) -> String {
+        match self.name {
+            Some(ref name) => format!("@{}", name),
+            None           => "toplevel".to_owned(),
+        }
+    }
+}
+
+/// A program: its top-level code and functions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub main:      Function,
+    pub functions: Vec<Function>,
+}
+
+impl Module {
+    /// The top-level code, then every function.
+    pub fn functions_mut(&mut self) -> Vec<&mut Function> {
+        Some(&mut self.main).into_iter().chain(self.functions.iter_mut()).collect()
+    }
+
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name.as_ref().map_or(false, |n| n == name))
+    }
+}
+
+fn list<T: fmt::Display>(f: &mut fmt::Formatter, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?
+        }
+        write!(f, "{}", item)?
+    }
+    Ok(())
+}
+
+fn target(f: &mut fmt::Formatter, block: Block, arguments: &[Value]) -> fmt::Result {
+    write!(f, "{}", block)?;
+    if !arguments.is_empty() {
+        write!(f, "(")?;
+        list(f, arguments)?;
+        write!(f, ")")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "function {}(", self.display_name())?;
+        let parameters: Vec<_> = self.parameters().iter().map(|&p| self.type_of(p)).collect();
+        list(f, &parameters)?;
+        writeln!(f, ") -> {} {{", self.result)?;
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            write!(f, "{}", Block(i as u32))?;
+            if !block.parameters.is_empty() {
+                write!(f, "(")?;
+                for (j, &parameter) in block.parameters.iter().enumerate() {
+                    if j > 0 {
+                        write!(f, ", ")?
+                    }
+                    write!(f, "{}: {}", parameter, self.type_of(parameter))?
+                }
+                write!(f, ")")?;
+            }
+            writeln!(f, ":")?;
+
+            for instruction in block.instructions.iter() {
+                write!(f, "    {}: {} = ", instruction.result, self.type_of(instruction.result))?;
+                match instruction.kind {
+                    InstructionKind::Const(ref constant)     => write!(f, "const {}", constant)?,
+                    InstructionKind::Unary(op, operand)      => write!(f, "{}{}", op.as_str(), operand)?,
+                    InstructionKind::Binary(op, left, right) => write!(f, "{} {} {}", left, op.as_str(), right)?,
+                    InstructionKind::Call(ref name, ref arguments) => {
+                        write!(f, "call @{}(", name)?;
+                        list(f, arguments)?;
+                        write!(f, ")")?
+                    },
+                }
+                writeln!(f)?;
+            }
+
+            write!(f, "    ")?;
+            match block.terminator {
+                Some(Terminator::Jump(block, ref arguments)) => {
+                    write!(f, "jump ")?;
+                    target(f, block, arguments)?
+                },
+                Some(Terminator::Branch(condition, then, ref then_arguments, otherwise, ref otherwise_arguments)) => {
+                    write!(f, "branch {}, ", condition)?;
+                    target(f, then, then_arguments)?;
+                    write!(f, ", ")?;
+                    target(f, otherwise, otherwise_arguments)?
+                },
+                Some(Terminator::Return(value)) => write!(f, "return {}", value)?,
+                None                            => write!(f, "<unterminated>")?,
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.main)?;
+        for function in self.functions.iter() {
+            write!(f, "\n{}", function)?
+        }
+        Ok(())
+    }
+}
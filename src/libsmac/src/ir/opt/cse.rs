@@ -0,0 +1,123 @@
+use ir::{Function, InstructionKind, Module, Value};
+use ir::opt::Pass;
+
+use std::collections::{HashMap, HashSet};
+
+/// Common subexpression elimination.
+///
+/// An instruction computing what one dominating it already
+/// has is removed, and its uses take the earlier result.
+/// If the earlier one raises an error, the later one never
+/// runs. Calls are left alone.
+pub struct Cse;
+
+impl Pass for Cse {
+    fn name(&self) -> &'static str {
+        "cse"
+    }
+
+    fn run(&mut self, module: &mut Module) -> bool {
+        let mut changed = false;
+        for function in module.functions_mut() {
+            changed |= cse(function)
+        }
+        changed
+    }
+}
+
+fn cse(function: &mut Function) -> bool {
+    let dominators = function.dominators();
+
+    // What has been computed: the instruction, with its operands
+    // already substituted, its block and its result
+    let mut available: Vec<(InstructionKind, usize, Value)> = Vec::new();
+    let mut substitutions = HashMap::new();
+
+    for b in function.reverse_postorder() {
+        for instruction in function.blocks[b].instructions.iter() {
+            if let InstructionKind::Call(..) = instruction.kind {
+                continue
+            }
+            let mut resolved = instruction.clone();
+            for value in resolved.arguments_mut() {
+                if let Some(&earlier) = substitutions.get(value) {
+                    *value = earlier
+                }
+            }
+            let kind = resolved.kind;
+
+            let dominated_by = dominators[b].as_ref().unwrap();
+            let earlier = available.iter().find(|&&(ref k, block, _)| dominated_by[block] && same(k, &kind));
+            match earlier {
+                Some(&(_, _, earlier)) => {
+                    substitutions.insert(instruction.result, earlier);
+                },
+                None => available.push((kind, b, instruction.result)),
+            }
+        }
+    }
+
+    if substitutions.is_empty() {
+        return false
+    }
+    let removed: HashSet<Value> = substitutions.keys().cloned().collect();
+    for block in function.blocks.iter_mut() {
+        block.instructions.retain(|instruction| !removed.contains(&instruction.result))
+    }
+    function.substitute(&substitutions);
+    true
+}
+
+/// Whether two instructions compute the same value.
+fn same(a: &InstructionKind, b: &InstructionKind) -> bool {
+    match (a, b) {
+        (&InstructionKind::Const(ref a), &InstructionKind::Const(ref b)) => a.same(b),
+        (a, b)                                                           => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ir::opt::tests::{dump, lines};
+
+    #[test]
+    fn reuse_dominating_results() {
+        let source = "let f a b =\n  let x = a * b + 1\n  if a > 0\n    a * b + x\n  else\n    a * b - 1\nf 2 3";
+        assert_eq!(dump(source, 1)[6..].to_vec(), lines(indoc!("
+            }
+            function @f(int, int) -> int {
+            b0(v0: int, v1: int):
+                v2: int = v0 * v1
+                v3: int = const 1
+                v4: int = v2 + v3
+                v5: int = const 0
+                v6: bool = v0 > v5
+                branch v6, b1, b2
+            b1:
+                v7: int = v2 + v4
+                jump b3(v7)
+            b2:
+                v8: int = v2 - v3
+                jump b3(v8)
+            b3(v9: int):
+                return v9
+            }
+        ")));
+    }
+
+    #[test]
+    fn keep_floats_apart() {
+        assert_eq!(dump("let f x = x * 0.0 + x * -0.0\nf 1.0", 1)[5..].to_vec(), lines(indoc!("
+            }
+            function @f(float) -> float {
+            b0(v0: float):
+                v1: float = const 0.0
+                v2: float = v0 * v1
+                v3: float = const -0.0
+                v4: float = v0 * v3
+                v5: float = v2 + v4
+                return v5
+            }
+        ")));
+    }
+}
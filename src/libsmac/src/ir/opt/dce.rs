@@ -0,0 +1,264 @@
+use ir::{Block, Constant, Function, Instruction, InstructionKind, Module, Terminator, Value};
+use ir::opt::Pass;
+use ir::opt::fold::remove_parameters;
+use lexer::token::Op;
+use typeck::Type;
+
+use std::collections::HashMap;
+
+/// Dead code elimination.
+///
+/// Removes the blocks which cannot run, and the instructions
+/// and block parameters whose values are never used, unless
+/// computing them could raise an error. Branches to the same
+/// place become jumps, jumps to blocks which only jump on go
+/// straight there, and a block only ever jumped to from one
+/// other is merged into it.
+pub struct Dce;
+
+impl Pass for Dce {
+    fn name(&self) -> &'static str {
+        "dce"
+    }
+
+    fn run(&mut self, module: &mut Module) -> bool {
+        let mut changed = false;
+        for function in module.functions_mut() {
+            loop {
+                let round = simplify_branches(function) | merge_blocks(function)
+                    | remove_unreachable(function) | remove_unused(function);
+                if !round {
+                    break
+                }
+                changed = true
+            }
+        }
+        changed
+    }
+}
+
+fn remove_unreachable(function: &mut Function) -> bool {
+    let mut keep = vec![false; function.blocks.len()];
+    for b in function.reverse_postorder() {
+        keep[b] = true
+    }
+    if keep.iter().all(|&kept| kept) {
+        return false
+    }
+    function.retain_blocks(&keep);
+    true
+}
+
+/// Turns branches going the same way either way into jumps,
+/// and sends edges to blocks that only jump on to where they
+/// jump, with the arguments the blocks would pass.
+fn simplify_branches(function: &mut Function) -> bool {
+    let mut changed = false;
+
+    let mut uses: HashMap<Value, usize> = HashMap::new();
+    for block in function.blocks.iter() {
+        let instructions = block.instructions.iter().flat_map(|instruction| instruction.arguments());
+        let terminator   = block.terminator.iter().flat_map(|terminator| terminator.arguments());
+        for value in instructions.chain(terminator) {
+            *uses.entry(value).or_insert(0) += 1
+        }
+    }
+
+    // Where each block without instructions jumps, if its
+    // parameters are only used to pass them on
+    let forwards: Vec<Option<(Block, Vec<Value>, Vec<Value>)>> = function.blocks.iter().enumerate().map(|(b, block)| {
+        match block.terminator {
+            Some(Terminator::Jump(target, ref arguments)) if b != 0 && target.0 as usize != b && block.instructions.is_empty() => {
+                let passed_only = block.parameters.iter().all(|p| {
+                    uses.get(p).cloned().unwrap_or(0) == arguments.iter().filter(|&a| a == p).count()
+                });
+                if passed_only {
+                    Some((target, block.parameters.clone(), arguments.clone()))
+                } else {
+                    None
+                }
+            },
+            _ => None,
+        }
+    }).collect();
+
+    for block in function.blocks.iter_mut() {
+        let terminator = match block.terminator {
+            Some(ref mut terminator) => terminator,
+            None                     => continue,
+        };
+        for (target, arguments) in terminator.successors_mut() {
+            // Only one step, so that loops of such blocks end
+            if let Some((next, ref parameters, ref passed)) = forwards[target.0 as usize] {
+                let forwarded: Vec<Value> = passed.iter().map(|value| match parameters.iter().position(|p| p == value) {
+                    Some(i) => arguments[i],
+                    None    => *value,
+                }).collect();
+                if (next, &forwarded) != (*target, arguments) {
+                    *target    = next;
+                    *arguments = forwarded;
+                    changed = true
+                }
+            }
+        }
+
+        let jump = match *terminator {
+            Terminator::Branch(_, then, ref then_arguments, otherwise, ref otherwise_arguments)
+                if then == otherwise && then_arguments == otherwise_arguments => {
+                Some(Terminator::Jump(then, then_arguments.clone()))
+            },
+            _ => None,
+        };
+        if let Some(jump) = jump {
+            *terminator = jump;
+            changed = true
+        }
+    }
+    changed
+}
+
+/// Merges each block jumped to from only one other into it.
+fn merge_blocks(function: &mut Function) -> bool {
+    let mut changed = false;
+    let mut b = 0;
+    while b < function.blocks.len() {
+        let (target, arguments) = match function.blocks[b].terminator {
+            Some(Terminator::Jump(target, ref arguments)) => (target.0 as usize, arguments.clone()),
+            _ => {
+                b += 1;
+                continue
+            },
+        };
+        if target == 0 || target == b || function.predecessors()[target] != vec![b] {
+            b += 1;
+            continue
+        }
+
+        let merged = ::std::mem::replace(&mut function.blocks[target], ::ir::BasicBlock::new());
+        let substitutions: HashMap<Value, Value> = merged.parameters.iter().cloned().zip(arguments.into_iter()).collect();
+        let block = &mut function.blocks[b];
+        block.instructions.extend(merged.instructions.into_iter());
+        block.terminator = merged.terminator;
+        function.substitute(&substitutions);
+        // The emptied block is unreachable now; this one
+        // may jump somewhere else to merge
+        changed = true
+    }
+    changed
+}
+
+/// Removes unused instructions which cannot raise errors,
+/// and unused parameters of every block but the entry.
+fn remove_unused(function: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let mut uses: HashMap<Value, usize> = HashMap::new();
+        let mut constants = HashMap::new();
+        for block in function.blocks.iter() {
+            for instruction in block.instructions.iter() {
+                for value in instruction.arguments() {
+                    *uses.entry(value).or_insert(0) += 1
+                }
+                if let InstructionKind::Const(ref constant) = instruction.kind {
+                    constants.insert(instruction.result, constant.clone());
+                }
+            }
+            if let Some(ref terminator) = block.terminator {
+                for value in terminator.arguments() {
+                    *uses.entry(value).or_insert(0) += 1
+                }
+            }
+        }
+        let used = |value: &Value| uses.get(value).map_or(false, |&count| count > 0);
+
+        let mut round = false;
+        let types = function.values.clone();
+        for block in function.blocks.iter_mut() {
+            let before = block.instructions.len();
+            block.instructions.retain(|instruction| used(&instruction.result) || !pure(instruction, &types, &constants));
+            round |= block.instructions.len() != before;
+        }
+
+        let removed: Vec<Vec<usize>> = function.blocks.iter().enumerate().map(|(b, block)| {
+            if b == 0 {
+                return vec![]
+            }
+            block.parameters.iter().enumerate().filter(|&(_, p)| !used(p)).map(|(i, _)| i).collect()
+        }).collect();
+        if removed.iter().any(|indices| !indices.is_empty()) {
+            remove_parameters(function, &removed);
+            round = true
+        }
+
+        if !round {
+            return changed
+        }
+        changed = true
+    }
+}
+
+/// Whether an instruction can be removed when its
+/// result is unused: it cannot raise an error.
+fn pure(instruction: &Instruction, types: &[Type], constants: &HashMap<Value, Constant>) -> bool {
+    match instruction.kind {
+        InstructionKind::Const(_) | InstructionKind::Unary(..) => true,
+        InstructionKind::Binary(Op::Div, left, right) | InstructionKind::Binary(Op::Mod, left, right) => {
+            types[left.0 as usize] != Type::Int || match constants.get(&right) {
+                Some(&Constant::Int(divisor)) => divisor != 0,
+                _                             => false,
+            }
+        },
+        InstructionKind::Binary(..) => true,
+        // Calls can overflow the stack
+        InstructionKind::Call(..)   => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ir::opt::tests::{dump, lines};
+
+    #[test]
+    fn remove_dead_code() {
+        let source = "let f n =\n  let unused = n * 2\n  let kept = n / (n - 1)\n  if false\n    kept\n  else\n    n + 1\nf 5";
+        assert_eq!(dump(source, 1)[5..].to_vec(), lines(indoc!("
+            }
+            function @f(int) -> int {
+            b0(v0: int):
+                v1: int = const 1
+                v2: int = v0 - v1
+                v3: int = v0 / v2
+                v4: int = v0 + v1
+                return v4
+            }
+        ")));
+    }
+
+    #[test]
+    fn merge_straight_line_blocks() {
+        let source = "let f a b =\n  if a > 0\n    if b > 0\n      1\n    else\n      2\n  else\n    3\nf 1 2";
+        assert_eq!(dump(source, 1)[6..].to_vec(), lines(indoc!("
+            }
+            function @f(int, int) -> int {
+            b0(v0: int, v1: int):
+                v2: int = const 0
+                v3: bool = v0 > v2
+                branch v3, b1, b2
+            b1:
+                v4: bool = v1 > v2
+                branch v4, b3, b4
+            b2:
+                v5: int = const 3
+                jump b5(v5)
+            b3:
+                v6: int = const 1
+                jump b5(v6)
+            b4:
+                v7: int = const 2
+                jump b5(v7)
+            b5(v8: int):
+                return v8
+            }
+        ")));
+    }
+}
@@ -0,0 +1,215 @@
+use ir::{Constant, Function, Instruction, InstructionKind, Module, Terminator, Value};
+use ir::opt::Pass;
+use value;
+
+use std::collections::HashMap;
+
+/// Constant folding and propagation.
+///
+/// Evaluates the instructions whose operands are all constant,
+/// unless that would raise an error, which is left to happen
+/// at runtime. Branches on a constant become jumps, and a
+/// block parameter passed the same value, or equal constants,
+/// along every edge is replaced by it.
+pub struct Fold;
+
+impl Pass for Fold {
+    fn name(&self) -> &'static str {
+        "fold"
+    }
+
+    fn run(&mut self, module: &mut Module) -> bool {
+        let mut changed = false;
+        for function in module.functions_mut() {
+            changed |= fold(function);
+            changed |= propagate(function);
+        }
+        changed
+    }
+}
+
+/// Folds instructions and branches, in an order that
+/// sees every definition before its uses.
+fn fold(function: &mut Function) -> bool {
+    let mut changed   = false;
+    let mut constants = HashMap::new();
+
+    for b in function.reverse_postorder() {
+        let block = &mut function.blocks[b];
+        for instruction in block.instructions.iter_mut() {
+            let folded = match instruction.kind {
+                InstructionKind::Const(ref constant) => {
+                    constants.insert(instruction.result, constant.clone());
+                    continue
+                },
+                InstructionKind::Unary(op, operand) => {
+                    constants.get(&operand).and_then(|c: &Constant| value::unary(op, c.to_value()).ok())
+                },
+                InstructionKind::Binary(op, left, right) => match (constants.get(&left), constants.get(&right)) {
+                    (Some(l), Some(r)) => value::binary(op, l.to_value(), r.to_value()).ok(),
+                    _                  => None,
+                },
+                InstructionKind::Call(..) => None,
+            };
+            if let Some(folded) = folded {
+                let constant = Constant::from_value(folded);
+                constants.insert(instruction.result, constant.clone());
+                instruction.kind = InstructionKind::Const(constant);
+                changed = true
+            }
+        }
+
+        let jump = match block.terminator {
+            Some(Terminator::Branch(condition, then, ref then_arguments, otherwise, ref otherwise_arguments)) => {
+                match constants.get(&condition) {
+                    Some(&Constant::Bool(true))  => Some(Terminator::Jump(then, then_arguments.clone())),
+                    Some(&Constant::Bool(false)) => Some(Terminator::Jump(otherwise, otherwise_arguments.clone())),
+                    _                            => None,
+                }
+            },
+            _ => None,
+        };
+        if jump.is_some() {
+            block.terminator = jump;
+            changed = true
+        }
+    }
+    changed
+}
+
+/// Replaces block parameters which can only have one value.
+fn propagate(function: &mut Function) -> bool {
+    let mut constants = HashMap::new();
+    for block in function.blocks.iter() {
+        for instruction in block.instructions.iter() {
+            if let InstructionKind::Const(ref constant) = instruction.kind {
+                constants.insert(instruction.result, constant.clone());
+            }
+        }
+    }
+
+    // The values passed to each parameter of each block
+    let mut incoming: Vec<Vec<Vec<Value>>> = function.blocks.iter()
+        .map(|block| vec![Vec::new(); block.parameters.len()])
+        .collect();
+    for block in function.blocks.iter() {
+        if let Some(ref terminator) = block.terminator {
+            for (target, arguments) in terminator.successors() {
+                for (i, &argument) in arguments.iter().enumerate() {
+                    incoming[target.0 as usize][i].push(argument)
+                }
+            }
+        }
+    }
+
+    let mut substitutions = HashMap::new();
+    // The indices of the parameters removed from each block
+    let mut removed = vec![Vec::new(); function.blocks.len()];
+    // The entry's parameters are the function's
+    for b in 1..function.blocks.len() {
+        for (i, &parameter) in function.blocks[b].parameters.clone().iter().enumerate() {
+            // A block passing a parameter to itself leaves it as it was
+            let values: Vec<Value> = incoming[b][i].iter().cloned().filter(|&v| v != parameter).collect();
+            let first = match values.first() {
+                Some(&first) => first,
+                None         => continue,
+            };
+
+            if values.iter().all(|&v| v == first) {
+                substitutions.insert(parameter, first);
+                removed[b].push(i);
+            } else if let Some(constant) = constants.get(&first).cloned() {
+                if values.iter().all(|v| constants.get(v).map_or(false, |c| c.same(&constant))) {
+                    // The parameter becomes the constant's definition
+                    function.blocks[b].instructions.insert(0, Instruction {
+                        result:   parameter,
+                        kind:     InstructionKind::Const(constant),
+                        position: Default::default(),
+                    });
+                    removed[b].push(i);
+                }
+            }
+        }
+    }
+
+    if removed.iter().all(|indices| indices.is_empty()) {
+        return false
+    }
+    remove_parameters(function, &removed);
+    function.substitute(&substitutions);
+    true
+}
+
+/// Removes parameters of blocks, by index, and the
+/// arguments passed to them.
+pub fn remove_parameters(function: &mut Function, removed: &[Vec<usize>]) {
+    for (b, indices) in removed.iter().enumerate() {
+        let parameters = &mut function.blocks[b].parameters;
+        let mut i = 0;
+        parameters.retain(|_| {
+            i += 1;
+            !indices.contains(&(i - 1))
+        });
+    }
+    for block in function.blocks.iter_mut() {
+        if let Some(ref mut terminator) = block.terminator {
+            for (target, arguments) in terminator.successors_mut() {
+                let indices = &removed[target.0 as usize];
+                let mut i = 0;
+                arguments.retain(|_| {
+                    i += 1;
+                    !indices.contains(&(i - 1))
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ir::opt::tests::{dump, lines};
+
+    #[test]
+    fn fold_constant_expressions() {
+        assert_eq!(dump("1 << 4 + 0x10", 1), lines(indoc!("
+            function toplevel() -> int {
+            b0:
+                v0: int = const 1048576
+                return v0
+            }
+        ")));
+        assert_eq!(dump("\"a\" + \"b\" == \"ab\" && 2.0 * 0.5 < -(1.0)", 1), lines(indoc!("
+            function toplevel() -> bool {
+            b0:
+                v0: bool = const false
+                return v0
+            }
+        ")));
+    }
+
+    #[test]
+    fn leave_errors_to_runtime() {
+        assert_eq!(dump("let x = 10\nx / (x - 10)", 1), lines(indoc!("
+            function toplevel() -> int {
+            b0:
+                v0: int = const 10
+                v1: int = const 0
+                v2: int = v0 / v1
+                return v2
+            }
+        ")));
+    }
+
+    #[test]
+    fn propagate_through_branches() {
+        let source = "let f n =\n  let mut a = 1\n  let mut b = 2\n  if n > 0\n    b = 3 - a\n  else\n    a = 3 - b\n  a + b\nf 5";
+        assert_eq!(dump(source, 1)[5..].to_vec(), lines(indoc!("
+            }
+            function @f(int) -> int {
+            b0(v0: int):
+                v1: int = const 3
+                return v1
+            }
+        ")));
+    }
+}
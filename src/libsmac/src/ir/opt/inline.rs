@@ -0,0 +1,201 @@
+use ir::{Function, InstructionKind, Module, Terminator, Value};
+use ir::opt::Pass;
+
+use std::collections::HashMap;
+
+/// The most instructions a function may have to be inlined.
+const MAX_SIZE: usize = 16;
+
+/// Inlining of small functions.
+///
+/// Calls to functions with at most `MAX_SIZE` instructions
+/// and no calls of their own are replaced with a copy of
+/// their body, so callers become candidates in turn once
+/// everything they call is inlined. Since an inlined call
+/// takes no stack, programs may then recurse a little deeper
+/// before overflowing.
+pub struct Inline;
+
+impl Pass for Inline {
+    fn name(&self) -> &'static str {
+        "inline"
+    }
+
+    fn run(&mut self, module: &mut Module) -> bool {
+        let candidates: HashMap<String, Function> = module.functions.iter()
+            .filter(|function| small(function))
+            .map(|function| (function.name.clone().unwrap(), function.clone()))
+            .collect();
+        if candidates.is_empty() {
+            return false
+        }
+
+        let mut changed = false;
+        for function in module.functions_mut() {
+            let mut b = 0;
+            while b < function.blocks.len() {
+                let call = function.blocks[b].instructions.iter().position(|instruction| match instruction.kind {
+                    InstructionKind::Call(ref name, _) => candidates.contains_key(name),
+                    _                                  => false,
+                });
+                match call {
+                    Some(index) => {
+                        let name = match function.blocks[b].instructions[index].kind {
+                            InstructionKind::Call(ref name, _) => name.clone(),
+                            _                                  => unreachable!(),
+                        };
+                        inline(function, b, index, &candidates[&name]);
+                        changed = true
+                    },
+                    None => b += 1,
+                }
+            }
+        }
+        changed
+    }
+}
+
+fn small(function: &Function) -> bool {
+    let instructions = function.blocks.iter().flat_map(|block| block.instructions.iter());
+    let mut size = 0;
+    for instruction in instructions {
+        if let InstructionKind::Call(..) = instruction.kind {
+            return false
+        }
+        size += 1
+    }
+    size <= MAX_SIZE
+}
+
+/// Replaces the call at `index` of block `b` with a copy of
+/// `callee`, whose returns jump to a new block holding the
+/// rest of `b`. The call's result becomes its parameter.
+fn inline(function: &mut Function, b: usize, index: usize, callee: &Function) {
+    let mut rest = function.blocks[b].instructions.split_off(index);
+    let call = rest.remove(0);
+    let arguments = match call.kind {
+        InstructionKind::Call(_, arguments) => arguments,
+        _                                   => unreachable!(),
+    };
+
+    let after = function.block();
+    let terminator = function.blocks[b].terminator.take();
+    {
+        let after = &mut function.blocks[after.0 as usize];
+        after.parameters   = vec![call.result];
+        after.instructions = rest;
+        after.terminator   = terminator;
+    }
+
+    // The callee's values and blocks in the caller
+    let mut values: HashMap<Value, Value> = callee.parameters().iter().cloned().zip(arguments.into_iter()).collect();
+    let mut blocks = Vec::new();
+    for (i, block) in callee.blocks.iter().enumerate() {
+        blocks.push(function.block());
+        let parameters = if i == 0 { &[][..] } else { &block.parameters[..] };
+        let results = block.instructions.iter().map(|instruction| instruction.result);
+        for value in parameters.iter().cloned().chain(results) {
+            let copy = function.value(callee.type_of(value).clone());
+            values.insert(value, copy);
+        }
+    }
+
+    for (i, block) in callee.blocks.iter().enumerate() {
+        let mut copy = block.clone();
+        if i == 0 {
+            copy.parameters.clear()
+        }
+        for parameter in copy.parameters.iter_mut() {
+            *parameter = values[parameter]
+        }
+        for instruction in copy.instructions.iter_mut() {
+            instruction.result = values[&instruction.result];
+            for value in instruction.arguments_mut() {
+                *value = values[value]
+            }
+        }
+        copy.terminator = copy.terminator.map(|terminator| match terminator {
+            Terminator::Return(value) => Terminator::Jump(after, vec![values[&value]]),
+            mut terminator => {
+                for value in terminator.arguments_mut() {
+                    *value = values[value]
+                }
+                for (target, _) in terminator.successors_mut() {
+                    *target = blocks[target.0 as usize]
+                }
+                terminator
+            },
+        });
+        function.blocks[blocks[i].0 as usize] = copy;
+    }
+
+    function.blocks[b].terminator = Some(Terminator::Jump(blocks[0], vec![]));
+}
+
+#[cfg(test)]
+mod tests {
+    use ir::opt::tests::{dump, lines};
+
+    #[test]
+    fn inline_small_functions() {
+        let source = "let abs x =\n  if x < 0\n    -x\n  else\n    x\nlet g y = abs y + abs (y - 10)\ng 3";
+        assert_eq!(dump(source, 2), lines(indoc!("
+            function toplevel() -> int {
+            b0:
+                v0: int = const 10
+                return v0
+            }
+            function @abs(int) -> int {
+            b0(v0: int):
+                v1: int = const 0
+                v2: bool = v0 < v1
+                branch v2, b1, b2(v0)
+            b1:
+                v3: int = -v0
+                jump b2(v3)
+            b2(v4: int):
+                return v4
+            }
+            function @g(int) -> int {
+            b0(v0: int):
+                v1: int = const 0
+                v2: bool = v0 < v1
+                branch v2, b1, b2(v0)
+            b1:
+                v3: int = -v0
+                jump b2(v3)
+            b2(v4: int):
+                v5: int = const 10
+                v6: int = v0 - v5
+                v7: bool = v6 < v1
+                branch v7, b3, b4(v6)
+            b3:
+                v8: int = -v6
+                jump b4(v8)
+            b4(v9: int):
+                v10: int = v4 + v9
+                return v10
+            }
+        ")));
+    }
+
+    #[test]
+    fn keep_recursive_calls() {
+        let source = "let loop n = loop (n + 1)\nloop 0";
+        assert_eq!(dump(source, 2), lines(indoc!("
+            function toplevel() -> () {
+            b0:
+                v0: int = const 0
+                v1: () = call @loop(v0)
+                return v1
+            }
+            function @loop(int) -> () {
+            b0(v0: int):
+                v1: int = const 1
+                v2: int = v0 + v1
+                v3: () = call @loop(v2)
+                return v3
+            }
+        ")));
+    }
+}
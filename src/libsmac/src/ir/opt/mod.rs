@@ -0,0 +1,169 @@
+//! Optimizations over the IR.
+//!
+//! Each pass rewrites a module without changing what it
+//! computes or the errors it reports, which keep the
+//! positions of the instructions that raise them.
+
+pub mod fold;
+pub mod cse;
+pub mod dce;
+pub mod inline;
+
+pub use self::fold::Fold;
+pub use self::cse::Cse;
+pub use self::dce::Dce;
+pub use self::inline::Inline;
+
+use ir::Module;
+
+/// The most times a pass manager runs its passes,
+/// in case they keep finding something to change.
+const MAX_ROUNDS: usize = 16;
+
+/// A transformation of a module.
+pub trait Pass {
+    fn name(&self) -> &'static str;
+
+    /// Transforms the module, returning whether it changed.
+    fn run(&mut self, module: &mut Module) -> bool;
+}
+
+/// Runs passes in order, over and over until none of
+/// them changes anything, then renumbers the values.
+pub struct PassManager {
+    passes: Vec<Box<Pass>>,
+}
+
+impl PassManager {
+    pub fn new() -> PassManager {
+        PassManager {
+            passes: Vec::new(),
+        }
+    }
+
+    /// The passes run at an optimization level: none at 0,
+    /// constant folding, common subexpression and dead code
+    /// elimination from 1, and inlining too from 2.
+    pub fn with_level(level: u32) -> PassManager {
+        let mut manager = PassManager::new();
+        if level >= 2 {
+            manager.add(Box::new(Inline));
+        }
+        if level >= 1 {
+            manager.add(Box::new(Fold));
+            manager.add(Box::new(Cse));
+            manager.add(Box::new(Dce));
+        }
+        manager
+    }
+
+    pub fn add(&mut self, pass: Box<Pass>) {
+        self.passes.push(pass)
+    }
+
+    /// The names of the passes, in the order they run.
+    pub fn passes(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    pub fn run(&mut self, module: &mut Module) {
+        if self.passes.is_empty() {
+            return
+        }
+        for _ in 0..MAX_ROUNDS {
+            let mut changed = false;
+            for pass in self.passes.iter_mut() {
+                changed |= pass.run(module)
+            }
+            if !changed {
+                break
+            }
+        }
+        for function in module.functions_mut() {
+            function.renumber()
+        }
+    }
+}
+
+/// Optimizes a module at a level, as `-O<level>` does.
+pub fn optimize(module: &mut Module, level: u32) {
+    PassManager::with_level(level).run(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PassManager;
+    use document::Document;
+    use ir::{lower, verify};
+    use typeck::check;
+
+    /// The lines of the IR for `source` at an optimization
+    /// level, without indentation.
+    pub fn dump(source: &str, level: u32) -> Vec<String> {
+        let document = Document::new(source);
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let types = check(&ast).unwrap();
+        let mut module = lower(&ast, &types).unwrap();
+        PassManager::with_level(level).run(&mut module);
+        assert_eq!(verify(&module), Ok(()), "\n{}", module);
+        lines(&module.to_string())
+    }
+
+    pub fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|line| line.trim().to_owned()).filter(|line| !line.is_empty()).collect()
+    }
+
+    #[test]
+    fn choose_passes_by_level() {
+        assert_eq!(PassManager::with_level(0).passes(), Vec::<&str>::new());
+        assert_eq!(PassManager::with_level(1).passes(), vec!["fold", "cse", "dce"]);
+        assert_eq!(PassManager::with_level(2).passes(), vec!["inline", "fold", "cse", "dce"]);
+    }
+
+    #[test]
+    fn leave_code_alone_at_level_zero() {
+        assert_eq!(dump("1 << 4 + 0x10", 0), lines(indoc!("
+            function toplevel() -> int {
+            b0:
+                v0: int = const 1
+                v1: int = const 4
+                v2: int = const 16
+                v3: int = v1 + v2
+                v4: int = v0 << v3
+                return v4
+            }
+        ")));
+    }
+
+    #[test]
+    fn optimize_whole_programs() {
+        let source = "let square x = x * x\nlet f n =\n  let k = 2 + 3\n  if k > 4\n    square n + square n\n  else\n    n / 0\nf 7";
+        assert_eq!(dump(source, 1)[..7].to_vec(), lines(indoc!("
+            function toplevel() -> int {
+            b0:
+                v0: int = const 7
+                v1: int = call @f(v0)
+                return v1
+            }
+            function @square(int) -> int {
+        ")));
+        assert_eq!(dump(source, 2), lines(indoc!("
+            function toplevel() -> int {
+            b0:
+                v0: int = const 98
+                return v0
+            }
+            function @square(int) -> int {
+            b0(v0: int):
+                v1: int = v0 * v0
+                return v1
+            }
+            function @f(int) -> int {
+            b0(v0: int):
+                v1: int = v0 * v0
+                v2: int = v1 + v1
+                return v2
+            }
+        ")));
+    }
+}
@@ -0,0 +1,326 @@
+use ir::{Block, Constant, Function, InstructionKind, Module, Terminator, Value};
+use lexer::token::Op;
+use typeck::Type;
+
+use std::fmt;
+
+/// Something wrong with the IR of a function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    /// The function, as the text format names it.
+    pub function: String,
+    pub message:  String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "in {}: {}", self.function, self.message)
+    }
+}
+
+/// Checks that a module is well-formed.
+///
+/// Every block must end in a terminator passing its targets
+/// as many arguments as they have parameters, of the same
+/// types. Every value must be defined exactly once, in a
+/// place that dominates all of its uses, and every
+/// instruction must be given operands of the types it takes.
+pub fn verify(module: &Module) -> Result<(), Vec<VerifyError>> {
+    let mut errors = Vec::new();
+    let functions = Some(&module.main).into_iter().chain(module.functions.iter());
+    for function in functions {
+        let mut verifier = Verifier {
+            module:   module,
+            function: function,
+            errors:   Vec::new(),
+        };
+        verifier.function();
+        for message in verifier.errors.into_iter() {
+            errors.push(VerifyError {
+                function: function.display_name(),
+                message:  message,
+            })
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+struct Verifier<'m> {
+    module:   &'m Module,
+    function: &'m Function,
+    errors:   Vec<String>,
+}
+
+impl<'m> Verifier<'m> {
+    fn error(&mut self, message: String) {
+        self.errors.push(message)
+    }
+
+    fn function(&mut self) {
+        let function = self.function;
+        if function.name.is_none() && !function.parameters().is_empty() {
+            self.error("the top-level code takes parameters".to_owned())
+        }
+
+        // Where each value is defined: the block, and the index
+        // of the instruction, or `None` for a parameter
+        let mut definitions: Vec<Option<(usize, Option<usize>)>> = vec![None; function.values.len()];
+        for (b, block) in function.blocks.iter().enumerate() {
+            let parameters = block.parameters.iter().map(|&p| (p, None));
+            let results = block.instructions.iter().enumerate().map(|(i, instruction)| (instruction.result, Some(i)));
+            for (value, index) in parameters.chain(results).collect::<Vec<_>>() {
+                match definitions.get_mut(value.0 as usize) {
+                    Some(&mut Some(_)) => self.error(format!("{} is defined more than once", value)),
+                    Some(slot)         => *slot = Some((b, index)),
+                    None               => self.error(format!("{} has no type", value)),
+                }
+            }
+        }
+
+        if !function.predecessors()[0].is_empty() {
+            self.error(format!("{} is the entry, but is branched to", Block(0)))
+        }
+        let dominators = function.dominators();
+
+        for (b, block) in function.blocks.iter().enumerate() {
+            let here = Block(b as u32);
+
+            for (i, instruction) in block.instructions.iter().enumerate() {
+                for value in instruction.arguments() {
+                    self.check_use(value, here, Some(i), &definitions, &dominators)
+                }
+                self.instruction(&instruction.kind, instruction.result, here)
+            }
+
+            let terminator = match block.terminator {
+                Some(ref terminator) => terminator,
+                None => {
+                    self.error(format!("{} has no terminator", here));
+                    continue
+                },
+            };
+            for value in terminator.arguments() {
+                self.check_use(value, here, None, &definitions, &dominators)
+            }
+            match *terminator {
+                Terminator::Branch(condition, ..) => self.expect(condition, &Type::Bool, here),
+                Terminator::Return(value)         => self.expect(value, &function.result, here),
+                Terminator::Jump(..)              => (),
+            }
+            for (target, arguments) in terminator.successors() {
+                let parameters = match function.blocks.get(target.0 as usize) {
+                    Some(target) => &target.parameters,
+                    None => {
+                        self.error(format!("{} branches to {}, which does not exist", here, target));
+                        continue
+                    },
+                };
+                if parameters.len() != arguments.len() {
+                    self.error(format!("{} passes {} {} arguments, but it takes {}",
+                                       here, target, arguments.len(), parameters.len()));
+                    continue
+                }
+                for (&argument, &parameter) in arguments.iter().zip(parameters.iter()) {
+                    if self.known(argument) && self.known(parameter) {
+                        let t = function.type_of(parameter).clone();
+                        self.expect(argument, &t, here)
+                    }
+                }
+            }
+        }
+    }
+
+    fn known(&self, value: Value) -> bool {
+        (value.0 as usize) < self.function.values.len()
+    }
+
+    /// Checks that a use at instruction `index` of `block`,
+    /// or at its terminator, sees the definition of `value`.
+    fn check_use(&mut self,
+                 value: Value,
+                 block: Block,
+                 index: Option<usize>,
+                 definitions: &[Option<(usize, Option<usize>)>],
+                 dominators: &[Option<Vec<bool>>]) {
+        let (defined_in, defined_at) = match definitions.get(value.0 as usize) {
+            Some(&Some(definition)) => definition,
+            _ => return self.error(format!("{} uses {}, which is never defined", block, value)),
+        };
+        let b = block.0 as usize;
+        let visible = if defined_in == b {
+            match (defined_at, index) {
+                (None, _)                 => true,
+                (Some(_), None)           => true,
+                (Some(at), Some(index))   => at < index,
+            }
+        } else {
+            match dominators[b] {
+                Some(ref dominated_by) => dominated_by[defined_in],
+                // Code that cannot run uses nothing
+                None                   => true,
+            }
+        };
+        if !visible {
+            self.error(format!("{} uses {}, whose definition does not dominate it", block, value))
+        }
+    }
+
+    fn expect(&mut self, value: Value, t: &Type, block: Block) {
+        if !self.known(value) {
+            return
+        }
+        let found = self.function.type_of(value);
+        if found != t {
+            self.error(format!("{} expects {} to be `{}`, found `{}`", block, value, t, found))
+        }
+    }
+
+    fn instruction(&mut self, kind: &InstructionKind, result: Value, block: Block) {
+        if !self.known(result) {
+            return
+        }
+        let function = self.function;
+        let t = function.type_of(result).clone();
+
+        let expected = match *kind {
+            InstructionKind::Const(ref constant) => Some(match *constant {
+                Constant::Unit      => Type::Unit,
+                Constant::Int(_)    => Type::Int,
+                Constant::Float(_)  => Type::Float,
+                Constant::Bool(_)   => Type::Bool,
+                Constant::Char(_)   => Type::Char,
+                Constant::String(_) => Type::String,
+            }),
+            InstructionKind::Unary(op, operand) => {
+                if !self.known(operand) {
+                    return
+                }
+                unary_type(op, function.type_of(operand))
+            },
+            InstructionKind::Binary(op, left, right) => {
+                if !self.known(left) || !self.known(right) {
+                    return
+                }
+                self.expect(right, &function.type_of(left).clone(), block);
+                binary_type(op, function.type_of(left))
+            },
+            InstructionKind::Call(ref name, ref arguments) => {
+                let callee = match self.module.function(name) {
+                    Some(callee) => callee,
+                    None => return self.error(format!("{} calls @{}, which does not exist", block, name)),
+                };
+                let parameters = callee.parameters();
+                if parameters.len() != arguments.len() {
+                    return self.error(format!("{} passes @{} {} arguments, but it takes {}",
+                                              block, name, arguments.len(), parameters.len()))
+                }
+                for (&argument, &parameter) in arguments.iter().zip(parameters.iter()) {
+                    self.expect(argument, callee.type_of(parameter), block)
+                }
+                Some(callee.result.clone())
+            },
+        };
+
+        match expected {
+            Some(ref expected) if *expected == t => (),
+            Some(expected) => {
+                self.error(format!("{} gives {} type `{}`, but it is `{}`", block, result, t, expected))
+            },
+            None => self.error(format!("{} defines {} with an operator its operands do not take", block, result)),
+        }
+    }
+}
+
+/// The type of `op` applied to `t`, if it applies.
+fn unary_type(op: Op, t: &Type) -> Option<Type> {
+    match (op, t) {
+        (Op::Add, &Type::Int) | (Op::Add, &Type::Float)
+            | (Op::Sub, &Type::Int) | (Op::Sub, &Type::Float)
+            | (Op::Not, &Type::Bool)
+            | (Op::BitNot, &Type::Int) => Some(t.clone()),
+        _                              => None,
+    }
+}
+
+/// The type of `op` applied to two operands of type `t`,
+/// if it applies. `&&` and `||` must be branches.
+fn binary_type(op: Op, t: &Type) -> Option<Type> {
+    match (op, t) {
+        (Op::Eq, _) | (Op::Ne, _) => Some(Type::Bool),
+        (Op::Lt, &Type::Int) | (Op::Lt, &Type::Float) | (Op::Lt, &Type::Char) | (Op::Lt, &Type::String)
+            | (Op::Le, &Type::Int) | (Op::Le, &Type::Float) | (Op::Le, &Type::Char) | (Op::Le, &Type::String)
+            | (Op::Gt, &Type::Int) | (Op::Gt, &Type::Float) | (Op::Gt, &Type::Char) | (Op::Gt, &Type::String)
+            | (Op::Ge, &Type::Int) | (Op::Ge, &Type::Float) | (Op::Ge, &Type::Char) | (Op::Ge, &Type::String) => {
+            Some(Type::Bool)
+        },
+        (Op::Add, &Type::String) => Some(Type::String),
+        (Op::Add, &Type::Int) | (Op::Add, &Type::Float)
+            | (Op::Sub, &Type::Int) | (Op::Sub, &Type::Float)
+            | (Op::Mul, &Type::Int) | (Op::Mul, &Type::Float)
+            | (Op::Div, &Type::Int) | (Op::Div, &Type::Float)
+            | (Op::Mod, &Type::Int) | (Op::Mod, &Type::Float) => Some(t.clone()),
+        (Op::BitAnd, &Type::Int) | (Op::BitOr, &Type::Int) | (Op::BitXor, &Type::Int)
+            | (Op::Shl, &Type::Int) | (Op::Shr, &Type::Int) => Some(Type::Int),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify;
+    use document::Document;
+    use ir::{lower, Block, Terminator, Value};
+    use typeck::check;
+
+    fn module(source: &str) -> ::ir::Module {
+        let document = Document::new(source);
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let types = check(&ast).unwrap();
+        lower(&ast, &types).unwrap()
+    }
+
+    fn errors(module: &::ir::Module) -> Vec<String> {
+        match verify(module) {
+            Ok(())      => vec![],
+            Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn accept_lowered_code() {
+        let source = "let f n =\n  let mut x = n\n  if n > 0 && n < 10 || n == 20\n    x = x + 1\n  x\nf 3";
+        assert_eq!(errors(&module(source)), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reject_malformed_code() {
+        let source = "let f n =\n  if n > 0\n    1\n  else\n    2\nf 3";
+        let mut broken = module(source);
+        broken.functions[0].blocks[1].terminator = Some(Terminator::Jump(Block(3), vec![]));
+        assert_eq!(errors(&broken), vec![
+            "in @f: b1 passes b3 0 arguments, but it takes 1".to_owned(),
+        ]);
+
+        let mut broken = module(source);
+        let one = broken.functions[0].blocks[1].instructions.remove(0);
+        assert_eq!(one.result, Value(3));
+        broken.functions[0].blocks[2].instructions.insert(0, one);
+        assert_eq!(errors(&broken), vec![
+            "in @f: b1 uses v3, whose definition does not dominate it".to_owned(),
+        ]);
+
+        let mut broken = module(source);
+        broken.functions[0].blocks[1].terminator = None;
+        broken.functions[0].values[1] = ::typeck::Type::Float;
+        assert_eq!(errors(&broken), vec![
+            "in @f: b0 gives v1 type `float`, but it is `int`".to_owned(),
+            "in @f: b0 expects v1 to be `int`, found `float`".to_owned(),
+            "in @f: b1 has no terminator".to_owned(),
+        ]);
+    }
+}
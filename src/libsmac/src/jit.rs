@@ -0,0 +1,779 @@
+//! Native code generation with Cranelift.
+//!
+//! Top-level functions working only on `int`, `float`, `bool`
+//! and unit values are compiled to machine code in process.
+//! Calls to them are then dispatched to the native code,
+//! leaving everything else to the interpreter.
+//!
+//! Native functions take a pointer to a `Context` before their
+//! arguments. A runtime error stores the site it happened at
+//! there and returns at once, as does every caller after it.
+
+use diagnostic::Diagnostic;
+use interpreter::{Dispatch, MAX_DEPTH};
+use lexer::token::{Op, TokenPosition};
+use parser::node::{Node, NodeType};
+use typeck::{Type, Types};
+use value::Value;
+
+use cranelift_codegen::ir::{self, types, AbiParam, InstBuilder, MemFlags, Signature};
+use cranelift_codegen::ir::condcodes::{IntCC, FloatCC};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+
+use std::collections::HashMap;
+
+/// The state native code shares with the caller.
+#[repr(C)]
+struct Context {
+    /// The site of the runtime error that stopped
+    /// the code, counting from one, or zero.
+    error: u64,
+    /// How deep calls are nested.
+    depth: u64,
+}
+
+const ERROR_OFFSET: i32 = 0;
+const DEPTH_OFFSET: i32 = 8;
+
+/// Calls a native function with its arguments
+/// packed into 64-bit slots, returning its result
+/// packed the same way.
+type Trampoline = unsafe extern "C" fn(*mut Context, *const u64) -> u64;
+
+/// A compiled top-level function.
+struct Compiled {
+    parameters: Vec<Type>,
+    result:     Type,
+    trampoline: Trampoline,
+}
+
+/// Top-level functions compiled to native code.
+pub struct Jit {
+    /// Owns the code, freed on drop.
+    module:    Option<JITModule>,
+    functions: HashMap<String, Compiled>,
+    /// Where each runtime error can happen, and what it is.
+    sites:     Vec<(TokenPosition, &'static str)>,
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // Nothing refers to the code once `self` is gone
+            unsafe { module.free_memory() }
+        }
+    }
+}
+
+extern "C" fn fmod(left: f64, right: f64) -> f64 {
+    left % right
+}
+
+impl Jit {
+    /// Compiles every top-level function which can be,
+    /// given the types `check` found for `nodes`.
+    ///
+    /// Fails only if the host has no code generator.
+    pub fn compile(nodes: &[Node], types: &Types) -> Result<Jit, String> {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").map_err(|e| e.to_string())?;
+        let isa = cranelift_native::builder()
+            .map_err(|e| e.to_owned())?
+            .finish(settings::Flags::new(flags))
+            .map_err(|e| e.to_string())?;
+
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("smac_fmod", fmod as *const u8);
+        let mut module = JITModule::new(builder);
+
+        let definitions = supported_functions(nodes, types);
+
+        let mut sites      = Vec::new();
+        let mut signatures = HashMap::new();
+        for (i, &(name, _, _)) in definitions.iter().enumerate() {
+            let (parameters, result) = match types.function(name) {
+                Some(&Type::Function(ref parameters, ref result)) => (parameters.clone(), (**result).clone()),
+                _ => unreachable!(),
+            };
+            let signature = signature(&module, &parameters, &result);
+            let id = module.declare_function(&format!("smac_{}", i), Linkage::Local, &signature)
+                .map_err(|e| e.to_string())?;
+            signatures.insert(name.to_owned(), (id, parameters, result));
+        }
+
+        let mut fmod_signature = module.make_signature();
+        fmod_signature.params.push(AbiParam::new(types::F64));
+        fmod_signature.params.push(AbiParam::new(types::F64));
+        fmod_signature.returns.push(AbiParam::new(types::F64));
+        let fmod = module.declare_function("smac_fmod", Linkage::Import, &fmod_signature)
+            .map_err(|e| e.to_string())?;
+
+        let mut context  = module.make_context();
+        let mut builders = FunctionBuilderContext::new();
+        let mut trampolines = Vec::new();
+
+        for (i, &(name, parameters, body)) in definitions.iter().enumerate() {
+            let (id, ref parameter_types, ref result) = signatures[name];
+
+            context.func.signature = module.declarations().get_function_decl(id).signature.clone();
+            {
+                let lower = Lower {
+                    builder:    FunctionBuilder::new(&mut context.func, &mut builders),
+                    module:     &mut module,
+                    types:      types,
+                    signatures: &signatures,
+                    fmod:       fmod,
+                    sites:      &mut sites,
+                    context:    None,
+                    result:     result.clone(),
+                    scopes:     Vec::new(),
+                    variables:  0,
+                };
+                lower.function(parameters, parameter_types, body);
+            }
+            module.define_function(id, &mut context).map_err(|e| format!("{:?}", e))?;
+            module.clear_context(&mut context);
+
+            let trampoline = build_trampoline(&mut module, &mut context, &mut builders,
+                                              i, id, parameter_types, result)?;
+            trampolines.push((name, trampoline));
+        }
+
+        module.finalize_definitions().map_err(|e| e.to_string())?;
+
+        let mut functions = HashMap::new();
+        for (name, id) in trampolines {
+            let (_, ref parameters, ref result) = signatures[name];
+            let code = module.get_finalized_function(id);
+            functions.insert(name.to_owned(), Compiled {
+                parameters: parameters.clone(),
+                result:     result.clone(),
+                trampoline: unsafe { ::std::mem::transmute::<*const u8, Trampoline>(code) },
+            });
+        }
+
+        Ok(Jit {
+            module:    Some(module),
+            functions: functions,
+            sites:     sites,
+        })
+    }
+
+    /// The names of the functions compiled, sorted.
+    pub fn compiled(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.functions.keys().map(|n| &n[..]).collect();
+        names.sort();
+        names
+    }
+
+    /// Calls a compiled function, or returns `None` if there is
+    /// none by that name taking arguments of these types.
+    pub fn call(&self, name: &str, arguments: &[Value]) -> Option<Result<Value, Diagnostic>> {
+        let function = self.functions.get(name)?;
+        if function.parameters.len() != arguments.len() {
+            return None
+        }
+
+        let mut slots = Vec::with_capacity(arguments.len());
+        for (t, argument) in function.parameters.iter().zip(arguments.iter()) {
+            slots.push(match (t, argument) {
+                (&Type::Int, &Value::Int(v))       => v as u64,
+                (&Type::Float, &Value::Float(v))   => v.to_bits(),
+                (&Type::Bool, &Value::Boolean(v))  => v as u64,
+                (&Type::Unit, &Value::Unit)        => 0,
+                _                                  => return None,
+            })
+        }
+
+        let mut context = Context {
+            error: 0,
+            depth: 0,
+        };
+        let result = unsafe { (function.trampoline)(&mut context, slots.as_ptr()) };
+
+        if context.error != 0 {
+            let (position, message) = self.sites[context.error as usize - 1];
+            return Some(Err(Diagnostic::new(position, message.to_owned())))
+        }
+
+        Some(Ok(match function.result {
+            Type::Int   => Value::Int(result as i64),
+            Type::Float => Value::Float(f64::from_bits(result)),
+            Type::Bool  => Value::Boolean(result & 0xFF != 0),
+            _           => Value::Unit,
+        }))
+    }
+}
+
+impl Dispatch for Jit {
+    fn call(&mut self, name: &str, arguments: &[Value], _position: TokenPosition)
+        -> Option<Result<Value, Diagnostic>>
+    {
+        Jit::call(self, name, arguments)
+    }
+}
+
+/// The machine type a value of a type is kept in, if supported.
+fn machine_type(t: &Type) -> Option<ir::Type> {
+    match *t {
+        Type::Int                => Some(types::I64),
+        Type::Float              => Some(types::F64),
+        Type::Bool | Type::Unit  => Some(types::I8),
+        _                        => None,
+    }
+}
+
+fn signature(module: &JITModule, parameters: &[Type], result: &Type) -> Signature {
+    let mut signature = module.make_signature();
+    signature.params.push(AbiParam::new(module.target_config().pointer_type()));
+    for parameter in parameters.iter() {
+        signature.params.push(AbiParam::new(machine_type(parameter).unwrap()))
+    }
+    signature.returns.push(AbiParam::new(machine_type(result).unwrap()));
+    signature
+}
+
+/// The top-level functions whose types and bodies native
+/// code supports, calling only each other.
+fn supported_functions<'a>(nodes: &'a [Node], types: &Types) -> Vec<(&'a str, &'a [String], &'a Node)> {
+    let mut functions: Vec<(&str, &[String], &Node)> = nodes.iter().filter_map(|node| {
+        match *node.node_type() {
+            NodeType::Function(ref name, ref parameters, ref body) => {
+                match types.function(name) {
+                    Some(&Type::Function(ref p, ref r)) if p.iter().chain(Some(&**r)).all(|t| machine_type(t).is_some()) => {
+                        Some((&name[..], &parameters[..], &**body))
+                    },
+                    _ => None,
+                }
+            },
+            _ => None,
+        }
+    }).collect();
+
+    // Dropping a function can leave its callers unsupported
+    loop {
+        let count = functions.len();
+        let names: Vec<&str> = functions.iter().map(|&(n, _, _)| n).collect();
+        functions.retain(|&(_, _, body)| supported(body, types, &names));
+        if functions.len() == count {
+            return functions
+        }
+    }
+}
+
+fn supported(node: &Node, types: &Types, functions: &[&str]) -> bool {
+    if types.of(node).and_then(machine_type).is_none() {
+        return false
+    }
+
+    match *node.node_type() {
+        NodeType::IntLiteral(..) | NodeType::FloatLiteral(_)
+            | NodeType::BooleanLiteral(_) | NodeType::Identifier(_) => true,
+        NodeType::Unary(_, ref operand) => supported(operand, types, functions),
+        NodeType::Binary(_, ref left, ref right) => {
+            supported(left, types, functions) && supported(right, types, functions)
+        },
+        NodeType::Call(ref function, ref arguments) => {
+            let named = match *function.node_type() {
+                NodeType::Identifier(ref name) => functions.contains(&&name[..]),
+                _                              => false,
+            };
+            named && arguments.iter().all(|a| supported(a, types, functions))
+        },
+        NodeType::If(ref condition, ref then, ref otherwise) => {
+            supported(condition, types, functions) && supported(then, types, functions)
+                && otherwise.as_ref().map_or(true, |o| supported(o, types, functions))
+        },
+        NodeType::Let(_, _, Some(ref value)) | NodeType::Assign(_, ref value) => supported(value, types, functions),
+        NodeType::CompoundAssign(_, ref target, ref value) => match *target.node_type() {
+            NodeType::Identifier(_) => supported(value, types, functions),
+            _                       => false,
+        },
+        NodeType::Block(ref nodes) => nodes.iter().all(|n| supported(n, types, functions)),
+        _ => false,
+    }
+}
+
+/// Builds the function calling `id` with arguments unpacked from
+/// 64-bit slots, which Rust calls without knowing its signature.
+fn build_trampoline(module: &mut JITModule, context: &mut ::cranelift_codegen::Context,
+                    builders: &mut FunctionBuilderContext, index: usize, id: FuncId,
+                    parameters: &[Type], result: &Type) -> Result<FuncId, String> {
+    let pointer = module.target_config().pointer_type();
+
+    let mut signature = module.make_signature();
+    signature.params.push(AbiParam::new(pointer));
+    signature.params.push(AbiParam::new(pointer));
+    signature.returns.push(AbiParam::new(types::I64));
+
+    let trampoline = module.declare_function(&format!("smac_trampoline_{}", index), Linkage::Local, &signature)
+        .map_err(|e| e.to_string())?;
+    context.func.signature = signature;
+    {
+        let mut builder = FunctionBuilder::new(&mut context.func, builders);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+
+        let params = builder.block_params(entry).to_vec();
+        let mut arguments = vec![params[0]];
+        for (i, parameter) in parameters.iter().enumerate() {
+            let offset = 8 * i as i32;
+            let value = match *parameter {
+                Type::Float => builder.ins().load(types::F64, MemFlags::trusted(), params[1], offset),
+                Type::Int   => builder.ins().load(types::I64, MemFlags::trusted(), params[1], offset),
+                _           => {
+                    let slot = builder.ins().load(types::I64, MemFlags::trusted(), params[1], offset);
+                    builder.ins().ireduce(types::I8, slot)
+                },
+            };
+            arguments.push(value)
+        }
+
+        let callee = module.declare_func_in_func(id, builder.func);
+        let call   = builder.ins().call(callee, &arguments);
+        let value  = builder.inst_results(call)[0];
+        let packed = match *result {
+            Type::Float => builder.ins().bitcast(types::I64, MemFlags::new(), value),
+            Type::Int   => value,
+            _           => builder.ins().uextend(types::I64, value),
+        };
+        builder.ins().return_(&[packed]);
+        builder.finalize();
+    }
+    module.define_function(trampoline, context).map_err(|e| format!("{:?}", e))?;
+    module.clear_context(context);
+    Ok(trampoline)
+}
+
+/// Lowers the body of one function.
+struct Lower<'a, 'f> {
+    builder:    FunctionBuilder<'f>,
+    module:     &'a mut JITModule,
+    types:      &'a Types,
+    signatures: &'a HashMap<String, (FuncId, Vec<Type>, Type)>,
+    fmod:       FuncId,
+    sites:      &'a mut Vec<(TokenPosition, &'static str)>,
+    /// The context pointer.
+    context:    Option<ir::Value>,
+    /// The function's result type.
+    result:     Type,
+    scopes:     Vec<Vec<(String, Variable)>>,
+    variables:  u32,
+}
+
+impl<'a, 'f> Lower<'a, 'f> {
+    fn function(mut self, parameters: &[String], parameter_types: &[Type], body: &Node) {
+        let entry = self.builder.create_block();
+        self.builder.append_block_params_for_function_params(entry);
+        self.builder.switch_to_block(entry);
+        self.builder.seal_block(entry);
+
+        let params = self.builder.block_params(entry).to_vec();
+        self.context = Some(params[0]);
+
+        let mut scope = Vec::new();
+        for (i, (name, t)) in parameters.iter().zip(parameter_types.iter()).enumerate() {
+            let variable = self.variable(t);
+            self.builder.def_var(variable, params[i + 1]);
+            scope.push((name.clone(), variable))
+        }
+        self.scopes.push(scope);
+
+        let value = self.node(body);
+        self.builder.ins().return_(&[value]);
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+    }
+
+    fn variable(&mut self, t: &Type) -> Variable {
+        let variable = Variable::from_u32(self.variables);
+        self.variables += 1;
+        self.builder.declare_var(variable, machine_type(t).unwrap());
+        variable
+    }
+
+    fn type_of(&self, node: &Node) -> Type {
+        self.types.of(node).cloned().unwrap_or(Type::Unit)
+    }
+
+    fn unit(&mut self) -> ir::Value {
+        self.builder.ins().iconst(types::I8, 0)
+    }
+
+    fn lookup(&self, name: &str) -> Variable {
+        for scope in self.scopes.iter().rev() {
+            if let Some(&(_, variable)) = scope.iter().rev().find(|&&(ref n, _)| n == name) {
+                return variable
+            }
+        }
+        unreachable!("names are resolved by the type checker")
+    }
+
+    /// Returns a placeholder of the function's result type,
+    /// for when a runtime error stops the code.
+    fn bail(&mut self) {
+        let value = match self.result {
+            Type::Int   => self.builder.ins().iconst(types::I64, 0),
+            Type::Float => self.builder.ins().f64const(0.0),
+            _           => self.builder.ins().iconst(types::I8, 0),
+        };
+        self.builder.ins().return_(&[value]);
+    }
+
+    /// Stops with an error at `node` if `condition` holds.
+    fn fail_if(&mut self, condition: ir::Value, node: &Node, message: &'static str) {
+        self.sites.push((node.span().start, message));
+        let site = self.sites.len() as i64;
+
+        let failed = self.builder.create_block();
+        let next   = self.builder.create_block();
+        self.builder.ins().brif(condition, failed, &[], next, &[]);
+
+        self.builder.switch_to_block(failed);
+        let context = self.context.unwrap();
+        let site    = self.builder.ins().iconst(types::I64, site);
+        self.builder.ins().store(MemFlags::trusted(), site, context, ERROR_OFFSET);
+        self.bail();
+
+        self.builder.switch_to_block(next);
+    }
+
+    fn node(&mut self, node: &Node) -> ir::Value {
+        match *node.node_type() {
+            NodeType::IntLiteral(value, _)  => self.builder.ins().iconst(types::I64, value as i64),
+            NodeType::FloatLiteral(value)   => self.builder.ins().f64const(value),
+            NodeType::BooleanLiteral(value) => self.builder.ins().iconst(types::I8, value as i64),
+            NodeType::Identifier(ref name) => {
+                let variable = self.lookup(name);
+                self.builder.use_var(variable)
+            },
+            NodeType::Unary(op, ref operand) => {
+                let t     = self.type_of(operand);
+                let value = self.node(operand);
+                match (op, t) {
+                    (Op::Sub, Type::Float) => self.builder.ins().fneg(value),
+                    (Op::Sub, _)           => self.builder.ins().ineg(value),
+                    (Op::Not, _)           => self.builder.ins().bxor_imm(value, 1),
+                    (Op::BitNot, _)        => self.builder.ins().bnot(value),
+                    _                      => value,
+                }
+            },
+            NodeType::Binary(Op::And, ref left, ref right) => self.short_circuit(left, right, false),
+            NodeType::Binary(Op::Or, ref left, ref right)  => self.short_circuit(left, right, true),
+            NodeType::Binary(op, ref left, ref right) => {
+                let t = self.type_of(left);
+                let l = self.node(left);
+                let r = self.node(right);
+                match t {
+                    Type::Float => self.float(op, l, r),
+                    Type::Unit  => {
+                        let equal = (op == Op::Eq) as i64;
+                        self.builder.ins().iconst(types::I8, equal)
+                    },
+                    _ => self.int(node, op, l, r),
+                }
+            },
+            NodeType::Call(ref function, ref arguments) => self.call(node, function, arguments),
+            NodeType::If(ref condition, ref then, ref otherwise) => {
+                let t = match *otherwise {
+                    Some(_) => self.type_of(node),
+                    None    => Type::Unit,
+                };
+                let c = self.node(condition);
+
+                let then_block = self.builder.create_block();
+                let else_block = self.builder.create_block();
+                let merge      = self.builder.create_block();
+                self.builder.append_block_param(merge, machine_type(&t).unwrap());
+                self.builder.ins().brif(c, then_block, &[], else_block, &[]);
+
+                self.builder.switch_to_block(then_block);
+                let mut value = self.node(then);
+                if otherwise.is_none() {
+                    value = self.unit()
+                }
+                self.builder.ins().jump(merge, &[value]);
+
+                self.builder.switch_to_block(else_block);
+                let value = match *otherwise {
+                    Some(ref otherwise) => self.node(otherwise),
+                    None                => self.unit(),
+                };
+                self.builder.ins().jump(merge, &[value]);
+
+                self.builder.switch_to_block(merge);
+                self.builder.block_params(merge)[0]
+            },
+            NodeType::Let(ref name, _, Some(ref value)) => {
+                let t        = self.type_of(value);
+                let value    = self.node(value);
+                let variable = self.variable(&t);
+                self.builder.def_var(variable, value);
+                self.scopes.last_mut().unwrap().push((name.clone(), variable));
+                self.unit()
+            },
+            NodeType::Assign(ref target, ref value) => {
+                let value = self.node(value);
+                if let NodeType::Identifier(ref name) = *target.node_type() {
+                    let variable = self.lookup(name);
+                    self.builder.def_var(variable, value);
+                }
+                self.unit()
+            },
+            NodeType::CompoundAssign(op, ref target, ref value) => {
+                let t = self.type_of(value);
+                let r = self.node(value);
+                if let NodeType::Identifier(ref name) = *target.node_type() {
+                    let variable = self.lookup(name);
+                    let l = self.builder.use_var(variable);
+                    let value = match t {
+                        Type::Float => self.float(op, l, r),
+                        _           => self.int(node, op, l, r),
+                    };
+                    self.builder.def_var(variable, value);
+                }
+                self.unit()
+            },
+            NodeType::Block(ref nodes) => {
+                self.scopes.push(Vec::new());
+                let mut value = None;
+                for node in nodes.iter() {
+                    value = Some(self.node(node))
+                }
+                self.scopes.pop();
+                match value {
+                    Some(value) => value,
+                    None        => self.unit(),
+                }
+            },
+            _ => unreachable!("checked by `supported`"),
+        }
+    }
+
+    /// `&&` when `when` is false, `||` when it is true: the
+    /// right operand only runs if the left one is not `when`.
+    fn short_circuit(&mut self, left: &Node, right: &Node, when: bool) -> ir::Value {
+        let l = self.node(left);
+
+        let rest  = self.builder.create_block();
+        let merge = self.builder.create_block();
+        self.builder.append_block_param(merge, types::I8);
+        if when {
+            self.builder.ins().brif(l, merge, &[l], rest, &[]);
+        } else {
+            self.builder.ins().brif(l, rest, &[], merge, &[l]);
+        }
+
+        self.builder.switch_to_block(rest);
+        let r = self.node(right);
+        self.builder.ins().jump(merge, &[r]);
+
+        self.builder.switch_to_block(merge);
+        self.builder.block_params(merge)[0]
+    }
+
+    fn int(&mut self, node: &Node, op: Op, l: ir::Value, r: ir::Value) -> ir::Value {
+        match op {
+            Op::Add    => self.builder.ins().iadd(l, r),
+            Op::Sub    => self.builder.ins().isub(l, r),
+            Op::Mul    => self.builder.ins().imul(l, r),
+            Op::Div | Op::Mod => {
+                let zero = self.builder.ins().icmp_imm(IntCC::Equal, r, 0);
+                self.fail_if(zero, node, "Division by zero");
+
+                // `sdiv` traps on the minimum divided by -1,
+                // where division wraps instead
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, r, -1);
+                let one       = self.builder.ins().iconst(types::I64, 1);
+                let divisor   = self.builder.ins().select(minus_one, one, r);
+                if op == Op::Div {
+                    let quotient = self.builder.ins().sdiv(l, divisor);
+                    let negated  = self.builder.ins().ineg(l);
+                    self.builder.ins().select(minus_one, negated, quotient)
+                } else {
+                    self.builder.ins().srem(l, divisor)
+                }
+            },
+            Op::BitAnd => self.builder.ins().band(l, r),
+            Op::BitOr  => self.builder.ins().bor(l, r),
+            Op::BitXor => self.builder.ins().bxor(l, r),
+            Op::Shl    => self.builder.ins().ishl(l, r),
+            Op::Shr    => self.builder.ins().sshr(l, r),
+            Op::Eq     => self.builder.ins().icmp(IntCC::Equal, l, r),
+            Op::Ne     => self.builder.ins().icmp(IntCC::NotEqual, l, r),
+            Op::Lt     => self.builder.ins().icmp(IntCC::SignedLessThan, l, r),
+            Op::Le     => self.builder.ins().icmp(IntCC::SignedLessThanOrEqual, l, r),
+            Op::Gt     => self.builder.ins().icmp(IntCC::SignedGreaterThan, l, r),
+            Op::Ge     => self.builder.ins().icmp(IntCC::SignedGreaterThanOrEqual, l, r),
+            _          => unreachable!("checked by the type checker"),
+        }
+    }
+
+    fn float(&mut self, op: Op, l: ir::Value, r: ir::Value) -> ir::Value {
+        match op {
+            Op::Add => self.builder.ins().fadd(l, r),
+            Op::Sub => self.builder.ins().fsub(l, r),
+            Op::Mul => self.builder.ins().fmul(l, r),
+            Op::Div => self.builder.ins().fdiv(l, r),
+            Op::Mod => {
+                let fmod = self.module.declare_func_in_func(self.fmod, self.builder.func);
+                let call = self.builder.ins().call(fmod, &[l, r]);
+                self.builder.inst_results(call)[0]
+            },
+            // Ordered comparisons are false with NaN, and `!=` is true
+            Op::Eq => self.builder.ins().fcmp(FloatCC::Equal, l, r),
+            Op::Ne => self.builder.ins().fcmp(FloatCC::NotEqual, l, r),
+            Op::Lt => self.builder.ins().fcmp(FloatCC::LessThan, l, r),
+            Op::Le => self.builder.ins().fcmp(FloatCC::LessThanOrEqual, l, r),
+            Op::Gt => self.builder.ins().fcmp(FloatCC::GreaterThan, l, r),
+            Op::Ge => self.builder.ins().fcmp(FloatCC::GreaterThanOrEqual, l, r),
+            _      => unreachable!("checked by the type checker"),
+        }
+    }
+
+    fn call(&mut self, node: &Node, function: &Node, arguments: &[Node]) -> ir::Value {
+        let name = match *function.node_type() {
+            NodeType::Identifier(ref name) => name,
+            _                              => unreachable!("checked by `supported`"),
+        };
+
+        let mut values = vec![self.context.unwrap()];
+        for argument in arguments.iter() {
+            values.push(self.node(argument))
+        }
+
+        let context = self.context.unwrap();
+        let depth   = self.builder.ins().load(types::I64, MemFlags::trusted(), context, DEPTH_OFFSET);
+        let full    = self.builder.ins().icmp_imm(IntCC::UnsignedGreaterThanOrEqual, depth, MAX_DEPTH as i64);
+        self.fail_if(full, node, "Stack overflow");
+
+        let deeper = self.builder.ins().iadd_imm(depth, 1);
+        self.builder.ins().store(MemFlags::trusted(), deeper, context, DEPTH_OFFSET);
+
+        let id     = self.signatures[name].0;
+        let callee = self.module.declare_func_in_func(id, self.builder.func);
+        let call   = self.builder.ins().call(callee, &values);
+        let value  = self.builder.inst_results(call)[0];
+
+        self.builder.ins().store(MemFlags::trusted(), depth, context, DEPTH_OFFSET);
+
+        // Pass on errors from the callee
+        let error  = self.builder.ins().load(types::I64, MemFlags::trusted(), context, ERROR_OFFSET);
+        let failed = self.builder.create_block();
+        let next   = self.builder.create_block();
+        self.builder.ins().brif(error, failed, &[], next, &[]);
+        self.builder.switch_to_block(failed);
+        self.bail();
+        self.builder.switch_to_block(next);
+
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Jit;
+    use document::Document;
+    use interpreter::Interpreter;
+    use parser::node::Node;
+    use typeck::check;
+    use value::Value;
+
+    fn parse(source: &str) -> Vec<Node> {
+        let document = Document::new(source);
+        assert!(document.diagnostics().is_empty(), "{:?}", document.diagnostics());
+        document.ast().into_iter().cloned().collect()
+    }
+
+    fn compile(source: &str) -> Jit {
+        let ast = parse(source);
+        Jit::compile(&ast, &check(&ast).unwrap()).unwrap()
+    }
+
+    /// An expression for `value`, since literals cannot be negative.
+    fn int(value: i64) -> String {
+        if value < 0 {
+            format!("(0 - {} - 1)", -(value + 1))
+        } else {
+            value.to_string()
+        }
+    }
+
+    #[test]
+    fn compile_supported_functions() {
+        let jit = compile(concat!(
+            "let fib n =\n  if n < 2\n    n\n  else\n    fib (n - 1) + fib (n - 2)\n",
+            "let text s = s + \"!\"\n",
+            "let shout n = text \"a\"\n",
+            "let mean a b = (a + b) / 2.0\n",
+        ));
+
+        assert_eq!(jit.compiled(), vec!["fib", "mean"]);
+        assert_eq!(jit.call("fib", &[Value::Int(20)]), Some(Ok(Value::Int(6765))));
+        assert_eq!(jit.call("mean", &[Value::Float(1.0), Value::Float(2.0)]), Some(Ok(Value::Float(1.5))));
+        assert_eq!(jit.call("fib", &[Value::Float(1.0)]), None);
+        assert_eq!(jit.call("text", &[Value::String("a".to_owned())]), None);
+    }
+
+    #[test]
+    fn agree_with_the_interpreter() {
+        let functions = concat!(
+            "let ints a b =\n",
+            "  let mut x = a * b - a / b + a % b\n",
+            "  x = x ^ (a << 3) | b >> 1 & ~a\n",
+            "  if -x < 0 && !(a == b) || x >= 10\n",
+            "    x\n",
+            "  else\n",
+            "    0 - x\n",
+            "let floats a b =\n",
+            "  let c = a % b + -a * b\n",
+            "  if c != c || a <= b\n",
+            "    c / 0.0\n",
+            "  else if c > 1.0\n",
+            "    c\n",
+            "  else\n",
+            "    -c\n",
+        );
+        let mut jit = compile(functions);
+
+        let ints = [0, 1, -1, 7, -9, 100, i64::max_value(), i64::min_value()];
+        let floats = ["0.0", "1.5", "(0.0 - 2.25)", "(0.0 / 0.0)", "(1.0 / 0.0)"];
+        let mut calls = Vec::new();
+        for &a in ints.iter() {
+            for &b in ints.iter() {
+                calls.push(format!("ints {} {}", int(a), int(b)))
+            }
+        }
+        for a in floats.iter() {
+            for b in floats.iter() {
+                calls.push(format!("floats {} {}", a, b))
+            }
+        }
+
+        for call in calls.iter() {
+            let ast = parse(&format!("{}{}", functions, call));
+            let expected = Interpreter::new().run(&ast);
+            let native   = Interpreter::with_dispatch(&mut jit).run(&ast);
+            match (expected, native) {
+                (Ok(Value::Float(e)), Ok(Value::Float(n))) if e != e => assert!(n != n, "{}", call),
+                (expected, native) => assert_eq!(expected, native, "{}", call),
+            }
+        }
+    }
+
+    #[test]
+    fn report_runtime_errors() {
+        let jit = compile("let f x = 1 / x\nlet g x = 1 + f x\nlet loop x = loop (x + 1)");
+
+        let error = jit.call("g", &[Value::Int(0)]).unwrap().unwrap_err();
+        assert_eq!((error.position().line, error.position().col, &error.message()[..]), (1, 10, "Division by zero"));
+
+        let error = jit.call("loop", &[Value::Int(0)]).unwrap().unwrap_err();
+        assert_eq!((error.position().line, error.position().col, &error.message()[..]), (3, 13, "Stack overflow"));
+    }
+}
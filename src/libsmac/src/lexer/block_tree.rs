@@ -1,40 +1,99 @@
 use lexer::Token;
 
-#[derive(Debug)]
-pub enum ChunkValue {
-    Source(String),
-    Tokens(Vec<Token>),
-    Block(Branch),
+#[derive(Debug, PartialEq)]
+pub enum ChunkValue<'a> {
+    Source(&'a str),
+    Tokens(Vec<Token<'a>>),
+    Block(Branch<'a>),
 }
 
-#[derive(Debug)]
-pub struct Chunk {
-    value: ChunkValue,
+#[derive(Debug, PartialEq)]
+pub struct Chunk<'a> {
+    value: ChunkValue<'a>,
+    line: usize,
 }
 
-impl Chunk {
-    pub fn new(value: ChunkValue) -> Chunk {
+impl<'a> Chunk<'a> {
+    pub fn new(value: ChunkValue<'a>, line: usize) -> Chunk<'a> {
         Chunk {
             value: value,
+            line:  line,
         }
     }
 
-    pub fn value(&self) -> &ChunkValue {
+    pub fn value(&self) -> &ChunkValue<'a> {
         &self.value
     }
+
+    pub fn value_mut(&mut self) -> &mut ChunkValue<'a> {
+        &mut self.value
+    }
+
+    /// The source line this chunk starts on.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Renumbers this chunk, and the positions of
+    /// its tokens, so it starts on `line`.
+    pub fn renumber(&mut self, line: usize) {
+        let first = self.line;
+        self.line = line;
+
+        match self.value {
+            ChunkValue::Tokens(ref mut tokens) => {
+                for t in tokens.iter_mut() {
+                    let span = t.span_mut();
+                    span.start.line = span.start.line - first + line;
+                    span.end.line   = span.end.line - first + line
+                }
+            },
+            ChunkValue::Block(ref mut b) => b.renumber(line),
+            _                            => (),
+        }
+    }
+
+    /// The last source line covered by this chunk.
+    pub fn last_line(&self) -> usize {
+        match self.value {
+            ChunkValue::Block(ref b) => b.lines().map_or(self.line, |(_, last)| last),
+            _                        => self.line,
+        }
+    }
 }
 
-#[derive(Debug)]
-pub struct Branch {
-    pub value: Vec<Chunk>,
+#[derive(Debug, PartialEq)]
+pub struct Branch<'a> {
+    pub value: Vec<Chunk<'a>>,
 }
 
-impl Branch {
-    pub fn new(value: Vec<Chunk>) -> Branch {
+impl<'a> Branch<'a> {
+    pub fn new(value: Vec<Chunk<'a>>) -> Branch<'a> {
         Branch {
             value: value,
         }
     }
+
+    /// The first and last source line covered by this branch.
+    pub fn lines(&self) -> Option<(usize, usize)> {
+        match (self.value.first(), self.value.last()) {
+            (Some(first), Some(last)) => Some((first.line(), last.last_line())),
+            _                         => None,
+        }
+    }
+
+    /// Renumbers the chunks of this branch so it starts on `line`.
+    pub fn renumber(&mut self, line: usize) {
+        let first = match self.lines() {
+            Some((first, _)) => first,
+            None             => return,
+        };
+
+        for c in self.value.iter_mut() {
+            let chunk_line = c.line - first + line;
+            c.renumber(chunk_line)
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -44,20 +103,20 @@ pub struct BlockTree<'a> {
 }
 
 impl<'a> BlockTree<'a> {
-    pub fn new(source: &str, current_line: usize) -> BlockTree {
+    pub fn new(source: &'a str, current_line: usize) -> BlockTree<'a> {
         BlockTree {
             source:       source,
             current_line: current_line,
         }
     }
 
-    pub fn collect_indents(&self) -> Vec<(usize, &'a str)> {
+    pub fn collect_indents(&self) -> Vec<(usize, usize, &'a str)> {
         let mut indents = Vec::new();
-        let mut lines   = self.source.lines();
+        let mut lines   = self.source.lines().enumerate();
 
-        while let Some(line) = lines.next() {
+        while let Some((number, line)) = lines.next() {
             if line.trim().len() > 0 {
-                indents.push((self.indent(&line), line.trim()))
+                indents.push((number, self.indent(&line), line))
             } 
         }
 
@@ -78,21 +137,21 @@ impl<'a> BlockTree<'a> {
         pos
     }
 
-    pub fn tree(&mut self, indents: &Vec<(usize, &'a str)>) -> Branch {
+    pub fn tree(&mut self, indents: &Vec<(usize, usize, &'a str)>) -> Branch<'a> {
         let mut branch = Branch::new(Vec::new());
         let line       = indents.get(self.current_line);
 
-        let &(base_indent, _) = match line {
+        let &(_, base_indent, _) = match line {
             Some(i) => i,
             None    => return branch,
         };
 
         while self.current_line < indents.len() {
-            let (indent, line) = indents[self.current_line];
+            let (number, indent, line) = indents[self.current_line];
 
             if indent == base_indent {
 
-                branch.value.push(Chunk::new(ChunkValue::Source(line.to_owned())))
+                branch.value.push(Chunk::new(ChunkValue::Source(line), number))
             
             } else if indent < base_indent {
             
@@ -101,7 +160,7 @@ impl<'a> BlockTree<'a> {
                 return branch
 
             } else if indent > base_indent {
-                branch.value.push(Chunk::new(ChunkValue::Block(self.tree(&indents))))
+                branch.value.push(Chunk::new(ChunkValue::Block(self.tree(&indents)), number))
             }
 
             self.current_line += 1
@@ -1,66 +1,105 @@
 use lexer::Tokenizer;
 use lexer::matcher::*;
-use lexer::token::{Token, TokenType, TokenPosition};
+use lexer::token::{Token, TokenType, TokenPosition, Span, Op, Kw, Sym};
 use lexer::block_tree::{Branch, Chunk, ChunkValue};
 
-use std::str::Chars;
+use std::io::{self, Read};
 
-pub fn grab_smaragdine_lexer(data: &mut Chars) -> Lexer {
-    let tokenizer = Tokenizer::new(data);
-    let mut lexer = Lexer::new(tokenizer);
+pub fn grab_smaragdine_lexer<'src>(data: &'src str) -> Lexer<'src> {
+    with_smaragdine_matchers(Lexer::new(Tokenizer::new(data)))
+}
 
-    let symbols = vec![
-        "(",
-        ")",
-        "[",
-        "]",
-        "->",
-    ].iter().map(|&x| x.to_string()).collect();
+/// Lexes a reader as a stream, without holding
+/// the whole source in memory.
+pub fn grab_smaragdine_reader_lexer<'src, R: Read + 'src>(reader: R) -> Lexer<'src> {
+    with_smaragdine_matchers(Lexer::new(Tokenizer::from_reader(reader)))
+}
+
+fn with_smaragdine_matchers(mut lexer: Lexer) -> Lexer {
 
+    let symbols = vec![
+        ("(",  Sym::LParen),
+        (")",  Sym::RParen),
+        ("[",  Sym::LBracket),
+        ("]",  Sym::RBracket),
+        ("->", Sym::Arrow),
+        (".",  Sym::Dot),
+        (",",  Sym::Comma),
+    ].into_iter().map(|(s, x)| (s, TokenType::Symbol(x))).collect();
+
+    // Compound assignments go first so `+=` is not `+` and `=`
     let operators = vec![
-        "+"   // add
-        ,"-"  // sub
-        ,"*"  // mul
-        ,"/"  // div
-        ,"==" // equality
-        ,"="  // assignment
-        ,"<<" // shift left
-        ,"<=" // less than or equal
-        ,"<"  // less than
-        ,">>" // shift right
-        ,">=" // greater than or equal
-        ,">"  // greater than
-        ,"~"  // bitwise negate
-        ,"!"  // logical not
-        ,"&&" // logical and
-        ,"&"  // bitwise and
-        ,"^"  // bitwise xor
-        ,"||" // logical or
-        ,"|"  // bitwise or
-        ,"%"  // modulo
-        ,":"  // type hint
-    ].iter().map(|&x| x.to_string()).collect();
+        ("<<=",  Op::ShlAssign)    // shift left and assign
+        ,(">>=", Op::ShrAssign)    // shift right and assign
+        ,("+=",  Op::AddAssign)    // add and assign
+        ,("-=",  Op::SubAssign)    // sub and assign
+        ,("*=",  Op::MulAssign)    // mul and assign
+        ,("/=",  Op::DivAssign)    // div and assign
+        ,("%=",  Op::ModAssign)    // modulo and assign
+        ,("&=",  Op::BitAndAssign) // bitwise and and assign
+        ,("|=",  Op::BitOrAssign)  // bitwise or and assign
+        ,("^=",  Op::BitXorAssign) // bitwise xor and assign
+        ,("+",  Op::Add)    // add
+        ,("-",  Op::Sub)    // sub
+        ,("*",  Op::Mul)    // mul
+        ,("/",  Op::Div)    // div
+        ,("==", Op::Eq)     // equality
+        ,("!=", Op::Ne)     // inequality
+        ,("=",  Op::Assign) // assignment
+        ,("<<", Op::Shl)    // shift left
+        ,("<=", Op::Le)     // less than or equal
+        ,("<",  Op::Lt)     // less than
+        ,(">>", Op::Shr)    // shift right
+        ,(">=", Op::Ge)     // greater than or equal
+        ,(">",  Op::Gt)     // greater than
+        ,("~",  Op::BitNot) // bitwise negate
+        ,("!",  Op::Not)    // logical not
+        ,("&&", Op::And)    // logical and
+        ,("&",  Op::BitAnd) // bitwise and
+        ,("^",  Op::BitXor) // bitwise xor
+        ,("|>", Op::Pipe)   // pipeline
+        ,("||", Op::Or)     // logical or
+        ,("|",  Op::BitOr)  // bitwise or
+        ,("%",  Op::Mod)    // modulo
+        ,(":",  Op::Hint)   // type hint
+    ].into_iter().map(|(s, x)| (s, TokenType::Operator(x))).collect();
+
+    let ranges = vec![
+        ("..=", Op::RangeInclusive),
+        ("..",  Op::Range),
+    ].into_iter().map(|(s, x)| (s, TokenType::Operator(x))).collect();
 
     let keywords = vec![
-        "let",
-        "if",
-        "else",
-    ].iter().map(|&x| x.to_string()).collect();
+        ("let",  Kw::Let),
+        ("if",   Kw::If),
+        ("else", Kw::Else),
+        ("type", Kw::Type),
+        ("with", Kw::With),
+        ("match", Kw::Match),
+        ("while", Kw::While),
+        ("for",   Kw::For),
+        ("in",    Kw::In),
+        ("break", Kw::Break),
+        ("continue", Kw::Continue),
+        ("mut",   Kw::Mut),
+    ].into_iter().map(|(s, x)| (s, TokenType::Keyword(x))).collect();
 
     let boolean = vec![
-        "true",
-        "false",
-    ].iter().map(|&x| x.to_string()).collect();
-
-    let matcher_symbol         = ConstantMatcher::new(TokenType::Symbol, symbols);
-    let matcher_operator       = ConstantMatcher::new(TokenType::Operator, operators);
-    let matcher_keyword        = ConstantMatcher::new(TokenType::Keyword, keywords);
-    let matcher_boolean        = ConstantMatcher::new(TokenType::BooleanLiteral, boolean);
+        ("true",  true),
+        ("false", false),
+    ].into_iter().map(|(s, x)| (s, TokenType::BooleanLiteral(x))).collect();
+
+    let matcher_symbol         = ConstantMatcher::new(symbols);
+    let matcher_operator       = ConstantMatcher::new(operators);
+    let matcher_range          = ConstantMatcher::new(ranges);
+    let matcher_keyword        = ConstantMatcher::words(keywords);
+    let matcher_boolean        = ConstantMatcher::words(boolean);
     let matcher_whitespace     = WhitespaceMatcher {};
     let matcher_int_literal    = IntLiteralMatcher {};
     let matcher_float_literal  = FloatLiteralMatcher {};
     let matcher_identifier     = IdentifierMatcher {};
     let matcher_string_literal = StringLiteralMatcher {};
+    let matcher_unexpected     = UnexpectedMatcher {};
 
     lexer.matchers_mut().push(Box::new(matcher_whitespace));
     lexer.matchers_mut().push(Box::new(matcher_float_literal));
@@ -69,25 +108,39 @@ pub fn grab_smaragdine_lexer(data: &mut Chars) -> Lexer {
     lexer.matchers_mut().push(Box::new(matcher_boolean));
     lexer.matchers_mut().push(Box::new(matcher_keyword));
     lexer.matchers_mut().push(Box::new(matcher_identifier));
-    lexer.matchers_mut().push(Box::new(matcher_operator));
+    // Ranges go before symbols so `..` is not lexed as two dots,
+    // and symbols before operators so `->` is not `-` and `>`
+    lexer.matchers_mut().push(Box::new(matcher_range));
     lexer.matchers_mut().push(Box::new(matcher_symbol));
+    lexer.matchers_mut().push(Box::new(matcher_operator));
+    lexer.matchers_mut().push(Box::new(matcher_unexpected));
 
     lexer
 }
 
-pub fn lex_branch(branch: &Branch) -> Branch {
+/// Lexes a single line of a block tree,
+/// positioning its tokens on that line.
+pub fn lex_line<'src>(source: &'src str, line: usize) -> Vec<Token<'src>> {
+    grab_smaragdine_lexer(source).map(|mut t| {
+        t.span_mut().start.line += line;
+        t.span_mut().end.line += line;
+        t
+    }).collect()
+}
+
+pub fn lex_branch<'src>(branch: &Branch<'src>) -> Branch<'src> {
     let mut lexed_branch = Branch::new(Vec::new());
 
     for c in branch.value.iter() {
         match c.value() {
-            &ChunkValue::Source(ref s) => {
-                let chunk = ChunkValue::Tokens(grab_smaragdine_lexer(&mut s.clone().chars()).collect());
-                lexed_branch.value.push(Chunk::new(chunk))
+            &ChunkValue::Source(s) => {
+                let chunk = ChunkValue::Tokens(lex_line(s, c.line()));
+                lexed_branch.value.push(Chunk::new(chunk, c.line()))
             },
 
             &ChunkValue::Block(ref b) => {
                 let chunk = ChunkValue::Block(lex_branch(&b));
-                lexed_branch.value.push(Chunk::new(chunk))
+                lexed_branch.value.push(Chunk::new(chunk, c.line()))
             },
 
             _ => (),
@@ -97,13 +150,27 @@ pub fn lex_branch(branch: &Branch) -> Branch {
     lexed_branch
 }
 
-pub fn flatten_branch(branch: &Branch) -> Vec<Token> {
+pub fn flatten_branch<'src>(branch: &Branch<'src>) -> Vec<Token<'src>> {
+    flatten_chunks(&branch.value)
+}
+
+pub fn flatten_chunks<'src>(chunks: &[Chunk<'src>]) -> Vec<Token<'src>> {
     let mut flat = Vec::new();
 
-    for c in branch.value.iter() {
+    for c in chunks.iter() {
         match c.value() {
             &ChunkValue::Tokens(ref t) => flat.append(&mut t.clone()),
-            &ChunkValue::Block(ref b)  => flat.push(Token::new(TokenType::Block(flatten_branch(b)), TokenPosition::new(0, 0), "".to_string())),
+            &ChunkValue::Block(ref b)  => {
+                let tokens = flatten_branch(b);
+                let span   = match (tokens.first(), tokens.last()) {
+                    (Some(first), Some(last)) => first.span().to(last.span()),
+                    _                         => {
+                        let position = TokenPosition::new(c.line() + 1, 0);
+                        Span::new(position, position)
+                    },
+                };
+                flat.push(Token::new(TokenType::Block(tokens), span, ""))
+            },
             _ => continue,
         }
     }
@@ -111,20 +178,20 @@ pub fn flatten_branch(branch: &Branch) -> Vec<Token> {
     flat
 }
 
-pub struct Lexer {
-    tokenizer: Tokenizer,
+pub struct Lexer<'src> {
+    tokenizer: Tokenizer<'src>,
     matchers: Vec<Box<Matcher>>,
 }
 
-impl Lexer {
-    pub fn new(tokenizer: Tokenizer) -> Lexer {
+impl<'src> Lexer<'src> {
+    pub fn new(tokenizer: Tokenizer<'src>) -> Lexer<'src> {
         Lexer {
             tokenizer: tokenizer,
             matchers: Vec::new(),
         }
     }
 
-    pub fn match_token(&mut self) -> Option<Token> {
+    pub fn match_token(&mut self) -> Option<Token<'src>> {
         for matcher in &mut self.matchers {
             match self.tokenizer.try_match_token(matcher.as_ref()) {
                 Some(t) => return Some(t),
@@ -134,6 +201,11 @@ impl Lexer {
         None
     }
     
+    /// Takes the error which ended a reader source early, if any.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.tokenizer.take_error()
+    }
+
     pub fn matchers(&self) -> &Vec<Box<Matcher>> {
         &self.matchers
     }
@@ -143,11 +215,14 @@ impl Lexer {
     }
 }
 
-impl Iterator for Lexer {
-    type Item = Token;
+impl<'src> Iterator for Lexer<'src> {
+    type Item = Token<'src>;
 
-    fn next(&mut self) -> Option<Token> {
-        let token = self.match_token().unwrap();
+    fn next(&mut self) -> Option<Token<'src>> {
+        let token = match self.match_token() {
+            Some(t) => t,
+            None    => return None,
+        };
         match *token.token_type() {
             TokenType::EOF => None,
             TokenType::Whitespace => {
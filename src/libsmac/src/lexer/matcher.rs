@@ -1,6 +1,8 @@
 use lexer::Tokenizer;
 use lexer::token::{Token, TokenType};
 
+use std::borrow::Cow;
+
 macro_rules! token {
     ($tokenizer:expr, $token_type:ident, $accum:expr) => {{
         token!($tokenizer , TokenType::$token_type, $accum)
@@ -8,27 +10,28 @@ macro_rules! token {
     ($tokenizer:expr, $token_type:expr, $accum:expr) => {{
         let tokenizer = $tokenizer as &$crate::lexer::Tokenizer;
         let token_type = $token_type as $crate::lexer::token::TokenType;
-        Some(Token::new(token_type, tokenizer.last_position(), $accum))
+        let span = $crate::lexer::token::Span::new(tokenizer.last_position(), tokenizer.pos);
+        Some(Token::new(token_type, span, $accum))
     }};
 }
 
 /// Matcher.
 pub trait Matcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer) -> Option<Token>;
+    fn try_match<'src>(&self, tokenizer: &mut Tokenizer<'src>) -> Option<Token<'src>>;
 }
 
 /// A matcher that only matches white-space.
 pub struct WhitespaceMatcher {}
 
 impl Matcher for WhitespaceMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer) -> Option<Token> {
+    fn try_match<'src>(&self, tokenizer: &mut Tokenizer<'src>) -> Option<Token<'src>> {
         let mut found = false;
         while !tokenizer.end() && tokenizer.peek().unwrap().is_whitespace() {
             found = true;
             tokenizer.next();
         }
         if found {
-            token!(tokenizer, Whitespace, String::new())
+            token!(tokenizer, Whitespace, "")
         } else {
             None
         }
@@ -39,17 +42,17 @@ impl Matcher for WhitespaceMatcher {
 pub struct IntLiteralMatcher {}
 
 impl Matcher for IntLiteralMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer) -> Option<Token> {
+    fn try_match<'src>(&self, tokenizer: &mut Tokenizer<'src>) -> Option<Token<'src>> {
         let mut accum = String::new();
-        let negative = tokenizer.peek() == Some(&'-');
+        let negative = tokenizer.peek() == Some('-');
         if negative { tokenizer.advance(1) };
-        let base = match tokenizer.peek().unwrap() {
-            &'0' => {
+        let base = match tokenizer.peek() {
+            Some('0') => {
                 match tokenizer.peek_n(1) {
                     Some(chr) => {
                         match chr {
-                            &'x' => 16, // base 16 (hexadecimal)
-                            &'b' => 2, // base 2 (binary)
+                            'x' => 16, // base 16 (hexadecimal)
+                            'b' => 2, // base 2 (binary)
                             _ => 10, // base 10 (decimal)
                         }
                     }
@@ -64,20 +67,23 @@ impl Matcher for IntLiteralMatcher {
         while !tokenizer.end() && tokenizer.peek().unwrap().is_digit(base) {
             accum.push(tokenizer.next().unwrap());
         }
+        if negative && base == 10 && tokenizer.peek() == Some('.')
+            && !tokenizer.peek_n(1).map_or(false, |c| c.is_alphabetic() || c == '_' || c == '.') {
+            // A negative float, left to the minus operator
+            return None
+        }
         if !accum.is_empty() {
             // Produce token as base-10 string
-            let literal: String = if negative {
-                match i64::from_str_radix(accum.as_str(), base) {
-                    Ok(result) => format!("-{}", result),
-                    Err(error) => panic!("Unable to parse integer literal: {}", error)
-                }
+            let parsed = if negative {
+                accum.insert(0, '-');
+                i64::from_str_radix(accum.as_str(), base).map(|result| (result as u64, result.to_string()))
             } else {
-                match u64::from_str_radix(accum.as_str(), base) {
-                    Ok(result) => result.to_string(),
-                    Err(error) => panic!("Unable to parse integer literal: {}", error)
-                }
+                u64::from_str_radix(accum.as_str(), base).map(|result| (result, result.to_string()))
             };
-            token!(tokenizer, IntLiteral, literal)
+            match parsed {
+                Ok((value, literal)) => token!(tokenizer, TokenType::IntLiteral(value, negative), literal),
+                Err(_) => token!(tokenizer, TokenType::Error("Integer literal out of range"), tokenizer.lexeme()),
+            }
         } else {
             None
         }
@@ -85,36 +91,48 @@ impl Matcher for IntLiteralMatcher {
 }
 
 /// A matcher that matches float literals.
+///
+/// A point not followed by a digit is left to be lexed as
+/// a dot, so `.x` and `1.x` are field accesses, unless it
+/// ends a number without starting a name, as in `1.`. Two
+/// points are left to be lexed as a range, as in `0..n`.
 pub struct FloatLiteralMatcher {}
 
 impl Matcher for FloatLiteralMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer) -> Option<Token> {
+    fn try_match<'src>(&self, tokenizer: &mut Tokenizer<'src>) -> Option<Token<'src>> {
         let mut accum = String::new();
-        let curr = tokenizer.next().unwrap();
-        if curr.is_digit(10) {
-            accum.push(curr)
-        } else if curr == '.' {
-            accum.push_str("0.")
-        } else {
-            return None;
+        match tokenizer.next() {
+            Some(curr) if curr.is_digit(10) => accum.push(curr),
+            Some('.') if tokenizer.peek().map_or(false, |c| c.is_digit(10)) => accum.push_str("0."),
+            _ => return None,
         }
-        while !tokenizer.end() {
-            let current = *tokenizer.peek().unwrap();
-            if !current.is_whitespace() && current.is_digit(10) || current == '.' {
+        let mut misplaced_point = false;
+        while let Some(current) = tokenizer.peek() {
+            if current == '.' && tokenizer.peek_n(1).map_or(false, |c| c.is_alphabetic() || c == '_' || c == '.') {
+                break
+            }
+            if current.is_digit(10) || current == '.' {
                 if current == '.' && accum.contains('.') {
-                    panic!("Unexpected decimal point")
+                    misplaced_point = true
                 }
 
-                accum.push(tokenizer.next().unwrap());
+                accum.push(current);
+                tokenizer.advance(1)
             } else {
                 break
             }
         }
+        if misplaced_point {
+            return token!(tokenizer, TokenType::Error("Unexpected decimal point"), tokenizer.lexeme())
+        }
         if accum.chars().last() == Some('.') {
             accum.push('0');
         }
         if accum.contains('.') {
-            token!(tokenizer, FloatLiteral, accum)
+            match accum.parse::<f64>() {
+                Ok(value) => token!(tokenizer, TokenType::FloatLiteral(value), accum),
+                Err(_) => token!(tokenizer, TokenType::Error("Invalid float literal"), tokenizer.lexeme()),
+            }
         } else {
             None
         }
@@ -125,12 +143,12 @@ impl Matcher for FloatLiteralMatcher {
 pub struct StringLiteralMatcher {}
 
 impl Matcher for StringLiteralMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer) -> Option<Token> {
+    fn try_match<'src>(&self, tokenizer: &mut Tokenizer<'src>) -> Option<Token<'src>> {
         let mut raw_marker = false;
-        let delimeter  = match tokenizer.peek().unwrap() {
-            &'"'  => Some('"'),
-            &'\'' => Some('\''),
-            &'r' if tokenizer.peek_n(1) == Some(&'"') => {
+        let delimeter  = match tokenizer.peek() {
+            Some('"')  => Some('"'),
+            Some('\'') => Some('\''),
+            Some('r') if tokenizer.peek_n(1) == Some('"') => {
                 raw_marker = true;
                 tokenizer.advance(1); // Skips prefix
 
@@ -139,113 +157,162 @@ impl Matcher for StringLiteralMatcher {
             _ => return None,
         };
         tokenizer.advance(1); // Skips the opening delimiter
-        let mut string       = String::new();
+        let start = *tokenizer.index();
+        // Only allocated once an escape makes the
+        // content differ from the source text
+        let mut escaped: Option<String> = None;
         let mut found_escape = false;
+        let mut error = None;
         loop {
             if tokenizer.end() {
+                error = Some("Unterminated string literal");
                 break
             }
             if raw_marker {
-                if tokenizer.peek().unwrap() == &'"' {
+                if tokenizer.peek().unwrap() == '"' {
                     break
                 }
-                string.push(tokenizer.next().unwrap())
+                tokenizer.next();
             } else {
                 if found_escape {
-                    string.push(
-                        match tokenizer.next().unwrap() {
-                            c @ '\\' | c @ '\'' | c @ '"' => c,
-                            'n' => '\n',
-                            'r' => '\r',
-                            't' => '\t',
-                            s => panic!("Invalid character escape: {}", s),
-                        }
-                    );
+                    let c = match tokenizer.next().unwrap() {
+                        c @ '\\' | c @ '\'' | c @ '"' => c,
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        c => {
+                            error = Some("Invalid character escape");
+                            c
+                        },
+                    };
+                    escaped.as_mut().unwrap().push(c);
                     found_escape = false
                 } else {
                     match tokenizer.peek().unwrap() {
-                        &'\\' => {
+                        '\\' => {
+                            if escaped.is_none() {
+                                let end = *tokenizer.index();
+                                escaped = Some(tokenizer.slice(start, end).into_owned());
+                            }
                             tokenizer.next();
                             found_escape = true
                         },
-                        &c if &c == &delimeter.unwrap() => break,
-                        _ => string.push(tokenizer.next().unwrap()),
+                        c if c == delimeter.unwrap() => break,
+                        c => {
+                            tokenizer.next();
+                            if let Some(ref mut s) = escaped {
+                                s.push(c)
+                            }
+                        },
                     }
                 }
             }
         }
+        let string = match escaped {
+            Some(s) => Cow::Owned(s),
+            None    => {
+                let end = *tokenizer.index();
+                tokenizer.slice(start, end)
+            },
+        };
         tokenizer.advance(1); // Skips the closing delimeter
+        if let Some(error) = error {
+            return token!(tokenizer, TokenType::Error(error), tokenizer.lexeme())
+        }
         match delimeter.unwrap() {
             '"'  => {
                 token!(tokenizer, StringLiteral, string)
             },
             _ => {
-                if string.len() == 1 {
-                    token!(tokenizer, CharLiteral, string)
+                if string.chars().count() == 1 {
+                    let value = string.chars().nth(0).unwrap();
+                    token!(tokenizer, TokenType::CharLiteral(value), string)
                 } else {
-                    panic!("Invalid char literal")
+                    token!(tokenizer, TokenType::Error("Invalid char literal"), tokenizer.lexeme())
                 }
             },
         }
     }
 }
 
-/// A matcher that matches constant elements
-/// of the specified token type.
+/// A matcher that matches constant elements,
+/// each producing its associated token type.
 pub struct ConstantMatcher {
-    token_type: TokenType,
-    constants: Vec<String>,
+    constants: Vec<(&'static str, TokenType<'static>)>,
+    words:     bool,
 }
 
 impl ConstantMatcher {
-    pub fn new(token_type: TokenType, constants: Vec<String>) -> Self {
+    pub fn new(constants: Vec<(&'static str, TokenType<'static>)>) -> Self {
         ConstantMatcher {
-            token_type: token_type,
             constants: constants,
+            words:     false,
+        }
+    }
+
+    /// Matches constants only as whole words, so
+    /// identifiers may start with them.
+    pub fn words(constants: Vec<(&'static str, TokenType<'static>)>) -> Self {
+        ConstantMatcher {
+            constants: constants,
+            words:     true,
         }
     }
 }
 
 impl Matcher for ConstantMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer) -> Option<Token> {
-        for constant in self.constants.clone() {
-            let dat = tokenizer.clone().take(constant.len());
-            if dat.size_hint().1.unwrap() != constant.len() {
-                return None;
-            }
-            if dat.collect::<String>() == constant {
-                tokenizer.advance(constant.len());
-                return token!(tokenizer, self.token_type.clone(), constant)
+    fn try_match<'src>(&self, tokenizer: &mut Tokenizer<'src>) -> Option<Token<'src>> {
+        for &(constant, ref token_type) in self.constants.iter() {
+            let len = constant.chars().count();
+            if constant.chars().enumerate().all(|(i, c)| tokenizer.peek_n(i) == Some(c)) {
+                if self.words && tokenizer.peek_n(len).map_or(false, is_identifier_char) {
+                    continue
+                }
+                tokenizer.advance(len);
+                return token!(tokenizer, token_type.clone(), constant)
             }
         }
         None
     }
 }
 
+/// Whether `c` may follow the first character of an identifier.
+fn is_identifier_char(c: char) -> bool {
+    "_?!".contains(c) || c.is_alphanumeric()
+}
+
 /// A matcher that matches identifiers.
 pub struct IdentifierMatcher {}
 
 impl Matcher for IdentifierMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer) -> Option<Token> {
-        let mut identifier = String::new();
-        let curr = tokenizer.next().unwrap();
-        if curr.is_alphabetic() || curr == '_' {
-            identifier.push(curr)
-        } else {
-            return None;
+    fn try_match<'src>(&self, tokenizer: &mut Tokenizer<'src>) -> Option<Token<'src>> {
+        match tokenizer.next() {
+            Some(curr) if curr.is_alphabetic() || curr == '_' => (),
+            _ => return None,
         }
-        while !tokenizer.end() {
-            let current = *tokenizer.peek().unwrap();
-            if !current.is_whitespace() && ("_?!".contains(current) || current.is_alphanumeric()) {
-                identifier.push(tokenizer.next().unwrap());
+        while let Some(current) = tokenizer.peek() {
+            // `x!=2` is `x != 2`, but `empty?==x` is `empty? == x`
+            let operator = (current == '!' || current == '?')
+                && tokenizer.peek_n(1) == Some('=') && tokenizer.peek_n(2) != Some('=');
+            if is_identifier_char(current) && !operator {
+                tokenizer.next();
             } else {
                 break;
             }
         }
-        if !identifier.is_empty() {
-            token!(tokenizer, Identifier, identifier)
-        } else {
-            None
+        token!(tokenizer, Identifier, tokenizer.lexeme())
+    }
+}
+
+/// A matcher that matches any single character, so the lexer
+/// can report characters no other matcher accepts and go on.
+pub struct UnexpectedMatcher {}
+
+impl Matcher for UnexpectedMatcher {
+    fn try_match<'src>(&self, tokenizer: &mut Tokenizer<'src>) -> Option<Token<'src>> {
+        match tokenizer.next() {
+            Some(_) => token!(tokenizer, TokenType::Error("Unexpected character"), tokenizer.lexeme()),
+            None    => None,
         }
     }
 }
@@ -1,10 +1,11 @@
 pub mod token;
+pub mod source;
 pub mod tokenizer;
 pub mod matcher;
 pub mod lexer;
 pub mod block_tree;
 
 pub use self::lexer::Lexer;
-pub use self::token::{Token, TokenType};
+pub use self::token::{Token, TokenType, TokenPosition, Span, Op, Kw, Sym};
 pub use self::tokenizer::Tokenizer;
-pub use self::lexer::{grab_smaragdine_lexer, lex_branch, flatten_branch};
\ No newline at end of file
+pub use self::lexer::{grab_smaragdine_lexer, grab_smaragdine_reader_lexer, lex_line, lex_branch, flatten_branch, flatten_chunks};
\ No newline at end of file
@@ -0,0 +1,170 @@
+use std::borrow::Cow;
+use std::io::{self, Read};
+use std::str;
+
+/// A source of characters, addressed by byte offset.
+pub trait Source<'src> {
+    /// Returns the character starting at `offset`,
+    /// or `None` at the end of the source.
+    fn char_at(&mut self, offset: usize) -> Option<char>;
+
+    /// Returns the text between two offsets,
+    /// borrowed from the source where possible.
+    fn slice(&self, start: usize, end: usize) -> Cow<'src, str>;
+
+    /// Signals that nothing before `offset` will be read again.
+    fn release(&mut self, _offset: usize) {}
+
+    /// Takes the error which ended the source early, if any.
+    fn take_error(&mut self) -> Option<io::Error> {
+        None
+    }
+}
+
+/// An in-memory source.
+pub struct StrSource<'src> {
+    data: &'src str,
+}
+
+impl<'src> StrSource<'src> {
+    pub fn new(data: &'src str) -> StrSource<'src> {
+        StrSource {
+            data: data,
+        }
+    }
+}
+
+impl<'src> Source<'src> for StrSource<'src> {
+    fn char_at(&mut self, offset: usize) -> Option<char> {
+        match self.data.get(offset..) {
+            Some(rest) => rest.chars().next(),
+            None       => None,
+        }
+    }
+
+    fn slice(&self, start: usize, end: usize) -> Cow<'src, str> {
+        Cow::Borrowed(&self.data[start..end])
+    }
+}
+
+const READ_CHUNK: usize = 8 * 1024;
+
+/// A source decoding UTF-8 from a reader, only keeping
+/// the text which has not been released in memory.
+///
+/// Invalid UTF-8 is replaced with `U+FFFD`. A read error
+/// ends the source, and can be taken with `take_error`.
+pub struct ReadSource<R: Read> {
+    reader:  R,
+    buffer:  String,
+    base:    usize,
+    pending: Vec<u8>,
+    done:    bool,
+    error:   Option<io::Error>,
+}
+
+impl<R: Read> ReadSource<R> {
+    pub fn new(reader: R) -> ReadSource<R> {
+        ReadSource {
+            reader:  reader,
+            buffer:  String::new(),
+            base:    0,
+            pending: Vec::new(),
+            done:    false,
+            error:   None,
+        }
+    }
+
+    /// Reads and decodes the next chunk,
+    /// returning false once nothing is left.
+    fn fill(&mut self) -> bool {
+        if self.done {
+            return false
+        }
+
+        let mut chunk = [0; READ_CHUNK];
+        let read = loop {
+            match self.reader.read(&mut chunk) {
+                Ok(n) => break n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.error = Some(e);
+                    break 0
+                },
+            }
+        };
+
+        if read == 0 {
+            self.done = true;
+            if !self.pending.is_empty() {
+                self.pending.clear();
+                self.buffer.push('\u{FFFD}');
+                return true
+            }
+            return false
+        }
+
+        self.pending.extend_from_slice(&chunk[..read]);
+        self.decode();
+        true
+    }
+
+    fn decode(&mut self) {
+        let mut start = 0;
+
+        loop {
+            match str::from_utf8(&self.pending[start..]) {
+                Ok(valid) => {
+                    self.buffer.push_str(valid);
+                    start = self.pending.len();
+                    break
+                },
+                Err(error) => {
+                    let valid_end = start + error.valid_up_to();
+                    self.buffer.push_str(str::from_utf8(&self.pending[start..valid_end]).unwrap());
+
+                    match error.error_len() {
+                        Some(len) => {
+                            self.buffer.push('\u{FFFD}');
+                            start = valid_end + len
+                        },
+                        // Incomplete sequence, wait for more input
+                        None => {
+                            start = valid_end;
+                            break
+                        },
+                    }
+                },
+            }
+        }
+
+        self.pending.drain(..start);
+    }
+}
+
+impl<'src, R: Read> Source<'src> for ReadSource<R> {
+    fn char_at(&mut self, offset: usize) -> Option<char> {
+        while offset >= self.base + self.buffer.len() {
+            if !self.fill() {
+                return None
+            }
+        }
+        self.buffer[offset - self.base..].chars().next()
+    }
+
+    fn slice(&self, start: usize, end: usize) -> Cow<'src, str> {
+        Cow::Owned(self.buffer[start - self.base..end - self.base].to_owned())
+    }
+
+    fn release(&mut self, offset: usize) {
+        if offset > self.base {
+            let len = (offset - self.base).min(self.buffer.len());
+            self.buffer.drain(..len);
+            self.base += len;
+        }
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
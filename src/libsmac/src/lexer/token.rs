@@ -1,25 +1,211 @@
+use std::borrow::Cow;
 use std::fmt;
 
 /// Represents the type of a token
 #[derive(Debug, Clone, PartialEq)]
-pub enum TokenType {
-    Block(Vec<Token>),
-    IntLiteral,
-    FloatLiteral,
-    Keyword,
-    Symbol,
-    Operator,
+pub enum TokenType<'src> {
+    Block(Vec<Token<'src>>),
+    IntLiteral(u64, bool /* signed */),
+    FloatLiteral(f64),
+    Keyword(Kw),
+    Symbol(Sym),
+    Operator(Op),
     Identifier,
     Whitespace,
     StringLiteral,
-    CharLiteral,
-    BooleanLiteral,
+    CharLiteral(char),
+    BooleanLiteral(bool),
     LiteralStringLiteral,
+    Error(&'static str),
     EOF,
 }
 
+impl<'src> TokenType<'src> {
+    /// Detaches the token type from the source it was lexed from.
+    pub fn into_owned(self) -> TokenType<'static> {
+        match self {
+            TokenType::Block(tokens)           => TokenType::Block(tokens.into_iter().map(Token::into_owned).collect()),
+            TokenType::IntLiteral(v, signed)   => TokenType::IntLiteral(v, signed),
+            TokenType::FloatLiteral(v)         => TokenType::FloatLiteral(v),
+            TokenType::Keyword(k)              => TokenType::Keyword(k),
+            TokenType::Symbol(s)               => TokenType::Symbol(s),
+            TokenType::Operator(o)             => TokenType::Operator(o),
+            TokenType::Identifier              => TokenType::Identifier,
+            TokenType::Whitespace              => TokenType::Whitespace,
+            TokenType::StringLiteral           => TokenType::StringLiteral,
+            TokenType::CharLiteral(c)          => TokenType::CharLiteral(c),
+            TokenType::BooleanLiteral(b)       => TokenType::BooleanLiteral(b),
+            TokenType::LiteralStringLiteral    => TokenType::LiteralStringLiteral,
+            TokenType::Error(message)          => TokenType::Error(message),
+            TokenType::EOF                     => TokenType::EOF,
+        }
+    }
+}
+
+/// Represents an operator
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Ne,
+    Assign,
+    Shl,
+    Le,
+    Lt,
+    Shr,
+    Ge,
+    Gt,
+    BitNot,
+    Not,
+    And,
+    BitAnd,
+    BitXor,
+    Or,
+    BitOr,
+    Mod,
+    Hint,
+    Range,
+    RangeInclusive,
+    Pipe,
+    AddAssign,
+    SubAssign,
+    MulAssign,
+    DivAssign,
+    ModAssign,
+    ShlAssign,
+    ShrAssign,
+    BitAndAssign,
+    BitOrAssign,
+    BitXorAssign,
+}
+
+impl Op {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Op::Add    => "+",
+            Op::Sub    => "-",
+            Op::Mul    => "*",
+            Op::Div    => "/",
+            Op::Eq     => "==",
+            Op::Ne     => "!=",
+            Op::Assign => "=",
+            Op::Shl    => "<<",
+            Op::Le     => "<=",
+            Op::Lt     => "<",
+            Op::Shr    => ">>",
+            Op::Ge     => ">=",
+            Op::Gt     => ">",
+            Op::BitNot => "~",
+            Op::Not    => "!",
+            Op::And    => "&&",
+            Op::BitAnd => "&",
+            Op::BitXor => "^",
+            Op::Or     => "||",
+            Op::BitOr  => "|",
+            Op::Mod    => "%",
+            Op::Hint   => ":",
+            Op::Range  => "..",
+            Op::RangeInclusive => "..=",
+            Op::Pipe   => "|>",
+            Op::AddAssign    => "+=",
+            Op::SubAssign    => "-=",
+            Op::MulAssign    => "*=",
+            Op::DivAssign    => "/=",
+            Op::ModAssign    => "%=",
+            Op::ShlAssign    => "<<=",
+            Op::ShrAssign    => ">>=",
+            Op::BitAndAssign => "&=",
+            Op::BitOrAssign  => "|=",
+            Op::BitXorAssign => "^=",
+        }
+    }
+
+    /// The operator a compound assignment such as `+=`
+    /// applies to its target and value.
+    pub fn compound(&self) -> Option<Op> {
+        match *self {
+            Op::AddAssign    => Some(Op::Add),
+            Op::SubAssign    => Some(Op::Sub),
+            Op::MulAssign    => Some(Op::Mul),
+            Op::DivAssign    => Some(Op::Div),
+            Op::ModAssign    => Some(Op::Mod),
+            Op::ShlAssign    => Some(Op::Shl),
+            Op::ShrAssign    => Some(Op::Shr),
+            Op::BitAndAssign => Some(Op::BitAnd),
+            Op::BitOrAssign  => Some(Op::BitOr),
+            Op::BitXorAssign => Some(Op::BitXor),
+            _                => None,
+        }
+    }
+}
+
+/// Represents a keyword
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Kw {
+    Let,
+    If,
+    Else,
+    Type,
+    With,
+    Match,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
+    Mut,
+}
+
+impl Kw {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Kw::Let  => "let",
+            Kw::If   => "if",
+            Kw::Else => "else",
+            Kw::Type => "type",
+            Kw::With => "with",
+            Kw::Match => "match",
+            Kw::While => "while",
+            Kw::For   => "for",
+            Kw::In    => "in",
+            Kw::Break => "break",
+            Kw::Continue => "continue",
+            Kw::Mut   => "mut",
+        }
+    }
+}
+
+/// Represents a symbol
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Sym {
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Arrow,
+    Dot,
+    Comma,
+}
+
+impl Sym {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Sym::LParen   => "(",
+            Sym::RParen   => ")",
+            Sym::LBracket => "[",
+            Sym::RBracket => "]",
+            Sym::Arrow    => "->",
+            Sym::Dot      => ".",
+            Sym::Comma    => ",",
+        }
+    }
+}
+
 /// Position of a token
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct TokenPosition {
     pub line: usize,
     pub col: usize,
@@ -49,62 +235,96 @@ impl fmt::Display for TokenPosition {
     }
 }
 
+/// Source range covered by a token or node,
+/// ending just after its last character
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Span {
+    pub start: TokenPosition,
+    pub end: TokenPosition,
+}
+
+impl Span {
+    pub fn new(start: TokenPosition, end: TokenPosition) -> Span {
+        Span {
+            start: start,
+            end: end,
+        }
+    }
+
+    /// The span covering both `self` and `other`.
+    pub fn to(&self, other: &Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
 /// Token representation
+///
+/// The content is borrowed from the source
+/// wherever it appears there verbatim.
 #[derive(Debug, Clone)]
-pub struct Token {
-    token_type: TokenType,
-    position: TokenPosition,
-    content: String,
+pub struct Token<'src> {
+    token_type: TokenType<'src>,
+    span: Span,
+    content: Cow<'src, str>,
 }
 
-impl fmt::Display for Token {
+impl<'src> fmt::Display for Token<'src> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f,
                "Token({}, {:?} '{}')",
-               self.position,
+               self.span.start,
                self.token_type,
                self.content)
     }
 }
 
-impl Token {
-    pub fn new(token_type: TokenType, position: TokenPosition, content: String) -> Token {
+impl<'src> Token<'src> {
+    pub fn new<C: Into<Cow<'src, str>>>(token_type: TokenType<'src>, span: Span, content: C) -> Token<'src> {
         Token {
             token_type: token_type,
-            position: position,
-            content: content,
+            span: span,
+            content: content.into(),
+        }
+    }
+
+    /// Detaches the token from the source it was lexed from.
+    pub fn into_owned(self) -> Token<'static> {
+        Token {
+            token_type: self.token_type.into_owned(),
+            span: self.span,
+            content: Cow::Owned(self.content.into_owned()),
         }
     }
 
     // Immutable access
-    pub fn token_type(&self) -> &TokenType {
+    pub fn token_type(&self) -> &TokenType<'src> {
         &self.token_type
     }
 
     pub fn position(&self) -> &TokenPosition {
-        &self.position
+        &self.span.start
     }
 
-    pub fn content(&self) -> &String {
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn content(&self) -> &str {
         &self.content
     }
 
     // Mutable access
-    pub fn token_type_mut(&mut self) -> &mut TokenType {
+    pub fn token_type_mut(&mut self) -> &mut TokenType<'src> {
         &mut self.token_type
     }
 
-    pub fn position_mut(&mut self) -> &mut TokenPosition {
-        &mut self.position
+    pub fn span_mut(&mut self) -> &mut Span {
+        &mut self.span
     }
 }
 
-impl<'a> PartialEq for Token {
-    fn eq(&self, other: &Token) -> bool {
-        &self.token_type == other.token_type()
+impl<'src> PartialEq for Token<'src> {
+    fn eq(&self, other: &Token<'src>) -> bool {
+        &self.token_type == other.token_type() && &*self.content == other.content()
     }
-
-    fn ne(&self, other: &Token) -> bool {
-        &self.token_type != other.token_type()
-    }
-}
\ No newline at end of file
+}
@@ -1,5 +1,9 @@
 use lexer::matcher::Matcher;
-use lexer::token::{Token, TokenType, TokenPosition};
+use lexer::source::{Source, StrSource, ReadSource};
+use lexer::token::{Token, TokenType, TokenPosition, Span};
+
+use std::borrow::Cow;
+use std::io::{self, Read};
 
 #[derive(Clone, Debug)]
 pub struct Snapshot {
@@ -20,70 +24,83 @@ impl Snapshot {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct Tokenizer {
+pub struct Tokenizer<'src> {
     pub pos: TokenPosition,
     index: usize,
-    items: Vec<char>,
+    source: Box<Source<'src> + 'src>,
     snapshots: Vec<Snapshot>,
 }
 
-impl Iterator for Tokenizer {
+impl<'src> Iterator for Tokenizer<'src> {
     type Item = char;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.read().cloned()
+        self.read()
     }
 }
 
-impl Tokenizer {
-    pub fn new(items: &mut Iterator<Item = char>) -> Tokenizer {
+impl<'src> Tokenizer<'src> {
+    pub fn new(data: &'src str) -> Tokenizer<'src> {
+        Tokenizer::with_source(Box::new(StrSource::new(data)))
+    }
+
+    pub fn from_reader<R: Read + 'src>(reader: R) -> Tokenizer<'src> {
+        Tokenizer::with_source(Box::new(ReadSource::new(reader)))
+    }
+
+    pub fn with_source(source: Box<Source<'src> + 'src>) -> Tokenizer<'src> {
         Tokenizer {
             index: 0,
             pos: TokenPosition::default(),
-            items: items.collect(),
+            source: source,
             snapshots: Vec::new(),
         }
     }
 
-    pub fn end(&self) -> bool {
+    pub fn end(&mut self) -> bool {
         self.end_n(0)
     }
 
-    pub fn end_n(&self, lookahead: usize) -> bool {
-        self.index + lookahead >= self.items.len()
+    pub fn end_n(&mut self, lookahead: usize) -> bool {
+        self.peek_n(lookahead).is_none()
     }
 
-    pub fn peek(&self) -> Option<&char> {
+    pub fn peek(&mut self) -> Option<char> {
         self.peek_n(0)
     }
 
-    pub fn peek_n(&self, n: usize) -> Option<&char> {
-        if self.end_n(n) {
-            return None
+    pub fn peek_n(&mut self, n: usize) -> Option<char> {
+        let mut offset = self.index;
+        for _ in 0..n {
+            match self.source.char_at(offset) {
+                Some(c) => offset += c.len_utf8(),
+                None    => return None,
+            }
         }
-        Some(&self.items[self.index + n])
+        self.source.char_at(offset)
     }
 
-    pub fn read(&mut self) -> Option<&char> {
-        if self.end() {
-            return None
-        }
+    pub fn read(&mut self) -> Option<char> {
+        let c = self.peek();
         self.advance(1);
-        Some(&self.items[self.index - 1])
+        c
     }
 
     pub fn advance(&mut self, a: usize) {
-        for i in 0..a {
-            match self.items[self.index + i] {
-                '\n' => {
+        for _ in 0..a {
+            match self.source.char_at(self.index) {
+                Some('\n') => {
                     self.pos.line += 1;
                     self.pos.col = 0;
+                    self.index += 1;
+                }
+                Some(c) => {
+                    self.pos.col += 1;
+                    self.index += c.len_utf8();
                 }
-                _ => self.pos.col += 1
+                None => break
             }
         }
-        self.index += a;
     }
 
     pub fn take_snapshot(&mut self) {
@@ -98,20 +115,45 @@ impl Tokenizer {
         let snapshot = self.snapshots.pop().unwrap();
         self.index = snapshot.index();
         self.pos = snapshot.pos;
+        self.release();
     }
 
     pub fn commit_snapshot(&mut self) {
         self.snapshots.pop();
+        self.release();
+    }
+
+    /// Lets the source drop everything before
+    /// the current offset once no snapshot needs it.
+    fn release(&mut self) {
+        if self.snapshots.is_empty() {
+            self.source.release(self.index)
+        }
     }
 
     pub fn last_position(&self) -> TokenPosition {
         self.peek_snapshot().unwrap().pos
     }
 
-    pub fn try_match_token(&mut self, matcher: &Matcher) -> Option<Token> {
+    /// The text between two byte offsets,
+    /// borrowed from the source where possible.
+    pub fn slice(&self, start: usize, end: usize) -> Cow<'src, str> {
+        self.source.slice(start, end)
+    }
+
+    /// The text matched since the last snapshot.
+    pub fn lexeme(&self) -> Cow<'src, str> {
+        self.slice(self.peek_snapshot().unwrap().index(), self.index)
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.source.take_error()
+    }
+
+    pub fn try_match_token(&mut self, matcher: &Matcher) -> Option<Token<'src>> {
         if self.end() {
             return Some(Token::new(TokenType::EOF,
-                                   TokenPosition::new(self.index, self.index),
+                                   Span::new(self.pos, self.pos),
                                    String::new()));
         }
 
@@ -130,7 +172,8 @@ impl Tokenizer {
     }
 
     // Immutable access
+    /// The current byte offset into the source.
     pub fn index(&self) -> &usize {
         &self.index
     }
-}
\ No newline at end of file
+}
@@ -1,31 +1,52 @@
 #![feature(plugin)]
 #![plugin(indoc)]
 
+#[cfg(feature = "jit")] extern crate cranelift_codegen;
+#[cfg(feature = "jit")] extern crate cranelift_frontend;
+#[cfg(feature = "jit")] extern crate cranelift_jit;
+#[cfg(feature = "jit")] extern crate cranelift_module;
+#[cfg(feature = "jit")] extern crate cranelift_native;
+
 pub mod lexer;
 pub mod parser;
+pub mod document;
+pub mod diagnostic;
+pub mod serialize;
+pub mod value;
+pub mod typeck;
+pub mod mutability;
+pub mod conventions;
+pub mod interpreter;
+pub mod ir;
+pub mod codegen;
+pub mod vm;
+#[cfg(feature = "jit")]
+pub mod jit;
 
 #[cfg(test)]
 mod tests {
-    use lexer::{Lexer, TokenType};
-    use lexer::grab_smaragdine_lexer;
+    use lexer::{Lexer, TokenType, Op, Kw, Sym};
+    use lexer::{grab_smaragdine_lexer, grab_smaragdine_reader_lexer};
+    use std::io::{self, Read};
     use std::iter::Iterator;
 
     macro_rules! match_seq {
-        (list src: $src:expr, $ttype:path => [ $($tvalue:expr),+, ]) => {{
+        (list src: $src:expr, [ $($ttype:expr => $tvalue:expr),+, ]) => {{
             match_seq!(src: $src $(, $ttype => $tvalue)+);
         }};
-        (list lex: $lex:expr, $ttype:path => [ $($tvalue:expr),+, ]) => {{
+        (list lex: $lex:expr, [ $($ttype:expr => $tvalue:expr),+, ]) => {{
             match_seq!(lex: $lex $(, $ttype => $tvalue)+);
         }};
-        (src: $src:expr $(, $ttype:path => $tvalue:expr)+) => {{
-            let mut lexer = grab_smaragdine_lexer(&mut format!("{}", $src).chars());
+        (src: $src:expr $(, $ttype:expr => $tvalue:expr)+) => {{
+            let src = format!("{}", $src);
+            let mut lexer = grab_smaragdine_lexer(&src);
             match_seq!(lex: lexer $(, $ttype => $tvalue)+);
         }};
-        (lex: $lex:expr $(, $ttype:path => $tvalue:expr)+) => {{
+        (lex: $lex:expr $(, $ttype:expr => $tvalue:expr)+) => {{
             let mut lexer = &mut $lex as &mut Lexer;
             $(match_seq!(inner: lexer, $ttype => $tvalue);)+
         }};
-        (inner: $lex:expr, $ttype:path => $tvalue:expr) => {{
+        (inner: $lex:expr, $ttype:expr => $tvalue:expr) => {{
             let mut lexer = &mut $lex as &mut Lexer;
             let token_type = $ttype as TokenType;
             let token_content = format!("{}", $tvalue);
@@ -48,12 +69,14 @@ mod tests {
                 1234
                 8765
                 192843718371235601
-            "), TokenType::IntLiteral => [
-                0,
-                12,
-                1234,
-                8765,
-                192843718371235601_u64,
+                -1234
+            "), [
+                TokenType::IntLiteral(0, false)                  => 0,
+                TokenType::IntLiteral(12, false)                 => 12,
+                TokenType::IntLiteral(1234, false)               => 1234,
+                TokenType::IntLiteral(8765, false)               => 8765,
+                TokenType::IntLiteral(192843718371235601, false) => 192843718371235601_u64,
+                TokenType::IntLiteral(-1234_i64 as u64, true)    => -1234,
             ]
         );
     }
@@ -69,14 +92,14 @@ mod tests {
                 0xA00000
                 0xABCDEF
                 0xFFFFFF
-            "), TokenType::IntLiteral => [
-                0x0,
-                0xFF,
-                0x1234,
-                0x00000A,
-                0xA00000,
-                0xABCDEF,
-                0xFFFFFF,
+            "), [
+                TokenType::IntLiteral(0x0, false)      => 0x0,
+                TokenType::IntLiteral(0xFF, false)     => 0xFF,
+                TokenType::IntLiteral(0x1234, false)   => 0x1234,
+                TokenType::IntLiteral(0x00000A, false) => 0x00000A,
+                TokenType::IntLiteral(0xA00000, false) => 0xA00000,
+                TokenType::IntLiteral(0xABCDEF, false) => 0xABCDEF,
+                TokenType::IntLiteral(0xFFFFFF, false) => 0xFFFFFF,
             ]
         )
     }
@@ -91,13 +114,13 @@ mod tests {
                 0b0101
                 0b1111
                 0b10101010
-            "), TokenType::IntLiteral => [
-                0b0,
-                0b0000,
-                0b1010,
-                0b0101,
-                0b1111,
-                0b10101010,
+            "), [
+                TokenType::IntLiteral(0b0, false)        => 0b0,
+                TokenType::IntLiteral(0b0000, false)     => 0b0000,
+                TokenType::IntLiteral(0b1010, false)     => 0b1010,
+                TokenType::IntLiteral(0b0101, false)     => 0b0101,
+                TokenType::IntLiteral(0b1111, false)     => 0b1111,
+                TokenType::IntLiteral(0b10101010, false) => 0b10101010,
             ]
         )
     }
@@ -111,15 +134,187 @@ mod tests {
                 .0
                 .86
                 1.0
-                3.141592653
-            "), TokenType::FloatLiteral => [
-                "0.0",
-                "0.0",
-                "0.0",
-                "0.86",
-                "1.0",
-                "3.141592653",
+                12.345678901
+            "), [
+                TokenType::FloatLiteral(0.0)          => "0.0",
+                TokenType::FloatLiteral(0.0)          => "0.0",
+                TokenType::FloatLiteral(0.0)          => "0.0",
+                TokenType::FloatLiteral(0.86)         => "0.86",
+                TokenType::FloatLiteral(1.0)          => "1.0",
+                TokenType::FloatLiteral(12.345678901) => "12.345678901",
+            ]
+        )
+    }
+
+    #[test]
+    fn lex_dots() {
+        match_seq!(
+            list src: "point.x .5 1.x 2. a.b",
+            [
+                TokenType::Identifier             => "point",
+                TokenType::Symbol(Sym::Dot)       => ".",
+                TokenType::Identifier             => "x",
+                TokenType::FloatLiteral(0.5)      => "0.5",
+                TokenType::IntLiteral(1, false)   => 1,
+                TokenType::Symbol(Sym::Dot)       => ".",
+                TokenType::Identifier             => "x",
+                TokenType::FloatLiteral(2.0)      => "2.0",
+                TokenType::Identifier             => "a",
+                TokenType::Symbol(Sym::Dot)       => ".",
+                TokenType::Identifier             => "b",
             ]
         )
     }
+
+    #[test]
+    fn lex_commas() {
+        match_seq!(
+            list src: "[1, 2.]",
+            [
+                TokenType::Symbol(Sym::LBracket)  => "[",
+                TokenType::IntLiteral(1, false)   => 1,
+                TokenType::Symbol(Sym::Comma)     => ",",
+                TokenType::FloatLiteral(2.0)      => "2.0",
+                TokenType::Symbol(Sym::RBracket)  => "]",
+            ]
+        )
+    }
+
+    #[test]
+    fn lex_arrows() {
+        match_seq!(
+            list src: "A x -> x - >",
+            [
+                TokenType::Identifier             => "A",
+                TokenType::Identifier             => "x",
+                TokenType::Symbol(Sym::Arrow)     => "->",
+                TokenType::Identifier             => "x",
+                TokenType::Operator(Op::Sub)      => "-",
+                TokenType::Operator(Op::Gt)       => ">",
+            ]
+        )
+    }
+
+    #[test]
+    fn lex_ranges() {
+        match_seq!(
+            list src: "0..n 0..=9 1.5..x.y",
+            [
+                TokenType::IntLiteral(0, false)         => "0",
+                TokenType::Operator(Op::Range)          => "..",
+                TokenType::Identifier                   => "n",
+                TokenType::IntLiteral(0, false)         => "0",
+                TokenType::Operator(Op::RangeInclusive) => "..=",
+                TokenType::IntLiteral(9, false)         => "9",
+                TokenType::FloatLiteral(1.5)            => "1.5",
+                TokenType::Operator(Op::Range)          => "..",
+                TokenType::Identifier                   => "x",
+                TokenType::Symbol(Sym::Dot)             => ".",
+                TokenType::Identifier                   => "y",
+            ]
+        )
+    }
+
+    #[test]
+    fn lex_constants() {
+        match_seq!(
+            list src: indoc!("
+                let if else
+                << <= < == =
+                ( ]
+                true false
+            "), [
+                TokenType::Keyword(Kw::Let)      => "let",
+                TokenType::Keyword(Kw::If)       => "if",
+                TokenType::Keyword(Kw::Else)     => "else",
+                TokenType::Operator(Op::Shl)     => "<<",
+                TokenType::Operator(Op::Le)      => "<=",
+                TokenType::Operator(Op::Lt)      => "<",
+                TokenType::Operator(Op::Eq)      => "==",
+                TokenType::Operator(Op::Assign)  => "=",
+                TokenType::Symbol(Sym::LParen)   => "(",
+                TokenType::Symbol(Sym::RBracket) => "]",
+                TokenType::BooleanLiteral(true)  => "true",
+                TokenType::BooleanLiteral(false) => "false",
+            ]
+        )
+    }
+
+    #[test]
+    fn lex_words_and_signs() {
+        match_seq!(
+            list src: "letter iffy truest != -1.5 -2",
+            [
+                TokenType::Identifier             => "letter",
+                TokenType::Identifier             => "iffy",
+                TokenType::Identifier             => "truest",
+                TokenType::Operator(Op::Ne)       => "!=",
+                TokenType::Operator(Op::Sub)      => "-",
+                TokenType::FloatLiteral(1.5)      => "1.5",
+                TokenType::IntLiteral(-2_i64 as u64, true) => -2,
+            ]
+        )
+    }
+
+    #[test]
+    fn lex_suffixes_before_equals() {
+        match_seq!(
+            list src: "x!=2 empty?==y done!",
+            [
+                TokenType::Identifier             => "x",
+                TokenType::Operator(Op::Ne)       => "!=",
+                TokenType::IntLiteral(2, false)   => 2,
+                TokenType::Identifier             => "empty?",
+                TokenType::Operator(Op::Eq)       => "==",
+                TokenType::Identifier             => "y",
+                TokenType::Identifier             => "done!",
+            ]
+        )
+    }
+
+    /// Reads at most a few bytes at a time,
+    /// splitting multi-byte characters.
+    struct TrickleReader<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl<'a> Read for TrickleReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.step = self.step % 3 + 1;
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn lex_reader() {
+        let src: String = (0..2000).map(|i| format!("ident_é{} 0x1F \"s\\n☃\" '€' {}.5 <= let\n", i, i)).collect();
+
+        let from_str: Vec<_> = grab_smaragdine_lexer(&src).collect();
+        let from_reader: Vec<_> = grab_smaragdine_reader_lexer(src.as_bytes()).collect();
+        let from_trickle: Vec<_> = grab_smaragdine_reader_lexer(TrickleReader {
+            data: src.as_bytes(),
+            step: 0,
+        }).collect();
+
+        assert_eq!(from_str.len(), 2000 * 7);
+        assert_eq!(from_str, from_reader);
+        assert_eq!(from_str, from_trickle);
+    }
+
+    #[test]
+    fn lex_borrows_source() {
+        let src   = "foo \"bar\" r\"baz\" let <<";
+        let start = src.as_ptr() as usize;
+
+        for token in grab_smaragdine_lexer(src) {
+            let content = token.content().as_ptr() as usize;
+            if token.token_type() == &TokenType::Identifier || token.token_type() == &TokenType::StringLiteral {
+                assert!(content >= start && content < start + src.len());
+            }
+        }
+    }
 }
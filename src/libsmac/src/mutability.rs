@@ -0,0 +1,531 @@
+//! Mutability and definite assignment.
+//!
+//! Bindings are immutable unless declared with `let mut`, and
+//! parameters, loop variables and the names patterns bind
+//! always are. Only a mutable binding may be assigned to, or
+//! have its fields or elements assigned to, except that one
+//! declared without a value, `let x`, may be assigned once.
+//! Such a binding must be assigned on every path to where it
+//! is read, and before any lambda reading it is made.
+
+use diagnostic::Diagnostic;
+use lexer::token::Op;
+use parser::node::{Node, NodeType};
+
+/// Checks the assignments in a program, returning
+/// a diagnostic for each that is not allowed.
+pub fn check(nodes: &[Node]) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        bindings:    Vec::new(),
+        scopes:      vec![Vec::new()],
+        state:       State::new(),
+        loops:       Vec::new(),
+        depth:       0,
+        quiet:       false,
+        diagnostics: Vec::new(),
+    };
+    for node in nodes.iter() {
+        checker.check(node)
+    }
+    checker.diagnostics
+}
+
+/// What bound a name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Let,
+    Parameter,
+    Loop,
+    Pattern,
+}
+
+struct Binding {
+    name:     String,
+    kind:     Kind,
+    mutable:  bool,
+    /// Declared without a value, to be assigned later.
+    deferred: bool,
+    /// The number of functions around it.
+    depth:    usize,
+}
+
+impl Binding {
+    fn describe(&self) -> String {
+        match self.kind {
+            Kind::Let       => format!("immutable `{}`", self.name),
+            Kind::Parameter => format!("parameter `{}`", self.name),
+            Kind::Loop      => format!("loop variable `{}`", self.name),
+            Kind::Pattern   => format!("pattern binding `{}`", self.name),
+        }
+    }
+
+    /// How to make it assignable.
+    fn fix(&self) -> String {
+        match self.kind {
+            Kind::Let => format!("declare it with `let mut {}`", self.name),
+            _         => format!("bind a mutable copy with `let mut {} = {}`", self.name, self.name),
+        }
+    }
+}
+
+/// What is known of the bindings at a point
+/// of the program, by their number.
+#[derive(Debug, Clone)]
+struct State {
+    /// Assigned on every path to here.
+    assigned: Vec<bool>,
+    /// Assigned on some path to here.
+    maybe:    Vec<bool>,
+    /// No path reaches here, after `break` or `continue`.
+    diverged: bool,
+}
+
+impl State {
+    fn new() -> State {
+        State {
+            assigned: Vec::new(),
+            maybe:    Vec::new(),
+            diverged: false,
+        }
+    }
+
+    fn diverged() -> State {
+        State { diverged: true, ..State::new() }
+    }
+
+    fn assigned(&self, id: usize) -> bool {
+        self.diverged || self.assigned.get(id).cloned().unwrap_or(false)
+    }
+
+    fn maybe(&self, id: usize) -> bool {
+        !self.diverged && self.maybe.get(id).cloned().unwrap_or(false)
+    }
+
+    fn set(&mut self, id: usize, assigned: bool) {
+        self.grow(id + 1);
+        self.assigned[id] = assigned;
+        self.maybe[id]    = assigned
+    }
+
+    fn grow(&mut self, len: usize) {
+        if self.assigned.len() < len {
+            self.assigned.resize(len, false);
+            self.maybe.resize(len, false)
+        }
+    }
+
+    /// Adds what may be assigned on the paths to `other`.
+    fn widen(&mut self, other: &State) {
+        if other.diverged {
+            return
+        }
+        self.grow(other.maybe.len());
+        for (id, &maybe) in other.maybe.iter().enumerate() {
+            self.maybe[id] |= maybe
+        }
+    }
+
+    /// The state where the paths to `self` and `other` meet.
+    fn join(self, other: State) -> State {
+        match (self.diverged, other.diverged) {
+            (true, _) => other,
+            (_, true) => self,
+            _         => State {
+                assigned: self.assigned.iter().zip(other.assigned.iter()).map(|(&a, &b)| a && b).collect(),
+                maybe:    self.maybe.iter().zip(other.maybe.iter()).map(|(&a, &b)| a || b).collect(),
+                diverged: false,
+            },
+        }
+    }
+}
+
+/// The states at the `break`s and
+/// `continue`s of a loop.
+struct Exits {
+    breaks:    Vec<State>,
+    continues: Vec<State>,
+}
+
+struct Checker {
+    /// Every binding so far, numbered in order.
+    bindings:    Vec<Binding>,
+    /// The numbers of the bindings in scope, innermost last.
+    scopes:      Vec<Vec<usize>>,
+    state:       State,
+    /// The loops around the node being checked.
+    loops:       Vec<Exits>,
+    /// The number of functions around the node being checked.
+    depth:       usize,
+    /// Whether to keep diagnostics, which are dropped while
+    /// a loop body is first checked for what it assigns.
+    quiet:       bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn error(&mut self, node: &Node, message: String) {
+        if !self.quiet {
+            self.diagnostics.push(Diagnostic::new(node.span().start, message))
+        }
+    }
+
+    fn declare(&mut self, name: &str, kind: Kind, mutable: bool, assigned: bool) {
+        let id = self.bindings.len();
+        self.bindings.push(Binding {
+            name:     name.to_owned(),
+            kind:     kind,
+            mutable:  mutable,
+            deferred: !assigned,
+            depth:    self.depth,
+        });
+        self.scopes.last_mut().unwrap().push(id);
+        self.state.set(id, assigned)
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes.iter().rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|&&id| self.bindings[id].name == name)
+            .cloned()
+    }
+
+    fn check(&mut self, node: &Node) {
+        match *node.node_type() {
+            NodeType::Identifier(ref name) => self.read(node, name),
+            NodeType::Let(ref name, mutable, ref value) => {
+                if let Some(ref value) = *value {
+                    self.check(value)
+                }
+                self.declare(name, Kind::Let, mutable, value.is_some())
+            },
+            NodeType::Assign(ref target, ref value) => {
+                self.check(value);
+                self.assign(target)
+            },
+            NodeType::CompoundAssign(_, ref target, ref value) => {
+                // Read as well as written
+                if let NodeType::Identifier(ref name) = *target.node_type() {
+                    self.read(target, name)
+                }
+                self.check(value);
+                self.assign(target)
+            },
+            NodeType::Function(_, ref parameters, ref body) => {
+                // Functions see none of the names around them
+                let scopes = ::std::mem::replace(&mut self.scopes, vec![Vec::new()]);
+                self.function(parameters, body);
+                self.scopes = scopes;
+            },
+            NodeType::Lambda(ref parameters, ref body) => {
+                self.scopes.push(Vec::new());
+                self.function(parameters, body);
+                self.scopes.pop();
+            },
+            NodeType::If(ref condition, ref then, ref otherwise) => {
+                self.check(condition);
+                let before = self.state.clone();
+                self.check(then);
+                let then = ::std::mem::replace(&mut self.state, before);
+                if let Some(ref otherwise) = *otherwise {
+                    self.check(otherwise)
+                }
+                let otherwise = ::std::mem::replace(&mut self.state, State::new());
+                self.state = then.join(otherwise)
+            },
+            NodeType::Binary(Op::And, ref left, ref right) | NodeType::Binary(Op::Or, ref left, ref right) => {
+                self.check(left);
+                // The right may not run
+                let before = self.state.clone();
+                self.check(right);
+                let right = ::std::mem::replace(&mut self.state, State::new());
+                self.state = before.join(right)
+            },
+            NodeType::Binary(_, ref left, ref right) => {
+                self.check(left);
+                self.check(right)
+            },
+            NodeType::Match(ref value, ref arms) => {
+                self.check(value);
+                let before = self.state.clone();
+                let mut after = State::diverged();
+                for arm in arms.iter() {
+                    self.state = before.clone();
+                    let mut names = Vec::new();
+                    arm.pattern.bindings(&mut names);
+                    self.scopes.push(Vec::new());
+                    for name in names.iter() {
+                        self.declare(name, Kind::Pattern, false, true)
+                    }
+                    self.check(&arm.body);
+                    self.scopes.pop();
+                    let state = ::std::mem::replace(&mut self.state, State::new());
+                    after = after.join(state)
+                }
+                self.state = if arms.is_empty() { before } else { after }
+            },
+            NodeType::While(ref condition, ref body) => {
+                // Only a `break` ends `while true`
+                let exits = match *condition.node_type() {
+                    NodeType::BooleanLiteral(true) => false,
+                    _                              => true,
+                };
+                self.repeat(Some(condition), None, body, exits)
+            },
+            NodeType::For(ref name, ref values, ref body) => {
+                self.check(values);
+                self.repeat(None, Some(name), body, true)
+            },
+            NodeType::Break(ref value) => {
+                if let Some(ref value) = *value {
+                    self.check(value)
+                }
+                let state = ::std::mem::replace(&mut self.state, State::diverged());
+                if let Some(exits) = self.loops.last_mut() {
+                    exits.breaks.push(state)
+                }
+            },
+            NodeType::Continue => {
+                let state = ::std::mem::replace(&mut self.state, State::diverged());
+                if let Some(exits) = self.loops.last_mut() {
+                    exits.continues.push(state)
+                }
+            },
+            NodeType::Block(ref nodes) => {
+                self.scopes.push(Vec::new());
+                for node in nodes.iter() {
+                    self.check(node)
+                }
+                self.scopes.pop();
+            },
+            NodeType::Unary(_, ref operand) => self.check(operand),
+            NodeType::Call(ref function, ref arguments) => {
+                self.check(function);
+                for argument in arguments.iter() {
+                    self.check(argument)
+                }
+            },
+            NodeType::Field(ref value, _) => self.check(value),
+            NodeType::Index(ref list, ref index) => {
+                self.check(list);
+                self.check(index)
+            },
+            NodeType::List(ref elements) | NodeType::Tuple(ref elements) => {
+                for element in elements.iter() {
+                    self.check(element)
+                }
+            },
+            NodeType::With(ref value, ref fields) => {
+                self.check(value);
+                for &(_, ref new) in fields.iter() {
+                    self.check(new)
+                }
+            },
+            NodeType::StringLiteral(_) | NodeType::CharLiteral(_) | NodeType::IntLiteral(..)
+                | NodeType::FloatLiteral(_) | NodeType::BooleanLiteral(_)
+                | NodeType::Record(..) | NodeType::Enum(..) | NodeType::Error => (),
+        }
+    }
+
+    fn read(&mut self, node: &Node, name: &str) {
+        let id = match self.lookup(name) {
+            Some(id) => id,
+            None     => return,
+        };
+        if self.state.assigned(id) {
+            return
+        }
+        let message = if self.state.maybe(id) {
+            format!("`{}` may be used before it is assigned", name)
+        } else {
+            format!("`{}` is used before it is assigned", name)
+        };
+        self.error(node, message);
+        // Reported once
+        self.state.set(id, true)
+    }
+
+    /// Checks an assignment to `target`, which is a
+    /// name, or a field or element of what one names.
+    fn assign(&mut self, target: &Node) {
+        let name = match *target.node_type() {
+            NodeType::Identifier(ref name) => name,
+            NodeType::Field(..) | NodeType::Index(..) => return self.assign_part(target, target),
+            _ => return self.check(target),
+        };
+        let id = match self.lookup(name) {
+            Some(id) => id,
+            None     => return,
+        };
+
+        let (mutable, deferred, depth) = {
+            let binding = &self.bindings[id];
+            (binding.mutable, binding.deferred, binding.depth)
+        };
+        if !mutable {
+            // Assigned its first value, which must be in the function declaring it
+            let first = deferred && depth == self.depth && !self.state.maybe(id);
+            if !first {
+                let binding = &self.bindings[id];
+                let twice   = if deferred && depth == self.depth { " twice" } else { "" };
+                let message = format!("Cannot assign{} to {}; {}", twice, binding.describe(), binding.fix());
+                self.error(target, message)
+            }
+        }
+        self.state.set(id, true)
+    }
+
+    /// Checks an assignment to a field or element of `node`,
+    /// part of the target `target`, which reads it.
+    fn assign_part(&mut self, target: &Node, node: &Node) {
+        match *node.node_type() {
+            NodeType::Field(ref value, _) => self.assign_part(target, value),
+            NodeType::Index(ref list, ref index) => {
+                self.check(index);
+                self.assign_part(target, list)
+            },
+            NodeType::Identifier(ref name) => {
+                self.read(node, name);
+                let binding = match self.lookup(name) {
+                    Some(id) if !self.bindings[id].mutable => &self.bindings[id],
+                    _                                      => return,
+                };
+                let part = match *target.node_type() {
+                    NodeType::Field(..) => "a field",
+                    _                   => "an element",
+                };
+                let message = format!("Cannot assign to {} of {}; {}", part, binding.describe(), binding.fix());
+                self.error(target, message)
+            },
+            _ => self.check(node),
+        }
+    }
+
+    /// Checks the body of a function or lambda, which sees the
+    /// bindings around it as they are when it is made.
+    fn function(&mut self, parameters: &[String], body: &Node) {
+        let state = self.state.clone();
+        let loops = ::std::mem::replace(&mut self.loops, Vec::new());
+        self.depth += 1;
+        for parameter in parameters.iter() {
+            self.declare(parameter, Kind::Parameter, false, true)
+        }
+        self.check(body);
+        self.depth -= 1;
+        self.loops = loops;
+        self.state = state;
+    }
+
+    /// Checks a loop, running `condition` before each time its body
+    /// runs and binding `name` in it, which ends after the condition
+    /// if it `exits`, and otherwise only by breaking.
+    fn repeat(&mut self, condition: Option<&Node>, name: Option<&String>, body: &Node, exits: bool) {
+        let entry = self.state.clone();
+
+        // What the body may assign may have been when it runs again
+        let quiet = ::std::mem::replace(&mut self.quiet, true);
+        let (_, back, _) = self.iterate(condition, name, body);
+        self.quiet = quiet;
+        self.state = entry;
+        self.state.widen(&back);
+
+        let (after, _, breaks) = self.iterate(condition, name, body);
+        let mut state = if exits { after } else { State::diverged() };
+        for exit in breaks {
+            state = state.join(exit)
+        }
+        self.state = state
+    }
+
+    /// Checks the condition and body of a loop once, returning
+    /// the state after the condition, the state after the body
+    /// and the states at its `break`s.
+    fn iterate(&mut self, condition: Option<&Node>, name: Option<&String>, body: &Node) -> (State, State, Vec<State>) {
+        if let Some(condition) = condition {
+            self.check(condition)
+        }
+        let after = self.state.clone();
+
+        self.loops.push(Exits { breaks: Vec::new(), continues: Vec::new() });
+        self.scopes.push(Vec::new());
+        if let Some(name) = name {
+            self.declare(name, Kind::Loop, false, true)
+        }
+        self.check(body);
+        self.scopes.pop();
+        let exits = self.loops.pop().unwrap();
+
+        let mut back = ::std::mem::replace(&mut self.state, State::new());
+        for state in exits.continues {
+            back = back.join(state)
+        }
+        (after, back, exits.breaks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check;
+    use document::Document;
+
+    fn diagnostics(source: &str) -> Vec<String> {
+        let document = Document::new(source);
+        assert!(document.diagnostics().is_empty(), "{:?}", document.diagnostics());
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        check(&ast).iter().map(|d| format!("{} {}", d.position(), d.message())).collect()
+    }
+
+    #[test]
+    fn allow_mutable_bindings() {
+        let source = "let mut x = 1\nx = 2\nx += 3\nlet mut p = [1]\np[0] = x\nlet f n =\n  let mut n = n\n  n -= 1\n  n";
+        assert_eq!(diagnostics(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn report_immutable_assignments() {
+        let source = "let x = 1\nx = 2\nx *= 2\nlet xs = [1]\nxs[0] = 2\nlet p = xs\np.len = 0\nlet f n =\n  n = 1\nfor i in xs\n  i = 0";
+        assert_eq!(diagnostics(source), vec![
+            "[ln 2, col 0] Cannot assign to immutable `x`; declare it with `let mut x`",
+            "[ln 3, col 0] Cannot assign to immutable `x`; declare it with `let mut x`",
+            "[ln 5, col 0] Cannot assign to an element of immutable `xs`; declare it with `let mut xs`",
+            "[ln 7, col 0] Cannot assign to a field of immutable `p`; declare it with `let mut p`",
+            "[ln 9, col 2] Cannot assign to parameter `n`; bind a mutable copy with `let mut n = n`",
+            "[ln 11, col 2] Cannot assign to loop variable `i`; bind a mutable copy with `let mut i = i`",
+        ]);
+    }
+
+    #[test]
+    fn assign_deferred_bindings_once() {
+        let source = "let x\nif true\n  x = 1\nelse\n  x = 2\nx\nlet y\ny = 1\ny = 2\nlet z\nwhile true\n  z = 1";
+        assert_eq!(diagnostics(source), vec![
+            "[ln 9, col 0] Cannot assign twice to immutable `y`; declare it with `let mut y`",
+            "[ln 12, col 2] Cannot assign twice to immutable `z`; declare it with `let mut z`",
+        ]);
+    }
+
+    #[test]
+    fn report_reads_before_assignment() {
+        let source = "let x\nx\nlet y\nif true\n  y = 1\ny\nlet z\nlet f = n -> z\nz = 1";
+        assert_eq!(diagnostics(source), vec![
+            "[ln 2, col 0] `x` is used before it is assigned",
+            "[ln 6, col 0] `y` may be used before it is assigned",
+            "[ln 8, col 13] `z` is used before it is assigned",
+        ]);
+    }
+
+    #[test]
+    fn follow_breaks_and_continues() {
+        let source = "let x\nwhile true\n  x = 1\n  break\nx\nlet y\nfor i in 0..3\n  if i > 1\n    continue\n  y = i\n  break\ny";
+        assert_eq!(diagnostics(source), vec![
+            "[ln 12, col 0] `y` may be used before it is assigned",
+        ]);
+    }
+
+    #[test]
+    fn check_captured_bindings() {
+        let source = "let mut count = 0\nlet bump = n -> count += n\nlet total = 0\nlet add = n -> total = n\nlet later\nlet set = n -> later = n";
+        assert_eq!(diagnostics(source), vec![
+            "[ln 4, col 15] Cannot assign to immutable `total`; declare it with `let mut total`",
+            "[ln 6, col 15] Cannot assign to immutable `later`; declare it with `let mut later`",
+        ]);
+    }
+}
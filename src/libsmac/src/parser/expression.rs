@@ -0,0 +1,1066 @@
+//! Expressions, parsed by precedence climbing.
+//!
+//! An expression ends with its line: operands and
+//! arguments are only read from the line their operator
+//! or function is on, while the indented blocks of an
+//! `if` and the `else` after them, of loops, and the
+//! arms of a `match`, follow on later lines.
+
+use diagnostic::Diagnostic;
+use parser::matcher::{LiteralMatcher, BlockMatcher};
+use parser::nodizer::Nodizer;
+use parser::node::{self, Node, NodeType, Hint, Pattern, Arm};
+use parser::token::{Token, TokenType, Op, Kw, Sym, Span};
+
+pub type Parse = Result<Node, Diagnostic>;
+
+/// The binding power of a binary operator, loosest first
+/// as laid out in `grammar.md`, and whether it is right
+/// associative.
+fn binary_power(op: Op) -> Option<(u8, bool)> {
+    match op {
+        Op::Assign                          => Some((1, true)),
+        Op::Pipe                            => Some((1, false)),
+        Op::Or                              => Some((2, false)),
+        Op::And                             => Some((3, false)),
+        Op::BitOr                           => Some((4, false)),
+        Op::BitXor                          => Some((5, false)),
+        Op::BitAnd                          => Some((6, false)),
+        Op::Eq | Op::Ne                     => Some((7, false)),
+        Op::Lt | Op::Gt | Op::Le | Op::Ge   => Some((8, false)),
+        Op::Range | Op::RangeInclusive      => Some((9, false)),
+        Op::Shl | Op::Shr                   => Some((10, false)),
+        Op::Add | Op::Sub                   => Some((11, false)),
+        Op::Mul | Op::Div | Op::Mod         => Some((12, false)),
+        Op::Not | Op::BitNot | Op::Hint     => None,
+        // Compound assignments, like `=`
+        _                                   => Some((1, true)),
+    }
+}
+
+/// Whether an operator can be made a section.
+fn is_sectioned(op: Op) -> bool {
+    match op {
+        Op::Assign | Op::Pipe => false,
+        op                    => binary_power(op).is_some() && op.compound().is_none(),
+    }
+}
+
+fn is_unary(op: Op) -> bool {
+    match op {
+        Op::Add | Op::Sub | Op::Not | Op::BitNot => true,
+        _                                        => false,
+    }
+}
+
+/// Whether a token can start an argument of a call.
+fn starts_atom(token: &Token) -> bool {
+    match *token.token_type() {
+        TokenType::StringLiteral | TokenType::CharLiteral(_) | TokenType::FloatLiteral(_)
+            | TokenType::BooleanLiteral(_) | TokenType::Identifier
+            | TokenType::Symbol(Sym::LParen) | TokenType::Symbol(Sym::LBracket) => true,
+        // A minus sign in front of a literal is read as subtraction
+        TokenType::IntLiteral(_, signed) => !signed,
+        _ => false,
+    }
+}
+
+/// Whether a token can start an expression.
+pub fn starts_expression(token: &Token) -> bool {
+    match *token.token_type() {
+        TokenType::IntLiteral(..) | TokenType::Keyword(Kw::If)
+            | TokenType::Keyword(Kw::Match) | TokenType::Keyword(Kw::While)
+            | TokenType::Keyword(Kw::For) | TokenType::Keyword(Kw::Break)
+            | TokenType::Keyword(Kw::Continue) => true,
+        TokenType::Operator(op)             => is_unary(op),
+        _                                   => starts_atom(token),
+    }
+}
+
+pub fn parse_expression(nodizer: &mut Nodizer) -> Parse {
+    parse_binary(nodizer, 0)
+}
+
+/// Parses the expression after `token`, which must
+/// start on the same line.
+pub fn parse_operand(nodizer: &mut Nodizer, token: &Token) -> Parse {
+    if nodizer.continues_line() {
+        parse_expression(nodizer)
+    } else {
+        Err(expected(&format!("an expression after `{}`", token.content()), token))
+    }
+}
+
+fn expected(what: &str, after: &Token) -> Diagnostic {
+    Diagnostic::new(after.span().end, format!("Expected {}", what))
+}
+
+fn parse_binary(nodizer: &mut Nodizer, min_power: u8) -> Parse {
+    // Looser than every operator but `=`, with
+    // its body taking the rest of the line
+    if min_power <= binary_power(Op::Assign).unwrap().0 && starts_lambda(nodizer) {
+        return parse_lambda(nodizer)
+    }
+    let left = parse_unary(nodizer)?;
+    parse_binary_from(nodizer, left, min_power)
+}
+
+/// Whether the next tokens are the parameters of a
+/// lambda: lowercase names followed by `->` on their line.
+fn starts_lambda(nodizer: &Nodizer) -> bool {
+    let line = match nodizer.peek() {
+        Some(token) => token.position().line,
+        None        => return false,
+    };
+
+    let mut n = 0;
+    while let Some(token) = nodizer.peek_n(n) {
+        if token.position().line != line {
+            return false
+        }
+        match *token.token_type() {
+            TokenType::Identifier if !is_capitalized(token.content()) => n += 1,
+            TokenType::Symbol(Sym::Arrow) => return n > 0,
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// Parses `parameters -> body`, where the body is the
+/// rest of the line or an indented block.
+fn parse_lambda(nodizer: &mut Nodizer) -> Parse {
+    let start = *nodizer.peek().unwrap().span();
+    let mut parameters = Vec::new();
+    while *nodizer.peek().unwrap().token_type() == TokenType::Identifier {
+        parameters.push(nodizer.next().unwrap().content().to_owned())
+    }
+    let arrow = nodizer.next().unwrap();
+    let body = if is_block(nodizer.peek()) {
+        parse_block(nodizer, "`->`", &arrow)?
+    } else {
+        parse_operand(nodizer, &arrow)?
+    };
+
+    let span = start.to(body.span());
+    Ok(Node::new(NodeType::Lambda(parameters, Box::new(body)), span))
+}
+
+/// Parses the operators following `left`
+/// that bind at least as tightly as `min_power`.
+fn parse_binary_from(nodizer: &mut Nodizer, mut left: Node, min_power: u8) -> Parse {
+    while nodizer.continues_line() {
+        let token = nodizer.peek().unwrap().clone();
+        let (op, operand) = match *token.token_type() {
+            // Looser than every operator but `=`
+            TokenType::Keyword(Kw::With) if min_power <= binary_power(Op::Assign).unwrap().0 => {
+                left = parse_with(nodizer, left)?;
+                continue
+            },
+            TokenType::Operator(op) if binary_power(op).is_some() => (op, None),
+            // `a -1` lexes the sign into the literal
+            TokenType::IntLiteral(value, true) => {
+                let mut span = *token.span();
+                span.start.col += 1;
+                let magnitude = (value as i64).wrapping_neg() as u64;
+                (Op::Sub, Some(Node::new(NodeType::IntLiteral(magnitude, false), span)))
+            },
+            _ => break,
+        };
+
+        let (power, right_associative) = binary_power(op).unwrap();
+        if power < min_power || ends_section(nodizer) {
+            break
+        }
+        nodizer.next();
+
+        let next_power = if right_associative { power } else { power + 1 };
+        let right = match operand {
+            Some(operand) => {
+                let operand = parse_arguments(nodizer, operand)?;
+                parse_binary_from(nodizer, operand, next_power)?
+            },
+            None => {
+                if !nodizer.continues_line() {
+                    return Err(expected(&format!("an expression after `{}`", op.as_str()), &token))
+                }
+                parse_binary(nodizer, next_power)?
+            },
+        };
+
+        let span = left.span().to(right.span());
+        left = if op == Op::Assign || op.compound().is_some() {
+            match *left.node_type() {
+                NodeType::Identifier(_) | NodeType::Field(..) | NodeType::Index(..) => (),
+                _ => return Err(Diagnostic::new(left.span().start, "Invalid assignment target".to_owned())),
+            }
+            match op.compound() {
+                Some(op) => Node::new(NodeType::CompoundAssign(op, Box::new(left), Box::new(right)), span),
+                None     => Node::new(NodeType::Assign(Box::new(left), Box::new(right)), span),
+            }
+        } else if op == Op::Pipe {
+            pipe(left, right, span)
+        } else {
+            Node::new(NodeType::Binary(op, Box::new(left), Box::new(right)), span)
+        }
+    }
+
+    Ok(left)
+}
+
+/// The call `left |> right` stands for, applying `right` to
+/// `left` after any arguments it is already given.
+fn pipe(left: Node, mut right: Node, span: Span) -> Node {
+    let node_type = match ::std::mem::replace(right.node_type_mut(), NodeType::Error) {
+        NodeType::Call(function, mut arguments) => {
+            arguments.push(left);
+            NodeType::Call(function, arguments)
+        },
+        function => {
+            *right.node_type_mut() = function;
+            NodeType::Call(Box::new(right), vec![left])
+        },
+    };
+    Node::new(node_type, span)
+}
+
+/// Parses the `field = value` pairs after `with`.
+fn parse_with(nodizer: &mut Nodizer, value: Node) -> Parse {
+    let mut fields = Vec::new();
+    loop {
+        let token = nodizer.next().unwrap();
+        let name = match nodizer.peek() {
+            Some(t) if nodizer.continues_line() && *t.token_type() == TokenType::Identifier => t.clone(),
+            _ => return Err(expected(&format!("a field name after `{}`", token.content()), &token)),
+        };
+        nodizer.next();
+        match nodizer.peek() {
+            Some(t) if nodizer.continues_line() && *t.token_type() == TokenType::Operator(Op::Assign) => (),
+            _ => return Err(expected("`=`", &name)),
+        }
+        let assign = nodizer.next().unwrap();
+        if !nodizer.continues_line() {
+            return Err(expected("an expression after `=`", &assign))
+        }
+        // Neither `=` nor another `with`
+        let new = parse_binary(nodizer, binary_power(Op::Or).unwrap().0)?;
+        fields.push((name.content().to_owned(), new));
+
+        // Any other comma belongs to a list, tuple or call around it
+        let field = match (nodizer.peek(), nodizer.peek_n(1), nodizer.peek_n(2)) {
+            (Some(comma), Some(name), Some(assign)) => {
+                *comma.token_type() == TokenType::Symbol(Sym::Comma)
+                    && *name.token_type() == TokenType::Identifier
+                    && *assign.token_type() == TokenType::Operator(Op::Assign)
+                    && name.position().line == comma.position().line
+                    && assign.position().line == comma.position().line
+            },
+            _ => false,
+        };
+        if !nodizer.continues_line() || !field {
+            break
+        }
+    }
+
+    let span = value.span().to(fields.last().unwrap().1.span());
+    Ok(Node::new(NodeType::With(Box::new(value), fields), span))
+}
+
+fn parse_unary(nodizer: &mut Nodizer) -> Parse {
+    let token = nodizer.peek().unwrap().clone();
+
+    match *token.token_type() {
+        TokenType::Operator(op) if is_unary(op) => {
+            nodizer.next();
+            if !nodizer.continues_line() {
+                return Err(expected(&format!("an expression after `{}`", op.as_str()), &token))
+            }
+            let operand = parse_unary(nodizer)?;
+            let span    = token.span().to(operand.span());
+            Ok(Node::new(NodeType::Unary(op, Box::new(operand)), span))
+        },
+        _ => parse_postfix(nodizer),
+    }
+}
+
+fn parse_postfix(nodizer: &mut Nodizer) -> Parse {
+    let function = parse_atom(nodizer)?;
+    parse_arguments(nodizer, function)
+}
+
+/// Applies `function` to the atoms following it on its line.
+fn parse_arguments(nodizer: &mut Nodizer, function: Node) -> Parse {
+    let mut arguments = Vec::new();
+    while nodizer.continues_line() && starts_atom(nodizer.peek().unwrap()) {
+        arguments.push(parse_atom(nodizer)?)
+    }
+
+    match arguments.last().map(|a| *a.span()) {
+        Some(last) => {
+            let span = function.span().to(&last);
+            Ok(Node::new(NodeType::Call(Box::new(function), arguments), span))
+        },
+        None => Ok(function),
+    }
+}
+
+/// Parses a primary expression and the fields and elements
+/// read from it, which bind tighter than application: `f p.x`
+/// is `f (p.x)`. An index must follow right after what it
+/// indexes, since `f [1]` applies `f` to a list.
+fn parse_atom(nodizer: &mut Nodizer) -> Parse {
+    let mut value = parse_primary(nodizer)?;
+    while nodizer.continues_line() {
+        let token = nodizer.peek().unwrap().clone();
+        match *token.token_type() {
+            TokenType::Symbol(Sym::Dot) => {
+                let dot = nodizer.next().unwrap();
+                let name = match nodizer.peek() {
+                    Some(t) if nodizer.continues_line() && *t.token_type() == TokenType::Identifier => t.clone(),
+                    _ => return Err(expected("a field name after `.`", &dot)),
+                };
+                nodizer.next();
+                let span = value.span().to(name.span());
+                value = Node::new(NodeType::Field(Box::new(value), name.content().to_owned()), span)
+            },
+            TokenType::Symbol(Sym::LBracket) if nodizer.previous().unwrap().span().end == token.span().start => {
+                nodizer.next();
+                let index = parse_operand(nodizer, &token)?;
+                let close = close(nodizer, Sym::RBracket)?;
+                let span  = value.span().to(close.span());
+                value = Node::new(NodeType::Index(Box::new(value), Box::new(index)), span)
+            },
+            _ => break,
+        }
+    }
+    Ok(value)
+}
+
+/// Takes the symbol closing a bracketed expression,
+/// which must end on the line it started on.
+fn close<'src>(nodizer: &mut Nodizer<'src>, symbol: Sym) -> Result<Token<'src>, Diagnostic> {
+    let last = nodizer.previous().unwrap().clone();
+    match nodizer.peek() {
+        Some(t) if nodizer.continues_line() && *t.token_type() == TokenType::Symbol(symbol) => (),
+        _ => return Err(expected(&format!("`{}`", symbol.as_str()), &last)),
+    }
+    Ok(nodizer.next().unwrap())
+}
+
+/// Parses the comma-separated elements of a list literal.
+fn parse_list(nodizer: &mut Nodizer) -> Parse {
+    let open = nodizer.next().unwrap();
+    let mut elements = Vec::new();
+    loop {
+        let last = nodizer.previous().unwrap().clone();
+        match nodizer.peek().map(|t| t.token_type().clone()) {
+            Some(TokenType::Symbol(Sym::RBracket)) if nodizer.continues_line() => break,
+            Some(TokenType::Symbol(Sym::Comma)) if nodizer.continues_line() && !elements.is_empty() => {
+                let comma = nodizer.next().unwrap();
+                elements.push(parse_operand(nodizer, &comma)?)
+            },
+            _ if elements.is_empty() => elements.push(parse_operand(nodizer, &open)?),
+            _ => return Err(expected("`,` or `]`", &last)),
+        }
+    }
+    let close = nodizer.next().unwrap();
+    Ok(Node::new(NodeType::List(elements), open.span().to(close.span())))
+}
+
+fn parse_primary(nodizer: &mut Nodizer) -> Parse {
+    let token = nodizer.peek().unwrap().clone();
+
+    match *token.token_type() {
+        TokenType::Identifier => {
+            nodizer.next();
+            Ok(Node::new(NodeType::Identifier(token.content().to_owned()), *token.span()))
+        },
+        TokenType::Symbol(Sym::LParen) => {
+            nodizer.next();
+            if let Some(op) = starts_section(nodizer) {
+                return parse_right_section(nodizer, &token, op)
+            }
+            let first = parse_operand(nodizer, &token)?;
+            if ends_section(nodizer) {
+                let op = match *nodizer.next().unwrap().token_type() {
+                    TokenType::Operator(op) => op,
+                    _                       => unreachable!(),
+                };
+                let close = nodizer.next().unwrap();
+                return Ok(section(op, first, true, token.span().to(close.span())))
+            }
+
+            let mut elements = vec![first];
+            while comma_follows(nodizer) {
+                let comma = nodizer.next().unwrap();
+                elements.push(parse_operand(nodizer, &comma)?)
+            }
+            let close = close(nodizer, Sym::RParen)?;
+            let span  = token.span().to(close.span());
+            if elements.len() > 1 {
+                return Ok(Node::new(NodeType::Tuple(elements), span))
+            }
+            let mut inner = elements.pop().unwrap();
+            *inner.span_mut() = span;
+            Ok(inner)
+        },
+        TokenType::Symbol(Sym::LBracket) => parse_list(nodizer),
+        TokenType::Keyword(Kw::If) => parse_if(nodizer),
+        TokenType::Keyword(Kw::Match) => parse_match(nodizer),
+        TokenType::Keyword(Kw::While) => parse_while(nodizer),
+        TokenType::Keyword(Kw::For)   => parse_for(nodizer),
+        TokenType::Keyword(Kw::Break) => parse_break(nodizer),
+        TokenType::Keyword(Kw::Continue) => {
+            nodizer.next();
+            Ok(Node::new(NodeType::Continue, *token.span()))
+        },
+        TokenType::Error(message) => {
+            Err(Diagnostic::new(token.span().start, format!("{} `{}`", message, token.content())))
+        },
+        _ => match nodizer.try_match_node(&LiteralMatcher {}) {
+            Some(node) => Ok(node),
+            None       => Err(Diagnostic::new(token.span().start, format!("Unexpected token `{}`", token.content()))),
+        },
+    }
+}
+
+/// The operator of a right section such as `(+ 1)`, if one
+/// follows `(`. A `-`, or a `+` written against its operand,
+/// is a sign instead.
+fn starts_section(nodizer: &Nodizer) -> Option<Op> {
+    let token = match nodizer.peek() {
+        Some(token) if nodizer.continues_line() => token,
+        _                                       => return None,
+    };
+    let spaced = nodizer.peek_n(1).map_or(true, |next| next.span().start != token.span().end);
+    match *token.token_type() {
+        TokenType::Operator(Op::Sub) => None,
+        TokenType::Operator(Op::Add) if !spaced => None,
+        TokenType::Operator(op) if is_sectioned(op) => Some(op),
+        _ => None,
+    }
+}
+
+/// Whether the next tokens are the operator and `)`
+/// ending a left section such as `(1 +)`.
+fn ends_section(nodizer: &Nodizer) -> bool {
+    match (nodizer.peek(), nodizer.peek_n(1)) {
+        (Some(op), Some(close)) if nodizer.continues_line() => {
+            let sectioned = match *op.token_type() {
+                TokenType::Operator(op) => is_sectioned(op),
+                _                       => false,
+            };
+            sectioned && *close.token_type() == TokenType::Symbol(Sym::RParen)
+                && close.position().line == op.position().line
+        },
+        _ => false,
+    }
+}
+
+/// Parses the operator and operand of a right section,
+/// which binds at least as tightly as the operator.
+fn parse_right_section(nodizer: &mut Nodizer, open: &Token, op: Op) -> Parse {
+    let token = nodizer.next().unwrap();
+    if !nodizer.continues_line() {
+        return Err(expected(&format!("an expression after `{}`", op.as_str()), &token))
+    }
+    let (power, right_associative) = binary_power(op).unwrap();
+    let operand = parse_binary(nodizer, if right_associative { power } else { power + 1 })?;
+    let close = close(nodizer, Sym::RParen)?;
+    Ok(section(op, operand, false, open.span().to(close.span())))
+}
+
+/// The lambda a section stands for, applying `op` to its
+/// argument and `operand`, on the left if `left`.
+fn section(op: Op, operand: Node, left: bool, span: Span) -> Node {
+    let parameter = Node::new(NodeType::Identifier(node::SECTION.to_owned()), span);
+    let (l, r) = if left { (operand, parameter) } else { (parameter, operand) };
+    let body = Node::new(NodeType::Binary(op, Box::new(l), Box::new(r)), span);
+    Node::new(NodeType::Lambda(vec![node::SECTION.to_owned()], Box::new(body)), span)
+}
+
+fn comma_follows(nodizer: &Nodizer) -> bool {
+    match nodizer.peek() {
+        Some(t) => nodizer.continues_line() && *t.token_type() == TokenType::Symbol(Sym::Comma),
+        None    => false,
+    }
+}
+
+fn is_capitalized(name: &str) -> bool {
+    name.chars().next().map_or(false, |c| c.is_uppercase())
+}
+
+fn is_block(token: Option<&Token>) -> bool {
+    match token.map(|t| t.token_type()) {
+        Some(&TokenType::Block(_)) => true,
+        _                          => false,
+    }
+}
+
+/// Parses the indented block following `token`.
+pub fn parse_block(nodizer: &mut Nodizer, after: &str, token: &Token) -> Parse {
+    if is_block(nodizer.peek()) {
+        Ok(nodizer.try_match_node(&BlockMatcher {}).unwrap())
+    } else {
+        Err(expected(&format!("an indented block after {}", after), token))
+    }
+}
+
+fn parse_if(nodizer: &mut Nodizer) -> Parse {
+    let token     = nodizer.next().unwrap();
+    let condition = parse_operand(nodizer, &token)?;
+    let last      = nodizer.previous().unwrap().clone();
+    let then      = parse_block(nodizer, "the condition", &last)?;
+    let mut span  = token.span().to(then.span());
+
+    let otherwise = match nodizer.peek().map(|t| t.token_type().clone()) {
+        Some(TokenType::Keyword(Kw::Else)) => {
+            let token = nodizer.next().unwrap();
+            let otherwise = match nodizer.peek().map(|t| t.token_type().clone()) {
+                Some(TokenType::Keyword(Kw::If)) if nodizer.continues_line() => parse_if(nodizer)?,
+                _ => parse_block(nodizer, "`else`", &token)?,
+            };
+            span = span.to(otherwise.span());
+            Some(Box::new(otherwise))
+        },
+        _ => None,
+    };
+
+    Ok(Node::new(NodeType::If(Box::new(condition), Box::new(then), otherwise), span))
+}
+
+fn parse_while(nodizer: &mut Nodizer) -> Parse {
+    let token     = nodizer.next().unwrap();
+    let condition = parse_operand(nodizer, &token)?;
+    let last      = nodizer.previous().unwrap().clone();
+    let body      = parse_block(nodizer, "the condition", &last)?;
+    let span      = token.span().to(body.span());
+    Ok(Node::new(NodeType::While(Box::new(condition), Box::new(body)), span))
+}
+
+/// Parses `for name in values` and the block after it.
+fn parse_for(nodizer: &mut Nodizer) -> Parse {
+    let token = nodizer.next().unwrap();
+    let name = match nodizer.peek() {
+        Some(t) if nodizer.continues_line() && *t.token_type() == TokenType::Identifier => t.content().to_owned(),
+        _ => return Err(expected("a name after `for`", &token)),
+    };
+    let name_token = nodizer.next().unwrap();
+    match nodizer.peek() {
+        Some(t) if nodizer.continues_line() && *t.token_type() == TokenType::Keyword(Kw::In) => (),
+        _ => return Err(expected("`in`", &name_token)),
+    }
+    let keyword = nodizer.next().unwrap();
+    let values  = parse_operand(nodizer, &keyword)?;
+    let last    = nodizer.previous().unwrap().clone();
+    let body    = parse_block(nodizer, "the values", &last)?;
+    let span    = token.span().to(body.span());
+    Ok(Node::new(NodeType::For(name, Box::new(values), Box::new(body)), span))
+}
+
+/// Parses `break`, and the value after it on the line if any.
+fn parse_break(nodizer: &mut Nodizer) -> Parse {
+    let token = nodizer.next().unwrap();
+    let value = match nodizer.peek() {
+        Some(t) if nodizer.continues_line() && starts_expression(t) => Some(parse_expression(nodizer)?),
+        _ => None,
+    };
+    let span = value.as_ref().map_or(*token.span(), |v| token.span().to(v.span()));
+    Ok(Node::new(NodeType::Break(value.map(Box::new)), span))
+}
+
+/// The tokens of the indented block `token`.
+fn block_tokens<'src>(token: &Token<'src>) -> Vec<Token<'src>> {
+    match *token.token_type() {
+        TokenType::Block(ref tokens) => tokens.clone(),
+        _                            => unreachable!(),
+    }
+}
+
+/// Parses `match value` and the block of arms after it.
+fn parse_match(nodizer: &mut Nodizer) -> Parse {
+    let token = nodizer.next().unwrap();
+    let value = parse_operand(nodizer, &token)?;
+    let last  = nodizer.previous().unwrap().clone();
+    if !is_block(nodizer.peek()) {
+        return Err(expected("an indented block of arms after the value", &last))
+    }
+    let block = nodizer.next().unwrap();
+
+    let mut lines = Nodizer::new(block_tokens(&block));
+    let mut arms  = Vec::new();
+    let mut error = None;
+    while !lines.end() {
+        match parse_arm(&mut lines) {
+            Ok(arm) => arms.push(arm),
+            Err(diagnostic) => {
+                error = Some(diagnostic);
+                break
+            },
+        }
+    }
+    // Reported by the blocks of the arms
+    for diagnostic in lines.take_diagnostics() {
+        nodizer.report(diagnostic)
+    }
+    if let Some(error) = error {
+        return Err(error)
+    }
+
+    let span = token.span().to(block.span());
+    Ok(Node::new(NodeType::Match(Box::new(value), arms), span))
+}
+
+/// Parses `pattern -> body`, where the body is the
+/// rest of the line or an indented block.
+fn parse_arm(nodizer: &mut Nodizer) -> Result<Arm, Diagnostic> {
+    let start   = *nodizer.peek().unwrap().span();
+    let pattern = parse_pattern(nodizer)?;
+    let last    = nodizer.previous().unwrap().clone();
+    match nodizer.peek() {
+        Some(t) if nodizer.continues_line() && *t.token_type() == TokenType::Symbol(Sym::Arrow) => (),
+        _ => return Err(expected("`->`", &last)),
+    }
+    let arrow = nodizer.next().unwrap();
+    let body = if is_block(nodizer.peek()) {
+        parse_block(nodizer, "`->`", &arrow)?
+    } else {
+        parse_operand(nodizer, &arrow)?
+    };
+    if nodizer.continues_line() {
+        let token = nodizer.peek().unwrap();
+        return Err(Diagnostic::new(token.span().start, format!("Unexpected token `{}`", token.content())))
+    }
+
+    Ok(Arm {
+        pattern: pattern,
+        span:    start.to(last.span()),
+        body:    body,
+    })
+}
+
+/// Whether a token can start a pattern.
+fn starts_pattern(token: &Token) -> bool {
+    match *token.token_type() {
+        TokenType::Identifier | TokenType::StringLiteral | TokenType::CharLiteral(_)
+            | TokenType::IntLiteral(..) | TokenType::FloatLiteral(_)
+            | TokenType::BooleanLiteral(_) | TokenType::Symbol(Sym::LParen) => true,
+        _ => false,
+    }
+}
+
+/// Parses a variant and the patterns for its payload
+/// following it on the line, or a single pattern.
+fn parse_pattern(nodizer: &mut Nodizer) -> Result<Pattern, Diagnostic> {
+    let token = nodizer.peek().unwrap().clone();
+    if *token.token_type() != TokenType::Identifier || !is_capitalized(token.content()) {
+        return parse_pattern_atom(nodizer)
+    }
+
+    nodizer.next();
+    let mut payload = Vec::new();
+    while nodizer.continues_line() && starts_pattern(nodizer.peek().unwrap()) {
+        payload.push(parse_pattern_atom(nodizer)?)
+    }
+    Ok(Pattern::Variant(token.content().to_owned(), payload))
+}
+
+/// Parses a pattern which needs no parentheses
+/// to be part of a payload.
+fn parse_pattern_atom(nodizer: &mut Nodizer) -> Result<Pattern, Diagnostic> {
+    let token = nodizer.peek().unwrap().clone();
+    match *token.token_type() {
+        TokenType::Identifier => {
+            nodizer.next();
+            Ok(match token.content() {
+                "_"                          => Pattern::Wildcard,
+                name if is_capitalized(name) => Pattern::Variant(name.to_owned(), Vec::new()),
+                name                         => Pattern::Binding(name.to_owned()),
+            })
+        },
+        TokenType::Symbol(Sym::LParen) => {
+            nodizer.next();
+            let mut elements = vec![parse_pattern_after(nodizer, &token)?];
+            while comma_follows(nodizer) {
+                let comma = nodizer.next().unwrap();
+                elements.push(parse_pattern_after(nodizer, &comma)?)
+            }
+            close(nodizer, Sym::RParen)?;
+            Ok(if elements.len() > 1 { Pattern::Tuple(elements) } else { elements.pop().unwrap() })
+        },
+        _ => match nodizer.try_match_node(&LiteralMatcher {}) {
+            Some(node) => Ok(Pattern::Literal(Box::new(node))),
+            None => Err(Diagnostic::new(token.span().start, format!("Expected a pattern, found `{}`", token.content()))),
+        },
+    }
+}
+
+/// Parses the pattern after `token`, which must
+/// start on the same line.
+fn parse_pattern_after(nodizer: &mut Nodizer, token: &Token) -> Result<Pattern, Diagnostic> {
+    if nodizer.continues_line() {
+        parse_pattern(nodizer)
+    } else {
+        Err(expected(&format!("a pattern after `{}`", token.content()), token))
+    }
+}
+
+/// Parses `let name = value` or `let name parameters = body`,
+/// where the value or body may be an indented block, or a
+/// binding without a value, `let name`. Bindings, but not
+/// functions, may be declared `mut`.
+pub fn parse_let(nodizer: &mut Nodizer) -> Parse {
+    let token = nodizer.next().unwrap();
+
+    let mutable = match nodizer.peek() {
+        Some(t) if nodizer.continues_line() && *t.token_type() == TokenType::Keyword(Kw::Mut) => Some(t.clone()),
+        _ => None,
+    };
+    if mutable.is_some() {
+        nodizer.next();
+    }
+    let before = nodizer.previous().unwrap().clone();
+    let name = match nodizer.peek() {
+        Some(t) if nodizer.continues_line() && *t.token_type() == TokenType::Identifier => t.clone(),
+        _ => return Err(expected(&format!("a name after `{}`", before.content()), &before)),
+    };
+    nodizer.next();
+
+    if !nodizer.continues_line() {
+        let span = token.span().to(name.span());
+        return Ok(Node::new(NodeType::Let(name.content().to_owned(), mutable.is_some(), None), span))
+    }
+
+    let mut parameters = Vec::new();
+    loop {
+        let last = nodizer.previous().unwrap().clone();
+        match nodizer.peek().map(|t| (t.token_type().clone(), t.content().to_owned())) {
+            Some((TokenType::Identifier, parameter)) if nodizer.continues_line() => {
+                if let Some(ref mutable) = mutable {
+                    return Err(Diagnostic::new(mutable.span().start, "Functions cannot be `mut`".to_owned()))
+                }
+                nodizer.next();
+                parameters.push(parameter)
+            },
+            Some((TokenType::Operator(Op::Assign), _)) if nodizer.continues_line() => {
+                nodizer.next();
+                break
+            },
+            _ => return Err(expected("`=`", &last)),
+        }
+    }
+
+    let assign = nodizer.previous().unwrap().clone();
+    let value = if is_block(nodizer.peek()) {
+        parse_block(nodizer, "`=`", &assign)?
+    } else {
+        parse_operand(nodizer, &assign)?
+    };
+
+    let span = token.span().to(value.span());
+    let name = name.content().to_owned();
+    let node_type = if parameters.is_empty() {
+        NodeType::Let(name, mutable.is_some(), Some(Box::new(value)))
+    } else {
+        NodeType::Function(name, parameters, Box::new(value))
+    };
+    Ok(Node::new(node_type, span))
+}
+
+/// Parses `type Name` and the block after it: the fields of
+/// a record, each a `name: type` on its own line, or the
+/// variants of an enum, each a capitalized name followed by
+/// the types of its payload.
+pub fn parse_type(nodizer: &mut Nodizer) -> Parse {
+    let token = nodizer.next().unwrap();
+
+    let name = match nodizer.peek() {
+        Some(t) if nodizer.continues_line() && *t.token_type() == TokenType::Identifier => t.clone(),
+        _ => return Err(expected("a name after `type`", &token)),
+    };
+    nodizer.next();
+    if nodizer.continues_line() {
+        return Err(expected("the fields on the lines below", &name))
+    }
+    if !is_block(nodizer.peek()) {
+        return Err(expected("an indented block of fields", &name))
+    }
+    let block = nodizer.next().unwrap();
+
+    let mut lines    = Nodizer::new(block_tokens(&block));
+    let mut fields   = Vec::new();
+    let mut variants = Vec::new();
+    while !lines.end() {
+        let field = lines.next().unwrap();
+        if *field.token_type() != TokenType::Identifier {
+            return Err(Diagnostic::new(field.span().start, format!("Expected a field name, found `{}`", field.content())))
+        }
+        match lines.peek() {
+            Some(t) if lines.continues_line() && *t.token_type() == TokenType::Operator(Op::Hint) => {
+                let colon = lines.next().unwrap();
+                fields.push((field.content().to_owned(), parse_hint(&mut lines, &colon)?))
+            },
+            _ if is_capitalized(field.content()) => {
+                let mut payload = Vec::new();
+                while lines.continues_line() {
+                    let last = lines.previous().unwrap().clone();
+                    payload.push(parse_hint(&mut lines, &last)?)
+                }
+                variants.push((field.content().to_owned(), payload))
+            },
+            _ => return Err(expected("`:` and a type", &field)),
+        }
+        if !fields.is_empty() && !variants.is_empty() {
+            return Err(Diagnostic::new(field.span().start, "Expected fields or variants, not both".to_owned()))
+        }
+        if lines.continues_line() {
+            let last = lines.previous().unwrap().clone();
+            return Err(expected("the end of the line", &last))
+        }
+    }
+
+    let span = token.span().to(block.span());
+    let node_type = if variants.is_empty() {
+        NodeType::Record(name.content().to_owned(), fields)
+    } else {
+        NodeType::Enum(name.content().to_owned(), variants)
+    };
+    Ok(Node::new(node_type, span))
+}
+
+/// Parses the type written after `token`: a name,
+/// or a type in brackets for a list.
+fn parse_hint(nodizer: &mut Nodizer, token: &Token) -> Result<Hint, Diagnostic> {
+    let next = match nodizer.peek() {
+        Some(t) if nodizer.continues_line() => t.clone(),
+        _ => return Err(expected(&format!("a type after `{}`", token.content()), token)),
+    };
+    match *next.token_type() {
+        TokenType::Identifier => {
+            nodizer.next();
+            Ok(Hint::Name(next.content().to_owned()))
+        },
+        TokenType::Symbol(Sym::LBracket) => {
+            nodizer.next();
+            let element = parse_hint(nodizer, &next)?;
+            close(nodizer, Sym::RBracket)?;
+            Ok(Hint::List(Box::new(element)))
+        },
+        _ => Err(expected(&format!("a type after `{}`", token.content()), token)),
+    }
+}
+
+/// Reports a failed parse and skips the rest of its line,
+/// returning an error node covering what was skipped.
+pub fn skip_line(nodizer: &mut Nodizer, diagnostic: Diagnostic, mut span: Span) -> Node {
+    nodizer.report(diagnostic);
+
+    while nodizer.continues_line() {
+        span = span.to(nodizer.next().unwrap().span())
+    }
+
+    Node::new(NodeType::Error, span)
+}
+
+#[cfg(test)]
+mod tests {
+    use document::Document;
+    use serialize::to_sexp;
+
+    fn parse(source: &str) -> (String, Vec<String>) {
+        let document = Document::new(source);
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let diagnostics = document.diagnostics().iter()
+            .map(|d| format!("{} {}", d.position(), d.message()))
+            .collect();
+        (to_sexp(&ast), diagnostics)
+    }
+
+    #[test]
+    fn parse_by_precedence() {
+        assert_eq!(parse("a = b = f x (y) - -1 * 2 == 3 || !c").0, concat!(
+            "(assign (ident a) (assign (ident b) (binary || (binary == (binary - (call (ident f) ",
+            "(ident x) (ident y)) (binary * (int -1) (int 2))) (int 3)) (unary ! (ident c)))))\n",
+        ));
+        assert_eq!(parse("x -1 * 2").0, "(binary - (ident x) (binary * (int 1) (int 2)))\n");
+    }
+
+    #[test]
+    fn parse_fields() {
+        assert_eq!(parse("f p.x (q).y.z\np.x = -a.b").0, concat!(
+            "(call (ident f) (field x (ident p)) (field z (field y (ident q))))\n",
+            "(assign (field x (ident p)) (unary - (field b (ident a))))\n",
+        ));
+        let (ast, diagnostics) = parse("p.\np.(x)");
+        assert_eq!(ast, "(error)\n(error)\n");
+        assert_eq!(diagnostics, vec![
+            "[ln 1, col 2] Expected a field name after `.`",
+            "[ln 2, col 2] Expected a field name after `.`",
+        ]);
+    }
+
+    #[test]
+    fn parse_lists() {
+        assert_eq!(parse("f [1, []] xs[0]\nxs [0]\nxs[i][j].x = [a + 1]").0, concat!(
+            "(call (ident f) (list (int 1) (list)) (index (ident xs) (int 0)))\n",
+            "(call (ident xs) (list (int 0)))\n",
+            "(assign (field x (index (index (ident xs) (ident i)) (ident j))) (list (binary + (ident a) (int 1))))\n",
+        ));
+        let (ast, diagnostics) = parse("[1, 2\nxs[0");
+        assert_eq!(ast, "(error)\n(error)\n");
+        assert_eq!(diagnostics, vec![
+            "[ln 1, col 5] Expected `,` or `]`",
+            "[ln 2, col 4] Expected `]`",
+        ]);
+    }
+
+    #[test]
+    fn parse_records() {
+        assert_eq!(parse("type P\n  x: int\n  ys: [[P]]\np with x = 1, ys = [] || q\na = p with x = p.x").0, concat!(
+            "(type P (x int) (ys [[P]]))\n",
+            "(with (ident p) (x (int 1)) (ys (binary || (list) (ident q))))\n",
+            "(assign (ident a) (with (ident p) (x (field x (ident p)))))\n",
+        ));
+        let (ast, diagnostics) = parse("type P\ntype Q\n  x int\np with x\np with 1");
+        assert_eq!(ast, "(error)\n(error)\n(error)\n(error)\n");
+        assert_eq!(diagnostics, vec![
+            "[ln 1, col 6] Expected an indented block of fields",
+            "[ln 3, col 3] Expected `:` and a type",
+            "[ln 4, col 8] Expected `=`",
+            "[ln 5, col 6] Expected a field name after `with`",
+        ]);
+    }
+
+    #[test]
+    fn parse_with_before_commas() {
+        assert_eq!(parse("[p with y = 5, p]\nf (p with y = 5, x = 1, p)\n(p == q, p with y = 5, p.x)").0, concat!(
+            "(list (with (ident p) (y (int 5))) (ident p))\n",
+            "(call (ident f) (tuple (with (ident p) (y (int 5)) (x (int 1))) (ident p)))\n",
+            "(tuple (binary == (ident p) (ident q)) (with (ident p) (y (int 5))) (field x (ident p)))\n",
+        ));
+    }
+
+    #[test]
+    fn parse_enums_and_matches() {
+        let source = "type S\n  A int [S]\n  B\nmatch (s, 1)\n  (A _ xs, 0) -> xs\n  (B, n) ->\n    n\n  (A 1 (B), \"a\") -> 2";
+        assert_eq!(parse(source).0, concat!(
+            "(enum S (A int [S]) (B))\n",
+            "(match (tuple (ident s) (int 1))\n",
+            "  (arm (tuple (variant A _ (bind xs)) (int 0)) (ident xs))\n",
+            "  (arm (tuple (variant B) (bind n)) (block\n",
+            "    (ident n)))\n",
+            "  (arm (tuple (variant A (int 1) (variant B)) (string \"a\")) (int 2)))\n",
+        ));
+        let (ast, diagnostics) = parse("type T\n  A\n  x: int\nmatch x\nmatch x\n  A 1\nmatch x\n  + -> 1");
+        assert_eq!(ast, "(error)\n(error)\n(error)\n(error)\n");
+        assert_eq!(diagnostics, vec![
+            "[ln 3, col 2] Expected fields or variants, not both",
+            "[ln 4, col 7] Expected an indented block of arms after the value",
+            "[ln 6, col 5] Expected `->`",
+            "[ln 8, col 2] Expected a pattern, found `+`",
+        ]);
+    }
+
+    #[test]
+    fn parse_loops() {
+        let source = "while i < n\n  i = i + 1\nfor x in 0..n + 1\n  if x == 2\n    continue\n  break x * 2\nfor y in ys\n  break";
+        assert_eq!(parse(source).0, concat!(
+            "(while (binary < (ident i) (ident n)) (block\n",
+            "  (assign (ident i) (binary + (ident i) (int 1)))))\n",
+            "(for x (binary .. (int 0) (binary + (ident n) (int 1))) (block\n",
+            "  (if (binary == (ident x) (int 2)) (block\n",
+            "    (continue)))\n",
+            "  (break (binary * (ident x) (int 2)))))\n",
+            "(for y (ident ys) (block\n",
+            "  (break)))\n",
+        ));
+        let (ast, diagnostics) = parse("while true\nfor 1 in xs\n  x\nfor x xs\n  x\nfor x in 0..=9");
+        assert_eq!(ast, "(error)\n(error)\n(block\n  (ident x))\n(error)\n(block\n  (ident x))\n(error)\n");
+        assert_eq!(diagnostics, vec![
+            "[ln 1, col 10] Expected an indented block after the condition",
+            "[ln 2, col 3] Expected a name after `for`",
+            "[ln 4, col 5] Expected `in`",
+            "[ln 6, col 14] Expected an indented block after the values",
+        ]);
+    }
+
+    #[test]
+    fn parse_lambdas() {
+        let source = "let add = x y -> x + y\nf (x -> x * 2) xs\nk = x -> y ->\n  x\nmatch s\n  A f -> f x -> x";
+        assert_eq!(parse(source).0, concat!(
+            "(let add (lambda (x y) (binary + (ident x) (ident y))))\n",
+            "(call (ident f) (lambda (x) (binary * (ident x) (int 2))) (ident xs))\n",
+            "(assign (ident k) (lambda (x) (lambda (y) (block\n",
+            "  (ident x)))))\n",
+            "(match (ident s)\n",
+            "  (arm (variant A (bind f)) (lambda (f x) (ident x))))\n",
+        ));
+        let (ast, diagnostics) = parse("x ->\n1 + x -> x\nA x -> x");
+        assert_eq!(ast, "(error)\n(binary + (int 1) (ident x))\n(error)\n(call (ident A) (ident x))\n(error)\n");
+        assert_eq!(diagnostics, vec![
+            "[ln 1, col 4] Expected an expression after `->`",
+            "[ln 2, col 6] Unexpected token `->`",
+            "[ln 3, col 4] Unexpected token `->`",
+        ]);
+    }
+
+    #[test]
+    fn parse_sections() {
+        let source = "(+ 1)\n(1 -)\n(+ x * 2)\n(+1)\n(- 1)\n(f x ==)\n(a, b)";
+        assert_eq!(parse(source).0, concat!(
+            "(lambda ($) (binary + (ident $) (int 1)))\n",
+            "(lambda ($) (binary - (int 1) (ident $)))\n",
+            "(lambda ($) (binary + (ident $) (binary * (ident x) (int 2))))\n",
+            "(unary + (int 1))\n",
+            "(unary - (int 1))\n",
+            "(lambda ($) (binary == (call (ident f) (ident x)) (ident $)))\n",
+            "(tuple (ident a) (ident b))\n",
+        ));
+    }
+
+    #[test]
+    fn parse_assignments() {
+        let source = "let mut x = 1\nlet y\nx += 2\nxs[0] <<= y = 1\nlet mut f x = x";
+        let (ast, diagnostics) = parse(source);
+        assert_eq!(ast, concat!(
+            "(let mut x (int 1))\n",
+            "(let y)\n",
+            "(assign + (ident x) (int 2))\n",
+            "(assign << (index (ident xs) (int 0)) (assign (ident y) (int 1)))\n",
+            "(error)\n",
+        ));
+        assert_eq!(diagnostics, vec!["[ln 5, col 4] Functions cannot be `mut`"]);
+    }
+
+    #[test]
+    fn parse_pipelines() {
+        let source = "xs |> map f |> sum\nx = a || b |> f\np |> move 1 with x = 0\nxs.map f";
+        assert_eq!(parse(source).0, concat!(
+            "(call (ident sum) (call (ident map) (ident f) (ident xs)))\n",
+            "(assign (ident x) (call (ident f) (binary || (ident a) (ident b))))\n",
+            "(with (call (ident move) (int 1) (ident p)) (x (int 0)))\n",
+            "(call (field map (ident xs)) (ident f))\n",
+        ));
+        let (ast, diagnostics) = parse("xs |>\n(|> f)");
+        assert_eq!(ast, "(error)\n(error)\n");
+        assert_eq!(diagnostics, vec![
+            "[ln 1, col 5] Expected an expression after `|>`",
+            "[ln 2, col 1] Unexpected token `|>`",
+        ]);
+    }
+
+    #[test]
+    fn report_incomplete_expressions() {
+        let (ast, diagnostics) = parse("1 +\n(1\nif true 1\nlet x y\n1 = 2\nif x\n  1\nelse 2\n3 3)");
+        assert_eq!(ast, "(error)\n(error)\n(error)\n(error)\n(error)\n(error)\n(call (int 3) (int 3))\n(error)\n");
+        assert_eq!(diagnostics, vec![
+            "[ln 1, col 3] Expected an expression after `+`",
+            "[ln 2, col 2] Expected `)`",
+            "[ln 3, col 9] Expected an indented block after the condition",
+            "[ln 4, col 7] Expected `=`",
+            "[ln 5, col 0] Invalid assignment target",
+            "[ln 8, col 4] Expected an indented block after `else`",
+            "[ln 9, col 3] Unexpected token `)`",
+        ]);
+    }
+}
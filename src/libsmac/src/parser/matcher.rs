@@ -1,7 +1,9 @@
+use parser::grab_smaragdine_parser;
+use parser::expression::{parse_expression, parse_let, parse_type, skip_line, starts_expression};
 use parser::nodizer::Nodizer;
 use parser::node::{Node, NodeType};
 
-use parser::token::TokenType;
+use parser::token::{TokenType, Kw};
 
 pub trait Matcher {
     fn try_match(&self, nodizer: &mut Nodizer) -> Option<Node>;
@@ -16,33 +18,103 @@ impl Matcher for LiteralMatcher {
             &TokenType::StringLiteral => {
                 nodizer.next();
                 let value = token.content().to_owned();
-                Some(Node::new(NodeType::StringLiteral(value)))
+                Some(Node::new(NodeType::StringLiteral(value), *token.span()))
             },
-            &TokenType::CharLiteral => {
+            &TokenType::CharLiteral(value) => {
                 nodizer.next();
-                let value = token.content().chars().nth(0).unwrap();
-                Some(Node::new(NodeType::CharLiteral(value)))
+                Some(Node::new(NodeType::CharLiteral(value), *token.span()))
             },
-            &TokenType::IntLiteral => {
+            &TokenType::IntLiteral(value, signed) => {
                 nodizer.next();
-                let signed = token.content().starts_with("-");
-                let value = match signed {
-                    true => token.content().parse::<i64>().unwrap() as u64,
-                    false => token.content().parse::<u64>().unwrap(),
-                };
-                Some(Node::new(NodeType::IntLiteral(value, signed)))
+                Some(Node::new(NodeType::IntLiteral(value, signed), *token.span()))
             },
-            &TokenType::FloatLiteral => {
+            &TokenType::FloatLiteral(value) => {
                 nodizer.next();
-                let value = token.content().parse::<f64>().unwrap();
-                Some(Node::new(NodeType::FloatLiteral(value)))
+                Some(Node::new(NodeType::FloatLiteral(value), *token.span()))
             },
-            &TokenType::BooleanLiteral => {
+            &TokenType::BooleanLiteral(value) => {
                 nodizer.next();
-                let value = token.content().contains("true");
-                Some(Node::new(NodeType::BooleanLiteral(value)))
+                Some(Node::new(NodeType::BooleanLiteral(value), *token.span()))
             },
             _ => None,
         }
     }
-}
\ No newline at end of file
+}
+
+/// Matches a `let` binding or function definition.
+pub struct LetMatcher {}
+
+impl Matcher for LetMatcher {
+    fn try_match(&self, nodizer: &mut Nodizer) -> Option<Node> {
+        let start = match nodizer.peek() {
+            Some(token) if *token.token_type() == TokenType::Keyword(Kw::Let) => *token.span(),
+            _ => return None,
+        };
+
+        match parse_let(nodizer) {
+            Ok(node)        => Some(node),
+            Err(diagnostic) => Some(skip_line(nodizer, diagnostic, start)),
+        }
+    }
+}
+
+/// Matches a `type` declaration.
+pub struct TypeMatcher {}
+
+impl Matcher for TypeMatcher {
+    fn try_match(&self, nodizer: &mut Nodizer) -> Option<Node> {
+        let start = match nodizer.peek() {
+            Some(token) if *token.token_type() == TokenType::Keyword(Kw::Type) => *token.span(),
+            _ => return None,
+        };
+
+        match parse_type(nodizer) {
+            Ok(node)        => Some(node),
+            Err(diagnostic) => {
+                let mut node = skip_line(nodizer, diagnostic, start);
+                // Fields and variants are no statements to parse
+                if let Some(&TokenType::Block(_)) = nodizer.peek().map(|t| t.token_type()) {
+                    let block = nodizer.next().unwrap();
+                    *node.span_mut() = node.span().to(block.span())
+                }
+                Some(node)
+            },
+        }
+    }
+}
+
+/// Matches an expression statement.
+pub struct ExpressionMatcher {}
+
+impl Matcher for ExpressionMatcher {
+    fn try_match(&self, nodizer: &mut Nodizer) -> Option<Node> {
+        let start = match nodizer.peek() {
+            Some(token) if starts_expression(token) => *token.span(),
+            _ => return None,
+        };
+
+        match parse_expression(nodizer) {
+            Ok(node)        => Some(node),
+            Err(diagnostic) => Some(skip_line(nodizer, diagnostic, start)),
+        }
+    }
+}
+
+pub struct BlockMatcher {}
+
+impl Matcher for BlockMatcher {
+    fn try_match(&self, nodizer: &mut Nodizer) -> Option<Node> {
+        let token = nodizer.next().unwrap();
+        match token.token_type() {
+            &TokenType::Block(ref tokens) => {
+                let mut parser = grab_smaragdine_parser(tokens.clone());
+                let nodes = parser.by_ref().collect();
+                for diagnostic in parser.take_diagnostics() {
+                    nodizer.report(diagnostic)
+                }
+                Some(Node::new(NodeType::Block(nodes), *token.span()))
+            },
+            _ => None,
+        }
+    }
+}
@@ -1,6 +1,7 @@
 pub mod node;
 pub mod nodizer;
 pub mod matcher;
+pub mod expression;
 pub mod parser;
 
 pub use super::lexer;
@@ -1,25 +1,908 @@
-#[derive(Debug, Clone)]
+use lexer::token::{Span, Op};
+
+use std::fmt;
+use std::mem;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum NodeType {
     StringLiteral(String),
     CharLiteral(char),
     IntLiteral(u64, bool /* signed */),
     FloatLiteral(f64),
     BooleanLiteral(bool),
+    Identifier(String),
+    Unary(Op, Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+    /// A function applied to its arguments by juxtaposition.
+    Call(Box<Node>, Vec<Node>),
+    /// `value.name`
+    Field(Box<Node>, String),
+    /// `[elements]`
+    List(Vec<Node>),
+    /// `list[index]`
+    Index(Box<Node>, Box<Node>),
+    /// A condition, the block run when it holds, and
+    /// the block or `if` run when it does not.
+    If(Box<Node>, Box<Node>, Option<Box<Node>>),
+    /// `let name = value`, or `let mut name = value` if mutable,
+    /// where a binding without a value is assigned one later.
+    Let(String, bool /* mutable */, Option<Box<Node>>),
+    /// `let name parameters = body`
+    Function(String, Vec<String>, Box<Node>),
+    /// `parameters -> body`, an anonymous function.
+    Lambda(Vec<String>, Box<Node>),
+    /// `target = value`
+    Assign(Box<Node>, Box<Node>),
+    /// `target op= value`, where the target is
+    /// evaluated once, then read and written.
+    CompoundAssign(Op, Box<Node>, Box<Node>),
+    /// `type Name`, with a `field: type` on each
+    /// line of its block.
+    Record(String, Vec<(String, Hint)>),
+    /// `value with field = new, ...`
+    With(Box<Node>, Vec<(String, Node)>),
+    /// `type Name`, with a `Variant types...` on each
+    /// line of its block.
+    Enum(String, Vec<(String, Vec<Hint>)>),
+    /// `(elements, ...)`, of two or more.
+    Tuple(Vec<Node>),
+    /// `match value`, with a `pattern -> body` on each
+    /// line of its block.
+    Match(Box<Node>, Vec<Arm>),
+    /// `while condition`, and the block run while it holds.
+    While(Box<Node>, Box<Node>),
+    /// `for name in values`, and the block run with
+    /// each of them bound to the name.
+    For(String, Box<Node>, Box<Node>),
+    /// `break`, with the value of the loop if any.
+    Break(Option<Box<Node>>),
+    Continue,
+    Block(Vec<Node>),
+    Error,
+}
+
+/// A type written after `:`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hint {
+    /// A built-in type, such as `int`, or a declared one.
+    Name(String),
+    /// `[element]`
+    List(Box<Hint>),
+}
+
+impl fmt::Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Hint::Name(ref name)    => write!(f, "{}", name),
+            Hint::List(ref element) => write!(f, "[{}]", element),
+        }
+    }
+}
+
+/// What a value is tested against in a `match`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `_`, matching anything.
+    Wildcard,
+    /// A name, matching anything and bound to it.
+    Binding(String),
+    /// A literal the value must equal.
+    Literal(Box<Node>),
+    /// `(patterns, ...)`
+    Tuple(Vec<Pattern>),
+    /// A variant, by its capitalized name, and
+    /// patterns for its payload.
+    Variant(String, Vec<Pattern>),
+}
+
+impl Pattern {
+    /// Adds the names the pattern binds, in order.
+    pub fn bindings(&self, names: &mut Vec<String>) {
+        match *self {
+            Pattern::Binding(ref name) => names.push(name.clone()),
+            Pattern::Tuple(ref elements) | Pattern::Variant(_, ref elements) => {
+                for element in elements.iter() {
+                    element.bindings(names)
+                }
+            },
+            Pattern::Wildcard | Pattern::Literal(_) => (),
+        }
+    }
+}
+
+/// A line of a `match`: the pattern, where it is
+/// written, and the body run when it matches.
+#[derive(Debug, Clone)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub span:    Span,
+    pub body:    Node,
+}
+
+impl PartialEq for Arm {
+    fn eq(&self, other: &Arm) -> bool {
+        self.pattern == other.pattern && self.body == other.body
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Node {
     node_type: NodeType,
+    span: Span,
 }
 
 impl Node {
-    pub fn new(node_type: NodeType) -> Node {
+    pub fn new(node_type: NodeType, span: Span) -> Node {
         Node {
             node_type: node_type,
+            span: span,
         }
     }
 
     pub fn node_type(&self) -> &NodeType {
         &self.node_type
     }
-}
\ No newline at end of file
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn node_type_mut(&mut self) -> &mut NodeType {
+        &mut self.node_type
+    }
+
+    pub fn span_mut(&mut self) -> &mut Span {
+        &mut self.span
+    }
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Node) -> bool {
+        &self.node_type == other.node_type()
+    }
+}
+
+/// Walks a tree of nodes by reference.
+///
+/// Every method defaults to walking into the children
+/// of its node, so a pass only overrides what it needs.
+/// Override `visit_node` and call `walk_node` to see
+/// every node before its kind is dispatched.
+pub trait Visitor {
+    fn visit_node(&mut self, node: &Node) {
+        walk_node(self, node)
+    }
+
+    fn visit_string_literal(&mut self, _value: &String) {}
+
+    fn visit_char_literal(&mut self, _value: char) {}
+
+    fn visit_int_literal(&mut self, _value: u64, _signed: bool) {}
+
+    fn visit_float_literal(&mut self, _value: f64) {}
+
+    fn visit_boolean_literal(&mut self, _value: bool) {}
+
+    fn visit_identifier(&mut self, _name: &String) {}
+
+    fn visit_unary(&mut self, _op: Op, operand: &Node) {
+        self.visit_node(operand)
+    }
+
+    fn visit_binary(&mut self, _op: Op, left: &Node, right: &Node) {
+        self.visit_node(left);
+        self.visit_node(right)
+    }
+
+    fn visit_call(&mut self, function: &Node, arguments: &Vec<Node>) {
+        self.visit_node(function);
+        walk_block(self, arguments)
+    }
+
+    fn visit_field(&mut self, value: &Node, _name: &String) {
+        self.visit_node(value)
+    }
+
+    fn visit_list(&mut self, elements: &Vec<Node>) {
+        walk_block(self, elements)
+    }
+
+    fn visit_index(&mut self, list: &Node, index: &Node) {
+        self.visit_node(list);
+        self.visit_node(index)
+    }
+
+    fn visit_if(&mut self, condition: &Node, then: &Node, otherwise: Option<&Node>) {
+        self.visit_node(condition);
+        self.visit_node(then);
+        if let Some(otherwise) = otherwise {
+            self.visit_node(otherwise)
+        }
+    }
+
+    fn visit_let(&mut self, _name: &String, _mutable: bool, value: Option<&Node>) {
+        if let Some(value) = value {
+            self.visit_node(value)
+        }
+    }
+
+    fn visit_function(&mut self, _name: &String, _parameters: &Vec<String>, body: &Node) {
+        self.visit_node(body)
+    }
+
+    fn visit_lambda(&mut self, _parameters: &Vec<String>, body: &Node) {
+        self.visit_node(body)
+    }
+
+    fn visit_assign(&mut self, target: &Node, value: &Node) {
+        self.visit_node(target);
+        self.visit_node(value)
+    }
+
+    fn visit_compound_assign(&mut self, _op: Op, target: &Node, value: &Node) {
+        self.visit_node(target);
+        self.visit_node(value)
+    }
+
+    fn visit_record(&mut self, _name: &String, _fields: &Vec<(String, Hint)>) {}
+
+    fn visit_with(&mut self, value: &Node, fields: &Vec<(String, Node)>) {
+        self.visit_node(value);
+        for &(_, ref new) in fields.iter() {
+            self.visit_node(new)
+        }
+    }
+
+    fn visit_enum(&mut self, _name: &String, _variants: &Vec<(String, Vec<Hint>)>) {}
+
+    fn visit_tuple(&mut self, elements: &Vec<Node>) {
+        walk_block(self, elements)
+    }
+
+    fn visit_match(&mut self, value: &Node, arms: &Vec<Arm>) {
+        self.visit_node(value);
+        for arm in arms.iter() {
+            walk_pattern(self, &arm.pattern);
+            self.visit_node(&arm.body)
+        }
+    }
+
+    fn visit_while(&mut self, condition: &Node, body: &Node) {
+        self.visit_node(condition);
+        self.visit_node(body)
+    }
+
+    fn visit_for(&mut self, _name: &String, values: &Node, body: &Node) {
+        self.visit_node(values);
+        self.visit_node(body)
+    }
+
+    fn visit_break(&mut self, value: Option<&Node>) {
+        if let Some(value) = value {
+            self.visit_node(value)
+        }
+    }
+
+    fn visit_continue(&mut self) {}
+
+    fn visit_block(&mut self, nodes: &Vec<Node>) {
+        walk_block(self, nodes)
+    }
+
+    fn visit_error(&mut self) {}
+}
+
+pub fn walk_node<V: Visitor + ?Sized>(visitor: &mut V, node: &Node) {
+    match *node.node_type() {
+        NodeType::StringLiteral(ref value)   => visitor.visit_string_literal(value),
+        NodeType::CharLiteral(value)         => visitor.visit_char_literal(value),
+        NodeType::IntLiteral(value, signed)  => visitor.visit_int_literal(value, signed),
+        NodeType::FloatLiteral(value)        => visitor.visit_float_literal(value),
+        NodeType::BooleanLiteral(value)      => visitor.visit_boolean_literal(value),
+        NodeType::Identifier(ref name)       => visitor.visit_identifier(name),
+        NodeType::Unary(op, ref operand)     => visitor.visit_unary(op, operand),
+        NodeType::Binary(op, ref l, ref r)   => visitor.visit_binary(op, l, r),
+        NodeType::Call(ref f, ref arguments) => visitor.visit_call(f, arguments),
+        NodeType::Field(ref value, ref name) => visitor.visit_field(value, name),
+        NodeType::List(ref elements)         => visitor.visit_list(elements),
+        NodeType::Index(ref list, ref index) => visitor.visit_index(list, index),
+        NodeType::If(ref c, ref t, ref o)    => visitor.visit_if(c, t, o.as_ref().map(|o| &**o)),
+        NodeType::Let(ref name, m, ref v)    => visitor.visit_let(name, m, v.as_ref().map(|v| &**v)),
+        NodeType::Function(ref n, ref p, ref b) => visitor.visit_function(n, p, b),
+        NodeType::Lambda(ref p, ref body)    => visitor.visit_lambda(p, body),
+        NodeType::Assign(ref target, ref v)  => visitor.visit_assign(target, v),
+        NodeType::CompoundAssign(op, ref target, ref v) => visitor.visit_compound_assign(op, target, v),
+        NodeType::Record(ref name, ref f)    => visitor.visit_record(name, f),
+        NodeType::With(ref value, ref f)     => visitor.visit_with(value, f),
+        NodeType::Enum(ref name, ref v)      => visitor.visit_enum(name, v),
+        NodeType::Tuple(ref elements)        => visitor.visit_tuple(elements),
+        NodeType::Match(ref value, ref arms) => visitor.visit_match(value, arms),
+        NodeType::While(ref c, ref body)     => visitor.visit_while(c, body),
+        NodeType::For(ref n, ref v, ref b)   => visitor.visit_for(n, v, b),
+        NodeType::Break(ref value)           => visitor.visit_break(value.as_ref().map(|v| &**v)),
+        NodeType::Continue                   => visitor.visit_continue(),
+        NodeType::Block(ref nodes)           => visitor.visit_block(nodes),
+        NodeType::Error                      => visitor.visit_error(),
+    }
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, nodes: &Vec<Node>) {
+    for node in nodes.iter() {
+        visitor.visit_node(node)
+    }
+}
+
+/// Visits the literals a pattern compares with.
+pub fn walk_pattern<V: Visitor + ?Sized>(visitor: &mut V, pattern: &Pattern) {
+    match *pattern {
+        Pattern::Literal(ref literal) => visitor.visit_node(literal),
+        Pattern::Tuple(ref elements) | Pattern::Variant(_, ref elements) => {
+            for element in elements.iter() {
+                walk_pattern(visitor, element)
+            }
+        },
+        Pattern::Wildcard | Pattern::Binding(_) => (),
+    }
+}
+
+/// Walks a tree of nodes by mutable reference,
+/// for passes rewriting nodes in place.
+pub trait VisitorMut {
+    fn visit_node_mut(&mut self, node: &mut Node) {
+        walk_node_mut(self, node)
+    }
+
+    fn visit_string_literal_mut(&mut self, _value: &mut String) {}
+
+    fn visit_char_literal_mut(&mut self, _value: &mut char) {}
+
+    fn visit_int_literal_mut(&mut self, _value: &mut u64, _signed: &mut bool) {}
+
+    fn visit_float_literal_mut(&mut self, _value: &mut f64) {}
+
+    fn visit_boolean_literal_mut(&mut self, _value: &mut bool) {}
+
+    fn visit_identifier_mut(&mut self, _name: &mut String) {}
+
+    fn visit_unary_mut(&mut self, _op: &mut Op, operand: &mut Node) {
+        self.visit_node_mut(operand)
+    }
+
+    fn visit_binary_mut(&mut self, _op: &mut Op, left: &mut Node, right: &mut Node) {
+        self.visit_node_mut(left);
+        self.visit_node_mut(right)
+    }
+
+    fn visit_call_mut(&mut self, function: &mut Node, arguments: &mut Vec<Node>) {
+        self.visit_node_mut(function);
+        walk_block_mut(self, arguments)
+    }
+
+    fn visit_field_mut(&mut self, value: &mut Node, _name: &mut String) {
+        self.visit_node_mut(value)
+    }
+
+    fn visit_list_mut(&mut self, elements: &mut Vec<Node>) {
+        walk_block_mut(self, elements)
+    }
+
+    fn visit_index_mut(&mut self, list: &mut Node, index: &mut Node) {
+        self.visit_node_mut(list);
+        self.visit_node_mut(index)
+    }
+
+    fn visit_if_mut(&mut self, condition: &mut Node, then: &mut Node, otherwise: Option<&mut Node>) {
+        self.visit_node_mut(condition);
+        self.visit_node_mut(then);
+        if let Some(otherwise) = otherwise {
+            self.visit_node_mut(otherwise)
+        }
+    }
+
+    fn visit_let_mut(&mut self, _name: &mut String, _mutable: &mut bool, value: Option<&mut Node>) {
+        if let Some(value) = value {
+            self.visit_node_mut(value)
+        }
+    }
+
+    fn visit_function_mut(&mut self, _name: &mut String, _parameters: &mut Vec<String>, body: &mut Node) {
+        self.visit_node_mut(body)
+    }
+
+    fn visit_lambda_mut(&mut self, _parameters: &mut Vec<String>, body: &mut Node) {
+        self.visit_node_mut(body)
+    }
+
+    fn visit_assign_mut(&mut self, target: &mut Node, value: &mut Node) {
+        self.visit_node_mut(target);
+        self.visit_node_mut(value)
+    }
+
+    fn visit_compound_assign_mut(&mut self, _op: &mut Op, target: &mut Node, value: &mut Node) {
+        self.visit_node_mut(target);
+        self.visit_node_mut(value)
+    }
+
+    fn visit_record_mut(&mut self, _name: &mut String, _fields: &mut Vec<(String, Hint)>) {}
+
+    fn visit_with_mut(&mut self, value: &mut Node, fields: &mut Vec<(String, Node)>) {
+        self.visit_node_mut(value);
+        for &mut (_, ref mut new) in fields.iter_mut() {
+            self.visit_node_mut(new)
+        }
+    }
+
+    fn visit_enum_mut(&mut self, _name: &mut String, _variants: &mut Vec<(String, Vec<Hint>)>) {}
+
+    fn visit_tuple_mut(&mut self, elements: &mut Vec<Node>) {
+        walk_block_mut(self, elements)
+    }
+
+    fn visit_match_mut(&mut self, value: &mut Node, arms: &mut Vec<Arm>) {
+        self.visit_node_mut(value);
+        for arm in arms.iter_mut() {
+            walk_pattern_mut(self, &mut arm.pattern);
+            self.visit_node_mut(&mut arm.body)
+        }
+    }
+
+    fn visit_while_mut(&mut self, condition: &mut Node, body: &mut Node) {
+        self.visit_node_mut(condition);
+        self.visit_node_mut(body)
+    }
+
+    fn visit_for_mut(&mut self, _name: &mut String, values: &mut Node, body: &mut Node) {
+        self.visit_node_mut(values);
+        self.visit_node_mut(body)
+    }
+
+    fn visit_break_mut(&mut self, value: Option<&mut Node>) {
+        if let Some(value) = value {
+            self.visit_node_mut(value)
+        }
+    }
+
+    fn visit_continue_mut(&mut self) {}
+
+    fn visit_block_mut(&mut self, nodes: &mut Vec<Node>) {
+        walk_block_mut(self, nodes)
+    }
+
+    fn visit_error_mut(&mut self) {}
+}
+
+pub fn walk_node_mut<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut Node) {
+    match *node.node_type_mut() {
+        NodeType::StringLiteral(ref mut value)              => visitor.visit_string_literal_mut(value),
+        NodeType::CharLiteral(ref mut value)                => visitor.visit_char_literal_mut(value),
+        NodeType::IntLiteral(ref mut value, ref mut signed) => visitor.visit_int_literal_mut(value, signed),
+        NodeType::FloatLiteral(ref mut value)               => visitor.visit_float_literal_mut(value),
+        NodeType::BooleanLiteral(ref mut value)             => visitor.visit_boolean_literal_mut(value),
+        NodeType::Identifier(ref mut name)                  => visitor.visit_identifier_mut(name),
+        NodeType::Unary(ref mut op, ref mut operand)        => visitor.visit_unary_mut(op, operand),
+        NodeType::Binary(ref mut op, ref mut l, ref mut r)  => visitor.visit_binary_mut(op, l, r),
+        NodeType::Call(ref mut f, ref mut arguments)        => visitor.visit_call_mut(f, arguments),
+        NodeType::Field(ref mut value, ref mut name)        => visitor.visit_field_mut(value, name),
+        NodeType::List(ref mut elements)                    => visitor.visit_list_mut(elements),
+        NodeType::Index(ref mut list, ref mut index)        => visitor.visit_index_mut(list, index),
+        NodeType::If(ref mut c, ref mut t, ref mut o)       => visitor.visit_if_mut(c, t, o.as_mut().map(|o| &mut **o)),
+        NodeType::Let(ref mut name, ref mut m, ref mut v)   => visitor.visit_let_mut(name, m, v.as_mut().map(|v| &mut **v)),
+        NodeType::Function(ref mut n, ref mut p, ref mut b) => visitor.visit_function_mut(n, p, b),
+        NodeType::Lambda(ref mut p, ref mut body)           => visitor.visit_lambda_mut(p, body),
+        NodeType::Assign(ref mut target, ref mut value)     => visitor.visit_assign_mut(target, value),
+        NodeType::CompoundAssign(ref mut op, ref mut target, ref mut value) => visitor.visit_compound_assign_mut(op, target, value),
+        NodeType::Record(ref mut name, ref mut fields)      => visitor.visit_record_mut(name, fields),
+        NodeType::With(ref mut value, ref mut fields)       => visitor.visit_with_mut(value, fields),
+        NodeType::Enum(ref mut name, ref mut variants)      => visitor.visit_enum_mut(name, variants),
+        NodeType::Tuple(ref mut elements)                   => visitor.visit_tuple_mut(elements),
+        NodeType::Match(ref mut value, ref mut arms)        => visitor.visit_match_mut(value, arms),
+        NodeType::While(ref mut c, ref mut body)            => visitor.visit_while_mut(c, body),
+        NodeType::For(ref mut n, ref mut v, ref mut b)      => visitor.visit_for_mut(n, v, b),
+        NodeType::Break(ref mut value)                      => visitor.visit_break_mut(value.as_mut().map(|v| &mut **v)),
+        NodeType::Continue                                  => visitor.visit_continue_mut(),
+        NodeType::Block(ref mut nodes)                      => visitor.visit_block_mut(nodes),
+        NodeType::Error                                     => visitor.visit_error_mut(),
+    }
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, nodes: &mut Vec<Node>) {
+    for node in nodes.iter_mut() {
+        visitor.visit_node_mut(node)
+    }
+}
+
+pub fn walk_pattern_mut<V: VisitorMut + ?Sized>(visitor: &mut V, pattern: &mut Pattern) {
+    match *pattern {
+        Pattern::Literal(ref mut literal) => visitor.visit_node_mut(literal),
+        Pattern::Tuple(ref mut elements) | Pattern::Variant(_, ref mut elements) => {
+            for element in elements.iter_mut() {
+                walk_pattern_mut(visitor, element)
+            }
+        },
+        Pattern::Wildcard | Pattern::Binding(_) => (),
+    }
+}
+
+/// Rebuilds a tree of nodes by value, for passes
+/// which replace a node with one of another kind.
+///
+/// Each kind method returns the node type replacing it;
+/// blocks are folded from their children upwards.
+pub trait Folder {
+    fn fold_node(&mut self, node: Node) -> Node {
+        fold_node(self, node)
+    }
+
+    fn fold_string_literal(&mut self, value: String) -> NodeType {
+        NodeType::StringLiteral(value)
+    }
+
+    fn fold_char_literal(&mut self, value: char) -> NodeType {
+        NodeType::CharLiteral(value)
+    }
+
+    fn fold_int_literal(&mut self, value: u64, signed: bool) -> NodeType {
+        NodeType::IntLiteral(value, signed)
+    }
+
+    fn fold_float_literal(&mut self, value: f64) -> NodeType {
+        NodeType::FloatLiteral(value)
+    }
+
+    fn fold_boolean_literal(&mut self, value: bool) -> NodeType {
+        NodeType::BooleanLiteral(value)
+    }
+
+    fn fold_identifier(&mut self, name: String) -> NodeType {
+        NodeType::Identifier(name)
+    }
+
+    fn fold_unary(&mut self, op: Op, operand: Node) -> NodeType {
+        NodeType::Unary(op, Box::new(self.fold_node(operand)))
+    }
+
+    fn fold_binary(&mut self, op: Op, left: Node, right: Node) -> NodeType {
+        NodeType::Binary(op, Box::new(self.fold_node(left)), Box::new(self.fold_node(right)))
+    }
+
+    fn fold_call(&mut self, function: Node, arguments: Vec<Node>) -> NodeType {
+        NodeType::Call(Box::new(self.fold_node(function)), fold_block(self, arguments))
+    }
+
+    fn fold_field(&mut self, value: Node, name: String) -> NodeType {
+        NodeType::Field(Box::new(self.fold_node(value)), name)
+    }
+
+    fn fold_list(&mut self, elements: Vec<Node>) -> NodeType {
+        NodeType::List(fold_block(self, elements))
+    }
+
+    fn fold_index(&mut self, list: Node, index: Node) -> NodeType {
+        NodeType::Index(Box::new(self.fold_node(list)), Box::new(self.fold_node(index)))
+    }
+
+    fn fold_if(&mut self, condition: Node, then: Node, otherwise: Option<Node>) -> NodeType {
+        NodeType::If(Box::new(self.fold_node(condition)),
+                     Box::new(self.fold_node(then)),
+                     otherwise.map(|o| Box::new(self.fold_node(o))))
+    }
+
+    fn fold_let(&mut self, name: String, mutable: bool, value: Option<Node>) -> NodeType {
+        NodeType::Let(name, mutable, value.map(|v| Box::new(self.fold_node(v))))
+    }
+
+    fn fold_function(&mut self, name: String, parameters: Vec<String>, body: Node) -> NodeType {
+        NodeType::Function(name, parameters, Box::new(self.fold_node(body)))
+    }
+
+    fn fold_lambda(&mut self, parameters: Vec<String>, body: Node) -> NodeType {
+        NodeType::Lambda(parameters, Box::new(self.fold_node(body)))
+    }
+
+    fn fold_assign(&mut self, target: Node, value: Node) -> NodeType {
+        NodeType::Assign(Box::new(self.fold_node(target)), Box::new(self.fold_node(value)))
+    }
+
+    fn fold_compound_assign(&mut self, op: Op, target: Node, value: Node) -> NodeType {
+        NodeType::CompoundAssign(op, Box::new(self.fold_node(target)), Box::new(self.fold_node(value)))
+    }
+
+    fn fold_record(&mut self, name: String, fields: Vec<(String, Hint)>) -> NodeType {
+        NodeType::Record(name, fields)
+    }
+
+    fn fold_with(&mut self, value: Node, fields: Vec<(String, Node)>) -> NodeType {
+        let fields = fields.into_iter().map(|(name, new)| (name, self.fold_node(new))).collect();
+        NodeType::With(Box::new(self.fold_node(value)), fields)
+    }
+
+    fn fold_enum(&mut self, name: String, variants: Vec<(String, Vec<Hint>)>) -> NodeType {
+        NodeType::Enum(name, variants)
+    }
+
+    fn fold_tuple(&mut self, elements: Vec<Node>) -> NodeType {
+        NodeType::Tuple(fold_block(self, elements))
+    }
+
+    fn fold_match(&mut self, value: Node, arms: Vec<Arm>) -> NodeType {
+        let arms = arms.into_iter().map(|arm| Arm {
+            pattern: fold_pattern(self, arm.pattern),
+            span:    arm.span,
+            body:    self.fold_node(arm.body),
+        }).collect();
+        NodeType::Match(Box::new(self.fold_node(value)), arms)
+    }
+
+    fn fold_while(&mut self, condition: Node, body: Node) -> NodeType {
+        NodeType::While(Box::new(self.fold_node(condition)), Box::new(self.fold_node(body)))
+    }
+
+    fn fold_for(&mut self, name: String, values: Node, body: Node) -> NodeType {
+        NodeType::For(name, Box::new(self.fold_node(values)), Box::new(self.fold_node(body)))
+    }
+
+    fn fold_break(&mut self, value: Option<Node>) -> NodeType {
+        NodeType::Break(value.map(|v| Box::new(self.fold_node(v))))
+    }
+
+    fn fold_continue(&mut self) -> NodeType {
+        NodeType::Continue
+    }
+
+    fn fold_block(&mut self, nodes: Vec<Node>) -> NodeType {
+        NodeType::Block(fold_block(self, nodes))
+    }
+
+    fn fold_error(&mut self) -> NodeType {
+        NodeType::Error
+    }
+}
+
+pub fn fold_node<F: Folder + ?Sized>(folder: &mut F, mut node: Node) -> Node {
+    let node_type = mem::replace(node.node_type_mut(), NodeType::Error);
+
+    *node.node_type_mut() = match node_type {
+        NodeType::StringLiteral(value)  => folder.fold_string_literal(value),
+        NodeType::CharLiteral(value)    => folder.fold_char_literal(value),
+        NodeType::IntLiteral(value, s)  => folder.fold_int_literal(value, s),
+        NodeType::FloatLiteral(value)   => folder.fold_float_literal(value),
+        NodeType::BooleanLiteral(value) => folder.fold_boolean_literal(value),
+        NodeType::Identifier(name)      => folder.fold_identifier(name),
+        NodeType::Unary(op, operand)    => folder.fold_unary(op, *operand),
+        NodeType::Binary(op, l, r)      => folder.fold_binary(op, *l, *r),
+        NodeType::Call(f, arguments)    => folder.fold_call(*f, arguments),
+        NodeType::Field(value, name)    => folder.fold_field(*value, name),
+        NodeType::List(elements)        => folder.fold_list(elements),
+        NodeType::Index(list, index)    => folder.fold_index(*list, *index),
+        NodeType::If(c, t, o)           => folder.fold_if(*c, *t, o.map(|o| *o)),
+        NodeType::Let(name, m, value)   => folder.fold_let(name, m, value.map(|v| *v)),
+        NodeType::Function(n, p, b)     => folder.fold_function(n, p, *b),
+        NodeType::Lambda(p, body)       => folder.fold_lambda(p, *body),
+        NodeType::Assign(target, value) => folder.fold_assign(*target, *value),
+        NodeType::CompoundAssign(op, target, value) => folder.fold_compound_assign(op, *target, *value),
+        NodeType::Record(name, fields)  => folder.fold_record(name, fields),
+        NodeType::With(value, fields)   => folder.fold_with(*value, fields),
+        NodeType::Enum(name, variants)  => folder.fold_enum(name, variants),
+        NodeType::Tuple(elements)       => folder.fold_tuple(elements),
+        NodeType::Match(value, arms)    => folder.fold_match(*value, arms),
+        NodeType::While(c, body)        => folder.fold_while(*c, *body),
+        NodeType::For(name, v, body)    => folder.fold_for(name, *v, *body),
+        NodeType::Break(value)          => folder.fold_break(value.map(|v| *v)),
+        NodeType::Continue              => folder.fold_continue(),
+        NodeType::Block(nodes)          => folder.fold_block(nodes),
+        NodeType::Error                 => folder.fold_error(),
+    };
+
+    node
+}
+
+pub fn fold_block<F: Folder + ?Sized>(folder: &mut F, nodes: Vec<Node>) -> Vec<Node> {
+    nodes.into_iter().map(|n| folder.fold_node(n)).collect()
+}
+
+pub fn fold_pattern<F: Folder + ?Sized>(folder: &mut F, pattern: Pattern) -> Pattern {
+    match pattern {
+        Pattern::Literal(literal) => Pattern::Literal(Box::new(folder.fold_node(*literal))),
+        Pattern::Tuple(elements) => {
+            Pattern::Tuple(elements.into_iter().map(|e| fold_pattern(folder, e)).collect())
+        },
+        Pattern::Variant(name, elements) => {
+            Pattern::Variant(name, elements.into_iter().map(|e| fold_pattern(folder, e)).collect())
+        },
+        pattern => pattern,
+    }
+}
+
+/// The parameter of the lambda an operator section such as
+/// `(+ 1)` is parsed as, which no name in source can be.
+pub const SECTION: &'static str = "$";
+
+/// The names a lambda uses without binding them itself,
+/// in the order they are first used. Those naming local
+/// variables around the lambda are the ones it captures.
+pub fn captures(parameters: &[String], body: &Node) -> Vec<String> {
+    let mut captures = Captures {
+        scopes: vec![parameters.to_vec()],
+        names:  Vec::new(),
+    };
+    captures.visit_node(body);
+    captures.names
+}
+
+struct Captures {
+    scopes: Vec<Vec<String>>,
+    names:  Vec<String>,
+}
+
+impl Captures {
+    fn scoped<F: FnOnce(&mut Captures)>(&mut self, names: Vec<String>, f: F) {
+        self.scopes.push(names);
+        f(self);
+        self.scopes.pop();
+    }
+}
+
+impl Visitor for Captures {
+    fn visit_identifier(&mut self, name: &String) {
+        let bound = self.scopes.iter().any(|scope| scope.contains(name));
+        if !bound && !self.names.contains(name) {
+            self.names.push(name.clone())
+        }
+    }
+
+    fn visit_let(&mut self, name: &String, _mutable: bool, value: Option<&Node>) {
+        if let Some(value) = value {
+            self.visit_node(value)
+        }
+        self.scopes.last_mut().unwrap().push(name.clone())
+    }
+
+    fn visit_function(&mut self, _name: &String, parameters: &Vec<String>, body: &Node) {
+        self.visit_lambda(parameters, body)
+    }
+
+    fn visit_lambda(&mut self, parameters: &Vec<String>, body: &Node) {
+        self.scoped(parameters.clone(), |c| c.visit_node(body))
+    }
+
+    fn visit_match(&mut self, value: &Node, arms: &Vec<Arm>) {
+        self.visit_node(value);
+        for arm in arms.iter() {
+            walk_pattern(self, &arm.pattern);
+            let mut names = Vec::new();
+            arm.pattern.bindings(&mut names);
+            self.scoped(names, |c| c.visit_node(&arm.body))
+        }
+    }
+
+    fn visit_for(&mut self, name: &String, values: &Node, body: &Node) {
+        self.visit_node(values);
+        self.scoped(vec![name.clone()], |c| c.visit_node(body))
+    }
+
+    fn visit_block(&mut self, nodes: &Vec<Node>) {
+        self.scoped(Vec::new(), |c| walk_block(c, nodes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(node_type: NodeType) -> Node {
+        Node::new(node_type, Span::default())
+    }
+
+    fn tree() -> Vec<Node> {
+        vec![
+            node(NodeType::IntLiteral(1, false)),
+            node(NodeType::Block(vec![
+                node(NodeType::StringLiteral("a".to_owned())),
+                node(NodeType::Error),
+                node(NodeType::Block(vec![
+                    node(NodeType::IntLiteral(2, false)),
+                ])),
+            ])),
+            node(NodeType::BooleanLiteral(true)),
+        ]
+    }
+
+    #[test]
+    fn visit_every_node() {
+        struct Counter {
+            nodes: usize,
+            ints:  u64,
+        }
+
+        impl Visitor for Counter {
+            fn visit_node(&mut self, node: &Node) {
+                self.nodes += 1;
+                walk_node(self, node)
+            }
+
+            fn visit_int_literal(&mut self, value: u64, _signed: bool) {
+                self.ints += value
+            }
+        }
+
+        let mut counter = Counter { nodes: 0, ints: 0 };
+        walk_block(&mut counter, &tree());
+
+        assert_eq!(counter.nodes, 7);
+        assert_eq!(counter.ints, 3);
+    }
+
+    #[test]
+    fn visit_every_node_mut() {
+        struct Doubler;
+
+        impl VisitorMut for Doubler {
+            fn visit_int_literal_mut(&mut self, value: &mut u64, _signed: &mut bool) {
+                *value *= 2
+            }
+        }
+
+        let mut nodes = tree();
+        walk_block_mut(&mut Doubler, &mut nodes);
+
+        assert_eq!(nodes[0], node(NodeType::IntLiteral(2, false)));
+        assert_eq!(nodes[1], node(NodeType::Block(vec![
+            node(NodeType::StringLiteral("a".to_owned())),
+            node(NodeType::Error),
+            node(NodeType::Block(vec![
+                node(NodeType::IntLiteral(4, false)),
+            ])),
+        ])));
+    }
+
+    #[test]
+    fn fold_every_node() {
+        /// Replaces errors with `false` and
+        /// inlines single-node blocks.
+        struct Cleaner;
+
+        impl Folder for Cleaner {
+            fn fold_block(&mut self, nodes: Vec<Node>) -> NodeType {
+                let mut nodes = fold_block(self, nodes);
+                if nodes.len() == 1 {
+                    nodes.remove(0).node_type().clone()
+                } else {
+                    NodeType::Block(nodes)
+                }
+            }
+
+            fn fold_error(&mut self) -> NodeType {
+                NodeType::BooleanLiteral(false)
+            }
+        }
+
+        let nodes = fold_block(&mut Cleaner, tree());
+
+        assert_eq!(nodes[1], node(NodeType::Block(vec![
+            node(NodeType::StringLiteral("a".to_owned())),
+            node(NodeType::BooleanLiteral(false)),
+            node(NodeType::IntLiteral(2, false)),
+        ])));
+    }
+
+    #[test]
+    fn find_captures() {
+        let document = ::document::Document::new(concat!(
+            "x ->\n",
+            "  let a = f x y\n",
+            "  for i in a..b\n",
+            "    g = i + a + z\n",
+            "  match p\n",
+            "    (Some n, _) -> n + y\n",
+            "  q -> q + a + w\n",
+            "  i\n",
+        ));
+        let lambda = document.ast()[0];
+        let (parameters, body) = match *lambda.node_type() {
+            NodeType::Lambda(ref parameters, ref body) => (parameters, body),
+            _ => unreachable!(),
+        };
+
+        assert_eq!(captures(parameters, body), vec!["f", "y", "b", "g", "z", "p", "w", "i"]);
+    }
+}
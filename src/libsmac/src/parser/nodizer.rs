@@ -1,6 +1,7 @@
+use diagnostic::Diagnostic;
 use parser::matcher::Matcher;
 use parser::node::Node;
-use parser::token::Token;
+use parser::token::{Token, TokenType};
 
 pub struct Snapshot {
     index: usize,
@@ -18,26 +19,28 @@ impl Snapshot {
     }
 }
 
-pub struct Nodizer {
+pub struct Nodizer<'src> {
     index:     usize,
-    items:     Vec<Token>,
-    snapshots: Vec<Snapshot>
+    items:     Vec<Token<'src>>,
+    snapshots: Vec<Snapshot>,
+    diagnostics: Vec<Diagnostic>,
 }
 
-impl Iterator for Nodizer {
-    type Item = Token;
+impl<'src> Iterator for Nodizer<'src> {
+    type Item = Token<'src>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.read().cloned()
     }
 }
 
-impl Nodizer {
-    pub fn new(items: Vec<Token>) -> Nodizer {
+impl<'src> Nodizer<'src> {
+    pub fn new(items: Vec<Token<'src>>) -> Nodizer<'src> {
         Nodizer {
             index:     0,
             items:     items,
             snapshots: Vec::new(),
+            diagnostics: Vec::new(),
         }
     }
 
@@ -49,18 +52,43 @@ impl Nodizer {
         self.index + lookahead >= self.items.len()
     }
 
-    pub fn peek(&self) -> Option<&Token> {
+    pub fn peek(&self) -> Option<&Token<'src>> {
         self.peek_n(0)
     }
 
-    pub fn peek_n(&self, n: usize) -> Option<&Token> {
+    pub fn peek_n(&self, n: usize) -> Option<&Token<'src>> {
         if self.end_n(n) {
             return None
         }
         Some(&self.items[self.index + n])
     }
 
-    pub fn read(&mut self) -> Option<&Token> {
+    /// The token read last.
+    pub fn previous(&self) -> Option<&Token<'src>> {
+        if self.index == 0 {
+            return None
+        }
+        self.items.get(self.index - 1)
+    }
+
+    /// Whether the next token is on the line the token read
+    /// last ends on, continuing the statement it belongs to.
+    pub fn continues_line(&self) -> bool {
+        let is_block = |token: &Token| match *token.token_type() {
+            TokenType::Block(_) => true,
+            _                   => false,
+        };
+
+        match (self.previous(), self.peek()) {
+            (Some(previous), Some(next)) => {
+                !is_block(previous) && !is_block(next)
+                    && previous.span().end.line == next.position().line
+            },
+            _ => false,
+        }
+    }
+
+    pub fn read(&mut self) -> Option<&Token<'src>> {
         if self.end() {
             return None;
         }
@@ -107,7 +135,19 @@ impl Nodizer {
         }
     }
 
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic)
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        ::std::mem::replace(&mut self.diagnostics, Vec::new())
+    }
+
     pub fn index(&self) -> &usize {
         &self.index
     }
+
+    pub fn diagnostics(&self) -> &Vec<Diagnostic> {
+        &self.diagnostics
+    }
 }
\ No newline at end of file
@@ -1,26 +1,34 @@
+use diagnostic::Diagnostic;
 use parser::nodizer::Nodizer;
 use parser::matcher::*;
+use parser::expression::skip_line;
 use parser::node::Node;
 
-use parser::token::Token;
+use parser::token::{Token, TokenType};
 
-pub fn grab_smaragdine_parser(data: Vec<Token>) -> Parser {
+pub fn grab_smaragdine_parser<'src>(data: Vec<Token<'src>>) -> Parser<'src> {
     let nodizer = Nodizer::new(data);
     let mut parser = Parser::new(nodizer);
 
-    let matcher_literal = LiteralMatcher {};
+    let matcher_let        = LetMatcher {};
+    let matcher_type       = TypeMatcher {};
+    let matcher_expression = ExpressionMatcher {};
+    let matcher_block      = BlockMatcher {};
 
-    parser.matchers_mut().push(Box::new(matcher_literal));
+    parser.matchers_mut().push(Box::new(matcher_let));
+    parser.matchers_mut().push(Box::new(matcher_type));
+    parser.matchers_mut().push(Box::new(matcher_expression));
+    parser.matchers_mut().push(Box::new(matcher_block));
     parser
 }
 
-pub struct Parser {
-    nodizer: Nodizer,
+pub struct Parser<'src> {
+    nodizer: Nodizer<'src>,
     matchers: Vec<Box<Matcher>>,
 }
 
-impl Parser {
-    pub fn new(nodizer: Nodizer) -> Parser {
+impl<'src> Parser<'src> {
+    pub fn new(nodizer: Nodizer<'src>) -> Parser<'src> {
         Parser {
             nodizer:  nodizer,
             matchers: Vec::new(),
@@ -37,6 +45,28 @@ impl Parser {
         None
     }
 
+    /// Reports the token no matcher accepted, then skips to
+    /// the next line or block so parsing can resume there.
+    pub fn recover(&mut self) -> Node {
+        let token = self.nodizer.next().unwrap();
+
+        let message = match *token.token_type() {
+            TokenType::Error(message) => format!("{} `{}`", message, token.content()),
+            _                         => format!("Unexpected token `{}`", token.content()),
+        };
+        let diagnostic = Diagnostic::new(*token.position(), message);
+
+        skip_line(&mut self.nodizer, diagnostic, *token.span())
+    }
+
+    pub fn diagnostics(&self) -> &Vec<Diagnostic> {
+        self.nodizer.diagnostics()
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.nodizer.take_diagnostics()
+    }
+
     pub fn matchers(&self) -> &Vec<Box<Matcher>> {
         &self.matchers
     }
@@ -46,14 +76,70 @@ impl Parser {
     }
 }
 
-impl Iterator for Parser {
+impl<'src> Iterator for Parser<'src> {
     type Item = Node;
 
     fn next(&mut self) -> Option<Node> {
         if self.nodizer.end() {
             None
+        } else if self.nodizer.continues_line() {
+            // A statement ends with its line
+            Some(self.recover())
         } else {
-            Some(self.match_node().unwrap())
+            match self.match_node() {
+                Some(node) => Some(node),
+                None       => Some(self.recover()),
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use lexer::{lex_branch, flatten_branch};
+    use lexer::block_tree::BlockTree;
+    use lexer::token::TokenPosition;
+    use parser::grab_smaragdine_parser;
+    use parser::node::{Node, NodeType};
+    use lexer::token::Span;
+
+    fn node(node_type: NodeType) -> Node {
+        Node::new(node_type, Span::default())
+    }
+
+    #[test]
+    fn recover_at_line_and_block_boundaries() {
+        let source         = "1 ) 2\n3\n  ] 4\n  5\n)\n6";
+        let mut block_tree = BlockTree::new(source, 0);
+        let indents        = block_tree.collect_indents();
+        let lexed_root     = lex_branch(&block_tree.tree(&indents));
+
+        let mut parser     = grab_smaragdine_parser(flatten_branch(&lexed_root));
+        let ast: Vec<Node> = parser.by_ref().collect();
+
+        assert_eq!(ast, vec![
+            node(NodeType::IntLiteral(1, false)),
+            node(NodeType::Error),
+            node(NodeType::IntLiteral(3, false)),
+            node(NodeType::Block(vec![
+                node(NodeType::Error),
+                node(NodeType::IntLiteral(5, false)),
+            ])),
+            node(NodeType::Error),
+            node(NodeType::IntLiteral(6, false)),
+        ]);
+
+        let diagnostics: Vec<_> = parser.diagnostics().iter()
+            .map(|d| (*d.position(), d.message().to_owned()))
+            .collect();
+
+        assert_eq!(diagnostics, vec![
+            (TokenPosition::new(1, 2), "Unexpected token `)`".to_owned()),
+            (TokenPosition::new(3, 2), "Unexpected token `]`".to_owned()),
+            (TokenPosition::new(5, 0), "Unexpected token `)`".to_owned()),
+        ]);
+
+        assert_eq!(ast[1].span(), &Span::new(TokenPosition::new(1, 2), TokenPosition::new(1, 5)));
+        assert_eq!(ast[3].span(), &Span::new(TokenPosition::new(3, 2), TokenPosition::new(4, 3)));
+    }
+}
@@ -0,0 +1,480 @@
+use diagnostic::Diagnostic;
+use lexer::block_tree::{Branch, Chunk, ChunkValue};
+use lexer::token::{Token, TokenType, TokenPosition, Span};
+use parser::node::{Node, NodeType, Pattern};
+use serialize::{quote, float};
+
+use std::fmt;
+
+/// A JSON value.
+///
+/// Objects keep their keys in insertion order
+/// so the output is stable between runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Boolean(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+    }
+
+    pub fn string(s: &str) -> Json {
+        Json::String(s.to_owned())
+    }
+
+    pub fn number<N: fmt::Display>(n: N) -> Json {
+        Json::Number(n.to_string())
+    }
+
+    pub fn float(value: f64) -> Json {
+        if value.is_finite() {
+            Json::Number(float(value))
+        } else {
+            Json::Null
+        }
+    }
+
+    fn is_scalar(&self) -> bool {
+        match *self {
+            Json::Array(ref items)   => items.is_empty(),
+            Json::Object(ref fields) => fields.is_empty(),
+            _                        => true,
+        }
+    }
+
+    /// Formats the value over several lines, keeping
+    /// arrays and objects of scalars on one line.
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize) {
+        let inline = match *self {
+            Json::Array(ref items)   => items.iter().all(Json::is_scalar),
+            Json::Object(ref fields) => fields.iter().all(|&(_, ref v)| v.is_scalar()),
+            _                        => true,
+        };
+
+        if inline {
+            out.push_str(&self.to_string());
+            return
+        }
+
+        let pad = "  ".repeat(indent + 1);
+        match *self {
+            Json::Array(ref items) => {
+                out.push_str("[\n");
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(",\n")
+                    }
+                    out.push_str(&pad);
+                    item.write_pretty(out, indent + 1)
+                }
+                out.push('\n');
+                out.push_str(&"  ".repeat(indent));
+                out.push(']')
+            },
+            Json::Object(ref fields) => {
+                out.push_str("{\n");
+                for (i, &(ref key, ref value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(",\n")
+                    }
+                    out.push_str(&pad);
+                    out.push_str(&quote(key));
+                    out.push_str(": ");
+                    value.write_pretty(out, indent + 1)
+                }
+                out.push('\n');
+                out.push_str(&"  ".repeat(indent));
+                out.push('}')
+            },
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null          => write!(f, "null"),
+            Json::Boolean(b)    => write!(f, "{}", b),
+            Json::Number(ref n) => write!(f, "{}", n),
+            Json::String(ref s) => write!(f, "{}", quote(s)),
+            Json::Array(ref items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?
+                    }
+                    write!(f, "{}", item)?
+                }
+                write!(f, "]")
+            },
+            Json::Object(ref fields) => {
+                write!(f, "{{")?;
+                for (i, &(ref key, ref value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?
+                    }
+                    write!(f, "{}: {}", quote(key), value)?
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+/// Conversion into a stable JSON representation.
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> Json {
+        Json::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> Json {
+        self[..].to_json()
+    }
+}
+
+impl<'a, T: ToJson + ?Sized> ToJson for &'a T {
+    fn to_json(&self) -> Json {
+        (*self).to_json()
+    }
+}
+
+impl ToJson for TokenPosition {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("line", Json::number(self.line)),
+            ("col",  Json::number(self.col)),
+        ])
+    }
+}
+
+impl ToJson for Span {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("start", self.start.to_json()),
+            ("end",   self.end.to_json()),
+        ])
+    }
+}
+
+impl<'src> ToJson for Token<'src> {
+    fn to_json(&self) -> Json {
+        let mut fields = vec![
+            ("type",    Json::string(token_type_name(self.token_type()))),
+            ("content", Json::string(self.content())),
+        ];
+
+        match *self.token_type() {
+            TokenType::IntLiteral(value, true) => fields.push(("value", Json::number(value as i64))),
+            TokenType::IntLiteral(value, _)    => fields.push(("value", Json::number(value))),
+            TokenType::FloatLiteral(value)     => fields.push(("value", Json::float(value))),
+            TokenType::CharLiteral(value)      => fields.push(("value", Json::String(value.to_string()))),
+            TokenType::BooleanLiteral(value)   => fields.push(("value", Json::Boolean(value))),
+            TokenType::Keyword(kw)             => fields.push(("value", Json::string(kw.as_str()))),
+            TokenType::Symbol(sym)             => fields.push(("value", Json::string(sym.as_str()))),
+            TokenType::Operator(op)            => fields.push(("value", Json::string(op.as_str()))),
+            TokenType::Error(message)          => fields.push(("value", Json::string(message))),
+            _                                  => (),
+        }
+
+        fields.push(("span", self.span().to_json()));
+
+        if let TokenType::Block(ref tokens) = *self.token_type() {
+            fields.push(("tokens", tokens.to_json()))
+        }
+
+        Json::object(fields)
+    }
+}
+
+fn token_type_name(token_type: &TokenType) -> &'static str {
+    match *token_type {
+        TokenType::Block(_)             => "Block",
+        TokenType::IntLiteral(..)       => "IntLiteral",
+        TokenType::FloatLiteral(_)      => "FloatLiteral",
+        TokenType::Keyword(_)           => "Keyword",
+        TokenType::Symbol(_)            => "Symbol",
+        TokenType::Operator(_)          => "Operator",
+        TokenType::Identifier           => "Identifier",
+        TokenType::Whitespace           => "Whitespace",
+        TokenType::StringLiteral        => "StringLiteral",
+        TokenType::CharLiteral(_)       => "CharLiteral",
+        TokenType::BooleanLiteral(_)    => "BooleanLiteral",
+        TokenType::LiteralStringLiteral => "LiteralStringLiteral",
+        TokenType::Error(_)             => "Error",
+        TokenType::EOF                  => "EOF",
+    }
+}
+
+impl<'a> ToJson for Chunk<'a> {
+    fn to_json(&self) -> Json {
+        let line = ("line", Json::number(self.line() + 1));
+
+        Json::object(match *self.value() {
+            ChunkValue::Source(s)         => vec![line, ("source", Json::string(s))],
+            ChunkValue::Tokens(ref t)     => vec![line, ("tokens", t.to_json())],
+            ChunkValue::Block(ref branch) => vec![line, ("block", branch.to_json())],
+        })
+    }
+}
+
+impl<'a> ToJson for Branch<'a> {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("chunks", self.value.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Node {
+    fn to_json(&self) -> Json {
+        let (name, value) = match *self.node_type() {
+            NodeType::StringLiteral(ref value)   => ("StringLiteral", Some(Json::string(value))),
+            NodeType::CharLiteral(value)         => ("CharLiteral", Some(Json::String(value.to_string()))),
+            NodeType::IntLiteral(value, true)    => ("IntLiteral", Some(Json::number(value as i64))),
+            NodeType::IntLiteral(value, _)       => ("IntLiteral", Some(Json::number(value))),
+            NodeType::FloatLiteral(value)        => ("FloatLiteral", Some(Json::float(value))),
+            NodeType::BooleanLiteral(value)      => ("BooleanLiteral", Some(Json::Boolean(value))),
+            NodeType::Identifier(ref name)       => ("Identifier", Some(Json::string(name))),
+            NodeType::Unary(op, _)               => ("Unary", Some(Json::string(op.as_str()))),
+            NodeType::Binary(op, _, _)           => ("Binary", Some(Json::string(op.as_str()))),
+            NodeType::Call(..)                   => ("Call", None),
+            NodeType::Field(_, ref name)         => ("Field", Some(Json::string(name))),
+            NodeType::List(_)                    => ("List", None),
+            NodeType::Index(..)                  => ("Index", None),
+            NodeType::If(..)                     => ("If", None),
+            NodeType::Let(ref name, _, _)        => ("Let", Some(Json::string(name))),
+            NodeType::Function(ref name, _, _)   => ("Function", Some(Json::string(name))),
+            NodeType::Lambda(..)                 => ("Lambda", None),
+            NodeType::Assign(..)                 => ("Assign", None),
+            NodeType::CompoundAssign(op, _, _)   => ("CompoundAssign", Some(Json::string(op.as_str()))),
+            NodeType::Record(ref name, _)        => ("Record", Some(Json::string(name))),
+            NodeType::With(..)                   => ("With", None),
+            NodeType::Enum(ref name, _)          => ("Enum", Some(Json::string(name))),
+            NodeType::Tuple(_)                   => ("Tuple", None),
+            NodeType::Match(..)                  => ("Match", None),
+            NodeType::While(..)                  => ("While", None),
+            NodeType::For(ref name, _, _)        => ("For", Some(Json::string(name))),
+            NodeType::Break(_)                   => ("Break", None),
+            NodeType::Continue                   => ("Continue", None),
+            NodeType::Block(_)                   => ("Block", None),
+            NodeType::Error                      => ("Error", None),
+        };
+
+        let mut fields = vec![("type", Json::string(name))];
+        if let Some(value) = value {
+            fields.push(("value", value))
+        }
+        fields.push(("span", self.span().to_json()));
+
+        match *self.node_type() {
+            NodeType::Unary(_, ref operand) => {
+                fields.push(("operand", operand.to_json()))
+            },
+            NodeType::Binary(_, ref left, ref right) | NodeType::Assign(ref left, ref right) |
+            NodeType::CompoundAssign(_, ref left, ref right) => {
+                fields.push(("left", left.to_json()));
+                fields.push(("right", right.to_json()))
+            },
+            NodeType::Call(ref function, ref arguments) => {
+                fields.push(("function", function.to_json()));
+                fields.push(("arguments", arguments.to_json()))
+            },
+            NodeType::Field(ref value, _) => {
+                fields.push(("object", value.to_json()))
+            },
+            NodeType::List(ref elements) | NodeType::Tuple(ref elements) => {
+                fields.push(("elements", elements.to_json()))
+            },
+            NodeType::Index(ref list, ref index) => {
+                fields.push(("object", list.to_json()));
+                fields.push(("index", index.to_json()))
+            },
+            NodeType::If(ref condition, ref then, ref otherwise) => {
+                fields.push(("condition", condition.to_json()));
+                fields.push(("then", then.to_json()));
+                if let Some(ref otherwise) = *otherwise {
+                    fields.push(("else", otherwise.to_json()))
+                }
+            },
+            NodeType::Let(_, mutable, ref value) => {
+                fields.push(("mutable", Json::Boolean(mutable)));
+                if let Some(ref value) = *value {
+                    fields.push(("value", value.to_json()))
+                }
+            },
+            NodeType::Function(_, ref parameters, ref body) | NodeType::Lambda(ref parameters, ref body) => {
+                let parameters = parameters.iter().map(|p| Json::string(p)).collect();
+                fields.push(("parameters", Json::Array(parameters)));
+                fields.push(("body", body.to_json()))
+            },
+            NodeType::Record(_, ref record) => {
+                let record = record.iter().map(|&(ref name, ref hint)| Json::object(vec![
+                    ("name", Json::string(name)),
+                    ("type", Json::String(hint.to_string())),
+                ])).collect();
+                fields.push(("fields", Json::Array(record)))
+            },
+            NodeType::With(ref value, ref updates) => {
+                fields.push(("object", value.to_json()));
+                let updates = updates.iter().map(|&(ref name, ref new)| Json::object(vec![
+                    ("name",  Json::string(name)),
+                    ("value", new.to_json()),
+                ])).collect();
+                fields.push(("fields", Json::Array(updates)))
+            },
+            NodeType::Enum(_, ref variants) => {
+                let variants = variants.iter().map(|&(ref name, ref payload)| Json::object(vec![
+                    ("name",    Json::string(name)),
+                    ("payload", Json::Array(payload.iter().map(|h| Json::String(h.to_string())).collect())),
+                ])).collect();
+                fields.push(("variants", Json::Array(variants)))
+            },
+            NodeType::Match(ref value, ref arms) => {
+                fields.push(("value", value.to_json()));
+                let arms = arms.iter().map(|arm| Json::object(vec![
+                    ("pattern", arm.pattern.to_json()),
+                    ("span",    arm.span.to_json()),
+                    ("body",    arm.body.to_json()),
+                ])).collect();
+                fields.push(("arms", Json::Array(arms)))
+            },
+            NodeType::While(ref condition, ref body) => {
+                fields.push(("condition", condition.to_json()));
+                fields.push(("body", body.to_json()))
+            },
+            NodeType::For(_, ref values, ref body) => {
+                fields.push(("values", values.to_json()));
+                fields.push(("body", body.to_json()))
+            },
+            NodeType::Break(Some(ref value)) => {
+                fields.push(("value", value.to_json()))
+            },
+            NodeType::Block(ref nodes) => {
+                fields.push(("nodes", nodes.to_json()))
+            },
+            _ => (),
+        }
+
+        Json::object(fields)
+    }
+}
+
+impl ToJson for Pattern {
+    fn to_json(&self) -> Json {
+        match *self {
+            Pattern::Wildcard => Json::object(vec![("type", Json::string("Wildcard"))]),
+            Pattern::Binding(ref name) => Json::object(vec![
+                ("type", Json::string("Binding")),
+                ("name", Json::string(name)),
+            ]),
+            Pattern::Literal(ref value) => Json::object(vec![
+                ("type",  Json::string("Literal")),
+                ("value", value.to_json()),
+            ]),
+            Pattern::Tuple(ref elements) => Json::object(vec![
+                ("type",     Json::string("Tuple")),
+                ("elements", elements.to_json()),
+            ]),
+            Pattern::Variant(ref name, ref payload) => Json::object(vec![
+                ("type",    Json::string("Variant")),
+                ("name",    Json::string(name)),
+                ("payload", payload.to_json()),
+            ]),
+        }
+    }
+}
+
+impl ToJson for Diagnostic {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("message",  Json::string(self.message())),
+            ("position", self.position().to_json()),
+            ("severity", Json::string(&self.severity().to_string())),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::{grab_smaragdine_lexer, lex_branch};
+    use lexer::block_tree::BlockTree;
+
+    #[test]
+    fn token_json() {
+        let tokens: Vec<_> = grab_smaragdine_lexer("-12 \"a\\\"\" <<").collect();
+
+        assert_eq!(tokens.to_json().pretty(), concat!(
+            "[\n",
+            "  {\n",
+            "    \"type\": \"IntLiteral\",\n",
+            "    \"content\": \"-12\",\n",
+            "    \"value\": -12,\n",
+            "    \"span\": {\n",
+            "      \"start\": {\"line\": 1, \"col\": 0},\n",
+            "      \"end\": {\"line\": 1, \"col\": 3}\n",
+            "    }\n",
+            "  },\n",
+            "  {\n",
+            "    \"type\": \"StringLiteral\",\n",
+            "    \"content\": \"a\\\"\",\n",
+            "    \"span\": {\n",
+            "      \"start\": {\"line\": 1, \"col\": 4},\n",
+            "      \"end\": {\"line\": 1, \"col\": 9}\n",
+            "    }\n",
+            "  },\n",
+            "  {\n",
+            "    \"type\": \"Operator\",\n",
+            "    \"content\": \"<<\",\n",
+            "    \"value\": \"<<\",\n",
+            "    \"span\": {\n",
+            "      \"start\": {\"line\": 1, \"col\": 10},\n",
+            "      \"end\": {\"line\": 1, \"col\": 12}\n",
+            "    }\n",
+            "  }\n",
+            "]",
+        ));
+    }
+
+    #[test]
+    fn branch_json() {
+        let mut block_tree = BlockTree::new("1\n  2.5", 0);
+        let indents        = block_tree.collect_indents();
+        let lexed_root     = lex_branch(&block_tree.tree(&indents));
+
+        assert_eq!(lexed_root.to_json().to_string(), concat!(
+            "{\"chunks\": [",
+                "{\"line\": 1, \"tokens\": [",
+                    "{\"type\": \"IntLiteral\", \"content\": \"1\", \"value\": 1, ",
+                    "\"span\": {\"start\": {\"line\": 1, \"col\": 0}, \"end\": {\"line\": 1, \"col\": 1}}}",
+                "]}, ",
+                "{\"line\": 2, \"block\": {\"chunks\": [",
+                    "{\"line\": 2, \"tokens\": [",
+                        "{\"type\": \"FloatLiteral\", \"content\": \"2.5\", \"value\": 2.5, ",
+                        "\"span\": {\"start\": {\"line\": 2, \"col\": 2}, \"end\": {\"line\": 2, \"col\": 5}}}",
+                    "]}",
+                "]}}",
+            "]}",
+        ));
+    }
+}
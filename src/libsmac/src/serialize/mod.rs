@@ -0,0 +1,37 @@
+pub mod json;
+pub mod sexp;
+pub mod source;
+
+pub use self::json::{Json, ToJson};
+pub use self::sexp::to_sexp;
+pub use self::source::to_source;
+
+/// Quotes a string, escaping it the same way
+/// in every format so outputs stay comparable.
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"'  => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == '\u{7f}' => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c    => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Formats a float so it always reads back as one.
+pub fn float(value: f64) -> String {
+    let formatted = format!("{:?}", value);
+    if formatted.contains('.') || formatted.contains('e') || !value.is_finite() {
+        formatted
+    } else {
+        format!("{}.0", formatted)
+    }
+}
@@ -0,0 +1,281 @@
+use lexer::token::Op;
+use parser::node::{Node, Hint, Pattern, Arm, Visitor, walk_node};
+use serialize::{quote, float};
+
+/// Prints nodes as S-expressions, one top-level node
+/// per line, with block children indented beneath.
+///
+/// ```text
+/// (int 1)
+/// (block
+///   (string "a")
+///   (error))
+/// ```
+pub fn to_sexp(nodes: &[Node]) -> String {
+    let mut printer = Printer {
+        out:    String::new(),
+        indent: 0,
+    };
+
+    for node in nodes.iter() {
+        printer.visit_node(node);
+        printer.out.push('\n')
+    }
+
+    printer.out
+}
+
+struct Printer {
+    out:    String,
+    indent: usize,
+}
+
+impl Printer {
+    /// Prints `(head child...)` with the children on the same line.
+    fn form(&mut self, head: &str, nodes: &[&Node]) {
+        self.out.push('(');
+        self.out.push_str(head);
+        for node in nodes.iter() {
+            self.out.push(' ');
+            self.visit_node(node)
+        }
+        self.out.push(')')
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match *pattern {
+            Pattern::Wildcard           => self.out.push('_'),
+            Pattern::Binding(ref name)  => self.out.push_str(&format!("(bind {})", name)),
+            Pattern::Literal(ref value) => self.visit_node(value),
+            Pattern::Tuple(ref elements) => {
+                self.out.push_str("(tuple");
+                for element in elements.iter() {
+                    self.out.push(' ');
+                    self.pattern(element)
+                }
+                self.out.push(')')
+            },
+            Pattern::Variant(ref name, ref payload) => {
+                self.out.push_str(&format!("(variant {}", name));
+                for element in payload.iter() {
+                    self.out.push(' ');
+                    self.pattern(element)
+                }
+                self.out.push(')')
+            },
+        }
+    }
+}
+
+impl Visitor for Printer {
+    fn visit_node(&mut self, node: &Node) {
+        walk_node(self, node)
+    }
+
+    fn visit_string_literal(&mut self, value: &String) {
+        self.out.push_str(&format!("(string {})", quote(value)))
+    }
+
+    fn visit_char_literal(&mut self, value: char) {
+        self.out.push_str(&format!("(char {})", quote(&value.to_string())))
+    }
+
+    fn visit_int_literal(&mut self, value: u64, signed: bool) {
+        if signed {
+            self.out.push_str(&format!("(int {})", value as i64))
+        } else {
+            self.out.push_str(&format!("(int {})", value))
+        }
+    }
+
+    fn visit_float_literal(&mut self, value: f64) {
+        self.out.push_str(&format!("(float {})", float(value)))
+    }
+
+    fn visit_boolean_literal(&mut self, value: bool) {
+        self.out.push_str(&format!("(bool {})", value))
+    }
+
+    fn visit_identifier(&mut self, name: &String) {
+        self.out.push_str(&format!("(ident {})", name))
+    }
+
+    fn visit_unary(&mut self, op: Op, operand: &Node) {
+        self.form(&format!("unary {}", op.as_str()), &[operand])
+    }
+
+    fn visit_binary(&mut self, op: Op, left: &Node, right: &Node) {
+        self.form(&format!("binary {}", op.as_str()), &[left, right])
+    }
+
+    fn visit_call(&mut self, function: &Node, arguments: &Vec<Node>) {
+        let mut nodes = vec![function];
+        nodes.extend(arguments.iter());
+        self.form("call", &nodes)
+    }
+
+    fn visit_field(&mut self, value: &Node, name: &String) {
+        self.form(&format!("field {}", name), &[value])
+    }
+
+    fn visit_list(&mut self, elements: &Vec<Node>) {
+        let nodes: Vec<&Node> = elements.iter().collect();
+        self.form("list", &nodes)
+    }
+
+    fn visit_index(&mut self, list: &Node, index: &Node) {
+        self.form("index", &[list, index])
+    }
+
+    fn visit_if(&mut self, condition: &Node, then: &Node, otherwise: Option<&Node>) {
+        let mut nodes = vec![condition, then];
+        nodes.extend(otherwise);
+        self.form("if", &nodes)
+    }
+
+    fn visit_let(&mut self, name: &String, mutable: bool, value: Option<&Node>) {
+        let head = if mutable { format!("let mut {}", name) } else { format!("let {}", name) };
+        let nodes: Vec<&Node> = value.into_iter().collect();
+        self.form(&head, &nodes)
+    }
+
+    fn visit_function(&mut self, name: &String, parameters: &Vec<String>, body: &Node) {
+        self.form(&format!("function {} ({})", name, parameters.join(" ")), &[body])
+    }
+
+    fn visit_lambda(&mut self, parameters: &Vec<String>, body: &Node) {
+        self.form(&format!("lambda ({})", parameters.join(" ")), &[body])
+    }
+
+    fn visit_assign(&mut self, target: &Node, value: &Node) {
+        self.form("assign", &[target, value])
+    }
+
+    fn visit_compound_assign(&mut self, op: Op, target: &Node, value: &Node) {
+        self.form(&format!("assign {}", op.as_str()), &[target, value])
+    }
+
+    fn visit_record(&mut self, name: &String, fields: &Vec<(String, Hint)>) {
+        self.out.push_str(&format!("(type {}", name));
+        for &(ref field, ref hint) in fields.iter() {
+            self.out.push_str(&format!(" ({} {})", field, hint))
+        }
+        self.out.push(')')
+    }
+
+    fn visit_with(&mut self, value: &Node, fields: &Vec<(String, Node)>) {
+        self.out.push_str("(with ");
+        self.visit_node(value);
+        for &(ref field, ref new) in fields.iter() {
+            self.out.push_str(&format!(" ({} ", field));
+            self.visit_node(new);
+            self.out.push(')')
+        }
+        self.out.push(')')
+    }
+
+    fn visit_enum(&mut self, name: &String, variants: &Vec<(String, Vec<Hint>)>) {
+        self.out.push_str(&format!("(enum {}", name));
+        for &(ref variant, ref payload) in variants.iter() {
+            self.out.push_str(&format!(" ({}", variant));
+            for hint in payload.iter() {
+                self.out.push_str(&format!(" {}", hint))
+            }
+            self.out.push(')')
+        }
+        self.out.push(')')
+    }
+
+    fn visit_tuple(&mut self, elements: &Vec<Node>) {
+        let nodes: Vec<&Node> = elements.iter().collect();
+        self.form("tuple", &nodes)
+    }
+
+    fn visit_match(&mut self, value: &Node, arms: &Vec<Arm>) {
+        self.out.push_str("(match ");
+        self.visit_node(value);
+        self.indent += 1;
+        for arm in arms.iter() {
+            self.out.push('\n');
+            self.out.push_str(&"  ".repeat(self.indent));
+            self.out.push_str("(arm ");
+            self.pattern(&arm.pattern);
+            self.out.push(' ');
+            self.visit_node(&arm.body);
+            self.out.push(')')
+        }
+        self.indent -= 1;
+        self.out.push(')')
+    }
+
+    fn visit_while(&mut self, condition: &Node, body: &Node) {
+        self.form("while", &[condition, body])
+    }
+
+    fn visit_for(&mut self, name: &String, values: &Node, body: &Node) {
+        self.form(&format!("for {}", name), &[values, body])
+    }
+
+    fn visit_break(&mut self, value: Option<&Node>) {
+        let nodes: Vec<&Node> = value.into_iter().collect();
+        self.form("break", &nodes)
+    }
+
+    fn visit_continue(&mut self) {
+        self.out.push_str("(continue)")
+    }
+
+    fn visit_block(&mut self, nodes: &Vec<Node>) {
+        self.out.push_str("(block");
+        self.indent += 1;
+        for node in nodes.iter() {
+            self.out.push('\n');
+            self.out.push_str(&"  ".repeat(self.indent));
+            self.visit_node(node)
+        }
+        self.indent -= 1;
+        self.out.push(')')
+    }
+
+    fn visit_error(&mut self) {
+        self.out.push_str("(error)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_sexp;
+    use lexer::token::Span;
+    use parser::node::{Node, NodeType};
+
+    fn node(node_type: NodeType) -> Node {
+        Node::new(node_type, Span::default())
+    }
+
+    #[test]
+    fn print_nested_blocks() {
+        let nodes = vec![
+            node(NodeType::IntLiteral(-1_i64 as u64, true)),
+            node(NodeType::Block(vec![
+                node(NodeType::StringLiteral("a\n\"b\"".to_owned())),
+                node(NodeType::Block(vec![
+                    node(NodeType::CharLiteral('c')),
+                    node(NodeType::Error),
+                ])),
+                node(NodeType::FloatLiteral(2.0)),
+            ])),
+            node(NodeType::BooleanLiteral(false)),
+        ];
+
+        assert_eq!(to_sexp(&nodes), concat!(
+            "(int -1)\n",
+            "(block\n",
+            "  (string \"a\\n\\\"b\\\"\")\n",
+            "  (block\n",
+            "    (char \"c\")\n",
+            "    (error))\n",
+            "  (float 2.0))\n",
+            "(bool false)\n",
+        ));
+    }
+}
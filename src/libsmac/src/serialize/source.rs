@@ -0,0 +1,465 @@
+use lexer::token::Op;
+use parser::node::{self, Node, NodeType, Pattern, Visitor, walk_node, walk_block};
+
+/// Prints nodes back as Smaragdine source, one node
+/// per line, with block children indented beneath.
+/// Error nodes have no source form and are left out.
+///
+/// ```text
+/// 1
+///   "a"
+///     'c'
+/// false
+/// ```
+pub fn to_source(nodes: &[Node]) -> String {
+    let mut printer = Printer {
+        out:    String::new(),
+        indent: 0,
+    };
+
+    for node in nodes.iter() {
+        printer.visit_node(node)
+    }
+
+    printer.out
+}
+
+/// Quotes a string or char literal with the
+/// escapes the lexer reads back.
+fn quote(s: &str, delimeter: char) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push(delimeter);
+    for c in s.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c == delimeter => {
+                quoted.push('\\');
+                quoted.push(c)
+            },
+            c    => quoted.push(c),
+        }
+    }
+    quoted.push(delimeter);
+    quoted
+}
+
+/// The binding power of a binary operator, as in the parser.
+fn binary_power(op: Op) -> (u8, bool) {
+    match op {
+        Op::Assign                          => (1, true),
+        Op::Pipe                            => (1, false),
+        Op::Or                              => (2, false),
+        Op::And                             => (3, false),
+        Op::BitOr                           => (4, false),
+        Op::BitXor                          => (5, false),
+        Op::BitAnd                          => (6, false),
+        Op::Eq | Op::Ne                     => (7, false),
+        Op::Lt | Op::Gt | Op::Le | Op::Ge   => (8, false),
+        Op::Range | Op::RangeInclusive      => (9, false),
+        Op::Shl | Op::Shr                   => (10, false),
+        Op::Add | Op::Sub                   => (11, false),
+        _                                   => (12, false),
+    }
+}
+
+const UNARY_POWER: u8 = 13;
+const CALL_POWER:  u8 = 14;
+const ATOM_POWER:  u8 = 15;
+
+fn int(value: u64, signed: bool) -> String {
+    if signed {
+        // Signed literals are always written with their sign,
+        // which also keeps `-0` apart from `0`
+        format!("-{}", (value as i64).wrapping_neg() as u64)
+    } else {
+        value.to_string()
+    }
+}
+
+fn float(value: f64) -> String {
+    // Float literals have no exponent, which `Display` never uses
+    let mut text = value.to_string();
+    if !text.contains('.') {
+        text.push_str(".0")
+    }
+    text
+}
+
+/// The operator of the section a lambda was parsed from, and
+/// its operand, with whether that is on the left.
+fn section(node: &Node) -> Option<(Op, &Node, bool)> {
+    let body = match *node.node_type() {
+        NodeType::Lambda(ref parameters, ref body) if *parameters == [node::SECTION] => body,
+        _ => return None,
+    };
+    let is_parameter = |node: &Node| match *node.node_type() {
+        NodeType::Identifier(ref name) => name == node::SECTION,
+        _                              => false,
+    };
+    match *body.node_type() {
+        NodeType::Binary(op, ref left, ref right) if is_parameter(left) => Some((op, &**right, false)),
+        NodeType::Binary(op, ref left, ref right) if is_parameter(right) => Some((op, &**left, true)),
+        _ => None,
+    }
+}
+
+struct Printer {
+    out:    String,
+    indent: usize,
+}
+
+impl Printer {
+
+    fn start_line(&mut self) {
+        self.out.push_str(&"  ".repeat(self.indent))
+    }
+
+    /// Ends the current line, unless a block already did.
+    fn end_line(&mut self) {
+        if !self.out.ends_with('\n') {
+            self.out.push('\n')
+        }
+    }
+
+    /// Prints the indented block of a statement, or the
+    /// single node standing in for it.
+    fn block(&mut self, node: &Node) {
+        self.end_line();
+        match *node.node_type() {
+            NodeType::Block(_) => self.visit_node(node),
+            _ => {
+                self.indent += 1;
+                self.visit_node(node);
+                self.indent -= 1
+            },
+        }
+    }
+
+    /// Prints `node` inline, in parentheses unless it
+    /// binds at least as tightly as `power`.
+    fn expression(&mut self, node: &Node, power: u8) {
+        let own = match *node.node_type() {
+            NodeType::Assign(..)       => 1,
+            NodeType::CompoundAssign(..) => 1,
+            NodeType::With(..)         => 1,
+            NodeType::Lambda(..) if section(node).is_some() => ATOM_POWER,
+            NodeType::Lambda(..)       => 1,
+            NodeType::Binary(op, _, _) => binary_power(op).0,
+            NodeType::Break(Some(_))   => 1,
+            NodeType::Unary(..)        => UNARY_POWER,
+            NodeType::IntLiteral(_, true) => UNARY_POWER,
+            NodeType::Call(..)         => CALL_POWER,
+            _                          => ATOM_POWER,
+        };
+
+        if own < power {
+            self.out.push('(');
+            self.expression(node, 0);
+            self.out.push(')');
+            return
+        }
+
+        match *node.node_type() {
+            NodeType::StringLiteral(ref value) => self.out.push_str(&quote(value, '"')),
+            NodeType::CharLiteral(value)       => self.out.push_str(&quote(&value.to_string(), '\'')),
+            NodeType::IntLiteral(value, signed) => self.out.push_str(&int(value, signed)),
+            NodeType::FloatLiteral(value)      => self.out.push_str(&float(value)),
+            NodeType::BooleanLiteral(value)    => self.out.push_str(&value.to_string()),
+            NodeType::Identifier(ref name)     => self.out.push_str(name),
+            NodeType::Unary(op, ref operand) => {
+                self.out.push_str(op.as_str());
+                // Keep `-(1)` and `-(1[0])` from reading back as signed literals
+                let literal = match *operand.node_type() {
+                    NodeType::Unary(..) => true,
+                    _                   => starts_with_number(operand),
+                };
+                self.expression(operand, if literal { ATOM_POWER + 1 } else { UNARY_POWER })
+            },
+            NodeType::Binary(op, ref left, ref right) => {
+                let (own, right_associative) = binary_power(op);
+                self.expression(left, if right_associative { own + 1 } else { own });
+                self.out.push_str(&format!(" {} ", op.as_str()));
+                self.expression(right, if right_associative { own } else { own + 1 })
+            },
+            NodeType::Assign(ref target, ref value) => {
+                self.expression(target, 2);
+                self.out.push_str(" = ");
+                self.expression(value, 1)
+            },
+            NodeType::CompoundAssign(op, ref target, ref value) => {
+                self.expression(target, 2);
+                self.out.push_str(&format!(" {}= ", op.as_str()));
+                self.expression(value, 1)
+            },
+            NodeType::With(ref value, ref fields) => {
+                self.expression(value, 2);
+                self.out.push_str(" with ");
+                for (i, &(ref field, ref new)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ")
+                    }
+                    self.out.push_str(&format!("{} = ", field));
+                    self.expression(new, 2)
+                }
+            },
+            NodeType::Field(ref value, ref name) => {
+                // Keep the point from reading as part of a number
+                let number = match *value.node_type() {
+                    NodeType::IntLiteral(..) | NodeType::FloatLiteral(_) => true,
+                    _ => false,
+                };
+                self.expression(value, if number { ATOM_POWER + 1 } else { ATOM_POWER });
+                self.out.push('.');
+                self.out.push_str(name)
+            },
+            NodeType::Tuple(ref elements) => {
+                self.out.push('(');
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ")
+                    }
+                    // Keep a `with` from taking the next element as a field
+                    self.expression(element, 2)
+                }
+                self.out.push(')')
+            },
+            NodeType::List(ref elements) => {
+                self.out.push('[');
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ")
+                    }
+                    // As in a tuple
+                    self.expression(element, 2)
+                }
+                self.out.push(']')
+            },
+            NodeType::Index(ref list, ref index) => {
+                self.expression(list, ATOM_POWER);
+                self.out.push('[');
+                self.expression(index, 0);
+                self.out.push(']')
+            },
+            NodeType::Call(ref function, ref arguments) => {
+                self.expression(function, ATOM_POWER);
+                for argument in arguments.iter() {
+                    self.out.push(' ');
+                    self.expression(argument, ATOM_POWER)
+                }
+            },
+            NodeType::If(ref condition, ref then, ref otherwise) => {
+                self.out.push_str("if ");
+                self.expression(condition, 0);
+                self.block(then);
+                if let Some(ref otherwise) = *otherwise {
+                    self.start_line();
+                    self.out.push_str("else");
+                    match *otherwise.node_type() {
+                        NodeType::If(..) => {
+                            self.out.push(' ');
+                            self.expression(otherwise, 0)
+                        },
+                        _ => self.block(otherwise),
+                    }
+                }
+            },
+            NodeType::Let(ref name, mutable, ref value) => {
+                self.out.push_str(if mutable { "let mut " } else { "let " });
+                self.out.push_str(name);
+                if let Some(ref value) = *value {
+                    self.out.push_str(" =");
+                    self.value(value)
+                }
+            },
+            NodeType::Function(ref name, ref parameters, ref body) => {
+                self.out.push_str(&format!("let {} {} =", name, parameters.join(" ")));
+                self.value(body)
+            },
+            NodeType::Lambda(ref parameters, ref body) => match section(node) {
+                Some((op, operand, true)) => {
+                    self.out.push('(');
+                    self.expression(operand, 2);
+                    self.out.push_str(&format!(" {})", op.as_str()))
+                },
+                Some((op, operand, false)) => {
+                    let (own, right_associative) = binary_power(op);
+                    self.out.push_str(&format!("({} ", op.as_str()));
+                    self.expression(operand, if right_associative { own } else { own + 1 });
+                    self.out.push(')')
+                },
+                None => {
+                    self.out.push_str(&format!("{} ->", parameters.join(" ")));
+                    self.value(body)
+                },
+            },
+            NodeType::Record(ref name, ref fields) => {
+                self.out.push_str(&format!("type {}", name));
+                self.indent += 1;
+                for &(ref field, ref hint) in fields.iter() {
+                    self.end_line();
+                    self.start_line();
+                    self.out.push_str(&format!("{}: {}", field, hint))
+                }
+                self.indent -= 1
+            },
+            NodeType::Enum(ref name, ref variants) => {
+                self.out.push_str(&format!("type {}", name));
+                self.indent += 1;
+                for &(ref variant, ref payload) in variants.iter() {
+                    self.end_line();
+                    self.start_line();
+                    self.out.push_str(variant);
+                    for hint in payload.iter() {
+                        self.out.push_str(&format!(" {}", hint))
+                    }
+                }
+                self.indent -= 1
+            },
+            NodeType::Match(ref value, ref arms) => {
+                self.out.push_str("match ");
+                self.expression(value, 0);
+                self.indent += 1;
+                for arm in arms.iter() {
+                    self.end_line();
+                    self.start_line();
+                    self.pattern(&arm.pattern, false);
+                    self.out.push_str(" ->");
+                    self.value(&arm.body)
+                }
+                self.indent -= 1
+            },
+            NodeType::While(ref condition, ref body) => {
+                self.out.push_str("while ");
+                self.expression(condition, 0);
+                self.block(body)
+            },
+            NodeType::For(ref name, ref values, ref body) => {
+                self.out.push_str(&format!("for {} in ", name));
+                self.expression(values, 0);
+                self.block(body)
+            },
+            NodeType::Break(ref value) => {
+                self.out.push_str("break");
+                if let Some(ref value) = *value {
+                    self.out.push(' ');
+                    self.expression(value, 1)
+                }
+            },
+            NodeType::Continue => self.out.push_str("continue"),
+            NodeType::Block(_) | NodeType::Error => (),
+        }
+    }
+
+    /// Prints a pattern, in parentheses if it is a variant
+    /// with a payload and `atom` asks for a single token.
+    fn pattern(&mut self, pattern: &Pattern, atom: bool) {
+        match *pattern {
+            Pattern::Wildcard           => self.out.push('_'),
+            Pattern::Binding(ref name)  => self.out.push_str(name),
+            Pattern::Literal(ref value) => self.expression(value, if atom { ATOM_POWER } else { 0 }),
+            Pattern::Tuple(ref elements) => {
+                self.out.push('(');
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ")
+                    }
+                    self.pattern(element, false)
+                }
+                self.out.push(')')
+            },
+            Pattern::Variant(ref name, ref payload) => {
+                let parenthesize = atom && !payload.is_empty();
+                if parenthesize {
+                    self.out.push('(')
+                }
+                self.out.push_str(name);
+                for element in payload.iter() {
+                    self.out.push(' ');
+                    self.pattern(element, true)
+                }
+                if parenthesize {
+                    self.out.push(')')
+                }
+            },
+        }
+    }
+
+    /// Prints the value of a binding, on its line or as a block.
+    fn value(&mut self, node: &Node) {
+        match *node.node_type() {
+            NodeType::Block(_) => self.block(node),
+            _ => {
+                self.out.push(' ');
+                self.expression(node, 0)
+            },
+        }
+    }
+}
+
+impl Visitor for Printer {
+    fn visit_node(&mut self, node: &Node) {
+        match *node.node_type() {
+            NodeType::Block(_) | NodeType::Error => walk_node(self, node),
+            _ => {
+                self.start_line();
+                self.expression(node, 0);
+                self.end_line()
+            },
+        }
+    }
+
+    fn visit_block(&mut self, nodes: &Vec<Node>) {
+        self.indent += 1;
+        walk_block(self, nodes);
+        self.indent -= 1
+    }
+
+    fn visit_error(&mut self) {}
+}
+
+/// Whether a node is printed starting with a number literal.
+fn starts_with_number(node: &Node) -> bool {
+    match *node.node_type() {
+        NodeType::IntLiteral(..) | NodeType::FloatLiteral(_) => true,
+        NodeType::Field(ref value, _) | NodeType::Index(ref value, _) => starts_with_number(value),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_source;
+    use lexer::token::Span;
+    use parser::node::{Node, NodeType};
+
+    fn node(node_type: NodeType) -> Node {
+        Node::new(node_type, Span::default())
+    }
+
+    #[test]
+    fn print_nested_blocks() {
+        let nodes = vec![
+            node(NodeType::IntLiteral(-1_i64 as u64, true)),
+            node(NodeType::Block(vec![
+                node(NodeType::StringLiteral("a\n\"b\"".to_owned())),
+                node(NodeType::Block(vec![
+                    node(NodeType::CharLiteral('\'')),
+                    node(NodeType::Error),
+                ])),
+                node(NodeType::FloatLiteral(2.0)),
+            ])),
+            node(NodeType::BooleanLiteral(false)),
+        ];
+
+        assert_eq!(to_source(&nodes), concat!(
+            "-1\n",
+            "  \"a\\n\\\"b\\\"\"\n",
+            "    '\\''\n",
+            "  2.0\n",
+            "false\n",
+        ));
+    }
+}
@@ -0,0 +1,1430 @@
+//! Type inference.
+//!
+//! Every expression has a single type, found by unifying the
+//! types its uses demand. Functions are defined at the top
+//! level, where they can all see each other. Lambdas are
+//! function values, which see the variables around them.
+//! Both are curried: given fewer arguments than they take,
+//! they are a function of the rest, and given more, what
+//! they return is applied to the rest, so `a -> b -> c` is
+//! the same type as a function of two arguments. Record
+//! types are only ever called with all their fields, in
+//! order, and the variants of enum types with their payload.
+//! Where a value has no field `f`, `x.f` is the function `f`
+//! applied to it, so `xs.map g` is `map xs g`.
+//!
+//! A `match` must have an arm for every value, and each arm
+//! must match some value the arms before it do not.
+
+use diagnostic::Diagnostic;
+use lexer::token::{Op, TokenPosition};
+use parser::node::{Node, NodeType, Hint, Pattern, Arm};
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Unit,
+    Int,
+    Float,
+    Bool,
+    Char,
+    String,
+    /// Parameter types and the return type.
+    Function(Vec<Type>, Box<Type>),
+    /// The type of its elements.
+    List(Box<Type>),
+    /// A record type, by name.
+    Record(String),
+    /// An enum type, by name.
+    Enum(String),
+    /// The types of its elements.
+    Tuple(Vec<Type>),
+    /// A type not known yet, shown as `'a`, `'b` and so on.
+    Var(usize),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Type::Unit   => write!(f, "()"),
+            Type::Int    => write!(f, "int"),
+            Type::Float  => write!(f, "float"),
+            Type::Bool   => write!(f, "bool"),
+            Type::Char   => write!(f, "char"),
+            Type::String => write!(f, "string"),
+            Type::Function(ref parameters, ref result) => {
+                for parameter in parameters.iter() {
+                    match *parameter {
+                        Type::Function(..) => write!(f, "({}) -> ", parameter)?,
+                        _                  => write!(f, "{} -> ", parameter)?,
+                    }
+                }
+                write!(f, "{}", result)
+            },
+            Type::List(ref element) => write!(f, "[{}]", element),
+            Type::Record(ref name) | Type::Enum(ref name) => write!(f, "{}", name),
+            Type::Tuple(ref elements) => {
+                write!(f, "(")?;
+                for (i, element) in elements.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { "" } else { ", " }, element)?
+                }
+                write!(f, ")")
+            },
+            Type::Var(id) if id < 26 => write!(f, "'{}", (b'a' + id as u8) as char),
+            Type::Var(id)            => write!(f, "'{}{}", (b'a' + (id % 26) as u8) as char, id / 26),
+        }
+    }
+}
+
+/// The types found by `check`.
+///
+/// Nodes are looked up by address, so the tree
+/// must not be moved while these are in use.
+#[derive(Debug, Clone)]
+pub struct Types {
+    nodes:     HashMap<usize, Type>,
+    functions: HashMap<String, Type>,
+    records:   Vec<(String, Vec<(String, Type)>)>,
+    enums:     Vec<(String, Vec<(String, Vec<Type>)>)>,
+}
+
+impl Types {
+    /// The type of an expression.
+    pub fn of(&self, node: &Node) -> Option<&Type> {
+        self.nodes.get(&address(node))
+    }
+
+    /// The type of a top-level function.
+    pub fn function(&self, name: &str) -> Option<&Type> {
+        self.functions.get(name)
+    }
+
+    /// The fields of a record type, in the order they are declared.
+    pub fn record(&self, name: &str) -> Option<&[(String, Type)]> {
+        self.records.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref fields)| &fields[..])
+    }
+
+    /// The variants of an enum type and the types of their
+    /// payloads, in the order they are declared.
+    pub fn variants(&self, name: &str) -> Option<&[(String, Vec<Type>)]> {
+        self.enums.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref variants)| &variants[..])
+    }
+}
+
+fn address(node: &Node) -> usize {
+    node as *const Node as usize
+}
+
+/// What an operator needs of its operands' type.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Constraint {
+    /// `int` or `float`.
+    Numeric,
+    /// A number, `string` or list.
+    Addable,
+    /// A number, `char` or `string`.
+    Ordered,
+    /// Anything with no function in it.
+    Equatable,
+}
+
+impl Constraint {
+    fn allows(&self, t: &Type) -> bool {
+        match (*self, t) {
+            (_, &Type::Int) | (_, &Type::Float)                         => true,
+            (Constraint::Addable, &Type::String)
+                | (Constraint::Addable, &Type::List(_))                 => true,
+            (Constraint::Ordered, &Type::String)
+                | (Constraint::Ordered, &Type::Char)                    => true,
+            (Constraint::Equatable, t)                                  => !holds_function(t),
+            _                                                           => false,
+        }
+    }
+}
+
+/// Infers the type of every expression.
+pub fn check(nodes: &[Node]) -> Result<Types, Vec<Diagnostic>> {
+    let mut checker = Checker {
+        bindings:    Vec::new(),
+        nodes:       Vec::new(),
+        functions:   HashMap::new(),
+        records:     Vec::new(),
+        enums:       Vec::new(),
+        scopes:      vec![Vec::new()],
+        outer:       Vec::new(),
+        loops:       Vec::new(),
+        constraints: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+
+    let mut duplicates = Vec::new();
+    for node in nodes.iter() {
+        let name = match *node.node_type() {
+            NodeType::Function(ref name, ..) | NodeType::Record(ref name, _)
+                | NodeType::Enum(ref name, _) => name,
+            _ => continue,
+        };
+        if checker.functions.contains_key(name) || checker.record(name).is_some()
+            || checker.enums.iter().any(|&(ref n, _)| n == name) {
+            checker.error(node, format!("`{}` is already defined", name));
+            duplicates.push(address(node));
+            continue
+        }
+        match *node.node_type() {
+            NodeType::Function(_, ref parameters, _) => {
+                let parameters = parameters.iter().map(|_| checker.fresh()).collect();
+                let result     = Box::new(checker.fresh());
+                checker.functions.insert(name.clone(), Type::Function(parameters, result));
+            },
+            NodeType::Record(..) => checker.records.push((name.clone(), Vec::new())),
+            _                    => checker.enums.push((name.clone(), Vec::new())),
+        }
+    }
+
+    // Fields and payloads may name any type, declared before or after
+    for node in nodes.iter() {
+        if duplicates.contains(&address(node)) {
+            continue
+        }
+        match *node.node_type() {
+            NodeType::Record(ref name, ref fields)  => checker.check_record(node, name, fields),
+            NodeType::Enum(ref name, ref variants) => checker.check_enum(node, name, variants),
+            _ => (),
+        }
+    }
+
+    for node in nodes.iter() {
+        match *node.node_type() {
+            NodeType::Function(ref name, ref parameters, ref body) => {
+                if !duplicates.contains(&address(node)) {
+                    checker.check_function(node, name, parameters, body)
+                }
+            },
+            NodeType::Record(..) | NodeType::Enum(..) => (),
+            _ => {
+                checker.check(node);
+            },
+        }
+    }
+
+    checker.diagnostics.extend(::mutability::check(nodes));
+    checker.finish()
+}
+
+struct Checker {
+    /// What each type variable stands for, once known.
+    bindings:    Vec<Option<Type>>,
+    nodes:       Vec<(usize, Type)>,
+    functions:   HashMap<String, Type>,
+    /// The record types and their fields, in the
+    /// order they are declared.
+    records:     Vec<(String, Vec<(String, Type)>)>,
+    /// The enum types and the payloads of their
+    /// variants, in the order they are declared.
+    enums:       Vec<(String, Vec<(String, Vec<Type>)>)>,
+    scopes:      Vec<Vec<(String, Type)>>,
+    /// The scopes around the function being checked,
+    /// whose names it cannot use.
+    outer:       Vec<Vec<(String, Type)>>,
+    /// The loops around the node being checked, with the
+    /// type of the value a `while true` loop breaks with.
+    loops:       Vec<Option<Type>>,
+    constraints: Vec<(Constraint, Type, Op, Vec<Type> /* operands */, TokenPosition)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn fresh(&mut self) -> Type {
+        self.bindings.push(None);
+        Type::Var(self.bindings.len() - 1)
+    }
+
+    fn error(&mut self, node: &Node, message: String) {
+        self.error_at(node.span().start, message)
+    }
+
+    fn error_at(&mut self, position: TokenPosition, message: String) {
+        self.diagnostics.push(Diagnostic::new(position, message))
+    }
+
+    /// Follows bound variables as far as they go.
+    fn resolve(&self, t: &Type) -> Type {
+        match *t {
+            Type::Var(id) => match self.bindings[id] {
+                Some(ref bound) => self.resolve(bound),
+                None            => t.clone(),
+            },
+            Type::Function(ref parameters, ref result) => {
+                Type::Function(parameters.iter().map(|p| self.resolve(p)).collect(),
+                               Box::new(self.resolve(result)))
+            },
+            Type::List(ref element) => Type::List(Box::new(self.resolve(element))),
+            Type::Tuple(ref elements) => Type::Tuple(elements.iter().map(|e| self.resolve(e)).collect()),
+            _ => t.clone(),
+        }
+    }
+
+    /// Makes `found`, the type of `node`, agree with `expected`.
+    fn unify(&mut self, expected: &Type, found: &Type, node: &Node) {
+        let position = value(node).span().start;
+        self.unify_at(expected, found, position)
+    }
+
+    fn unify_at(&mut self, expected: &Type, found: &Type, position: TokenPosition) {
+        let expected = self.resolve(expected);
+        let found    = self.resolve(found);
+
+        if !self.agree(&expected, &found) {
+            let names = readable(&[expected, found]);
+            self.error_at(position, format!("Expected `{}`, found `{}`", names[0], names[1]))
+        }
+    }
+
+    /// Binds variables so `a` and `b` become the same type,
+    /// looking inside lists, tuples and functions. False if
+    /// they cannot be.
+    fn agree(&mut self, a: &Type, b: &Type) -> bool {
+        match (self.resolve(a), self.resolve(b)) {
+            (Type::Var(a), Type::Var(b)) if a == b => true,
+            (Type::Var(id), t) | (t, Type::Var(id)) => {
+                // `'a` cannot be `['a]`
+                if occurs(id, &t) {
+                    return false
+                }
+                self.bindings[id] = Some(t);
+                true
+            },
+            (Type::List(a), Type::List(b)) => self.agree(&a, &b),
+            (Type::Tuple(a), Type::Tuple(b)) => {
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| self.agree(a, b))
+            },
+            (Type::Function(a, r), Type::Function(b, s)) => {
+                // Whichever takes more parameters returns
+                // a function of the rest, curried
+                let n = a.len().min(b.len());
+                a.iter().zip(b.iter()).all(|(a, b)| self.agree(a, b))
+                    && self.agree(&curried(&a[n..], *r), &curried(&b[n..], *s))
+            },
+            (a, b) => a == b,
+        }
+    }
+
+    /// Checks `t` against `constraint` once inference is done.
+    /// The operands are the types to show if it fails.
+    fn constrain(&mut self, constraint: Constraint, t: &Type, op: Op, operands: Vec<Type>, node: &Node) {
+        self.constraints.push((constraint, t.clone(), op, operands, node.span().start))
+    }
+
+    /// Looks up a name among the current function's locals.
+    fn local(&mut self, node: &Node, name: &str) -> Option<Type> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(&(_, ref t)) = scope.iter().rev().find(|&&(ref n, _)| n == name) {
+                return Some(t.clone())
+            }
+        }
+
+        let captured = self.outer.iter().any(|scope| scope.iter().any(|&(ref n, _)| n == name));
+        if captured {
+            self.error(node, format!("Functions cannot capture `{}`", name));
+            return Some(self.fresh())
+        }
+        None
+    }
+
+    /// Whether a name is a local variable, here or
+    /// around the function being checked.
+    fn is_local(&self, name: &str) -> bool {
+        self.scopes.iter().chain(self.outer.iter()).any(|scope| scope.iter().any(|&(ref n, _)| n == name))
+    }
+
+    fn record(&self, name: &str) -> Option<&Vec<(String, Type)>> {
+        self.records.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref fields)| fields)
+    }
+
+    fn check_record(&mut self, node: &Node, name: &str, fields: &[(String, Hint)]) {
+        let mut types: Vec<(String, Type)> = Vec::new();
+        for &(ref field, ref hint) in fields.iter() {
+            if types.iter().any(|&(ref n, _)| n == field) {
+                self.error(node, format!("Duplicate field `{}`", field))
+            }
+            let t = self.hint(node, hint);
+            types.push((field.clone(), t))
+        }
+        let record = self.records.iter_mut().find(|&&mut (ref n, _)| n == name).unwrap();
+        record.1 = types
+    }
+
+    fn check_enum(&mut self, node: &Node, name: &str, variants: &[(String, Vec<Hint>)]) {
+        let mut types: Vec<(String, Vec<Type>)> = Vec::new();
+        for &(ref variant, ref payload) in variants.iter() {
+            if types.iter().any(|&(ref n, _)| n == variant) {
+                self.error(node, format!("Duplicate variant `{}`", variant))
+            } else if self.functions.contains_key(variant) || self.record(variant).is_some()
+                || self.variant(variant).is_some() {
+                self.error(node, format!("`{}` is already defined", variant))
+            }
+            let payload = payload.iter().map(|hint| self.hint(node, hint)).collect();
+            types.push((variant.clone(), payload))
+        }
+        let declared = self.enums.iter_mut().find(|&&mut (ref n, _)| n == name).unwrap();
+        declared.1 = types
+    }
+
+    /// The enum type a variant belongs to and its payload.
+    fn variant(&self, name: &str) -> Option<(String, Vec<Type>)> {
+        for &(ref e, ref variants) in self.enums.iter() {
+            if let Some(&(_, ref payload)) = variants.iter().find(|&&(ref n, _)| n == name) {
+                return Some((e.clone(), payload.clone()))
+            }
+        }
+        None
+    }
+
+    /// The type a hint names.
+    fn hint(&mut self, node: &Node, hint: &Hint) -> Type {
+        match *hint {
+            Hint::List(ref element) => Type::List(Box::new(self.hint(node, element))),
+            Hint::Name(ref name) => match name.as_str() {
+                "int"    => Type::Int,
+                "float"  => Type::Float,
+                "bool"   => Type::Bool,
+                "char"   => Type::Char,
+                "string" => Type::String,
+                _ if self.record(name).is_some() => Type::Record(name.clone()),
+                _ if self.enums.iter().any(|&(ref n, _)| n == name) => Type::Enum(name.clone()),
+                _ => {
+                    self.error(node, format!("Unknown type `{}`", name));
+                    self.fresh()
+                },
+            },
+        }
+    }
+
+    /// Whether a value of type `t` has the field `name`,
+    /// which when unknown is if any record type has it.
+    fn has_field(&self, t: &Type, name: &str) -> bool {
+        let fields = |fields: &Vec<(String, Type)>| fields.iter().any(|&(ref n, _)| n == name);
+        match self.resolve(t) {
+            Type::Var(_)             => self.records.iter().any(|&(_, ref f)| fields(f)),
+            Type::Record(ref record) => self.record(record).map_or(false, fields),
+            _                        => false,
+        }
+    }
+
+    /// The type of the field `name` of a value of type `t`,
+    /// which when unknown is taken to be the record type
+    /// declared last with such a field.
+    fn field(&mut self, node: &Node, t: &Type, name: &str) -> Type {
+        let t = match self.resolve(t) {
+            Type::Var(_) => {
+                let record = self.records.iter().rev()
+                    .find(|&&(_, ref fields)| fields.iter().any(|&(ref n, _)| n == name))
+                    .map(|&(ref record, _)| Type::Record(record.clone()));
+                match record {
+                    Some(record) => {
+                        self.unify(&record, t, node);
+                        record
+                    },
+                    None => {
+                        self.error(node, format!("Cannot read field `{}` of a value of unknown type", name));
+                        return self.fresh()
+                    },
+                }
+            },
+            t => t,
+        };
+
+        let field = match t {
+            Type::Record(ref record) => {
+                self.record(record).unwrap().iter().find(|&&(ref n, _)| n == name).map(|&(_, ref t)| t.clone())
+            },
+            _ => None,
+        };
+        match field {
+            Some(field) => field,
+            None        => {
+                self.error(node, format!("`{}` has no field `{}`", readable(&[t.clone()])[0], name));
+                self.fresh()
+            },
+        }
+    }
+
+    fn check_function(&mut self, node: &Node, name: &str, parameters: &[String], body: &Node) {
+        let (parameter_types, result) = match self.functions.get(name) {
+            Some(&Type::Function(ref parameters, ref result)) => (parameters.clone(), (**result).clone()),
+            _ => return,
+        };
+
+        let mut scope = Vec::new();
+        for (parameter, t) in parameters.iter().zip(parameter_types.into_iter()) {
+            if scope.iter().any(|&(ref n, _)| n == parameter) {
+                self.error(node, format!("Duplicate parameter `{}`", parameter))
+            }
+            scope.push((parameter.clone(), t))
+        }
+
+        let scopes = ::std::mem::replace(&mut self.scopes, vec![scope]);
+        self.outer = scopes;
+        let loops = ::std::mem::replace(&mut self.loops, Vec::new());
+        let t = self.check(body);
+        self.unify(&result, &t, body);
+        self.loops = loops;
+        self.scopes = ::std::mem::replace(&mut self.outer, Vec::new());
+    }
+
+    fn check(&mut self, node: &Node) -> Type {
+        let t = self.infer(node);
+        self.nodes.push((address(node), t.clone()));
+        t
+    }
+
+    fn infer(&mut self, node: &Node) -> Type {
+        match *node.node_type() {
+            NodeType::StringLiteral(_)  => Type::String,
+            NodeType::CharLiteral(_)    => Type::Char,
+            NodeType::IntLiteral(..)    => Type::Int,
+            NodeType::FloatLiteral(_)   => Type::Float,
+            NodeType::BooleanLiteral(_) => Type::Bool,
+            NodeType::Identifier(ref name) => {
+                if let Some(t) = self.local(node, name) {
+                    return t
+                }
+                match self.functions.get(name).cloned() {
+                    Some(t) => return t,
+                    None => match self.record(name).map(|fields| fields.len()) {
+                        Some(fields) => self.error(node, arity(name, fields, 0)),
+                        None => match self.variant(name) {
+                            Some((e, ref payload)) if payload.is_empty() => return Type::Enum(e),
+                            Some((_, payload)) => self.error(node, arity(name, payload.len(), 0)),
+                            None => self.error(node, format!("Unknown name `{}`", name)),
+                        },
+                    },
+                }
+                self.fresh()
+            },
+            NodeType::Unary(op, ref operand) => {
+                let t = self.check(operand);
+                match op {
+                    Op::Not    => self.unify(&Type::Bool, &t, operand),
+                    Op::BitNot => self.unify(&Type::Int, &t, operand),
+                    _          => self.constrain(Constraint::Numeric, &t, op, vec![t.clone()], node),
+                }
+                t
+            },
+            NodeType::Binary(op, ref left, ref right) => {
+                let l = self.check(left);
+                let r = self.check(right);
+                self.binary(node, op, left, l, right, r)
+            },
+            NodeType::Call(ref function, ref arguments) => {
+                let arguments: Vec<(&Node, Type)> = arguments.iter().map(|a| (a, self.check(a))).collect();
+
+                let name = match *function.node_type() {
+                    NodeType::Identifier(ref name) if !self.is_local(name) => name,
+                    _ => {
+                        let t = self.check(function);
+                        return self.apply(function, &t, &arguments)
+                    },
+                };
+
+                match self.functions.get(name).cloned() {
+                    Some(t) => self.apply(function, &t, &arguments),
+                    None => match self.record(name).cloned() {
+                        Some(fields) => {
+                            if fields.len() != arguments.len() {
+                                self.error(function, arity(name, fields.len(), arguments.len()));
+                            }
+                            for (&(_, ref field), &(argument, ref t)) in fields.iter().zip(arguments.iter()) {
+                                self.unify(field, t, argument)
+                            }
+                            Type::Record(name.clone())
+                        },
+                        None => match self.variant(name) {
+                            Some((e, payload)) => {
+                                if payload.len() != arguments.len() {
+                                    self.error(function, arity(name, payload.len(), arguments.len()));
+                                }
+                                for (part, &(argument, ref t)) in payload.iter().zip(arguments.iter()) {
+                                    self.unify(part, t, argument)
+                                }
+                                Type::Enum(e)
+                            },
+                            None => {
+                                self.error(function, format!("Unknown name `{}`", name));
+                                self.fresh()
+                            },
+                        },
+                    },
+                }
+            },
+            NodeType::Field(ref value, ref name) => {
+                let t = self.check(value);
+                match self.functions.get(name).cloned() {
+                    // Without such a field, `x.f` is `f x`
+                    Some(function) if !self.has_field(&t, name) => self.apply(node, &function, &[(&**value, t)]),
+                    _ => self.field(node, &t, name),
+                }
+            },
+            NodeType::With(ref value, ref fields) => {
+                let t = self.check(value);
+                for &(ref name, ref new) in fields.iter() {
+                    let field = self.field(node, &t, name);
+                    let n     = self.check(new);
+                    self.unify(&field, &n, new)
+                }
+                t
+            },
+            NodeType::Tuple(ref elements) => {
+                Type::Tuple(elements.iter().map(|e| self.check(e)).collect())
+            },
+            NodeType::Match(ref value, ref arms) => {
+                let t      = self.check(value);
+                let result = self.fresh();
+                let mut valid = true;
+                for arm in arms.iter() {
+                    let errors = self.diagnostics.len();
+                    let mut scope = Vec::new();
+                    self.pattern(arm.span.start, &arm.pattern, &t, &mut scope);
+                    valid &= self.diagnostics.len() == errors;
+
+                    self.scopes.push(scope);
+                    let b = self.check(&arm.body);
+                    self.unify(&result, &b, &arm.body);
+                    self.scopes.pop();
+                }
+                // Coverage means little once the patterns are wrong
+                if valid {
+                    self.check_arms(node, &t, arms)
+                }
+                result
+            },
+            NodeType::List(ref elements) => {
+                let element = self.fresh();
+                for e in elements.iter() {
+                    let t = self.check(e);
+                    self.unify(&element, &t, e)
+                }
+                Type::List(Box::new(element))
+            },
+            NodeType::Index(ref list, ref index) => {
+                let element = self.fresh();
+                let l = self.check(list);
+                self.unify(&Type::List(Box::new(element.clone())), &l, list);
+                let i = self.check(index);
+                self.unify(&Type::Int, &i, index);
+                element
+            },
+            NodeType::If(ref condition, ref then, ref otherwise) => {
+                let c = self.check(condition);
+                self.unify(&Type::Bool, &c, condition);
+                let t = self.check(then);
+                match *otherwise {
+                    Some(ref otherwise) => {
+                        let o = self.check(otherwise);
+                        self.unify(&t, &o, otherwise);
+                        t
+                    },
+                    None => Type::Unit,
+                }
+            },
+            NodeType::While(ref condition, ref body) => {
+                let c = self.check(condition);
+                self.unify(&Type::Bool, &c, condition);
+                // Only a loop that never ends on its own has a value
+                let result = match *condition.node_type() {
+                    NodeType::BooleanLiteral(true) => Some(self.fresh()),
+                    _                              => None,
+                };
+                self.loops.push(result);
+                self.check(body);
+                self.loops.pop().unwrap().unwrap_or(Type::Unit)
+            },
+            NodeType::For(ref name, ref values, ref body) => {
+                let element = self.fresh();
+                let v = self.check(values);
+                self.unify(&Type::List(Box::new(element.clone())), &v, values);
+                self.scopes.push(vec![(name.clone(), element)]);
+                self.loops.push(None);
+                self.check(body);
+                self.loops.pop();
+                self.scopes.pop();
+                Type::Unit
+            },
+            NodeType::Break(ref value) => {
+                let t = match *value {
+                    Some(ref value) => self.check(value),
+                    None            => Type::Unit,
+                };
+                match self.loops.last().cloned() {
+                    Some(Some(result)) => self.unify(&result, &t, value.as_ref().map_or(node, |v| &**v)),
+                    Some(None) if value.is_some() => {
+                        self.error(node, "Only `while true` loops can break with a value".to_owned())
+                    },
+                    Some(None) => (),
+                    None => self.error(node, "`break` outside of a loop".to_owned()),
+                }
+                self.fresh()
+            },
+            NodeType::Continue => {
+                if self.loops.is_empty() {
+                    self.error(node, "`continue` outside of a loop".to_owned())
+                }
+                self.fresh()
+            },
+            NodeType::Let(ref name, _, ref value) => {
+                let t = match *value {
+                    Some(ref value) => self.check(value),
+                    None            => self.fresh(),
+                };
+                self.scopes.last_mut().unwrap().push((name.clone(), t));
+                Type::Unit
+            },
+            NodeType::Function(..) => {
+                self.error(node, "Functions can only be defined at the top level".to_owned());
+                Type::Unit
+            },
+            NodeType::Lambda(ref parameters, ref body) => {
+                let mut scope: Vec<(String, Type)> = Vec::new();
+                for parameter in parameters.iter() {
+                    if scope.iter().any(|&(ref n, _)| n == parameter) {
+                        self.error(node, format!("Duplicate parameter `{}`", parameter))
+                    }
+                    let t = self.fresh();
+                    scope.push((parameter.clone(), t))
+                }
+                let types = scope.iter().map(|&(_, ref t)| t.clone()).collect();
+
+                // The body sees the variables around it, but not their loops
+                self.scopes.push(scope);
+                let loops  = ::std::mem::replace(&mut self.loops, Vec::new());
+                let result = self.check(body);
+                self.loops = loops;
+                self.scopes.pop();
+                Type::Function(types, Box::new(result))
+            },
+            NodeType::Record(..) | NodeType::Enum(..) => {
+                self.error(node, "Types can only be declared at the top level".to_owned());
+                Type::Unit
+            },
+            NodeType::Assign(ref target, ref value) => {
+                let v = self.check(value);
+                if let Some(t) = self.target(target) {
+                    self.unify(&t, &v, value)
+                }
+                Type::Unit
+            },
+            NodeType::CompoundAssign(op, ref target, ref value) => {
+                let t = self.target(target).unwrap_or_else(|| self.fresh());
+                let v = self.check(value);
+                let result = self.binary(node, op, target, t.clone(), value, v);
+                self.unify(&t, &result, node);
+                Type::Unit
+            },
+            NodeType::Block(ref nodes) => {
+                self.scopes.push(Vec::new());
+                let mut t = Type::Unit;
+                for node in nodes.iter() {
+                    t = self.check(node)
+                }
+                self.scopes.pop();
+                t
+            },
+            NodeType::Error => self.fresh(),
+        }
+    }
+
+    /// The type of `left op right`, where the
+    /// operands are of types `l` and `r`.
+    fn binary(&mut self, node: &Node, op: Op, left: &Node, l: Type, right: &Node, r: Type) -> Type {
+        // As they were before being made to agree
+        let operands = vec![self.resolve(&l), self.resolve(&r)];
+        match op {
+            Op::And | Op::Or => {
+                self.unify(&Type::Bool, &l, left);
+                self.unify(&Type::Bool, &r, right);
+                Type::Bool
+            },
+            Op::Eq | Op::Ne => {
+                self.unify(&l, &r, right);
+                self.constrain(Constraint::Equatable, &l, op, operands, node);
+                Type::Bool
+            },
+            Op::Lt | Op::Le | Op::Gt | Op::Ge => {
+                self.unify(&l, &r, right);
+                self.constrain(Constraint::Ordered, &l, op, operands, node);
+                Type::Bool
+            },
+            Op::Add => {
+                self.unify(&l, &r, right);
+                self.constrain(Constraint::Addable, &l, op, operands, node);
+                l
+            },
+            Op::Sub | Op::Mul | Op::Div | Op::Mod => {
+                self.unify(&l, &r, right);
+                self.constrain(Constraint::Numeric, &l, op, operands, node);
+                l
+            },
+            Op::Range | Op::RangeInclusive => {
+                self.unify(&Type::Int, &l, left);
+                self.unify(&Type::Int, &r, right);
+                Type::List(Box::new(Type::Int))
+            },
+            _ => {
+                self.unify(&Type::Int, &l, left);
+                self.unify(&Type::Int, &r, right);
+                Type::Int
+            },
+        }
+    }
+
+    /// The type of what an assignment writes to, if any.
+    fn target(&mut self, target: &Node) -> Option<Type> {
+        match *target.node_type() {
+            NodeType::Identifier(ref name) => match self.local(target, name) {
+                Some(t) => return Some(t),
+                None if self.functions.contains_key(name) => {
+                    self.error(target, format!("Cannot assign to function `{}`", name))
+                },
+                None => self.error(target, format!("Unknown name `{}`", name)),
+            },
+            NodeType::Field(..) | NodeType::Index(..) => return Some(self.check(target)),
+            _ => (),
+        }
+        None
+    }
+
+    /// The type of calling `function`, a value of type `t`,
+    /// with some arguments: a function of those it was not
+    /// given, or what it returns applied to those left over.
+    fn apply(&mut self, function: &Node, t: &Type, arguments: &[(&Node, Type)]) -> Type {
+        let mut name = match *function.node_type() {
+            NodeType::Identifier(ref name) => Some(name),
+            _                              => None,
+        };
+        let mut t = t.clone();
+        let mut arguments = arguments;
+        loop {
+            let (parameters, result) = match self.resolve(&t) {
+                Type::Function(parameters, result) => (parameters, *result),
+                Type::Var(_) => {
+                    let parameters: Vec<Type> = arguments.iter().map(|_| self.fresh()).collect();
+                    let result = self.fresh();
+                    self.unify(&Type::Function(parameters.clone(), Box::new(result.clone())), &t, function);
+                    (parameters, result)
+                },
+                t => {
+                    match name {
+                        Some(name) => self.error(function, format!("`{}` is not a function", name)),
+                        None       => self.error(function, format!("Cannot call `{}`", readable(&[t.clone()])[0])),
+                    }
+                    return self.fresh()
+                },
+            };
+
+            for (parameter, &(argument, ref t)) in parameters.iter().zip(arguments.iter()) {
+                self.unify(parameter, t, argument)
+            }
+            if arguments.len() < parameters.len() {
+                return Type::Function(parameters[arguments.len()..].to_vec(), Box::new(result))
+            }
+            arguments = &arguments[parameters.len()..];
+            if arguments.is_empty() {
+                return result
+            }
+            name = None;
+            t    = result;
+        }
+    }
+
+    /// Checks a pattern against the type of the value it
+    /// matches, adding the names it binds to `scope`.
+    fn pattern(&mut self, position: TokenPosition, pattern: &Pattern, t: &Type, scope: &mut Vec<(String, Type)>) {
+        match *pattern {
+            Pattern::Wildcard => (),
+            Pattern::Binding(ref name) => {
+                if scope.iter().any(|&(ref n, _)| n == name) {
+                    self.error_at(position, format!("Duplicate binding `{}`", name))
+                }
+                scope.push((name.clone(), t.clone()))
+            },
+            Pattern::Literal(ref value) => {
+                let l = self.check(value);
+                self.unify(t, &l, value)
+            },
+            Pattern::Tuple(ref elements) => {
+                let types: Vec<Type> = elements.iter().map(|_| self.fresh()).collect();
+                self.unify_at(t, &Type::Tuple(types.clone()), position);
+                for (element, t) in elements.iter().zip(types.iter()) {
+                    self.pattern(position, element, t, scope)
+                }
+            },
+            Pattern::Variant(ref name, ref payload) => {
+                let types = match self.variant(name) {
+                    Some((_, ref types)) if types.len() != payload.len() => {
+                        self.error_at(position, arity(name, types.len(), payload.len()));
+                        None
+                    },
+                    Some((e, types)) => {
+                        self.unify_at(t, &Type::Enum(e), position);
+                        Some(types)
+                    },
+                    None => {
+                        self.error_at(position, format!("Unknown variant `{}`", name));
+                        None
+                    },
+                };
+                // Still bind the names in a wrong pattern
+                let types = types.unwrap_or_else(|| payload.iter().map(|_| self.fresh()).collect());
+                for (part, t) in payload.iter().zip(types.iter()) {
+                    self.pattern(position, part, t, scope)
+                }
+            },
+        }
+    }
+
+    /// Reports arms matching nothing the arms before
+    /// them do not, and a value no arm matches.
+    fn check_arms(&mut self, node: &Node, t: &Type, arms: &[Arm]) {
+        let types    = [self.resolve(t)];
+        let mut rows = Vec::new();
+        for arm in arms.iter() {
+            let row = vec![Pat::from(&arm.pattern)];
+            if self.useful(&rows, &row, &types).is_none() {
+                self.error_at(arm.span.start, "Unreachable arm".to_owned())
+            }
+            rows.push(row)
+        }
+
+        if let Some(witness) = self.useful(&rows, &[Pat::Any], &types) {
+            self.error(node, format!("Non-exhaustive match: `{}` is not covered", witness[0]))
+        }
+    }
+
+    /// A value matching `row` but none of `rows`, given as a
+    /// pattern for each column, whose types are `types`; or
+    /// `None` if there is no such value.
+    fn useful(&self, rows: &[Vec<Pat>], row: &[Pat], types: &[Type]) -> Option<Vec<Pat>> {
+        let (first, rest) = match row.split_first() {
+            Some(split) => split,
+            None        => return if rows.is_empty() { Some(Vec::new()) } else { None },
+        };
+        let t = self.resolve(&types[0]);
+
+        if let Pat::Ctor(ref c, ref parts) = *first {
+            return self.useful_ctor(rows, c, parts, rest, &t, &types[1..])
+        }
+        let used: Vec<&Ctor> = rows.iter().filter_map(|r| match r[0] {
+            Pat::Ctor(ref c, _) => Some(c),
+            Pat::Any            => None,
+        }).collect();
+        let all = self.constructors(&t);
+
+        // Where the rows name every constructor, one of them
+        // must leave a value of some constructor unmatched
+        if let Some(ref all) = all {
+            if !all.is_empty() && all.iter().all(|&(ref c, _)| used.contains(&c)) {
+                return all.iter().filter_map(|&(ref c, ref parts)| {
+                    self.useful_ctor(rows, c, &vec![Pat::Any; parts.len()], rest, &t, &types[1..])
+                }).next()
+            }
+        }
+
+        // Otherwise only the rows matching anything here matter
+        let default: Vec<Vec<Pat>> = rows.iter().filter(|r| r[0] == Pat::Any).map(|r| r[1..].to_vec()).collect();
+        self.useful(&default, rest, &types[1..]).map(|mut witness| {
+            let missing = all.and_then(|all| all.into_iter().find(|&(ref c, _)| !used.contains(&c)))
+                .map_or(Pat::Any, |(c, parts)| Pat::Ctor(c, vec![Pat::Any; parts.len()]));
+            witness.insert(0, missing);
+            witness
+        })
+    }
+
+    /// `useful` for a row starting with the constructor `c`
+    /// applied to `parts`, followed by `rest`.
+    fn useful_ctor(&self, rows: &[Vec<Pat>], c: &Ctor, parts: &[Pat], rest: &[Pat],
+                   t: &Type, types: &[Type]) -> Option<Vec<Pat>> {
+        let mut part_types = self.constructors(t)
+            .and_then(|all| all.into_iter().find(|&(ref k, _)| k == c))
+            .map_or(Vec::new(), |(_, parts)| parts);
+        part_types.resize(parts.len(), Type::Unit);
+
+        let specialized: Vec<Vec<Pat>> = rows.iter().filter_map(|r| {
+            let head = match r[0] {
+                Pat::Ctor(ref k, ref ps) if k == c => ps.clone(),
+                Pat::Ctor(..)                      => return None,
+                Pat::Any                           => vec![Pat::Any; parts.len()],
+            };
+            Some(head.into_iter().chain(r[1..].iter().cloned()).collect())
+        }).collect();
+        let row: Vec<Pat>    = parts.iter().chain(rest.iter()).cloned().collect();
+        let types: Vec<Type> = part_types.into_iter().chain(types.iter().cloned()).collect();
+
+        self.useful(&specialized, &row, &types).map(|mut witness| {
+            let rest = witness.split_off(parts.len());
+            let mut result = vec![Pat::Ctor(c.clone(), witness)];
+            result.extend(rest);
+            result
+        })
+    }
+
+    /// Every constructor of a type and the types of its parts,
+    /// or `None` where there are too many values to list.
+    fn constructors(&self, t: &Type) -> Option<Vec<(Ctor, Vec<Type>)>> {
+        match *t {
+            Type::Bool => Some(vec![(Ctor::Bool(true), Vec::new()), (Ctor::Bool(false), Vec::new())]),
+            Type::Tuple(ref elements) => Some(vec![(Ctor::Tuple, elements.clone())]),
+            Type::Enum(ref name) => {
+                let variants = &self.enums.iter().find(|&&(ref n, _)| n == name)?.1;
+                Some(variants.iter().map(|&(ref v, ref payload)| (Ctor::Variant(v.clone()), payload.clone())).collect())
+            },
+            _ => None,
+        }
+    }
+
+    /// Checks operators against the types they ended up with,
+    /// and settles the types nothing decided.
+    fn finish(mut self) -> Result<Types, Vec<Diagnostic>> {
+        let constraints = ::std::mem::replace(&mut self.constraints, Vec::new());
+        for (constraint, t, op, operands, position) in constraints {
+            let t = match self.resolve(&t) {
+                // Whatever it turns out to be can be compared
+                Type::Var(_) if constraint == Constraint::Equatable => continue,
+                Type::Var(id) => {
+                    self.bindings[id] = Some(Type::Int);
+                    Type::Int
+                },
+                t => t,
+            };
+            if !constraint.allows(&t) {
+                let operands: Vec<Type> = operands.iter().map(|o| self.resolve(o)).collect();
+                let message = format!("Cannot apply `{}` to `{}`", op.as_str(), readable(&operands).join("` and `"));
+                self.diagnostics.push(Diagnostic::new(position, message))
+            }
+        }
+
+        if !self.diagnostics.is_empty() {
+            let mut diagnostics = self.diagnostics;
+            diagnostics.sort_by_key(|d| (d.position().line, d.position().col));
+            return Err(diagnostics)
+        }
+
+        for id in 0..self.bindings.len() {
+            if self.resolve(&Type::Var(id)) == Type::Var(id) {
+                self.bindings[id] = Some(Type::Unit)
+            }
+        }
+
+        let nodes = self.nodes.iter().map(|&(a, ref t)| (a, self.resolve(t))).collect();
+        let functions = self.functions.iter().map(|(n, t)| (n.clone(), self.resolve(t))).collect();
+        let records = self.records.iter()
+            .map(|&(ref n, ref fields)| (n.clone(), fields.iter().map(|&(ref f, ref t)| (f.clone(), self.resolve(t))).collect()))
+            .collect();
+        let enums = self.enums.iter()
+            .map(|&(ref n, ref variants)| (n.clone(), variants.iter().map(|&(ref v, ref payload)| {
+                (v.clone(), payload.iter().map(|t| self.resolve(t)).collect())
+            }).collect()))
+            .collect();
+        Ok(Types {
+            nodes:     nodes,
+            functions: functions,
+            records:   records,
+            enums:     enums,
+        })
+    }
+}
+
+fn arity(name: &str, expected: usize, found: usize) -> String {
+    format!("`{}` takes {} argument{}, found {}", name, expected, if expected == 1 { "" } else { "s" }, found)
+}
+
+/// The type of a function taking `parameters`, or just
+/// `result` when it takes none.
+fn curried(parameters: &[Type], result: Type) -> Type {
+    if parameters.is_empty() {
+        result
+    } else {
+        Type::Function(parameters.to_vec(), Box::new(result))
+    }
+}
+
+/// Shows resolved types for a message, numbering the
+/// variables in them in the order they first appear,
+/// so they read `'a`, `'b` and so on.
+fn readable(types: &[Type]) -> Vec<String> {
+    fn number(t: &Type, vars: &mut Vec<usize>) -> Type {
+        match *t {
+            Type::Var(id) => match vars.iter().position(|&v| v == id) {
+                Some(i) => Type::Var(i),
+                None    => {
+                    vars.push(id);
+                    Type::Var(vars.len() - 1)
+                },
+            },
+            Type::Function(ref parameters, ref result) => {
+                Type::Function(parameters.iter().map(|p| number(p, vars)).collect(), Box::new(number(result, vars)))
+            },
+            Type::List(ref element) => Type::List(Box::new(number(element, vars))),
+            Type::Tuple(ref elements) => Type::Tuple(elements.iter().map(|e| number(e, vars)).collect()),
+            _ => t.clone(),
+        }
+    }
+
+    let mut vars = Vec::new();
+    types.iter().map(|t| number(t, &mut vars).to_string()).collect()
+}
+
+/// Whether values of `t`, which is resolved, can hold a
+/// function. Fields and payloads cannot be functions, so
+/// records and enums never do.
+fn holds_function(t: &Type) -> bool {
+    match *t {
+        Type::Function(..)        => true,
+        Type::List(ref element)   => holds_function(element),
+        Type::Tuple(ref elements) => elements.iter().any(holds_function),
+        _ => false,
+    }
+}
+
+/// Whether the variable `id` appears in `t`, which is resolved.
+fn occurs(id: usize, t: &Type) -> bool {
+    match *t {
+        Type::Var(other)                         => other == id,
+        Type::List(ref element)                  => occurs(id, element),
+        Type::Tuple(ref elements)                => elements.iter().any(|e| occurs(id, e)),
+        Type::Function(ref parameters, ref result) => {
+            parameters.iter().any(|p| occurs(id, p)) || occurs(id, result)
+        },
+        _ => false,
+    }
+}
+
+/// A pattern as coverage sees it: anything, or
+/// a constructor applied to patterns for its parts.
+#[derive(Debug, Clone, PartialEq)]
+enum Pat {
+    Any,
+    Ctor(Ctor, Vec<Pat>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ctor {
+    Variant(String),
+    Tuple,
+    Bool(bool),
+    /// A literal of a type with too many values to
+    /// list, as the pattern writes it.
+    Literal(String),
+}
+
+impl<'a> From<&'a Pattern> for Pat {
+    fn from(pattern: &Pattern) -> Pat {
+        let parts = |patterns: &[Pattern]| patterns.iter().map(Pat::from).collect();
+        match *pattern {
+            Pattern::Wildcard | Pattern::Binding(_) => Pat::Any,
+            Pattern::Tuple(ref elements)            => Pat::Ctor(Ctor::Tuple, parts(elements)),
+            Pattern::Variant(ref name, ref payload) => Pat::Ctor(Ctor::Variant(name.clone()), parts(payload)),
+            Pattern::Literal(ref value) => {
+                let literal = match *value.node_type() {
+                    NodeType::BooleanLiteral(value) => return Pat::Ctor(Ctor::Bool(value), Vec::new()),
+                    NodeType::IntLiteral(value, _)  => (value as i64).to_string(),
+                    NodeType::FloatLiteral(value)   => format!("{:?}", value),
+                    NodeType::CharLiteral(value)    => format!("{:?}", value),
+                    NodeType::StringLiteral(ref value) => format!("{:?}", value),
+                    _ => unreachable!("patterns only hold literals"),
+                };
+                Pat::Ctor(Ctor::Literal(literal), Vec::new())
+            },
+        }
+    }
+}
+
+impl fmt::Display for Pat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Pat::Any => write!(f, "_"),
+            Pat::Ctor(Ctor::Tuple, ref parts) => {
+                write!(f, "(")?;
+                for (i, part) in parts.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { "" } else { ", " }, part)?
+                }
+                write!(f, ")")
+            },
+            Pat::Ctor(Ctor::Variant(ref name), ref parts) => {
+                write!(f, "{}", name)?;
+                for part in parts.iter() {
+                    match *part {
+                        Pat::Ctor(Ctor::Variant(_), ref inner) if !inner.is_empty() => write!(f, " ({})", part)?,
+                        _ => write!(f, " {}", part)?,
+                    }
+                }
+                Ok(())
+            },
+            Pat::Ctor(Ctor::Bool(value), _)       => write!(f, "{}", value),
+            Pat::Ctor(Ctor::Literal(ref text), _) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// The node giving a block its value, where
+/// a mismatch with it is best reported.
+fn value(node: &Node) -> &Node {
+    match *node.node_type() {
+        NodeType::Block(ref nodes) if !nodes.is_empty() => value(nodes.last().unwrap()),
+        _                                               => node,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, Type};
+    use document::Document;
+
+    fn diagnostics(source: &str) -> Vec<String> {
+        let document = Document::new(source);
+        assert!(document.diagnostics().is_empty(), "{:?}", document.diagnostics());
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        match check(&ast) {
+            Ok(_)       => vec![],
+            Err(errors) => errors.iter().map(|d| format!("{} {}", d.position(), d.message())).collect(),
+        }
+    }
+
+    #[test]
+    fn infer_function_types() {
+        let document = Document::new("let add x y = x + y\nlet half x = x / 2.0\nlet id x = x\nadd 1 2");
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let types = check(&ast).unwrap();
+
+        assert_eq!(types.function("add").unwrap().to_string(), "int -> int -> int");
+        assert_eq!(types.function("half").unwrap().to_string(), "float -> float");
+        assert_eq!(types.function("id").unwrap().to_string(), "() -> ()");
+        assert_eq!(types.of(&ast[3]), Some(&Type::Int));
+    }
+
+    #[test]
+    fn report_type_errors() {
+        assert_eq!(diagnostics("let mut x = 1\nx = true\n-\"a\"\nif 1\n  2\nelse\n  'c'"), vec![
+            "[ln 2, col 4] Expected `int`, found `bool`",
+            "[ln 3, col 0] Cannot apply `-` to `string`",
+            "[ln 4, col 3] Expected `bool`, found `int`",
+            "[ln 7, col 2] Expected `int`, found `char`",
+        ]);
+        assert_eq!(diagnostics("let f x = x\nf -1\n1.5 + 'a'"), vec![
+            "[ln 2, col 0] Cannot apply `-` to `'a -> 'a` and `int`",
+            "[ln 2, col 3] Expected `'a -> 'a`, found `int`",
+            "[ln 3, col 6] Expected `float`, found `char`",
+        ]);
+    }
+
+    #[test]
+    fn report_compound_assignment_errors() {
+        let source = "let mut total = 0\nlet add x = total += x\nlet mut s = \"a\"\ns -= \"b\"\nlet mut n = 1\nn += true";
+        assert_eq!(diagnostics(source), vec![
+            "[ln 2, col 12] Functions cannot capture `total`",
+            "[ln 4, col 0] Cannot apply `-` to `string` and `string`",
+            "[ln 6, col 5] Expected `int`, found `bool`",
+        ]);
+    }
+
+    #[test]
+    fn report_name_errors() {
+        assert_eq!(diagnostics("let f x = x\nlet y = 1\nlet g x = y\nf\nf 1 2\nz\ny 1\n(1) 2\n  let h x = x"), vec![
+            "[ln 3, col 10] Functions cannot capture `y`",
+            "[ln 5, col 0] Cannot call `int`",
+            "[ln 6, col 0] Unknown name `z`",
+            "[ln 7, col 0] `y` is not a function",
+            "[ln 8, col 0] Cannot call `int`",
+            "[ln 9, col 2] Functions can only be defined at the top level",
+        ]);
+    }
+
+    #[test]
+    fn report_field_errors() {
+        assert_eq!(diagnostics("let f p = p.x\nlet mut s = \"a\"\ns.len = 1\nf 1"), vec![
+            "[ln 1, col 10] Cannot read field `x` of a value of unknown type",
+            "[ln 3, col 0] `string` has no field `len`",
+        ]);
+    }
+
+    #[test]
+    fn check_list_elements() {
+        let document = Document::new("let mut xs = [1, 2]\nxs[0] = 3\nxs + []");
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let types = check(&ast).unwrap();
+        assert_eq!(types.of(&ast[2]).unwrap().to_string(), "[int]");
+
+        assert_eq!(diagnostics("let mut xs = [1, 'a']\nxs[true]\nxs[0] = \"b\"\n1[0]\n[[1], [2.0]]\nlet f x = [x] == x"), vec![
+            "[ln 1, col 17] Expected `int`, found `char`",
+            "[ln 2, col 3] Expected `int`, found `bool`",
+            "[ln 3, col 8] Expected `int`, found `string`",
+            "[ln 4, col 0] Expected `['a]`, found `int`",
+            "[ln 5, col 6] Expected `[int]`, found `[float]`",
+            "[ln 6, col 17] Expected `['a]`, found `'a`",
+        ]);
+    }
+
+    #[test]
+    fn check_records() {
+        let document = Document::new("type Point\n  x: int\n  tags: [string]\nlet move p = p with x = p.x + 1\nmove (Point 1 [])");
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let types = check(&ast).unwrap();
+        assert_eq!(types.function("move").unwrap().to_string(), "Point -> Point");
+        assert_eq!(types.record("Point").unwrap()[1].1.to_string(), "[string]");
+
+        assert_eq!(diagnostics("type P\n  x: int\n  x: Q\nP 1\nlet p = P 1 2\np.y\np with x = 'a'\nP == 1\nif true\n  type R\n    a: int"), vec![
+            "[ln 1, col 0] Duplicate field `x`",
+            "[ln 1, col 0] Unknown type `Q`",
+            "[ln 4, col 0] `P` takes 2 arguments, found 1",
+            "[ln 6, col 0] `P` has no field `y`",
+            "[ln 7, col 11] Expected `int`, found `char`",
+            "[ln 8, col 0] `P` takes 2 arguments, found 0",
+            "[ln 10, col 2] Types can only be declared at the top level",
+        ]);
+    }
+
+    #[test]
+    fn check_method_calls() {
+        let document = Document::new(concat!(
+            "type P\n  x: int\nlet x p = true\nlet norm p = p.x * p.x\nlet scale p n = p.x * n\n",
+            "let p = P 2\nlet n = 3\n",
+            "(p.x, p.norm, n.x, p.scale 2)",
+        ));
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let types = check(&ast).unwrap();
+        assert_eq!(types.function("x").unwrap().to_string(), "int -> bool");
+        assert_eq!(types.function("scale").unwrap().to_string(), "P -> int -> int");
+        assert_eq!(types.of(&ast[6]).unwrap().to_string(), "(int, int, bool, int)");
+
+        assert_eq!(diagnostics("type P\n  x: int\nlet norm p = p.x\nlet p = P 2\n(p.norm, p.y, 1.0.norm)"), vec![
+            "[ln 5, col 9] `P` has no field `y`",
+            "[ln 5, col 14] Expected `P`, found `float`",
+        ]);
+    }
+
+    #[test]
+    fn check_matches() {
+        let source = concat!(
+            "type Shape\n  Circle float\n  Rect int int\n  Dot\n",
+            "let area s =\n  match s\n    Circle r -> 0\n    Rect w h -> w * h\n    Dot -> 0\n",
+            "let both p =\n  match p\n    (true, Dot) -> 1\n    (_, x) -> area x\n",
+            "both (false, Rect 1 2)",
+        );
+        let document = Document::new(source);
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let types = check(&ast).unwrap();
+        assert_eq!(types.function("area").unwrap().to_string(), "Shape -> int");
+        assert_eq!(types.function("both").unwrap().to_string(), "(bool, Shape) -> int");
+        assert_eq!(types.variants("Shape").unwrap()[1].1, vec![Type::Int, Type::Int]);
+
+        assert_eq!(diagnostics(concat!(
+            "type T\n  A int\n  B T\n  C\n",
+            "match C\n  A 1 -> 1\n  B (A _) -> 2\n",
+            "match (C, true)\n  (_, true) -> 1\n  (A _, false) -> 2\n  (A 2, false) -> 3\n",
+            "match 1\n  0 -> 'a'\n  1 -> 1\n",
+            "match C\n  D -> 1\n  A x y -> 2\n  _ -> 3\n",
+            "match (1, 2)\n  (x, x) -> x\n  _ -> 0",
+        )), vec![
+            "[ln 5, col 0] Non-exhaustive match: `C` is not covered",
+            "[ln 8, col 0] Non-exhaustive match: `(B _, false)` is not covered",
+            "[ln 11, col 2] Unreachable arm",
+            "[ln 12, col 0] Non-exhaustive match: `_` is not covered",
+            "[ln 14, col 7] Expected `char`, found `int`",
+            "[ln 16, col 2] Unknown variant `D`",
+            "[ln 17, col 2] `A` takes 1 argument, found 2",
+            "[ln 20, col 2] Duplicate binding `x`",
+        ]);
+        assert_eq!(diagnostics("type E\n  X\n  X int\ntype F\n  Y\ntype G\n  Y\nX 1"), vec![
+            "[ln 1, col 0] Duplicate variant `X`",
+            "[ln 6, col 0] `Y` is already defined",
+            "[ln 8, col 0] `X` takes 0 arguments, found 1",
+        ]);
+    }
+
+    #[test]
+    fn check_loops() {
+        let document = Document::new(concat!(
+            "let first xs =\n  let mut i = 0\n  while true\n    if xs[i] > 0\n      break i\n    i = i + 1\n",
+            "let mut total = 0\nfor x in 1..=first [0, 3]\n  total = total + x\n",
+            "for c in ['a']\n  c",
+        ));
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let types = check(&ast).unwrap();
+        assert_eq!(types.function("first").unwrap().to_string(), "[int] -> int");
+        assert_eq!(types.of(&ast[3]), Some(&Type::Unit));
+
+        assert_eq!(diagnostics(concat!(
+            "break\nlet f x =\n  continue\n",
+            "while 1\n  break 2\n",
+            "for x in 'a'..2\n  x\n",
+            "for s in \"ab\"\n  s\n",
+            "while true\n  break 1\n  break 'a'",
+        )), vec![
+            "[ln 1, col 0] `break` outside of a loop",
+            "[ln 3, col 2] `continue` outside of a loop",
+            "[ln 4, col 6] Expected `bool`, found `int`",
+            "[ln 5, col 2] Only `while true` loops can break with a value",
+            "[ln 6, col 9] Expected `int`, found `char`",
+            "[ln 8, col 9] Expected `['a]`, found `string`",
+            "[ln 12, col 8] Expected `int`, found `char`",
+        ]);
+    }
+
+    #[test]
+    fn check_lambdas() {
+        let document = Document::new(concat!(
+            "let map f xs =\n  let mut ys = []\n  for x in xs\n    ys = ys + [f x]\n  ys\n",
+            "let scale = 3\nlet adder = x -> y -> x + y * scale\n",
+            "map (adder 1) [1, 2]\n",
+            "(s -> s + \"!\") \"a\"",
+        ));
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let types = check(&ast).unwrap();
+        assert_eq!(types.function("map").unwrap().to_string(), "(int -> int) -> [int] -> [int]");
+        assert_eq!(types.of(&ast[3]).unwrap().to_string(), "[int]");
+        assert_eq!(types.of(&ast[4]), Some(&Type::String));
+
+        assert_eq!(diagnostics(concat!(
+            "let f = x -> x + 1\nf 1 2\nf 'a'\n",
+            "(x -> x) == (x -> x)\n",
+            "let g x = f x\n",
+            "while true\n  let h = x ->\n    break x\n",
+            "let k = x x -> 1\n",
+            "[u -> 1] != [u -> 1]\nlet same x y = x == y\nsame (1, u -> u) (2, u -> u)",
+        )), vec![
+            "[ln 2, col 0] Cannot call `int`",
+            "[ln 3, col 2] Expected `int`, found `char`",
+            "[ln 4, col 0] Cannot apply `==` to `'a -> 'a` and `'a -> 'a`",
+            "[ln 5, col 10] Functions cannot capture `f`",
+            "[ln 8, col 4] `break` outside of a loop",
+            "[ln 9, col 8] Duplicate parameter `x`",
+            "[ln 10, col 0] Cannot apply `!=` to `['a -> int]` and `['a -> int]`",
+            "[ln 11, col 15] Cannot apply `==` to `(int, 'a -> 'a)` and `(int, 'a -> 'a)`",
+        ]);
+    }
+
+    #[test]
+    fn check_partial_application() {
+        let document = Document::new(concat!(
+            "let add x y = x + y\nlet twice f x = f (f x)\nlet both f = f 1 2\nlet make x = y -> x * y\n",
+            "let inc = add 1\n",
+            "twice inc 3\n",
+            "(both add, both (x -> y -> x - y), make 2 3)\n",
+            "(\"a\" +)",
+        ));
+        let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+        let types = check(&ast).unwrap();
+        assert_eq!(types.function("twice").unwrap().to_string(), "(int -> int) -> int -> int");
+        assert_eq!(types.function("make").unwrap().to_string(), "int -> int -> int");
+        assert_eq!(types.of(&ast[5]), Some(&Type::Int));
+        assert_eq!(types.of(&ast[6]).unwrap().to_string(), "(int, int, int)");
+        assert_eq!(types.of(&ast[7]).unwrap().to_string(), "string -> string");
+
+        assert_eq!(diagnostics("let add x y = x + y\nadd 1 2 3\nadd 'a'\n(== 1) 2.0"), vec![
+            "[ln 2, col 0] Cannot call `int`",
+            "[ln 3, col 4] Expected `int`, found `char`",
+            "[ln 4, col 7] Expected `int`, found `float`",
+        ]);
+    }
+}
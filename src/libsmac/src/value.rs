@@ -0,0 +1,381 @@
+use lexer::token::Op;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// A value produced by running a program.
+///
+/// Integers are 64 bits wide, two's complement, and
+/// wrap on overflow; literals too large for them wrap
+/// the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Unit,
+    Int(i64),
+    Float(f64),
+    Boolean(bool),
+    Char(char),
+    String(String),
+    /// The name of a record type and its fields, in the
+    /// order they are declared.
+    Record(String, Vec<(String, Value)>),
+    /// A list, which grows by concatenation with `+`.
+    List(Vec<Value>),
+    /// The name of an enum type, the variant and its payload.
+    Variant(String, String, Vec<Value>),
+    /// Two or more values.
+    Tuple(Vec<Value>),
+    /// A function as a value.
+    Closure(Rc<Closure>),
+    /// A variable a closure captured, shared by the scope
+    /// that declared it and the closures using it. Only
+    /// ever held by a variable, never the value of an
+    /// expression.
+    Cell(Rc<RefCell<Value>>),
+}
+
+/// A function and the variables it captured, which it
+/// sees by reference rather than copying, along with the
+/// arguments it was partially applied to.
+#[derive(Debug, PartialEq)]
+pub struct Closure {
+    /// The function it runs, numbered by whatever made it.
+    pub function:  usize,
+    /// The number of parameters of the function,
+    /// including those already applied.
+    pub arity:     usize,
+    pub captured:  Vec<Rc<RefCell<Value>>>,
+    pub arguments: Vec<Value>,
+}
+
+impl Closure {
+    /// The number of arguments left to apply it to.
+    pub fn missing(&self) -> usize {
+        self.arity - self.arguments.len()
+    }
+
+    /// The closure after also applying `arguments`,
+    /// which must be fewer than are missing.
+    pub fn partial(&self, arguments: Vec<Value>) -> Closure {
+        let mut applied = self.arguments.clone();
+        applied.extend(arguments);
+        Closure {
+            function:  self.function,
+            arity:     self.arity,
+            captured:  self.captured.clone(),
+            arguments: applied,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Unit                => write!(f, "()"),
+            Value::Int(value)          => write!(f, "{}", value),
+            Value::Float(value)        => write!(f, "{:?}", value),
+            Value::Boolean(value)      => write!(f, "{}", value),
+            Value::Char(value)         => write!(f, "{}", value),
+            Value::String(ref value)   => write!(f, "{}", value),
+            Value::Record(ref name, ref fields) => {
+                write!(f, "{} {{", name)?;
+                for (i, &(ref field, ref value)) in fields.iter().enumerate() {
+                    write!(f, "{} {}: ", if i == 0 { "" } else { "," }, field)?;
+                    nested(f, value)?
+                }
+                write!(f, " }}")
+            },
+            Value::List(ref elements) => {
+                write!(f, "[")?;
+                sequence(f, elements)?;
+                write!(f, "]")
+            },
+            Value::Variant(_, ref name, ref payload) => {
+                write!(f, "{}", name)?;
+                if !payload.is_empty() {
+                    write!(f, "(")?;
+                    sequence(f, payload)?;
+                    write!(f, ")")?
+                }
+                Ok(())
+            },
+            Value::Tuple(ref elements) => {
+                write!(f, "(")?;
+                sequence(f, elements)?;
+                write!(f, ")")
+            },
+            Value::Closure(_)          => write!(f, "<function>"),
+            Value::Cell(ref cell)      => write!(f, "{}", cell.borrow()),
+        }
+    }
+}
+
+fn sequence(f: &mut fmt::Formatter, values: &[Value]) -> fmt::Result {
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?
+        }
+        nested(f, value)?
+    }
+    Ok(())
+}
+
+/// Writes a value inside another, quoting strings
+/// and chars so they stay apart.
+fn nested(f: &mut fmt::Formatter, value: &Value) -> fmt::Result {
+    match *value {
+        Value::Char(_) | Value::String(_) => write!(f, "{:?}", value.to_string()),
+        ref value                         => write!(f, "{}", value),
+    }
+}
+
+impl Value {
+    /// The name of the value's type, as the type checker writes it.
+    pub fn type_name(&self) -> &str {
+        match *self {
+            Value::Unit             => "()",
+            Value::Int(_)           => "int",
+            Value::Float(_)         => "float",
+            Value::Boolean(_)       => "bool",
+            Value::Char(_)          => "char",
+            Value::String(_)        => "string",
+            Value::Record(ref name, _) => name,
+            Value::List(_)          => "list",
+            Value::Variant(ref name, ..) => name,
+            Value::Tuple(_)         => "tuple",
+            Value::Closure(_)       => "function",
+            Value::Cell(_)          => "cell",
+        }
+    }
+}
+
+/// Reads an element of a list, or says why it cannot.
+pub fn index<'v>(value: &'v Value, index: &Value) -> Result<&'v Value, String> {
+    let i = element(value, index)?;
+    match *value {
+        Value::List(ref elements) => Ok(&elements[i]),
+        _                         => unreachable!(),
+    }
+}
+
+/// An element of a list to write to, or why there is none.
+pub fn index_mut<'v>(value: &'v mut Value, index: &Value) -> Result<&'v mut Value, String> {
+    let i = element(value, index)?;
+    match *value {
+        Value::List(ref mut elements) => Ok(&mut elements[i]),
+        _                             => unreachable!(),
+    }
+}
+
+/// Checks that `index` is in the bounds of the list `value`.
+fn element(value: &Value, index: &Value) -> Result<usize, String> {
+    match (value, index) {
+        (&Value::List(ref elements), &Value::Int(i)) => {
+            if i < 0 || i as u64 >= elements.len() as u64 {
+                Err(format!("Index {} is out of bounds for a list of length {}", i, elements.len()))
+            } else {
+                Ok(i as usize)
+            }
+        },
+        (&Value::List(_), index) => Err(format!("Cannot index a list with `{}`", index.type_name())),
+        (value, _)               => Err(format!("Cannot index `{}`", value.type_name())),
+    }
+}
+
+/// Reads an element of a tuple or of the payload of
+/// a variant, or says why it cannot.
+pub fn part(value: &Value, index: usize) -> Result<&Value, String> {
+    let parts = match *value {
+        Value::Tuple(ref elements) | Value::Variant(_, _, ref elements) => elements,
+        ref value => return Err(format!("Cannot destructure `{}`", value.type_name())),
+    };
+    parts.get(index).ok_or_else(|| format!("`{}` has no element {}", value.type_name(), index))
+}
+
+/// Reads a field of a record, or says why it cannot.
+pub fn field<'v>(value: &'v Value, name: &str) -> Result<&'v Value, String> {
+    if let Value::Record(_, ref fields) = *value {
+        if let Some(&(_, ref field)) = fields.iter().find(|&&(ref n, _)| n == name) {
+            return Ok(field)
+        }
+    }
+    Err(no_field(value, name))
+}
+
+/// A field of a record to write to, or why there is none.
+pub fn field_mut<'v>(value: &'v mut Value, name: &str) -> Result<&'v mut Value, String> {
+    let index = match *value {
+        Value::Record(_, ref fields) => fields.iter().position(|&(ref n, _)| n == name),
+        _                            => None,
+    };
+    match (index, value) {
+        (Some(i), &mut Value::Record(_, ref mut fields)) => Ok(&mut fields[i].1),
+        (_, value)                                       => Err(no_field(value, name)),
+    }
+}
+
+fn no_field(value: &Value, name: &str) -> String {
+    format!("`{}` has no field `{}`", value.type_name(), name)
+}
+
+/// Applies a unary operator, or says why it does not apply.
+///
+/// These are the semantics every backend implements.
+pub fn unary(op: Op, operand: Value) -> Result<Value, String> {
+    match (op, operand) {
+        (Op::Add, Value::Int(v))        => Ok(Value::Int(v)),
+        (Op::Add, Value::Float(v))      => Ok(Value::Float(v)),
+        (Op::Sub, Value::Int(v))        => Ok(Value::Int(v.wrapping_neg())),
+        (Op::Sub, Value::Float(v))      => Ok(Value::Float(-v)),
+        (Op::Not, Value::Boolean(v))    => Ok(Value::Boolean(!v)),
+        (Op::BitNot, Value::Int(v))     => Ok(Value::Int(!v)),
+        (op, operand) => {
+            Err(format!("Cannot apply `{}` to `{}`", op.as_str(), operand.type_name()))
+        },
+    }
+}
+
+/// Applies a binary operator, or says why it does not apply.
+///
+/// Integer arithmetic wraps, shift amounts are taken modulo 64
+/// and `>>` keeps the sign. Integer division by zero is an
+/// error, while floats follow IEEE 754. Both operands must
+/// have the same type; `&&` and `||` are not handled here
+/// since they only evaluate their right operand when needed.
+pub fn binary(op: Op, left: Value, right: Value) -> Result<Value, String> {
+    use self::Value::*;
+
+    let value = match (op, left, right) {
+        (Op::Eq, l @ Closure(_), r) | (Op::Ne, l @ Closure(_), r) => return mismatch(op, &l, &r),
+        (Op::Eq, ref l, ref r) if l.type_name() == r.type_name() => Boolean(l == r),
+        (Op::Ne, ref l, ref r) if l.type_name() == r.type_name() => Boolean(l != r),
+
+        (Op::Add, Int(l), Int(r))       => Int(l.wrapping_add(r)),
+        (Op::Sub, Int(l), Int(r))       => Int(l.wrapping_sub(r)),
+        (Op::Mul, Int(l), Int(r))       => Int(l.wrapping_mul(r)),
+        (Op::Div, Int(_), Int(0))
+            | (Op::Mod, Int(_), Int(0)) => return Err("Division by zero".to_owned()),
+        (Op::Div, Int(l), Int(r))       => Int(l.wrapping_div(r)),
+        (Op::Mod, Int(l), Int(r))       => Int(l.wrapping_rem(r)),
+        (Op::BitAnd, Int(l), Int(r))    => Int(l & r),
+        (Op::BitOr, Int(l), Int(r))     => Int(l | r),
+        (Op::BitXor, Int(l), Int(r))    => Int(l ^ r),
+        (Op::Shl, Int(l), Int(r))       => Int(l.wrapping_shl(r as u32)),
+        (Op::Shr, Int(l), Int(r))       => Int(l.wrapping_shr(r as u32)),
+        (Op::Range, Int(l), Int(r))     => List((l..r).map(Int).collect()),
+        (Op::RangeInclusive, Int(l), Int(r)) => List((l..=r).map(Int).collect()),
+
+        (Op::Add, Float(l), Float(r))   => Float(l + r),
+        (Op::Sub, Float(l), Float(r))   => Float(l - r),
+        (Op::Mul, Float(l), Float(r))   => Float(l * r),
+        (Op::Div, Float(l), Float(r))   => Float(l / r),
+        (Op::Mod, Float(l), Float(r))   => Float(l % r),
+
+        (Op::Add, String(mut l), String(r)) => {
+            l.push_str(&r);
+            String(l)
+        },
+        (Op::Add, List(mut l), List(r)) => {
+            l.extend(r);
+            List(l)
+        },
+
+        (Op::Lt, l, r) => compare(op, &l, &r, |o| o == ::std::cmp::Ordering::Less)?,
+        (Op::Le, l, r) => compare(op, &l, &r, |o| o != ::std::cmp::Ordering::Greater)?,
+        (Op::Gt, l, r) => compare(op, &l, &r, |o| o == ::std::cmp::Ordering::Greater)?,
+        (Op::Ge, l, r) => compare(op, &l, &r, |o| o != ::std::cmp::Ordering::Less)?,
+
+        (op, l, r) => return mismatch(op, &l, &r),
+    };
+    Ok(value)
+}
+
+fn compare<F>(op: Op, left: &Value, right: &Value, test: F) -> Result<Value, String>
+    where F: Fn(::std::cmp::Ordering) -> bool
+{
+    let ordering = match (left, right) {
+        (&Value::Int(l), &Value::Int(r))               => Some(l.cmp(&r)),
+        // Comparisons with NaN are all false
+        (&Value::Float(l), &Value::Float(r))           => match l.partial_cmp(&r) {
+            Some(ordering) => Some(ordering),
+            None           => return Ok(Value::Boolean(false)),
+        },
+        (&Value::Char(l), &Value::Char(r))             => Some(l.cmp(&r)),
+        (&Value::String(ref l), &Value::String(ref r)) => Some(l.cmp(r)),
+        _                                              => None,
+    };
+
+    match ordering {
+        Some(ordering) => Ok(Value::Boolean(test(ordering))),
+        None           => mismatch(op, left, right),
+    }
+}
+
+fn mismatch(op: Op, left: &Value, right: &Value) -> Result<Value, String> {
+    Err(format!("Cannot apply `{}` to `{}` and `{}`", op.as_str(), left.type_name(), right.type_name()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Value, unary, binary, field, field_mut, index, index_mut};
+    use lexer::token::Op;
+
+    #[test]
+    fn wrap_integer_arithmetic() {
+        let min = Value::Int(i64::min_value());
+
+        assert_eq!(binary(Op::Add, Value::Int(i64::max_value()), Value::Int(1)), Ok(min.clone()));
+        assert_eq!(binary(Op::Div, min.clone(), Value::Int(-1)), Ok(min.clone()));
+        assert_eq!(binary(Op::Mod, min.clone(), Value::Int(-1)), Ok(Value::Int(0)));
+        assert_eq!(unary(Op::Sub, min.clone()), Ok(min.clone()));
+        assert_eq!(binary(Op::Shl, Value::Int(1), Value::Int(65)), Ok(Value::Int(2)));
+        assert_eq!(binary(Op::Shr, Value::Int(-8), Value::Int(1)), Ok(Value::Int(-4)));
+        assert_eq!(binary(Op::Mod, Value::Int(-7), Value::Int(2)), Ok(Value::Int(-1)));
+        assert_eq!(binary(Op::Div, Value::Int(1), Value::Int(0)), Err("Division by zero".to_owned()));
+    }
+
+    #[test]
+    fn compare_and_concatenate() {
+        let nan = Value::Float(::std::f64::NAN);
+
+        assert_eq!(binary(Op::Lt, Value::Char('a'), Value::Char('b')), Ok(Value::Boolean(true)));
+        assert_eq!(binary(Op::Ge, nan.clone(), nan.clone()), Ok(Value::Boolean(false)));
+        assert_eq!(binary(Op::Eq, nan.clone(), nan.clone()), Ok(Value::Boolean(false)));
+        assert_eq!(binary(Op::Ne, Value::Unit, Value::Unit), Ok(Value::Boolean(false)));
+        assert_eq!(binary(Op::Add, Value::String("a".to_owned()), Value::String("b".to_owned())),
+                   Ok(Value::String("ab".to_owned())));
+        assert_eq!(binary(Op::Add, Value::Int(1), Value::Float(1.0)),
+                   Err("Cannot apply `+` to `int` and `float`".to_owned()));
+    }
+
+    #[test]
+    fn read_and_write_fields() {
+        let mut point = Value::Record("Point".to_owned(), vec![
+            ("x".to_owned(), Value::Int(1)),
+            ("label".to_owned(), Value::String("a \"b\"".to_owned())),
+        ]);
+
+        assert_eq!(field(&point, "x"), Ok(&Value::Int(1)));
+        *field_mut(&mut point, "x").unwrap() = Value::Int(2);
+        assert_eq!(point.to_string(), "Point { x: 2, label: \"a \\\"b\\\"\" }");
+        assert_eq!(field(&point, "y"), Err("`Point` has no field `y`".to_owned()));
+        assert_eq!(field_mut(&mut Value::Int(1), "x"), Err("`int` has no field `x`".to_owned()));
+    }
+
+    #[test]
+    fn index_and_grow_lists() {
+        let list = |elements: Vec<i64>| Value::List(elements.into_iter().map(Value::Int).collect());
+        let mut xs = list(vec![1, 2]);
+
+        assert_eq!(index(&xs, &Value::Int(1)), Ok(&Value::Int(2)));
+        *index_mut(&mut xs, &Value::Int(0)).unwrap() = Value::Int(3);
+        assert_eq!(binary(Op::Add, xs.clone(), list(vec![4])), Ok(list(vec![3, 2, 4])));
+        assert_eq!(Value::List(vec![xs.clone(), Value::List(vec![Value::Char('a')])]).to_string(), "[[3, 2], [\"a\"]]");
+        assert_eq!(index(&xs, &Value::Int(2)), Err("Index 2 is out of bounds for a list of length 2".to_owned()));
+        assert_eq!(index_mut(&mut xs, &Value::Int(-1)), Err("Index -1 is out of bounds for a list of length 2".to_owned()));
+        assert_eq!(index(&Value::Int(1), &Value::Int(0)), Err("Cannot index `int`".to_owned()));
+        assert_eq!(binary(Op::Range, Value::Int(1), Value::Int(3)), Ok(list(vec![1, 2])));
+        assert_eq!(binary(Op::RangeInclusive, Value::Int(3), Value::Int(1)), Ok(list(vec![])));
+    }
+}
@@ -0,0 +1,508 @@
+use codegen::bytecode::{Opcode, Program};
+use diagnostic::Diagnostic;
+use lexer::token::TokenPosition;
+use value::{self, Value, Closure};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// How deep calls may nest before the machine gives up.
+const MAX_FRAMES: usize = 1024;
+
+/// A function being run.
+struct Frame {
+    function: usize,
+    /// The offset of the next instruction.
+    ip:       usize,
+    /// Where the frame's locals start on the stack.
+    base:     usize,
+    /// The number of arguments below the frame left for
+    /// what it returns, when a call applied it to more
+    /// than it takes.
+    rest:     usize,
+}
+
+/// A stack machine running compiled programs.
+///
+/// Locals live on the value stack, at the bottom of their
+/// frame, with the arguments of a call as the first ones,
+/// after the cells a closure captured.
+pub struct Vm<'p> {
+    program: &'p Program,
+    stack:   Vec<Value>,
+    frames:  Vec<Frame>,
+}
+
+impl<'p> Vm<'p> {
+    pub fn new(program: &'p Program) -> Vm<'p> {
+        Vm {
+            program: program,
+            stack:   Vec::new(),
+            frames:  Vec::new(),
+        }
+    }
+
+    /// Runs the entry point, returning its result.
+    pub fn run(&mut self) -> Result<Value, Diagnostic> {
+        self.stack.clear();
+        self.frames.clear();
+        if self.program.functions.is_empty() {
+            return Err(self.error("Program has no entry point"))
+        }
+        self.call(0)?;
+
+        loop {
+            let (function, ip) = {
+                let frame = self.frames.last().unwrap();
+                (&self.program.functions[frame.function], frame.ip)
+            };
+            let opcode = match function.code.get(ip).and_then(|&b| Opcode::from_byte(b)) {
+                Some(opcode) => opcode,
+                None         => return Err(self.error("Invalid instruction")),
+            };
+            let operand = if opcode.operands() > 0 {
+                match function.operand(ip + 1) {
+                    Some(operand) => operand as usize,
+                    None          => return Err(self.error("Truncated instruction")),
+                }
+            } else {
+                0
+            };
+            self.frames.last_mut().unwrap().ip = ip + 1 + 2 * opcode.operands();
+
+            match opcode {
+                Opcode::Const => {
+                    match self.program.constants.get(operand) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None        => return Err(self.error("Invalid constant")),
+                    }
+                },
+                Opcode::Unit => self.stack.push(Value::Unit),
+                Opcode::Pop  => {
+                    self.pop()?;
+                },
+                Opcode::Load => {
+                    let slot  = self.local(operand)?;
+                    let value = self.stack[slot].clone();
+                    self.stack.push(value)
+                },
+                Opcode::Store => {
+                    let slot  = self.local(operand)?;
+                    let value = self.pop()?;
+                    self.stack[slot] = value
+                },
+                Opcode::Jump => self.jump(operand)?,
+                Opcode::JumpIfFalse => {
+                    match self.pop()? {
+                        Value::Boolean(true)  => (),
+                        Value::Boolean(false) => self.jump(operand)?,
+                        _                     => return Err(self.error("Condition is not a boolean")),
+                    }
+                },
+                Opcode::Call   => self.call(operand)?,
+                Opcode::Neg | Opcode::Not | Opcode::BitNot => {
+                    let value  = self.pop()?;
+                    let result = value::unary(opcode.operator().unwrap(), value);
+                    self.push_result(result)?
+                },
+                Opcode::List => {
+                    let mut elements = Vec::with_capacity(operand);
+                    for _ in 0..operand {
+                        elements.push(self.pop()?)
+                    }
+                    elements.reverse();
+                    self.stack.push(Value::List(elements))
+                },
+                Opcode::Index => {
+                    let index  = self.pop()?;
+                    let list   = self.pop()?;
+                    let result = value::index(&list, &index).map(Value::clone);
+                    self.push_result(result)?
+                },
+                Opcode::SetIndex => {
+                    let value    = self.pop()?;
+                    let index    = self.pop()?;
+                    let mut list = self.pop()?;
+                    let result   = value::index_mut(&mut list, &index).map(|slot| *slot = value);
+                    self.push_result(result.map(|()| list))?
+                },
+                Opcode::Record => {
+                    let (name, fields) = match self.program.constants.get(operand) {
+                        Some(&Value::Record(ref name, ref fields)) => (name.clone(), fields.clone()),
+                        _ => return Err(self.error("Invalid constant")),
+                    };
+                    let mut values = Vec::with_capacity(fields.len());
+                    for _ in 0..fields.len() {
+                        values.push(self.pop()?)
+                    }
+                    let fields = fields.into_iter().zip(values.into_iter().rev()).map(|((f, _), v)| (f, v)).collect();
+                    self.stack.push(Value::Record(name, fields))
+                },
+                Opcode::Field => {
+                    let name   = self.name(operand)?;
+                    let record = self.pop()?;
+                    let result = value::field(&record, &name).map(Value::clone);
+                    self.push_result(result)?
+                },
+                Opcode::SetField => {
+                    let name       = self.name(operand)?;
+                    let value      = self.pop()?;
+                    let mut record = self.pop()?;
+                    let result     = value::field_mut(&mut record, &name).map(|slot| *slot = value);
+                    self.push_result(result.map(|()| record))?
+                },
+                Opcode::Variant => {
+                    let (e, name, payload) = match self.program.constants.get(operand) {
+                        Some(&Value::Variant(ref e, ref name, ref payload)) => (e.clone(), name.clone(), payload.len()),
+                        _ => return Err(self.error("Invalid constant")),
+                    };
+                    let mut values = Vec::with_capacity(payload);
+                    for _ in 0..payload {
+                        values.push(self.pop()?)
+                    }
+                    values.reverse();
+                    self.stack.push(Value::Variant(e, name, values))
+                },
+                Opcode::Tuple => {
+                    let mut elements = Vec::with_capacity(operand);
+                    for _ in 0..operand {
+                        elements.push(self.pop()?)
+                    }
+                    elements.reverse();
+                    self.stack.push(Value::Tuple(elements))
+                },
+                Opcode::Element => {
+                    let value  = self.pop()?;
+                    let result = value::part(&value, operand).map(Value::clone);
+                    self.push_result(result)?
+                },
+                Opcode::IsVariant => {
+                    let name = self.name(operand)?;
+                    match self.pop()? {
+                        Value::Variant(_, ref variant, _) => self.stack.push(Value::Boolean(*variant == name)),
+                        value => return Err(self.error(&format!("Expected an enum, found `{}`", value.type_name()))),
+                    }
+                },
+                Opcode::Unmatched => {
+                    let value = self.pop()?;
+                    return Err(self.error(&format!("No arm matches `{}`", value)))
+                },
+                Opcode::Mark => {
+                    let slot = self.local(operand)?;
+                    self.stack[slot] = Value::Int(self.stack.len() as i64)
+                },
+                Opcode::Unwind => {
+                    let slot   = self.local(operand)?;
+                    let value  = self.pop()?;
+                    let height = match self.stack[slot] {
+                        Value::Int(height) if height as usize <= self.stack.len() => height as usize,
+                        _ => return Err(self.error("Invalid mark")),
+                    };
+                    self.stack.truncate(height);
+                    self.stack.push(value)
+                },
+                Opcode::Length => {
+                    match self.pop()? {
+                        Value::List(ref elements) => self.stack.push(Value::Int(elements.len() as i64)),
+                        value => return Err(self.error(&format!("Cannot loop over `{}`", value.type_name()))),
+                    }
+                },
+                Opcode::Cell => {
+                    let value = self.pop()?;
+                    self.stack.push(Value::Cell(Rc::new(RefCell::new(value))))
+                },
+                Opcode::LoadCell => {
+                    let slot  = self.local(operand)?;
+                    let value = match self.stack[slot] {
+                        Value::Cell(ref cell) => cell.borrow().clone(),
+                        _                     => return Err(self.error("Invalid cell")),
+                    };
+                    self.stack.push(value)
+                },
+                Opcode::StoreCell => {
+                    let slot  = self.local(operand)?;
+                    let value = self.pop()?;
+                    match self.stack[slot] {
+                        Value::Cell(ref cell) => *cell.borrow_mut() = value,
+                        _                     => return Err(self.error("Invalid cell")),
+                    }
+                },
+                Opcode::Closure => {
+                    let (captures, arity) = match self.program.functions.get(operand) {
+                        Some(function) => (function.captures as usize, function.arity as usize),
+                        None           => return Err(self.error("Invalid function")),
+                    };
+                    let mut captured = Vec::with_capacity(captures);
+                    for _ in 0..captures {
+                        match self.pop()? {
+                            Value::Cell(cell) => captured.push(cell),
+                            _                 => return Err(self.error("Invalid cell")),
+                        }
+                    }
+                    captured.reverse();
+                    self.stack.push(Value::Closure(Rc::new(Closure {
+                        function:  operand,
+                        arity:     arity,
+                        captured:  captured,
+                        arguments: Vec::new(),
+                    })))
+                },
+                Opcode::Apply => self.apply(operand)?,
+                Opcode::Method => {
+                    let function = match self.program.functions.get(operand) {
+                        Some(function) => function,
+                        None           => return Err(self.error("Invalid function")),
+                    };
+                    let value = self.pop()?;
+                    match value::field(&value, &function.name).ok().cloned() {
+                        Some(field) => self.stack.push(field),
+                        // Without such a field, `x.f` is `f x`
+                        None => {
+                            self.stack.push(Value::Closure(Rc::new(Closure {
+                                function:  operand,
+                                arity:     function.arity as usize,
+                                captured:  Vec::new(),
+                                arguments: Vec::new(),
+                            })));
+                            self.stack.push(value);
+                            self.apply(1)?
+                        },
+                    }
+                },
+                Opcode::Return => {
+                    let value = self.pop()?;
+                    let frame = self.frames.pop().unwrap();
+                    self.stack.truncate(frame.base);
+                    if self.frames.is_empty() {
+                        return Ok(value)
+                    }
+                    if frame.rest > 0 {
+                        // Under the arguments left for it, to apply it to them
+                        let at = self.stack.len() - frame.rest;
+                        self.stack.insert(at, value);
+                        self.apply(frame.rest)?
+                    } else {
+                        self.stack.push(value)
+                    }
+                },
+                _ => {
+                    let right  = self.pop()?;
+                    let left   = self.pop()?;
+                    let result = value::binary(opcode.operator().unwrap(), left, right);
+                    self.push_result(result)?
+                },
+            }
+        }
+    }
+
+    fn push_result(&mut self, result: Result<Value, String>) -> Result<(), Diagnostic> {
+        match result {
+            Ok(value)    => Ok(self.stack.push(value)),
+            Err(message) => Err(self.error(&message)),
+        }
+    }
+
+    fn call(&mut self, index: usize) -> Result<(), Diagnostic> {
+        let function = match self.program.functions.get(index) {
+            Some(function) => function,
+            None           => return Err(self.error("Invalid function")),
+        };
+        if self.frames.len() == MAX_FRAMES {
+            return Err(self.error("Stack overflow"))
+        }
+        let arguments = function.captures as usize + function.arity as usize;
+        if self.stack.len() < self.base() + arguments {
+            return Err(self.error("Stack underflow"))
+        }
+
+        let base = self.stack.len() - arguments;
+        for _ in arguments..function.locals as usize {
+            self.stack.push(Value::Unit)
+        }
+        self.frames.push(Frame {
+            function: index,
+            ip:       0,
+            base:     base,
+            rest:     0,
+        });
+        Ok(())
+    }
+
+    /// The string constant naming a field.
+    fn name(&self, index: usize) -> Result<String, Diagnostic> {
+        match self.program.constants.get(index) {
+            Some(&Value::String(ref name)) => Ok(name.clone()),
+            _                              => Err(self.error("Invalid constant")),
+        }
+    }
+
+    fn jump(&mut self, offset: usize) -> Result<(), Diagnostic> {
+        let frame = self.frames.last_mut().unwrap();
+        if offset >= self.program.functions[frame.function].code.len() {
+            return Err(self.error("Jump out of bounds"))
+        }
+        frame.ip = offset;
+        Ok(())
+    }
+
+    /// Applies the closure under the top `count` values to them.
+    /// Given too few, it pushes a closure of them, and given too
+    /// many, it leaves the rest below the call for its result.
+    fn apply(&mut self, count: usize) -> Result<(), Diagnostic> {
+        if self.stack.len() < self.base() + count + 1 {
+            return Err(self.error("Stack underflow"))
+        }
+        let at = self.stack.len() - count - 1;
+        let closure = match self.stack[at] {
+            Value::Closure(ref closure) => closure.clone(),
+            ref value => return Err(self.error(&format!("Cannot call `{}`", value.type_name()))),
+        };
+        self.stack.remove(at);
+
+        let missing = closure.missing();
+        if count < missing {
+            let arguments = self.stack.split_off(at);
+            self.stack.push(Value::Closure(Rc::new(closure.partial(arguments))));
+            return Ok(())
+        }
+
+        let last = self.stack.split_off(at);
+        let rest = count - missing;
+        self.stack.extend_from_slice(&last[missing..]);
+        for cell in closure.captured.iter() {
+            self.stack.push(Value::Cell(cell.clone()))
+        }
+        self.stack.extend_from_slice(&closure.arguments);
+        self.stack.extend_from_slice(&last[..missing]);
+        self.call(closure.function)?;
+        self.frames.last_mut().unwrap().rest = rest;
+        Ok(())
+    }
+
+    /// The stack slot of a local in the current frame.
+    fn local(&self, index: usize) -> Result<usize, Diagnostic> {
+        let frame = self.frames.last().unwrap();
+        if index < self.program.functions[frame.function].locals as usize {
+            Ok(frame.base + index)
+        } else {
+            Err(self.error("Invalid local"))
+        }
+    }
+
+    /// Pops a value pushed by the current frame.
+    fn pop(&mut self) -> Result<Value, Diagnostic> {
+        let base = self.base() + self.frames.last().map_or(0, |f| {
+            self.program.functions[f.function].locals as usize
+        });
+        if self.stack.len() > base {
+            Ok(self.stack.pop().unwrap())
+        } else {
+            Err(self.error("Stack underflow"))
+        }
+    }
+
+    /// Where the locals of the current frame start.
+    fn base(&self) -> usize {
+        self.frames.last().map_or(0, |f| f.base)
+    }
+
+    /// An error at the source position of the current instruction.
+    fn error(&self, message: &str) -> Diagnostic {
+        let position = self.frames.last().and_then(|frame| {
+            let function = &self.program.functions[frame.function];
+            // The instruction pointer has already moved past it
+            function.position(frame.ip.saturating_sub(1))
+        });
+        Diagnostic::new(position.unwrap_or(TokenPosition::default()), message.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Vm;
+    use codegen::bytecode::{Opcode, Function, Program};
+    use lexer::token::TokenPosition;
+    use value::Value;
+
+    fn at(line: usize) -> TokenPosition {
+        TokenPosition { line: line, col: 0 }
+    }
+
+    /// A function taking a boolean, returning
+    /// `"yes"` if it is true and `"no"` otherwise.
+    fn choose(program: &mut Program) -> Function {
+        let yes = program.add_constant(Value::String("yes".to_owned())).unwrap();
+        let no  = program.add_constant(Value::String("no".to_owned())).unwrap();
+
+        let mut function = Function::new("choose", 1);
+        function.emit(Opcode::Load, &[0], at(1));
+        let jump = function.emit(Opcode::JumpIfFalse, &[0], at(1));
+        function.emit(Opcode::Const, &[yes], at(2));
+        function.emit(Opcode::Return, &[], at(2));
+        let otherwise = function.code.len() as u16;
+        function.patch(jump, otherwise);
+        function.emit(Opcode::Const, &[no], at(3));
+        function.emit(Opcode::Return, &[], at(3));
+        function
+    }
+
+    #[test]
+    fn call_with_locals_and_jumps() {
+        let mut program = Program::new();
+        let truth = program.add_constant(Value::Boolean(true)).unwrap();
+        let lie   = program.add_constant(Value::Boolean(false)).unwrap();
+
+        // Stores the result of `choose true` in a local,
+        // then calls `choose false` and drops the result
+        let mut main = Function::new("main", 0);
+        main.locals = 1;
+        main.emit(Opcode::Const, &[truth], at(5));
+        main.emit(Opcode::Call, &[1], at(5));
+        main.emit(Opcode::Store, &[0], at(5));
+        main.emit(Opcode::Const, &[lie], at(6));
+        main.emit(Opcode::Call, &[1], at(6));
+        main.emit(Opcode::Pop, &[], at(6));
+        main.emit(Opcode::Load, &[0], at(7));
+        main.emit(Opcode::Return, &[], at(7));
+
+        program.add_function(main);
+        let choose = choose(&mut program);
+        program.add_function(choose);
+
+        assert_eq!(Vm::new(&program).run(), Ok(Value::String("yes".to_owned())));
+
+        program.functions[0].code[1] = lie as u8;
+        program.functions[0].code[2] = 0;
+        assert_eq!(Vm::new(&program).run(), Ok(Value::String("no".to_owned())));
+    }
+
+    #[test]
+    fn report_errors_at_their_position() {
+        let mut program = Program::new();
+        let one = program.add_constant(Value::Int(1)).unwrap();
+
+        let mut main = Function::new("main", 0);
+        main.emit(Opcode::Const, &[one], at(1));
+        main.emit(Opcode::Call, &[1], at(2));
+        main.emit(Opcode::Return, &[], at(2));
+        program.add_function(main);
+        let choose = choose(&mut program);
+        program.add_function(choose);
+
+        let error = Vm::new(&program).run().unwrap_err();
+        assert_eq!(*error.position(), at(1));
+        assert_eq!(error.message(), "Condition is not a boolean");
+    }
+
+    #[test]
+    fn report_stack_overflow() {
+        let mut program = Program::new();
+        let mut main = Function::new("main", 0);
+        main.emit(Opcode::Call, &[0], at(1));
+        main.emit(Opcode::Return, &[], at(1));
+        program.add_function(main);
+
+        let error = Vm::new(&program).run().unwrap_err();
+        assert_eq!(error.message(), "Stack overflow");
+    }
+}
@@ -0,0 +1,338 @@
+//! Golden-file tests.
+//!
+//! Every `.sma` file under `tests/` is run through the front end
+//! and its output compared with the files next to it:
+//!
+//! * `.tokens` - the lexed block tree, as JSON
+//! * `.ast`    - the parsed nodes, as S-expressions
+//! * `.stderr` - the diagnostics, empty if missing
+//! * `.stdout` - the program output, empty if missing
+//! * `.ir`     - the IR, for files under `tests/ir/` only
+//! * `.opt.ir` - the IR optimized at `-O2`, likewise
+//!
+//! Programs without syntax errors are type checked and have
+//! their naming conventions checked at the default levels, and
+//! programs without errors, but perhaps with warnings, are run
+//! by the interpreter,
+//! the virtual machine, from verified IR at every optimization
+//! level and, with the `jit` feature, by the interpreter
+//! calling native code, which must all agree.
+//! If a C compiler is found (`$CC`, or `cc`), the output of
+//! the programs translated to C must match too. So must the
+//! output of their WebAssembly, which must also validate and
+//! read the same in the text format. Backends skip programs
+//! using what they cannot translate yet, such as lists.
+//!
+//! Run with `SMAC_BLESS=1` to write the current
+//! output as the new expectations.
+
+extern crate libsmac;
+extern crate wasmi;
+extern crate wasmparser;
+extern crate wat;
+
+use libsmac::diagnostic::Diagnostic;
+use libsmac::document::Document;
+use libsmac::codegen::{compile, emit_c, emit_wasm};
+use libsmac::codegen::c::HEADER;
+use libsmac::conventions::{self, Conventions};
+use libsmac::interpreter::Interpreter;
+use libsmac::ir;
+#[cfg(feature = "jit")]
+use libsmac::jit::Jit;
+use libsmac::serialize::{ToJson, to_sexp};
+use libsmac::parser::node::Node;
+use libsmac::typeck::{check, Types};
+use libsmac::value::Value;
+use libsmac::vm::Vm;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The output of each stage, by expectation file extension.
+struct Outcome {
+    outputs: Vec<(&'static str, String)>,
+}
+
+fn run(source: &str, dump_ir: bool) -> Outcome {
+    let document = Document::new(source);
+
+    let ast: Vec<_> = document.ast().into_iter().cloned().collect();
+    let mut stderr: String = document.diagnostics().iter().map(|d| format!("{}\n", d)).collect();
+    let mut stdout = String::new();
+    let mut ir     = (String::new(), String::new());
+
+    let mut types    = None;
+    let mut warnings = String::new();
+    if stderr.is_empty() {
+        match check(&ast) {
+            Ok(checked) => {
+                let diagnostics = conventions::check(&ast, &checked, &Conventions::default());
+                if diagnostics.iter().any(|d| d.is_error()) {
+                    stderr = diagnostics.iter().map(|d| format!("{}\n", d)).collect()
+                } else {
+                    warnings = diagnostics.iter().map(|d| format!("{}\n", d)).collect();
+                    types = Some(checked)
+                }
+            },
+            Err(errors) => stderr = errors.iter().map(|d| format!("{}\n", d)).collect(),
+        }
+    }
+
+    if let Some(types) = types {
+        let evaluated = Interpreter::new().run(&ast);
+        let executed  = compile(&ast).and_then(|program| Vm::new(&program).run());
+        assert_eq!(evaluated, executed, "the interpreter and the virtual machine disagree");
+        match run_ir(&ast, &types, &evaluated) {
+            Some(text) => ir = text,
+            None       => assert!(!dump_ir, "programs under tests/ir must lower to IR"),
+        }
+        run_native(&ast, &types, &evaluated);
+
+        let wasm = run_wasm(&ast, &types, &evaluated);
+
+        match evaluated {
+            Ok(Value::Unit) => (),
+            Ok(value)       => stdout = format!("{}\n", value),
+            Err(error)      => stderr = format!("{}\n", error),
+        }
+
+        if let Some((wasm_stdout, wasm_stderr)) = wasm {
+            assert_eq!((&stdout, &stderr), (&wasm_stdout, &wasm_stderr), "the interpreter and WebAssembly disagree");
+        }
+
+        if let Some((c_stdout, c_stderr)) = run_c(&ast, &types) {
+            assert_eq!((&stdout, &stderr), (&c_stdout, &c_stderr), "the interpreter and C disagree");
+        }
+    }
+    // Warnings come before what running printed
+    stderr = warnings + &stderr;
+
+    let mut outputs = vec![
+        ("tokens", format!("{}\n", document.root().to_json().pretty())),
+        ("ast",    to_sexp(&ast)),
+        ("stderr", stderr),
+        ("stdout", stdout),
+    ];
+    if dump_ir {
+        outputs.push(("ir", ir.0));
+        outputs.push(("opt.ir", ir.1));
+    }
+    Outcome {
+        outputs: outputs,
+    }
+}
+
+/// Lowers a program to IR, which must verify and run like
+/// the interpreter at every optimization level, and returns
+/// its text unoptimized and at `-O2`, or `None` if it
+/// cannot be lowered.
+fn run_ir(ast: &[Node], types: &Types, evaluated: &Result<Value, Diagnostic>) -> Option<(String, String)> {
+    let lowered = match ir::lower(ast, types) {
+        Ok(lowered) => lowered,
+        Err(_)      => return None,
+    };
+    let mut text = Vec::new();
+    for level in 0..3 {
+        let mut module = lowered.clone();
+        ir::optimize(&mut module, level);
+        if let Err(errors) = ir::verify(&module) {
+            let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+            panic!("invalid IR at -O{}:\n{}\n\n{}", level, errors.join("\n"), module)
+        }
+        assert_eq!(evaluated, &ir::run(&module), "the interpreter and the IR at -O{} disagree", level);
+        text.push(module.to_string());
+    }
+    Some((text[0].clone(), text[2].clone()))
+}
+
+#[cfg(feature = "jit")]
+fn run_native(ast: &[Node], types: &Types, evaluated: &Result<Value, Diagnostic>) {
+    let mut jit = Jit::compile(ast, types).unwrap();
+    let native  = Interpreter::with_dispatch(&mut jit).run(ast);
+    assert_eq!(evaluated, &native, "the interpreter and native code disagree");
+}
+
+#[cfg(not(feature = "jit"))]
+fn run_native(_: &[Node], _: &Types, _: &Result<Value, Diagnostic>) {}
+
+/// Builds and runs a program translated to C, returning its
+/// output, or `None` if there is no C compiler or the
+/// program cannot be translated.
+fn run_c(ast: &[Node], types: &Types) -> Option<(String, String)> {
+    static BUILDS: AtomicUsize = AtomicUsize::new(0);
+
+    let source = match emit_c(ast, types) {
+        Ok(source) => source,
+        Err(_)     => return None,
+    };
+    let compiler = env::var("CC").unwrap_or("cc".to_owned());
+    let dir = env::temp_dir().join(format!("smac-golden-{}-{}", std::process::id(),
+                                           BUILDS.fetch_add(1, Ordering::SeqCst)));
+    fs::create_dir_all(&dir).unwrap();
+    File::create(dir.join("smac.h")).unwrap().write_all(HEADER.as_bytes()).unwrap();
+    File::create(dir.join("main.c")).unwrap().write_all(source.as_bytes()).unwrap();
+
+    let built = Command::new(&compiler)
+        .args(&["-std=c99", "-o", "main", "main.c", "-lm"])
+        .current_dir(&dir)
+        .output();
+    let output = match built {
+        Ok(ref built) if built.status.success() => Command::new(dir.join("main")).output().unwrap(),
+        Ok(built) => panic!("C does not compile:\n{}", String::from_utf8_lossy(&built.stderr)),
+        Err(_)    => {
+            let _ = fs::remove_dir_all(&dir);
+            return None
+        },
+    };
+    fs::remove_dir_all(&dir).unwrap();
+
+    Some((String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap()))
+}
+
+/// Validates and runs a program translated to WebAssembly,
+/// returning its output, or `None` if it cannot be translated.
+/// The interpreter's value tells how to read the one the
+/// module returns.
+fn run_wasm(ast: &[Node], types: &Types, evaluated: &Result<Value, Diagnostic>) -> Option<(String, String)> {
+    let module = match emit_wasm(ast, types) {
+        Ok(module) => module,
+        Err(_)     => return None,
+    };
+    let binary = module.to_binary();
+    wasmparser::Validator::new().validate_all(&binary).expect("invalid WebAssembly");
+    assert_eq!(wat::parse_str(module.to_text()).unwrap(), binary, "the text and binary formats differ");
+
+    let engine   = wasmi::Engine::default();
+    let compiled = wasmi::Module::new(&engine, &binary[..]).unwrap();
+    let mut store  = wasmi::Store::new(&engine, None);
+    let mut linker = wasmi::Linker::new(&engine);
+    linker.func_wrap("smac", "fail", |mut caller: wasmi::Caller<Option<String>>, line: i32, col: i32, message: i32| {
+        let memory  = caller.get_export("smac.memory").and_then(|e| e.into_memory()).unwrap();
+        let message = read_wasm_string(memory.data(&caller), message);
+        *caller.data_mut() = Some(format!("error [ln {}, col {}]: {}\n", line, col, message));
+    }).unwrap();
+    linker.func_wrap("smac", "fmod", |left: f64, right: f64| left % right).unwrap();
+
+    let instance = linker.instantiate(&mut store, &compiled).unwrap().start(&mut store).unwrap();
+    let main     = instance.get_func(&store, "smac.main").unwrap();
+    let memory   = instance.get_memory(&store, "smac.memory").unwrap();
+    let mut results = [wasmi::Val::I32(0)];
+    if main.call(&mut store, &[], &mut results).is_err() {
+        return Some((String::new(), store.data().clone().expect("trapped without reporting an error")))
+    }
+
+    let value = match (evaluated, &results[0]) {
+        (&Ok(Value::Unit), _)                      => return Some((String::new(), String::new())),
+        (&Ok(Value::Int(_)), &wasmi::Val::I64(v))   => Value::Int(v),
+        (&Ok(Value::Float(_)), &wasmi::Val::F64(v)) => Value::Float(v.into()),
+        (&Ok(Value::Boolean(_)), &wasmi::Val::I32(v)) => Value::Boolean(v != 0),
+        (&Ok(Value::Char(_)), &wasmi::Val::I32(v))  => Value::Char(std::char::from_u32(v as u32).unwrap()),
+        (&Ok(Value::String(_)), &wasmi::Val::I32(v)) => Value::String(read_wasm_string(memory.data(&store), v)),
+        (_, result) => panic!("WebAssembly returned {:?}", result),
+    };
+    Some((format!("{}\n", value), String::new()))
+}
+
+fn read_wasm_string(memory: &[u8], address: i32) -> String {
+    let address = address as usize;
+    let mut length = [0; 4];
+    length.copy_from_slice(&memory[address..address + 4]);
+    let length = u32::from_le_bytes(length) as usize;
+    String::from_utf8(memory[address + 4..address + 4 + length].to_vec()).unwrap()
+}
+
+fn collect_sources(dir: &Path, sources: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect_sources(&path, sources)
+        } else if path.extension().map_or(false, |e| e == "sma") {
+            sources.push(path)
+        }
+    }
+}
+
+fn read(path: &Path) -> Option<String> {
+    let mut data = String::new();
+    match File::open(path) {
+        Ok(mut f) => {
+            f.read_to_string(&mut data).unwrap();
+            Some(data)
+        },
+        Err(_) => None,
+    }
+}
+
+/// Describes the first line where two outputs differ.
+fn difference(expected: &str, actual: &str) -> String {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines   = actual.lines();
+    let mut line           = 1;
+
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => line += 1,
+            (e, a) => {
+                return format!("line {}:\n  expected: {}\n  actual:   {}",
+                               line,
+                               e.unwrap_or("<end of output>"),
+                               a.unwrap_or("<end of output>"))
+            },
+        }
+    }
+}
+
+#[test]
+fn golden() {
+    let bless = env::var("SMAC_BLESS").map(|v| v == "1").unwrap_or(false);
+    let root  = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+
+    let mut sources = Vec::new();
+    collect_sources(&root, &mut sources);
+    sources.sort();
+
+    let mut failures = Vec::new();
+
+    for source_path in sources.iter() {
+        let source  = read(source_path).unwrap();
+        let dump_ir = source_path.starts_with(root.join("ir"));
+        let outcome = run(&source, dump_ir);
+
+        for &(extension, ref actual) in outcome.outputs.iter() {
+            let path = source_path.with_extension(extension);
+            // Streams are optional, and empty when missing
+            let optional = extension == "stderr" || extension == "stdout";
+
+            if bless {
+                if optional && actual.is_empty() {
+                    let _ = fs::remove_file(&path);
+                } else {
+                    File::create(&path).unwrap().write_all(actual.as_bytes()).unwrap();
+                }
+                continue
+            }
+
+            match read(&path) {
+                Some(ref expected) if expected == actual => (),
+                None if optional && actual.is_empty()    => (),
+                Some(expected) => {
+                    failures.push(format!("{}: {}", path.display(), difference(&expected, actual)))
+                },
+                None => {
+                    failures.push(format!("{}: missing, run with SMAC_BLESS=1 to create it", path.display()))
+                },
+            }
+        }
+    }
+
+    assert!(!sources.is_empty(), "no .sma files found in {}", root.display());
+
+    if !failures.is_empty() {
+        panic!("{} golden file(s) differ:\n\n{}\n", failures.len(), failures.join("\n\n"))
+    }
+}
@@ -0,0 +1,198 @@
+//! Property tests for the front end.
+//!
+//! Generated syntax trees are printed as source and parsed
+//! again, which must give back the same nodes. Arbitrary
+//! text must lex and parse without panicking.
+
+extern crate libsmac;
+extern crate proptest;
+
+use libsmac::document::Document;
+use libsmac::lexer::block_tree::BlockTree;
+use libsmac::lexer::grab_smaragdine_lexer;
+use libsmac::lexer::token::{Op, Span};
+use libsmac::parser::node::{self, Node, NodeType, Pattern, Arm};
+use libsmac::serialize::to_source;
+
+use proptest::prelude::*;
+
+fn node(node_type: NodeType) -> Node {
+    Node::new(node_type, Span::default())
+}
+
+fn literal() -> BoxedStrategy<Node> {
+    prop_oneof![
+        any::<u64>().prop_map(|v| NodeType::IntLiteral(v, false)),
+        (i64::min_value()..=0).prop_map(|v| NodeType::IntLiteral(v as u64, true)),
+        (proptest::num::f64::POSITIVE | proptest::num::f64::ZERO
+            | proptest::num::f64::NORMAL | proptest::num::f64::SUBNORMAL)
+            .prop_map(|v| NodeType::FloatLiteral(v.abs())),
+        "[^\\p{Cc}]*".prop_map(NodeType::StringLiteral),
+        "[^\\p{Cc}]".prop_map(|s| NodeType::CharLiteral(s.chars().next().unwrap())),
+        any::<bool>().prop_map(NodeType::BooleanLiteral),
+    ].prop_map(node).boxed()
+}
+
+fn name() -> BoxedStrategy<String> {
+    "[a-z_][a-z0-9_]{0,5}"
+        .prop_filter("keywords are not names", |s| {
+            !["let", "if", "else", "type", "with", "match", "while", "for", "in",
+              "break", "continue", "true", "false", "mut"].contains(&&s[..])
+        })
+        .boxed()
+}
+
+fn identifier() -> BoxedStrategy<Node> {
+    name().prop_map(|s| node(NodeType::Identifier(s))).boxed()
+}
+
+/// The binary operators, other than assignment and `|>`.
+fn binary() -> BoxedStrategy<Op> {
+    prop_oneof![
+        Just(Op::Add), Just(Op::Sub), Just(Op::Mul), Just(Op::Div), Just(Op::Mod),
+        Just(Op::Shl), Just(Op::Shr), Just(Op::Lt), Just(Op::Le), Just(Op::Gt),
+        Just(Op::Ge), Just(Op::Eq), Just(Op::Ne), Just(Op::BitAnd), Just(Op::BitXor),
+        Just(Op::BitOr), Just(Op::And), Just(Op::Or), Just(Op::Range), Just(Op::RangeInclusive),
+    ].boxed()
+}
+
+/// The lambda a section such as `(+ 1)` or `(1 -)` stands for.
+/// `(- 1)` is a negative number instead.
+fn section(op: Op, operand: Node, left: bool) -> Node {
+    let parameter = node(NodeType::Identifier(node::SECTION.to_owned()));
+    let (l, r) = if left { (operand, parameter) } else { (parameter, operand) };
+    let body = node(NodeType::Binary(op, Box::new(l), Box::new(r)));
+    node(NodeType::Lambda(vec![node::SECTION.to_owned()], Box::new(body)))
+}
+
+/// Expressions of every precedence level, which the
+/// printer must parenthesize wherever they nest.
+fn expression() -> BoxedStrategy<Node> {
+    let leaf = prop_oneof![literal(), identifier()];
+
+    leaf.prop_recursive(3, 24, 3, |inner| {
+        let unary = prop_oneof![Just(Op::Sub), Just(Op::Add), Just(Op::Not), Just(Op::BitNot)];
+
+        prop_oneof![
+            (unary, inner.clone()).prop_map(|(op, e)| node(NodeType::Unary(op, Box::new(e)))),
+            (binary(), inner.clone(), inner.clone())
+                .prop_map(|(op, l, r)| node(NodeType::Binary(op, Box::new(l), Box::new(r)))),
+            (inner.clone(), name()).prop_map(|(e, name)| node(NodeType::Field(Box::new(e), name))),
+            prop::collection::vec(inner.clone(), 0..3).prop_map(|elements| node(NodeType::List(elements))),
+            (inner.clone(), inner.clone())
+                .prop_map(|(l, i)| node(NodeType::Index(Box::new(l), Box::new(i)))),
+            (identifier(), prop::collection::vec(inner.clone(), 1..3))
+                .prop_map(|(f, args)| node(NodeType::Call(Box::new(f), args))),
+            (inner.clone(), prop::collection::vec((name(), inner.clone()), 1..3))
+                .prop_map(|(e, fields)| node(NodeType::With(Box::new(e), fields))),
+            prop::collection::vec(inner.clone(), 2..4).prop_map(|elements| node(NodeType::Tuple(elements))),
+            (prop::collection::vec(name(), 1..3), inner.clone())
+                .prop_map(|(parameters, body)| node(NodeType::Lambda(parameters, Box::new(body)))),
+            (binary(), inner, any::<bool>())
+                .prop_filter("`(- 1)` is a negative number", |&(op, _, left)| left || op != Op::Sub)
+                .prop_map(|(op, operand, left)| section(op, operand, left)),
+        ]
+    }).boxed()
+}
+
+fn pattern() -> BoxedStrategy<Pattern> {
+    let leaf = prop_oneof![
+        Just(Pattern::Wildcard),
+        name().prop_filter("`_` is a wildcard", |s| s != "_").prop_map(Pattern::Binding),
+        "[A-Z][a-z]{0,3}".prop_map(|s| Pattern::Variant(s, Vec::new())),
+        literal().prop_map(|value| Pattern::Literal(Box::new(value))),
+    ];
+
+    leaf.prop_recursive(2, 8, 3, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 2..4).prop_map(Pattern::Tuple),
+            ("[A-Z][a-z]{0,3}", prop::collection::vec(inner, 1..3))
+                .prop_map(|(name, payload)| Pattern::Variant(name, payload)),
+        ]
+    }).boxed()
+}
+
+/// A value on the line of the statement it belongs to, or an indented block.
+fn value(block: BoxedStrategy<Vec<Node>>) -> BoxedStrategy<Node> {
+    prop_oneof![
+        expression(),
+        block.prop_map(|nodes| node(NodeType::Block(nodes))),
+    ].boxed()
+}
+
+/// Statements which may end with a block of their own.
+fn statement(block: BoxedStrategy<Vec<Node>>) -> BoxedStrategy<Node> {
+    let blocked = block.clone().prop_map(|nodes| node(NodeType::Block(nodes)));
+    let arm = (pattern(), value(block.clone()))
+        .prop_map(|(pattern, body)| Arm { pattern: pattern, span: Span::default(), body: body });
+
+    prop_oneof![
+        (name(), any::<bool>(), prop::option::of(value(block.clone())))
+            .prop_map(|(name, mutable, value)| node(NodeType::Let(name, mutable, value.map(Box::new)))),
+        (name(), prop::collection::vec(name(), 1..3), value(block.clone()))
+            .prop_map(|(name, parameters, body)| node(NodeType::Function(name, parameters, Box::new(body)))),
+        (expression(), blocked.clone(), prop::option::of(blocked))
+            .prop_map(|(condition, then, otherwise)| {
+                node(NodeType::If(Box::new(condition), Box::new(then), otherwise.map(Box::new)))
+            }),
+        (expression(), prop::collection::vec(arm, 1..3))
+            .prop_map(|(value, arms)| node(NodeType::Match(Box::new(value), arms))),
+    ].boxed()
+}
+
+/// Lines of expressions, where each line may open a block.
+/// Blocks can only follow a line, so one never comes first
+/// or right after another block.
+fn lines() -> BoxedStrategy<Vec<Node>> {
+    let leaf = prop::collection::vec(expression(), 1..4).boxed();
+
+    leaf.prop_recursive(4, 32, 4, |inner| {
+        let line = prop_oneof![
+            (expression(), prop::option::of(inner.clone())),
+            statement(inner).prop_map(|statement| (statement, None)),
+        ];
+        prop::collection::vec(line, 1..4)
+            .prop_map(|items| {
+                let mut nodes = Vec::new();
+                for (line, block) in items {
+                    nodes.push(line);
+                    if let Some(block) = block {
+                        nodes.push(node(NodeType::Block(block)))
+                    }
+                }
+                nodes
+            })
+            .boxed()
+    }).boxed()
+}
+
+proptest! {
+    #[test]
+    fn printed_source_parses_back(ast in lines()) {
+        let source = to_source(&ast);
+        let document = Document::new(&source);
+
+        prop_assert!(document.diagnostics().is_empty(), "{:?}", document.diagnostics());
+        let parsed: Vec<Node> = document.ast().into_iter().cloned().collect();
+        prop_assert_eq!(parsed, ast);
+    }
+
+    #[test]
+    fn lexing_terminates(source in any::<String>()) {
+        let count = grab_smaragdine_lexer(&source).count();
+        prop_assert!(count <= source.chars().count());
+    }
+
+    #[test]
+    fn block_tree_terminates(source in "[ a\n]*") {
+        let mut block_tree = BlockTree::new(&source, 0);
+        let indents = block_tree.collect_indents();
+        block_tree.tree(&indents);
+    }
+
+    #[test]
+    fn parsing_terminates(source in "([ \n]|[0-9.\"'rx\\\\-]|true|let)*") {
+        let document = Document::new(&source);
+        prop_assert!(document.ast().len() <= source.len() + 1);
+    }
+}
@@ -1,11 +1,41 @@
 extern crate libsmac;
 
 use libsmac::lexer::block_tree;
-use libsmac::lexer::{grab_smaragdine_lexer, lex_branch, flatten_branch};
+use libsmac::lexer::{lex_branch, flatten_branch};
+use libsmac::lexer::block_tree::Branch;
 use libsmac::parser::grab_smaragdine_parser;
+use libsmac::parser::node::Node;
+use libsmac::serialize::{ToJson, to_sexp};
+use libsmac::codegen::{self, compile, disassemble, emit_c, emit_wasm};
+use libsmac::conventions::{self, Conventions, Level};
+use libsmac::diagnostic::Diagnostic;
+use libsmac::interpreter::Interpreter;
+use libsmac::ir;
+#[cfg(feature = "jit")]
+use libsmac::jit::Jit;
+use libsmac::typeck::{self, Types};
+use libsmac::value::Value;
+use libsmac::vm::Vm;
 
-fn main() {
-    let data = r#"
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use std::process;
+
+const USAGE: &'static str = "\
+usage: smac [--emit=tokens|ast|sexp] [FILE]
+       smac run [--vm|--jit] [LINT...] [FILE]
+       smac build [--emit=smc|c|wasm|wat] [LINT...] FILE
+       smac build [-O0|-O1|-O2] --emit=ir [LINT...] FILE
+       smac disasm FILE.smc
+       smac repl
+
+LINT is --allow=RULE, --warn=RULE or --deny=RULE, where
+RULE is one of the naming conventions predicates, effects
+and purity";
+
+const SAMPLE: &'static str = r#"
 r"hey"
 '9'
 1234
@@ -15,14 +45,361 @@ r"hey"
 true
 false
     "#;
-    let mut block_tree = block_tree::BlockTree::new(&data, 0);
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let command = match args.first().map(|a| a.as_str()) {
+        Some("run") | Some("build") | Some("disasm") | Some("repl") => args.remove(0),
+        _                                                           => String::new(),
+    };
+
+    let mut emit  = None;
+    let mut level = None;
+    let mut vm    = false;
+    let mut jit   = false;
+    let mut path  = None;
+    let mut conventions = Conventions::default();
+
+    for arg in args {
+        if command == "repl" {
+            usage()
+        } else if command == "run" && arg == "--vm" && !jit {
+            vm = true
+        } else if command == "run" && arg == "--jit" && !vm {
+            jit = true
+        } else if command == "build" && (arg == "-O0" || arg == "-O1" || arg == "-O2") {
+            level = Some(arg[2..].parse().unwrap())
+        } else if command != "run" && command != "disasm" && arg.starts_with("--emit=") {
+            emit = Some(arg["--emit=".len()..].to_owned())
+        } else if (command == "run" || command == "build") && lint(&arg).is_some() {
+            let (level, rule) = lint(&arg).unwrap();
+            match conventions.level_mut(rule) {
+                Some(current) => *current = level,
+                None          => usage(),
+            }
+        } else if path.is_none() && !arg.starts_with("-") {
+            path = Some(arg)
+        } else {
+            usage()
+        }
+    }
+
+    match command.as_str() {
+        "run"    => run(path, vm, jit, &conventions),
+        "build"  => build(path, emit, level, &conventions),
+        "disasm" => disasm(path),
+        "repl"   => repl(),
+        _        => inspect(path, emit),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2)
+}
+
+/// The level a flag like `--warn=purity` gives a rule.
+fn lint(arg: &str) -> Option<(Level, &str)> {
+    let levels = [("--allow=", Level::Allow), ("--warn=", Level::Warn), ("--deny=", Level::Deny)];
+    levels.iter()
+        .find(|&&(flag, _)| arg.starts_with(flag))
+        .map(|&(flag, level)| (level, &arg[flag.len()..]))
+}
+
+/// Prints the output of a front-end stage.
+fn inspect(path: Option<String>, emit: Option<String>) {
+    let data = path.map_or(SAMPLE.to_owned(), |p| read_source(&p));
+    let (lexed_root, ast, diagnostics) = parse(&data);
+
+    match emit.as_ref().map_or("sexp", |e| e.as_str()) {
+        "tokens" => println!("{}", lexed_root.to_json().pretty()),
+        "ast"    => println!("{}", ast.to_json().pretty()),
+        "sexp"   => print!("{}", to_sexp(&ast)),
+        _        => usage(),
+    }
+
+    report(&diagnostics)
+}
+
+/// Runs a program, printing its value. Object
+/// files always run on the virtual machine.
+fn run(path: Option<String>, vm: bool, jit: bool, conventions: &Conventions) {
+    let result = match path {
+        Some(ref path) if path.ends_with(".smc") => {
+            let program = load(path);
+            Vm::new(&program).run()
+        },
+        path => {
+            let data = path.map_or(SAMPLE.to_owned(), |p| read_source(&p));
+            let (_, ast, diagnostics) = parse(&data);
+            // Nothing is run unless the whole program parsed
+            report(&diagnostics);
+            let types = check(&ast, conventions);
+
+            if vm {
+                compile(&ast).and_then(|program| Vm::new(&program).run())
+            } else if jit {
+                run_jit(&ast, &types)
+            } else {
+                Interpreter::new().run(&ast)
+            }
+        },
+    };
+
+    match result {
+        Ok(Value::Unit) => (),
+        Ok(value)       => println!("{}", value),
+        Err(error)      => report(&[error]),
+    }
+}
+
+/// Interprets a program, running the functions
+/// native code supports as native code.
+#[cfg(feature = "jit")]
+fn run_jit(ast: &[Node], types: &Types) -> Result<Value, Diagnostic> {
+    match Jit::compile(ast, types) {
+        Ok(mut jit) => Interpreter::with_dispatch(&mut jit).run(ast),
+        Err(error)  => {
+            eprintln!("note: interpreting, since native code is unavailable: {}", error);
+            Interpreter::new().run(ast)
+        },
+    }
+}
+
+#[cfg(not(feature = "jit"))]
+fn run_jit(ast: &[Node], _types: &Types) -> Result<Value, Diagnostic> {
+    eprintln!("note: interpreting, since smac was built without native code support");
+    Interpreter::new().run(ast)
+}
+
+/// Compiles a program to an object file next to it.
+/// Optimization levels only apply to the IR.
+fn build(path: Option<String>, emit: Option<String>, level: Option<u32>, conventions: &Conventions) {
+    let path = path.unwrap_or_else(|| usage());
+    if level.is_some() && emit.as_ref().map_or(true, |e| e != "ir") {
+        usage()
+    }
+    let (_, ast, diagnostics) = parse(&read_source(&path));
+    report(&diagnostics);
+    let types = check(&ast, conventions);
+
+    match emit.as_ref().map_or("smc", |e| e.as_str()) {
+        "smc" => {
+            let program = match compile(&ast) {
+                Ok(program) => program,
+                Err(error)  => return report(&[error]),
+            };
+            write(&Path::new(&path).with_extension("smc"), &codegen::write(&program))
+        },
+        "ir" => {
+            let mut module = match ir::lower(&ast, &types) {
+                Ok(module) => module,
+                Err(error) => return report(&[error]),
+            };
+            ir::optimize(&mut module, level.unwrap_or(0));
+            // Invalid IR is a bug in smac, not in the program
+            if let Err(errors) = ir::verify(&module) {
+                for error in errors.iter() {
+                    eprintln!("error: invalid IR {}", error)
+                }
+                process::exit(1)
+            }
+            write(&Path::new(&path).with_extension("ir"), module.to_string().as_bytes())
+        },
+        // The runtime header goes next to the C which includes it
+        "c" => {
+            let source = match emit_c(&ast, &types) {
+                Ok(source) => source,
+                Err(error) => return report(&[error]),
+            };
+            let output = Path::new(&path).with_extension("c");
+            write(&output, source.as_bytes());
+            write(&output.with_file_name("smac.h"), codegen::c::HEADER.as_bytes())
+        },
+        "wasm" | "wat" => {
+            let module = match emit_wasm(&ast, &types) {
+                Ok(module) => module,
+                Err(error) => return report(&[error]),
+            };
+            let output = Path::new(&path).with_extension(emit.as_ref().unwrap());
+            match emit.as_ref().unwrap().as_str() {
+                "wasm" => write(&output, &module.to_binary()),
+                _      => write(&output, module.to_text().as_bytes()),
+            }
+        },
+        _ => usage(),
+    }
+}
+
+fn write(path: &Path, data: &[u8]) {
+    if let Err(error) = File::create(path).and_then(|mut f| f.write_all(data)) {
+        eprintln!("error: unable to write {}: {}", path.display(), error);
+        process::exit(1)
+    }
+}
+
+/// Prints the instructions of an object file.
+fn disasm(path: Option<String>) {
+    let path = path.unwrap_or_else(|| usage());
+    print!("{}", disassemble(&load(&path)))
+}
+
+/// Reads a chunk of source at a time, printing its value and
+/// type. Each chunk is run along with every chunk accepted before
+/// it, so one with errors is reported and then forgotten.
+fn repl() {
+    let stdin = io::stdin();
+    let mut lines  = stdin.lock().lines();
+    let mut source = String::new();
+    // Where the last accepted chunk starts in the source
+    let mut last   = 0;
+
+    loop {
+        prompt("> ");
+        let mut chunk = match lines.next() {
+            Some(Ok(line)) => line,
+            _              => break,
+        };
+        // An indented line carries on the chunk before it
+        let indented = chunk.starts_with(char::is_whitespace) && !chunk.trim().is_empty();
+        let start    = if indented { last } else { source.len() };
+        // Blocks go on until a blank line
+        if indented || opens_block(&chunk) {
+            loop {
+                prompt(". ");
+                match lines.next() {
+                    Some(Ok(ref line)) if !line.trim().is_empty() => {
+                        chunk.push('\n');
+                        chunk.push_str(line)
+                    },
+                    _ => break,
+                }
+            }
+        }
+        if chunk.trim().is_empty() {
+            continue
+        }
+
+        let candidate = format!("{}{}\n", source, chunk);
+        match evaluate(&candidate) {
+            Ok(output) => {
+                source = candidate;
+                last   = start;
+                if let Some(output) = output {
+                    println!("{}", output)
+                }
+            },
+            Err(mut diagnostics) => {
+                // Count lines from the start of the chunk
+                let offset = source[..start].matches('\n').count();
+                for d in diagnostics.iter_mut() {
+                    if d.position().line > offset {
+                        d.position_mut().line -= offset
+                    }
+                    eprintln!("{}", d)
+                }
+            },
+        }
+    }
+}
+
+/// Whether a line is a header whose body follows it,
+/// such as a `while` or a definition ending in `=`.
+fn opens_block(line: &str) -> bool {
+    let line = line.trim();
+    let keyword = line.split_whitespace().next().unwrap_or("");
+    line.ends_with('=') || line.ends_with("->")
+        || ["type", "if", "else", "while", "for", "match"].contains(&keyword)
+}
+
+fn prompt(text: &str) {
+    print!("{}", text);
+    io::stdout().flush().ok();
+}
+
+/// Checks and interprets a program, describing its value
+/// unless it is unit.
+fn evaluate(source: &str) -> Result<Option<String>, Vec<Diagnostic>> {
+    let (_, ast, diagnostics) = parse(source);
+    if !diagnostics.is_empty() {
+        return Err(diagnostics)
+    }
+    let types = typeck::check(&ast)?;
+    // Warnings would repeat with every later chunk
+    let diagnostics = conventions::check(&ast, &types, &Conventions::default());
+    if diagnostics.iter().any(|d| d.is_error()) {
+        return Err(diagnostics.into_iter().filter(|d| d.is_error()).collect())
+    }
+    let value = Interpreter::new().run(&ast).map_err(|e| vec![e])?;
+
+    Ok(match (value, ast.last().and_then(|n| types.of(n))) {
+        (Value::Unit, _) => None,
+        (value, Some(t)) => Some(format!("{} : {}", value, t)),
+        (value, None)    => Some(value.to_string()),
+    })
+}
+
+fn read(path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    if let Err(error) = File::open(path).and_then(|mut f| f.read_to_end(&mut data)) {
+        eprintln!("error: unable to read {}: {}", path, error);
+        process::exit(1)
+    }
+    data
+}
+
+fn read_source(path: &str) -> String {
+    match String::from_utf8(read(path)) {
+        Ok(data) => data,
+        Err(_)   => {
+            eprintln!("error: {} is not valid UTF-8", path);
+            process::exit(1)
+        },
+    }
+}
+
+fn load(path: &str) -> codegen::Program {
+    match codegen::load(&read(path)) {
+        Ok(program) => program,
+        Err(error)  => {
+            eprintln!("error: {}: {}", path, error);
+            process::exit(1)
+        },
+    }
+}
+
+fn parse<'a>(data: &'a str) -> (Branch<'a>, Vec<Node>, Vec<Diagnostic>) {
+    let mut block_tree = block_tree::BlockTree::new(data, 0);
     let indents = block_tree.collect_indents();
     let lexed_root = lex_branch(&block_tree.tree(&indents));
     let flat_root = flatten_branch(&lexed_root);
-    println!("\n{:#?}", lexed_root);
-    println!("\n{:#?}", flat_root);
-    let parser = grab_smaragdine_parser(flat_root);
-    for t in parser {
-        println!("{:#?}", t)
+    let mut parser = grab_smaragdine_parser(flat_root);
+    let ast: Vec<Node> = parser.by_ref().collect();
+    let diagnostics = parser.take_diagnostics();
+
+    (lexed_root, ast, diagnostics)
+}
+
+/// Checks the types and naming conventions of a parsed
+/// program, printing warnings and exiting on errors.
+fn check(ast: &[Node], conventions: &Conventions) -> Types {
+    let types = match typeck::check(ast) {
+        Ok(types)        => types,
+        Err(diagnostics) => {
+            report(&diagnostics);
+            unreachable!()
+        },
+    };
+    report(&conventions::check(ast, &types, conventions));
+    types
+}
+
+/// Prints diagnostics, exiting if any are errors.
+fn report(diagnostics: &[Diagnostic]) {
+    for d in diagnostics {
+        eprintln!("{}", d)
+    }
+    if diagnostics.iter().any(|d| d.is_error()) {
+        process::exit(1)
     }
 }